@@ -5,8 +5,9 @@ use solana_program::{
     pubkey::Pubkey,
 };
 
-use crate::error::Result;
+use crate::error::{PythError, Result};
 use crate::pack::{PythInstruction, PythPack};
+use crate::state::PriceStatus;
 
 #[derive(Eq, PartialEq, PartialOrd, Debug, Clone, TryFromPrimitive, IntoPrimitive)]
 #[repr(u8)]
@@ -15,6 +16,10 @@ pub enum PythInstructionId {
     CreateProductAccount,
     CreateMappingAccount,
     PublishPrice,
+    SetStatus,
+    SetExponent,
+    MakeStale,
+    PublishPriceConf,
 }
 
 #[derive(Eq, PartialEq, PartialOrd, Debug, Clone)]
@@ -37,8 +42,24 @@ impl PythPack for CreatePriceAccountInstruction {
     }
 }
 
+/// Size of a product account.
+pub const PROD_ACCT_SIZE: usize = 512;
+
+/// Size of the product account header preceding the attributes.
+pub const PROD_HDR_SIZE: usize = 48;
+
+/// Space available for packed product attributes.
+pub const PROD_ATTR_SIZE: usize = PROD_ACCT_SIZE - PROD_HDR_SIZE;
+
 #[derive(Eq, PartialEq, PartialOrd, Debug, Clone)]
 pub struct CreateProductAccountInstruction {
+    /// price account of the product, or zeroes if there is none
+    pub px_acc: [u8; 32],
+    /// number of bytes of attr in use
+    pub attr_len: u32,
+    /// packed attributes, each a length prefixed key followed by a length
+    /// prefixed value
+    pub attr: [u8; PROD_ATTR_SIZE],
 }
 
 impl PythInstruction for CreateProductAccountInstruction {
@@ -46,13 +67,27 @@ impl PythInstruction for CreateProductAccountInstruction {
 }
 
 impl PythPack for CreateProductAccountInstruction {
-    const LEN: usize = 0;
+    const LEN: usize = 32 + 4 + PROD_ATTR_SIZE;
 
     fn unpack_from_slice(src: &[u8]) -> Result<Self> {
-        Ok(Self { })
+        let src = array_ref![src, 0, CreateProductAccountInstruction::LEN];
+        let (px_acc, attr_len, attr) = array_refs![src, 32, 4, PROD_ATTR_SIZE];
+
+        let attr_len = u32::from_le_bytes(*attr_len);
+
+        Ok(Self {
+            px_acc: *px_acc,
+            attr_len,
+            attr: *attr,
+        })
     }
 
     fn pack_into_slice(&self, dst: &mut [u8]) -> Result<()> {
+        let dst = array_mut_ref![dst, 0, CreateProductAccountInstruction::LEN];
+        let (px_acc_dst, attr_len_dst, attr_dst) = mut_array_refs![dst, 32, 4, PROD_ATTR_SIZE];
+        *px_acc_dst = self.px_acc;
+        *attr_len_dst = self.attr_len.to_le_bytes();
+        *attr_dst = self.attr;
         Ok(())
     }
 }
@@ -109,6 +144,124 @@ impl PythPack for PublishPriceInstruction {
     }
 }
 
+/// Sets the status of the aggregate price, such as to halt trading.
+#[derive(Eq, PartialEq, PartialOrd, Debug, Clone)]
+pub struct SetStatusInstruction {
+    pub status: PriceStatus,
+}
+
+impl PythInstruction for SetStatusInstruction {
+    const ID: PythInstructionId = PythInstructionId::SetStatus;
+}
+
+impl PythPack for SetStatusInstruction {
+    const LEN: usize = 4;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self> {
+        let src = array_ref![src, 0, SetStatusInstruction::LEN];
+        let status = PriceStatus::try_from_primitive(u32::from_le_bytes(*src))
+            .map_err(|_| PythError::CouldNotDecodeInstruction)?;
+        Ok(Self { status })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) -> Result<()> {
+        let dst = array_mut_ref![dst, 0, SetStatusInstruction::LEN];
+        let status: u32 = self.status.into();
+        *dst = status.to_le_bytes();
+        Ok(())
+    }
+}
+
+/// Sets the exponent of the price without publishing a new price.
+#[derive(Eq, PartialEq, PartialOrd, Debug, Clone)]
+pub struct SetExponentInstruction {
+    pub expo: i32,
+}
+
+impl PythInstruction for SetExponentInstruction {
+    const ID: PythInstructionId = PythInstructionId::SetExponent;
+}
+
+impl PythPack for SetExponentInstruction {
+    const LEN: usize = 4;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self> {
+        let src = array_ref![src, 0, SetExponentInstruction::LEN];
+        Ok(Self {
+            expo: i32::from_le_bytes(*src),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) -> Result<()> {
+        let dst = array_mut_ref![dst, 0, SetExponentInstruction::LEN];
+        *dst = self.expo.to_le_bytes();
+        Ok(())
+    }
+}
+
+/// Moves the price's publish slots back to lag the current slot.
+#[derive(Eq, PartialEq, PartialOrd, Debug, Clone)]
+pub struct MakeStaleInstruction {
+    pub slots_behind: u64,
+}
+
+impl PythInstruction for MakeStaleInstruction {
+    const ID: PythInstructionId = PythInstructionId::MakeStale;
+}
+
+impl PythPack for MakeStaleInstruction {
+    const LEN: usize = 8;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self> {
+        let src = array_ref![src, 0, MakeStaleInstruction::LEN];
+        Ok(Self {
+            slots_behind: u64::from_le_bytes(*src),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) -> Result<()> {
+        let dst = array_mut_ref![dst, 0, MakeStaleInstruction::LEN];
+        *dst = self.slots_behind.to_le_bytes();
+        Ok(())
+    }
+}
+
+/// Publishes a price together with its confidence interval.
+#[derive(Eq, PartialEq, PartialOrd, Debug, Clone)]
+pub struct PublishPriceConfInstruction {
+    pub price: i64,
+    pub conf: u64,
+    pub decimal: i32,
+}
+
+impl PythInstruction for PublishPriceConfInstruction {
+    const ID: PythInstructionId = PythInstructionId::PublishPriceConf;
+}
+
+impl PythPack for PublishPriceConfInstruction {
+    const LEN: usize = 20;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self> {
+        let src = array_ref![src, 0, PublishPriceConfInstruction::LEN];
+        let (price, conf, decimal) = array_refs![src, 8, 8, 4];
+
+        Ok(Self {
+            price: i64::from_le_bytes(*price),
+            conf: u64::from_le_bytes(*conf),
+            decimal: i32::from_le_bytes(*decimal),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) -> Result<()> {
+        let dst = array_mut_ref![dst, 0, PublishPriceConfInstruction::LEN];
+        let (price_dst, conf_dst, dec_dst) = mut_array_refs![dst, 8, 8, 4];
+        *price_dst = self.price.to_le_bytes();
+        *conf_dst = self.conf.to_le_bytes();
+        *dec_dst = self.decimal.to_le_bytes();
+        Ok(())
+    }
+}
+
 
 pub fn create_price_acc(
     program_id: &Pubkey,
@@ -145,3 +298,111 @@ pub fn publish_price(
         accounts,
     })
 }
+
+pub fn create_product_acc(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    acct_pkey: &Pubkey,
+    px_acc: Option<&Pubkey>,
+    attr: &[u8],
+) -> Result<Instruction> {
+    if attr.len() > PROD_ATTR_SIZE {
+        return Err(PythError::InvalidAccount);
+    }
+    let mut packed_attr = [0; PROD_ATTR_SIZE];
+    packed_attr[..attr.len()].copy_from_slice(attr);
+    let data = CreateProductAccountInstruction {
+        px_acc: px_acc.map(|px_acc| px_acc.to_bytes()).unwrap_or_default(),
+        attr_len: attr.len() as u32,
+        attr: packed_attr,
+    }
+    .pack_instruction_into_vec()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*payer, true),
+        AccountMeta::new(*acct_pkey, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn set_status(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    acct_pkey: &Pubkey,
+    status: PriceStatus,
+) -> Result<Instruction> {
+    let data = SetStatusInstruction { status }.pack_instruction_into_vec()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*payer, true),
+        AccountMeta::new(*acct_pkey, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn set_exponent(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    acct_pkey: &Pubkey,
+    expo: i32,
+) -> Result<Instruction> {
+    let data = SetExponentInstruction { expo }.pack_instruction_into_vec()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*payer, true),
+        AccountMeta::new(*acct_pkey, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn make_stale(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    acct_pkey: &Pubkey,
+    slots_behind: u64,
+) -> Result<Instruction> {
+    let data = MakeStaleInstruction { slots_behind }.pack_instruction_into_vec()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*payer, true),
+        AccountMeta::new(*acct_pkey, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn publish_price_conf(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    acct_pkey: &Pubkey,
+    price: i64,
+    conf: u64,
+    decimal: i32,
+) -> Result<Instruction> {
+    let data = PublishPriceConfInstruction {
+        price,
+        conf,
+        decimal,
+    }
+    .pack_instruction_into_vec()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*payer, true),
+        AccountMeta::new(*acct_pkey, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
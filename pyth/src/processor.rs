@@ -1,13 +1,16 @@
 pub mod create_mapping_account;
 pub mod create_price_account;
 pub mod create_product_account;
+pub mod make_stale;
 pub mod publish_price;
+pub mod set_exponent;
+pub mod set_status;
 
 use crate::error::{PythError, Result};
 use num_enum::TryFromPrimitive;
-use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey, msg};
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey, msg};
 
-use crate::instruction::{CreatePriceAccountInstruction, PublishPriceInstruction, CreateMappingAccountInstruction, CreateProductAccountInstruction, PythInstructionId};
+use crate::instruction::{CreatePriceAccountInstruction, PublishPriceInstruction, CreateMappingAccountInstruction, CreateProductAccountInstruction, PythInstructionId, SetExponentInstruction, SetStatusInstruction, MakeStaleInstruction, PublishPriceConfInstruction};
 use crate::pack::PythPack;
 
 pub fn process(
@@ -49,5 +52,46 @@ pub fn process(
                 CreateMappingAccountInstruction::unpack_from_slice(instruction_data)?;
             create_mapping_account::handle(program_id, accounts, unpacked_instruction)
         }
+
+        PythInstructionId::SetStatus => {
+            let unpacked_instruction =
+                SetStatusInstruction::unpack_from_slice(instruction_data)?;
+            set_status::handle(program_id, accounts, unpacked_instruction)
+        }
+
+        PythInstructionId::SetExponent => {
+            let unpacked_instruction =
+                SetExponentInstruction::unpack_from_slice(instruction_data)?;
+            set_exponent::handle(program_id, accounts, unpacked_instruction)
+        }
+
+        PythInstructionId::MakeStale => {
+            let unpacked_instruction =
+                MakeStaleInstruction::unpack_from_slice(instruction_data)?;
+            make_stale::handle(program_id, accounts, unpacked_instruction)
+        }
+
+        PythInstructionId::PublishPriceConf => {
+            let unpacked_instruction =
+                PublishPriceConfInstruction::unpack_from_slice(instruction_data)?;
+            publish_price::handle_with_conf(program_id, accounts, unpacked_instruction)
+        }
+    }
+}
+
+/// Checks that an admin instruction is signed by its payer and changes a
+/// price account owned by the program. The simulator records no publishers,
+/// so any signer may administer the program's price accounts.
+pub fn check_admin(
+    program_id: &Pubkey,
+    payer_acct: &AccountInfo,
+    acct_pkey: &AccountInfo,
+) -> ProgramResult {
+    if !payer_acct.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if acct_pkey.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
     }
+    Ok(())
 }
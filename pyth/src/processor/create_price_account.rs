@@ -2,17 +2,19 @@ use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
     pubkey::Pubkey,
-    msg,
 };
 
+use crate::error::PythError;
 use crate::instruction::CreatePriceAccountInstruction;
+use crate::pack::PythPack;
+use crate::state::{
+    AccKey, CorpAction, Ema, Price, PriceInfo, PriceStatus, PriceType, PRICE_ACCOUNT_SIZE,
+};
 
 use pyth_client:: {
     MAGIC,
     VERSION_2,
     AccountType,
-    PriceStatus,
-    CorpAction,
 };
 
 pub fn handle(
@@ -25,48 +27,46 @@ pub fn handle(
     let payer_acct = next_account_info(ai_iter)?;
     let acct_pkey = next_account_info(ai_iter)?;
 
-    let mut data = &mut *acct_pkey.data.borrow_mut();
-
-    for x in 0..4 {
-        data[x] = u32::to_le_bytes(MAGIC)[x];
-    }
-
-    for x in 4..8 {
-        data[x] = u32::to_le_bytes(VERSION_2)[x-4];
+    let data = &mut *acct_pkey.data.borrow_mut();
+    if data.len() < PRICE_ACCOUNT_SIZE {
+        return Err(PythError::InvalidAccount.into());
     }
 
-    for x in 8..12 {
-        data[x] = u32::to_le_bytes(AccountType::Price as u32)[x-8];
-    }
-    for x in 12..3312 {
-        data[x] = 0;
-    }
+    let ema = Ema { val: 0, numer: 0, denom: 0 };
+    let key = AccKey { val: [0; 32] };
+    let price = Price {
+        magic: MAGIC,
+        ver: VERSION_2,
+        atype: AccountType::Price as u32,
+        size: 0,
+        ptype: PriceType::Unknown,
+        expo: 0,
+        num: 0,
+        num_qt: 0,
+        last_slot: 0,
+        valid_slot: 0,
+        twap: ema,
+        twac: ema,
+        drv1: 0,
+        drv2: 0,
+        prod: key,
+        next: key,
+        prev_slot: 0,
+        prev_price: 0,
+        prev_conf: 0,
+        drv3: 0,
+        agg: PriceInfo {
+            price: 0,
+            conf: 100,
+            status: PriceStatus::Trading,
+            corp_act: CorpAction::NoCorpAct,
+            pub_slot: 0,
+        },
+    };
 
-    let pr: i64 = 0;
-    let conf: u64 = 100;
-    let status: PriceStatus = PriceStatus::Trading;
-    let corp_act = CorpAction::NoCorpAct;
-    let pub_slot: u64 = 0; 
-
-    for x in 208..216 {
-        data[x] = i64::to_le_bytes(pr)[x - 208];
-    }
+    // The component prices following the header start out empty.
+    data[..PRICE_ACCOUNT_SIZE].fill(0);
+    price.pack_into_slice(data)?;
 
-    for x in 216..224 {
-        data[x] = u64::to_le_bytes(conf)[x - 216];
-    }
-
-    for x in 224..228 {
-        data[x] = u32::to_le_bytes(status as u32)[x - 224];
-    }
-
-    for x in 228..232 {
-        data[x] = u32::to_le_bytes(corp_act as u32)[x - 228];
-    }
-
-    for x in 232..240 {
-        data[x] = u64::to_le_bytes(pub_slot)[x - 232];
-    }
-    
     Ok(())
 }
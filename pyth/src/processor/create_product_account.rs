@@ -4,16 +4,39 @@ use solana_program::{
     pubkey::Pubkey,
 };
 
-use crate::instruction::CreateProductAccountInstruction;
-use crate::state::Price;
+use crate::error::PythError;
+use crate::instruction::{CreateProductAccountInstruction, PROD_ACCT_SIZE, PROD_HDR_SIZE};
 
-use crate::pack::PythPack;
+use pyth_client:: {
+    MAGIC,
+    VERSION_2,
+    AccountType,
+};
 
 
 pub fn handle(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    pub_instr: CreateProductAccountInstruction,
+    create_instr: CreateProductAccountInstruction,
 ) -> ProgramResult {
+
+    let ai_iter = &mut accounts.iter();
+    let payer_acct = next_account_info(ai_iter)?;
+    let acct_pkey = next_account_info(ai_iter)?;
+
+    let data = &mut *acct_pkey.data.borrow_mut();
+    if data.len() < PROD_ACCT_SIZE {
+        return Err(PythError::InvalidAccount.into());
+    }
+
+    let size = PROD_HDR_SIZE as u32 + create_instr.attr_len;
+
+    data[0..4].copy_from_slice(&u32::to_le_bytes(MAGIC));
+    data[4..8].copy_from_slice(&u32::to_le_bytes(VERSION_2));
+    data[8..12].copy_from_slice(&u32::to_le_bytes(AccountType::Product as u32));
+    data[12..16].copy_from_slice(&u32::to_le_bytes(size));
+    data[16..48].copy_from_slice(&create_instr.px_acc);
+    data[PROD_HDR_SIZE..PROD_ACCT_SIZE].copy_from_slice(&create_instr.attr);
+
     Ok(())
 }
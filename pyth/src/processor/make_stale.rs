@@ -0,0 +1,33 @@
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use crate::instruction::MakeStaleInstruction;
+use crate::pack::PythPack;
+use crate::processor::check_admin;
+use crate::state::Price;
+
+pub fn handle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    stale_instr: MakeStaleInstruction,
+) -> ProgramResult {
+
+    let ai_iter = &mut accounts.iter();
+    let payer_acct = next_account_info(ai_iter)?;
+    let acct_pkey = next_account_info(ai_iter)?;
+    check_admin(program_id, payer_acct, acct_pkey)?;
+
+    let slot = Clock::get()?.slot.saturating_sub(stale_instr.slots_behind);
+
+    let mut price_struct: Price = Price::unpack_from_slice(&acct_pkey.data.borrow())?;
+    price_struct.agg.pub_slot = slot;
+    price_struct.valid_slot = slot;
+    price_struct.pack_into_slice(&mut *acct_pkey.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -7,7 +7,7 @@ use solana_program::{
     sysvar::Sysvar,
 };
 
-use crate::instruction::PublishPriceInstruction;
+use crate::instruction::{PublishPriceConfInstruction, PublishPriceInstruction};
 use crate::state::Price;
 
 use crate::pack::PythPack;
@@ -35,3 +35,24 @@ pub fn handle(
 
     Ok(())
 }
+
+pub fn handle_with_conf(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    pub_instr: PublishPriceConfInstruction,
+) -> ProgramResult {
+
+    let ai_iter = &mut accounts.iter();
+    let payer_acct = next_account_info(ai_iter)?;
+    let acct_pkey = next_account_info(ai_iter)?;
+
+    let mut price_struct: Price = Price::unpack_from_slice(&acct_pkey.data.borrow())?;
+
+    price_struct.agg.price = pub_instr.price;
+    price_struct.agg.conf = pub_instr.conf;
+    price_struct.agg.pub_slot = Clock::get()?.slot;
+    price_struct.expo = pub_instr.decimal;
+    price_struct.pack_into_slice(&mut *acct_pkey.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    pubkey::Pubkey,
+};
+
+use crate::instruction::SetStatusInstruction;
+use crate::pack::PythPack;
+use crate::processor::check_admin;
+use crate::state::Price;
+
+pub fn handle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    status_instr: SetStatusInstruction,
+) -> ProgramResult {
+
+    let ai_iter = &mut accounts.iter();
+    let payer_acct = next_account_info(ai_iter)?;
+    let acct_pkey = next_account_info(ai_iter)?;
+    check_admin(program_id, payer_acct, acct_pkey)?;
+
+    let mut price_struct: Price = Price::unpack_from_slice(&acct_pkey.data.borrow())?;
+    price_struct.agg.status = status_instr.status;
+    price_struct.pack_into_slice(&mut *acct_pkey.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -232,6 +232,45 @@ pub struct Price {
     // pub comp: [PriceComp; 32], SIZE BREAKS STACKFRAME, NOT SUPPORTED
 }
 
+/// Number of publisher components following the Price header.
+pub const PRICE_COMP_COUNT: usize = 32;
+
+/// Size of a price account: the header packed by Price followed by the
+/// component prices, which Price leaves unpacked.
+pub const PRICE_ACCOUNT_SIZE: usize = Price::LEN + PRICE_COMP_COUNT * PriceComp::LEN;
+
+/// Byte offsets of the fields of a price account, in the order Price packs
+/// them.
+pub mod price_offsets {
+    use super::{Ema, PriceInfo};
+
+    pub const MAGIC: usize = 0;
+    pub const VER: usize = MAGIC + 4;
+    pub const ATYPE: usize = VER + 4;
+    pub const SIZE: usize = ATYPE + 4;
+    pub const PTYPE: usize = SIZE + 4;
+    pub const EXPO: usize = PTYPE + 4;
+    pub const NUM: usize = EXPO + 4;
+    pub const NUM_QT: usize = NUM + 4;
+    pub const LAST_SLOT: usize = NUM_QT + 4;
+    pub const VALID_SLOT: usize = LAST_SLOT + 8;
+    pub const TWAP: usize = VALID_SLOT + 8;
+    pub const TWAC: usize = TWAP + Ema::LEN;
+    pub const DRV1: usize = TWAC + Ema::LEN;
+    pub const DRV2: usize = DRV1 + 8;
+    pub const PROD: usize = DRV2 + 8;
+    pub const NEXT: usize = PROD + 32;
+    pub const PREV_SLOT: usize = NEXT + 32;
+    pub const PREV_PRICE: usize = PREV_SLOT + 8;
+    pub const PREV_CONF: usize = PREV_PRICE + 8;
+    pub const DRV3: usize = PREV_CONF + 8;
+    pub const AGG: usize = DRV3 + 8;
+    pub const COMP: usize = AGG + PriceInfo::LEN;
+}
+
+// The offsets and the packed header must describe the same layout.
+const _: () = assert!(price_offsets::COMP == Price::LEN);
+
 impl PythPack for Price {
     const LEN: usize = 240; 
 
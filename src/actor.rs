@@ -1,46 +1,220 @@
-use crate::errors::{Error, Result};
-use crate::sandbox::Sandbox;
+use crate::errors::{Context, Error, Result};
+use crate::pda::PdaActor;
+use crate::sandbox::{PendingAirdrop, Sandbox};
+use crate::signer::RemoteSigner;
+use solana_client::client_error::ClientError;
 use solana_sdk::{
     account::Account,
+    hash::{hashv, Hash},
     instruction::Instruction,
+    message::Message,
+    nonce,
     pubkey::Pubkey,
-    signature::{Keypair, Signer},
+    signature::{keypair_from_seed, Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
 };
-use std::{io::Write, path::Path, process, thread, time};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+
+/// Derives a keypair from a 32 byte parent secret by hashing in each segment
+/// of the path in turn. Each intermediate hash is the secret key of the
+/// keypair at that point of the path.
+fn derive_keypair(parent: &[u8], path: &str) -> Result<Keypair> {
+    let seed = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(Hash::new(parent), |seed, segment| {
+            hashv(&[seed.as_ref(), segment.as_bytes()])
+        });
+    keypair_from_seed(seed.as_ref()).map_err(|err| {
+        Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            err.to_string(),
+        ))
+    })
+}
+
+/// Returns the keypair of the Actor that Actor::from_path creates for the
+/// path in a Sandbox with the given seed.
+pub(crate) fn path_keypair(seed: u64, path: &str) -> Result<Keypair> {
+    let root = hashv(&[b"solarium", &seed.to_le_bytes()]);
+    derive_keypair(root.as_ref(), path)
+}
+
+/// Logs the output of a command run on behalf of an Actor. Failures are
+/// logged at warn level so that they are visible without extra verbosity.
+fn log_output(command: &str, output: &process::Output) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() {
+        tracing::debug!(%stdout, %stderr, "{} succeeded", command);
+    } else {
+        tracing::warn!(status = %output.status, %stdout, %stderr, "{} failed", command);
+    }
+}
 
 /// Represents a keypair in a parent Sandbox environment.
+///
+/// An Actor may also be watch-only, in which case only its public key is
+/// known. Watch-only actors represent accounts that were created outside of
+/// this process and cannot sign transactions. A remote Actor signs through a
+/// RemoteSigner instead of an in-memory keypair, the way an application signs
+/// with a hardware wallet.
 pub struct Actor<'a> {
     sandbox: &'a Sandbox,
-    keypair: Keypair,
-    keyfile: tempfile::NamedTempFile,
+    keypair: Option<Keypair>,
+    remote: Option<RemoteSigner>,
+    keyfile: Mutex<Option<tempfile::NamedTempFile>>,
     pubkey: Pubkey,
 }
 
 impl<'a> Actor<'a> {
-    /// Creates an Actor in the given Sandbox environment.
+    /// Creates an Actor in the given Sandbox environment. The keypair is drawn
+    /// from the Sandbox's seeded random number generator.
     pub fn new(sandbox: &'a Sandbox) -> Result<Self> {
-        let keypair = Keypair::new();
-        let pubkey = keypair.pubkey();
-        let keyfile =
-            tempfile::NamedTempFile::new_in(sandbox.tmpdir()).expect("could not create keyfile");
-        if solana_sdk::signature::write_keypair_file(&keypair, &keyfile.path()).is_err() {
-            return Err(Error::InputOutputError(std::io::Error::from(
-                std::io::ErrorKind::NotFound,
-            )));
+        let keypair = sandbox.rng().keypair();
+        Actor::from_keypair(sandbox, keypair)
+    }
+
+    /// Creates an Actor with the given keypair. Its keyfile is written now,
+    /// or on first use if the Sandbox has lazy keyfiles.
+    pub fn from_keypair(sandbox: &'a Sandbox, keypair: Keypair) -> Result<Self> {
+        let actor = Self {
+            sandbox,
+            pubkey: keypair.pubkey(),
+            keypair: Some(keypair),
+            remote: None,
+            keyfile: Mutex::new(None),
+        };
+        if !sandbox.lazy_keyfiles() {
+            actor.keyfile()?;
+        }
+        sandbox.track_account(actor.pubkey());
+        Ok(actor)
+    }
+
+    /// Creates an Actor whose keypair is derived from the Sandbox's seed and
+    /// the given path, such as "market/maker-1". The same seed and path
+    /// always give the same keypair, regardless of how many other Actors were
+    /// created first.
+    pub fn from_path(sandbox: &'a Sandbox, path: &str) -> Result<Self> {
+        Actor::from_keypair(sandbox, path_keypair(sandbox.seed(), path)?)
+    }
+
+    /// Derives a child Actor from this Actor's keypair and a path of one or
+    /// more segments separated by "/". Derivation is deterministic, so a
+    /// scenario's keys are reproducible from a single parent, and deriving
+    /// "a/b" gives the same Actor as deriving "a" and then "b". Fails if the
+    /// Actor is watch-only or remote.
+    pub fn derive_child(&self, path: &str) -> Result<Actor<'a>> {
+        let keypair = self.keypair.as_ref().ok_or_else(|| {
+            Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "watch-only or remote actor cannot derive children",
+            ))
+        })?;
+        // The first half of the keypair bytes is the secret key.
+        let secret = &keypair.to_bytes()[..32];
+        Actor::from_keypair(self.sandbox, derive_keypair(secret, path)?)
+    }
+
+    /// Creates an Actor and registers the given name for it in the Sandbox.
+    pub fn new_named(sandbox: &'a Sandbox, name: &str) -> Result<Self> {
+        let actor = Actor::new(sandbox)?;
+        sandbox.register_name(actor.pubkey(), name);
+        Ok(actor)
+    }
+
+    /// Creates a watch-only Actor for an existing account whose keypair is
+    /// not known.
+    pub fn from_pubkey(sandbox: &'a Sandbox, pubkey: &Pubkey) -> Self {
+        sandbox.track_account(pubkey);
+        Self {
+            sandbox,
+            keypair: None,
+            remote: None,
+            pubkey: *pubkey,
+            keyfile: Mutex::new(None),
         }
-        keyfile.as_file().flush().expect("could not flush keyfile");
+    }
 
-        Ok(Self {
+    /// Creates an Actor that signs through the given RemoteSigner. It has no
+    /// keypair or keyfile, so it cannot deploy programs or derive children,
+    /// and it must be passed to transactions with signer rather than keypair.
+    pub fn remote(sandbox: &'a Sandbox, signer: RemoteSigner) -> Self {
+        sandbox.track_account(&signer.pubkey());
+        Self {
             sandbox,
-            keypair,
-            pubkey,
-            keyfile,
-        })
+            keypair: None,
+            pubkey: signer.pubkey(),
+            remote: Some(signer),
+            keyfile: Mutex::new(None),
+        }
+    }
+
+    /// Returns true if the Actor has a keypair or a remote signer and can
+    /// sign transactions.
+    pub fn can_sign(&self) -> bool {
+        self.keypair.is_some() || self.remote.is_some()
+    }
+
+    /// Returns true if the Actor signs through a RemoteSigner.
+    pub fn is_remote(&self) -> bool {
+        self.remote.is_some()
     }
 
-    /// Returns the Actor's keypair.
+    /// Returns the Actor's keypair. Panics if the Actor is watch-only or
+    /// remote; see try_keypair.
     pub fn keypair(&self) -> &Keypair {
-        &self.keypair
+        self.keypair
+            .as_ref()
+            .expect("watch-only or remote actor has no keypair")
+    }
+
+    /// Returns the Actor's keypair, or an error if the Actor is watch-only or
+    /// remote, such as an account attached with Market::new_from_existing.
+    pub fn try_keypair(&self) -> Result<&Keypair> {
+        self.keypair.as_ref().ok_or_else(|| {
+            Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "watch-only or remote actor {} has no keypair",
+                    self.sandbox.label(&self.pubkey)
+                ),
+            ))
+        })
+    }
+
+    /// Returns the Actor's signer, which is either its keypair or its remote
+    /// signer. Panics if the Actor is watch-only.
+    pub fn signer(&self) -> &dyn Signer {
+        match (&self.keypair, &self.remote) {
+            (Some(keypair), _) => keypair,
+            (None, Some(remote)) => remote,
+            (None, None) => panic!("watch-only actor has no signer"),
+        }
+    }
+
+    /// Adds this Actor's signature to a transaction signed by other parties,
+    /// keeping the signatures already present. The transaction's blockhash is
+    /// not changed, so the other signatures stay valid.
+    pub fn countersign(&self, transaction: &mut Transaction) -> Result<()> {
+        if !self.can_sign() {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "watch-only actor cannot sign",
+            )));
+        }
+        let blockhash = transaction.message.recent_blockhash;
+        transaction
+            .try_partial_sign(&vec![self.signer()], blockhash)
+            .map_err(ClientError::from)?;
+        Ok(())
     }
 
     /// Returns the Actor's public key.
@@ -49,26 +223,81 @@ impl<'a> Actor<'a> {
     }
 
     /// Returns the path to a JSON file on disk containing the Actor's private
-    /// key.
-    pub fn keyfile(&self) -> &Path {
-        self.keyfile.path()
+    /// key, writing the file if it does not exist yet. Fails if the Actor is
+    /// watch-only or remote, or the file cannot be written.
+    pub fn keyfile(&self) -> Result<PathBuf> {
+        let keypair = self.keypair.as_ref().ok_or_else(|| {
+            Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "watch-only or remote actor has no keyfile",
+            ))
+        })?;
+        let mut keyfile = self.keyfile.lock().expect("keyfile lock poisoned");
+        if let Some(keyfile) = keyfile.as_ref() {
+            return Ok(keyfile.path().to_path_buf());
+        }
+
+        let file =
+            tempfile::NamedTempFile::new_in(self.sandbox.tmpdir()).context("creating keyfile")?;
+        solana_sdk::signature::write_keypair_file(keypair, file.path()).map_err(|err| {
+            Error::from(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("writing keyfile: {}", err),
+            ))
+        })?;
+        file.as_file().flush().context("flushing keyfile")?;
+        let path = file.path().to_path_buf();
+        *keyfile = Some(file);
+        Ok(path)
     }
 
-    pub fn sandbox(&self) -> &Sandbox {
+    pub fn sandbox(&self) -> &'a Sandbox {
         self.sandbox
     }
 
     /// Airdrops the given number of lamports to this actor. Blocks until the
     /// airdrop is complete.
+    #[tracing::instrument(skip(self), fields(actor = %self.pubkey))]
     pub fn airdrop(&self, lamports: u64) -> Result<()> {
+        let pending = self.request_airdrop(lamports)?;
+        self.sandbox.confirm_all(&[pending]).with_context(|| {
+            format!(
+                "confirming airdrop to {}",
+                self.sandbox.label(self.pubkey())
+            )
+        })
+    }
+
+    /// Requests an airdrop of the given number of lamports to this actor
+    /// without waiting for it, from the faucet or a funder according to the
+    /// Sandbox's FundingSource. Pass the returned handles to
+    /// Sandbox::confirm_all to wait on many airdrops together:
+    ///
+    /// ```ignore
+    /// let pending = actors
+    ///     .iter()
+    ///     .map(|actor| actor.request_airdrop(LAMPORTS_PER_SOL))
+    ///     .collect::<Result<Vec<_>>>()?;
+    /// sandbox.confirm_all(&pending)?;
+    /// ```
+    pub fn request_airdrop(&self, lamports: u64) -> Result<PendingAirdrop> {
         let signature = self
             .sandbox
-            .client()
-            .request_airdrop(self.pubkey(), lamports)?;
-        while !self.sandbox.client().confirm_transaction(&signature)? {
-            thread::sleep(time::Duration::from_millis(10));
-        }
-        Ok(())
+            .request_airdrop(self.pubkey(), lamports)
+            .with_context(|| {
+                format!(
+                    "airdropping {} lamports to {}",
+                    lamports,
+                    self.sandbox.label(self.pubkey())
+                )
+            })?;
+        self.sandbox.audit_airdrop(self.pubkey(), lamports);
+        self.sandbox.account_cache().invalidate(self.pubkey());
+        Ok(PendingAirdrop {
+            signature,
+            pubkey: self.pubkey,
+            lamports,
+        })
     }
 
     /// Attempts to deploy a program if it exists locally. If it does not,
@@ -91,17 +320,22 @@ impl<'a> Actor<'a> {
     /// `target/deploy/program.so`. Returns the Actor representing the deployed
     /// program. In particular, the returned Actor's public key is the program's
     /// public key.
+    #[tracing::instrument(skip(self), fields(payer = %self.pubkey))]
     pub fn deploy_local(&self, program_location: &Path) -> Result<Actor> {
         let actor = Actor::new(self.sandbox)?;
+        let payer_keyfile = self.keyfile()?;
+        let program_keyfile = actor.keyfile()?;
 
-        let code = process::Command::new("solana")
+        let output = self
+            .sandbox
+            .solana_command()
             .args([
                 "program",
                 "deploy",
                 "--keypair",
-                self.keyfile().to_str().expect("could not specify keyfile"),
+                payer_keyfile.to_str().expect("could not specify keyfile"),
                 "--program-id",
-                actor.keyfile().to_str().expect("could not specify keyfile"),
+                program_keyfile.to_str().expect("could not specify keyfile"),
                 "--commitment",
                 "confirmed",
                 "--url",
@@ -110,10 +344,11 @@ impl<'a> Actor<'a> {
                     .to_str()
                     .expect("could not specify program location"),
             ])
-            .spawn()?
-            .wait()?;
+            .output()?;
+        log_output("solana program deploy", &output);
 
-        if code.success() {
+        if output.status.success() {
+            tracing::info!(program = %actor.pubkey, "deployed program");
             Ok(actor)
         } else {
             Err(Error::InputOutputError(std::io::Error::from(
@@ -122,36 +357,47 @@ impl<'a> Actor<'a> {
         }
     }
 
-    // Grabs executable from git and replicates it in the /solarium directory
+    // Grabs executable from git and saves it in the Sandbox's directory
     // Then, deploys the program to solana
     // remote_location: url to raw binary (i.e. ../../raw/../something.so)
     // file_name: local file name via wget
+    #[tracing::instrument(skip(self), fields(payer = %self.pubkey))]
     pub fn deploy_remote(&self, remote_location: &str, file_name: &str) -> Result<Actor> {
         let actor = Actor::new(self.sandbox)?;
 
-        let _ = process::Command::new("wget")
-            .args(["-O", file_name, remote_location])
-            .spawn()?
-            .wait()?;
+        let program_location = self.sandbox.tmpdir().join(file_name);
+        let output = process::Command::new("wget")
+            .arg("-O")
+            .arg(&program_location)
+            .arg(remote_location)
+            .output()?;
+        log_output("wget", &output);
 
-        let code = process::Command::new("solana")
+        let payer_keyfile = self.keyfile()?;
+        let program_keyfile = actor.keyfile()?;
+        let output = self
+            .sandbox
+            .solana_command()
             .args([
                 "program",
                 "deploy",
                 "--keypair",
-                self.keyfile().to_str().expect("could not specify keyfile"),
+                payer_keyfile.to_str().expect("could not specify keyfile"),
                 "--program-id",
-                actor.keyfile().to_str().expect("could not specify keyfile"),
+                program_keyfile.to_str().expect("could not specify keyfile"),
                 "--commitment",
                 "confirmed",
                 "--url",
                 &self.sandbox.url(),
-                &("./".to_owned() + file_name),
+                program_location
+                    .to_str()
+                    .expect("could not specify program location"),
             ])
-            .spawn()?
-            .wait()?;
+            .output()?;
+        log_output("solana program deploy", &output);
 
-        if code.success() {
+        if output.status.success() {
+            tracing::info!(program = %actor.pubkey, "deployed program");
             Ok(actor)
         } else {
             Err(Error::InputOutputError(std::io::Error::from(
@@ -179,8 +425,107 @@ impl<'a> Actor<'a> {
         ))
     }
 
+    /// Derives the program derived address of the seeds for owner_program
+    /// and funds it from this Actor with the rent-exempt minimum for size
+    /// bytes, less any lamports it already holds. The program must still
+    /// allocate and assign the account, typically in its initialization
+    /// instruction, since only the program can sign for the address.
+    pub fn create_pda_account(
+        &self,
+        seeds: &[&[u8]],
+        size: usize,
+        owner_program: &Pubkey,
+    ) -> Result<PdaActor<'a>> {
+        let pda = PdaActor::new(self.sandbox, seeds, owner_program);
+        let rent = self
+            .sandbox
+            .client()
+            .get_minimum_balance_for_rent_exemption(size)?;
+        let held = self.sandbox.client().get_balance(pda.pubkey())?;
+        if held < rent {
+            self.sandbox
+                .transaction()
+                .instruction(system_instruction::transfer(
+                    &self.pubkey,
+                    pda.pubkey(),
+                    rent - held,
+                ))
+                .payer(self.try_keypair()?)
+                .send()
+                .with_context(|| format!("funding program derived account {}", pda.pubkey()))?;
+        }
+        Ok(pda)
+    }
+
+    /// Creates a durable nonce account funded by this Actor. The nonce is
+    /// authorized by the given authority, or by this Actor if none is given.
+    /// Transactions can then use the stored blockhash through
+    /// TransactionBuilder::nonce instead of a recent blockhash, which allows
+    /// them to be signed long before they are sent.
+    pub fn create_nonce_account(&self, authority: Option<&Pubkey>) -> Result<Actor<'a>> {
+        let nonce = Actor::new(self.sandbox)?;
+        let authority = authority.unwrap_or(&self.pubkey);
+        let lamports = self
+            .sandbox
+            .client()
+            .get_minimum_balance_for_rent_exemption(nonce::state::State::size())?;
+        let instructions = system_instruction::create_nonce_account(
+            &self.pubkey,
+            nonce.pubkey(),
+            authority,
+            lamports,
+        );
+
+        self.sandbox
+            .transaction()
+            .instructions(&instructions)
+            .payer(self.try_keypair()?)
+            .signer(nonce.try_keypair()?)
+            .send()?;
+        Ok(nonce)
+    }
+
+    /// Advances a durable nonce account authorized by this Actor, so that
+    /// transactions signed with its previous blockhash can no longer land.
+    pub fn advance_nonce_account(&self, nonce_account: &Pubkey) -> Result<()> {
+        let instruction = system_instruction::advance_nonce_account(nonce_account, &self.pubkey);
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(self.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Closes this Actor's system account by transferring its entire balance,
+    /// less the transaction fee, to destination. The account no longer exists
+    /// once it holds no lamports. Returns the lamports transferred.
+    pub fn close_account(&self, destination: &Pubkey) -> Result<u64> {
+        let balance = self.sandbox.get_account(&self.pubkey)?.lamports;
+        let message = Message::new(
+            &[system_instruction::transfer(
+                &self.pubkey,
+                destination,
+                balance,
+            )],
+            Some(&self.pubkey),
+        );
+        let fee = self.sandbox.client().get_fee_for_message(&message)?;
+        let lamports = balance.saturating_sub(fee);
+        self.sandbox
+            .transaction()
+            .instruction(system_instruction::transfer(
+                &self.pubkey,
+                destination,
+                lamports,
+            ))
+            .payer(self.try_keypair()?)
+            .send()?;
+        Ok(lamports)
+    }
+
     /// Get account info
     pub fn get_account_info(&self) -> Result<Account> {
-        Ok(self.sandbox.client().get_account(&self.pubkey)?)
+        self.sandbox.get_account(&self.pubkey)
     }
 }
@@ -0,0 +1,462 @@
+use crate::actor::Actor;
+use crate::errors::{Error, Result};
+use crate::sandbox::Sandbox;
+use serde::Deserialize;
+use serde_json::Value;
+use solana_sdk::{
+    hash,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+use std::{collections::HashMap, fs, io, path::Path, str::FromStr};
+
+/// Parsed Anchor IDL. Only the parts needed to build instructions are kept.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Idl {
+    pub name: String,
+    #[serde(default)]
+    pub instructions: Vec<IdlInstruction>,
+    #[serde(default)]
+    pub types: Vec<IdlTypeDefinition>,
+    #[serde(default)]
+    pub accounts: Vec<IdlTypeDefinition>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdlInstruction {
+    pub name: String,
+    pub accounts: Vec<IdlAccountItem>,
+    pub args: Vec<IdlField>,
+}
+
+/// An instruction account, or a named group of accounts as produced by
+/// composite Accounts structs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum IdlAccountItem {
+    Accounts {
+        name: String,
+        accounts: Vec<IdlAccountItem>,
+    },
+    Account {
+        name: String,
+        #[serde(rename = "isMut")]
+        is_mut: bool,
+        #[serde(rename = "isSigner")]
+        is_signer: bool,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdlField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Value,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdlTypeDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: IdlTypeDefinitionTy,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum IdlTypeDefinitionTy {
+    Struct { fields: Vec<IdlField> },
+    Enum { variants: Vec<IdlEnumVariant> },
+}
+
+/// An enum variant. Fields are either a list of named fields or a list of
+/// types for tuple variants.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdlEnumVariant {
+    pub name: String,
+    #[serde(default)]
+    pub fields: Option<Vec<Value>>,
+}
+
+impl Idl {
+    /// Builds the named instruction for the program with this IDL. Accounts
+    /// are given by their IDL names; accounts nested in composite groups are
+    /// given by their own name. Arguments are a JSON object keyed by argument
+    /// name, or an array in argument order.
+    pub fn instruction(
+        &self,
+        program_id: &Pubkey,
+        name: &str,
+        accounts: &[(&str, Pubkey)],
+        args: &Value,
+    ) -> Result<Instruction> {
+        let ix = self
+            .instructions
+            .iter()
+            .find(|ix| ix.name == name)
+            .ok_or_else(|| invalid_input(format!("instruction {} not found in IDL", name)))?;
+
+        let accounts: HashMap<&str, Pubkey> = accounts.iter().cloned().collect();
+        let mut metas = Vec::new();
+        account_metas(&ix.accounts, &accounts, &mut metas)?;
+
+        let mut data = instruction_discriminator(&ix.name).to_vec();
+        for (index, field) in ix.args.iter().enumerate() {
+            let value = match args {
+                Value::Object(map) => map.get(&field.name),
+                Value::Array(values) => values.get(index),
+                Value::Null => None,
+                _ => return Err(invalid_input("arguments must be an object or an array")),
+            }
+            .ok_or_else(|| invalid_input(format!("missing argument {}", field.name)))?;
+            encode(self, &field.ty, value, &mut data)?;
+        }
+
+        Ok(Instruction {
+            program_id: *program_id,
+            accounts: metas,
+            data,
+        })
+    }
+}
+
+/// Represents a deployed Anchor program together with its IDL.
+///
+/// Instructions are built from the IDL at runtime: the discriminator is
+/// derived from the instruction name, accounts are ordered as the IDL lists
+/// them, and arguments given as JSON are Borsh-encoded according to their IDL
+/// types.
+///
+/// ```ignore
+/// let program = AnchorProgram::deploy(&actor, so_path, idl_path)?;
+/// let instruction = program.instruction(
+///     "setData",
+///     &[("data", *data.pubkey()), ("authority", *actor.pubkey())],
+///     &serde_json::json!({ "value": 42 }),
+/// )?;
+/// sandbox.transaction().instruction(instruction).payer(actor.keypair()).send()?;
+/// ```
+pub struct AnchorProgram<'a> {
+    program: Actor<'a>,
+    idl: Idl,
+}
+
+impl<'a> AnchorProgram<'a> {
+    /// Deploys the program at so_path and loads its IDL from idl_path,
+    /// typically `target/deploy/program.so` and `target/idl/program.json`.
+    pub fn deploy(actor: &'a Actor, so_path: &Path, idl_path: &Path) -> Result<Self> {
+        let idl = load_idl(idl_path)?;
+        let program = actor.deploy_local(so_path)?;
+        Ok(AnchorProgram { program, idl })
+    }
+
+    /// Attaches to an already deployed program.
+    pub fn from_existing(
+        sandbox: &'a Sandbox,
+        program_id: &Pubkey,
+        idl_path: &Path,
+    ) -> Result<Self> {
+        Ok(AnchorProgram {
+            program: Actor::from_pubkey(sandbox, program_id),
+            idl: load_idl(idl_path)?,
+        })
+    }
+
+    /// Returns the Actor representing the program.
+    pub fn program(&self) -> &Actor {
+        &self.program
+    }
+
+    /// Returns the program id.
+    pub fn program_id(&self) -> &Pubkey {
+        self.program.pubkey()
+    }
+
+    /// Returns the program's IDL.
+    pub fn idl(&self) -> &Idl {
+        &self.idl
+    }
+
+    /// Builds the named instruction. See Idl::instruction.
+    pub fn instruction(
+        &self,
+        name: &str,
+        accounts: &[(&str, Pubkey)],
+        args: &Value,
+    ) -> Result<Instruction> {
+        self.idl
+            .instruction(self.program.pubkey(), name, accounts, args)
+    }
+
+    /// Returns the data of an account owned by the program, with its account
+    /// discriminator checked and stripped.
+    pub fn account_data(&self, account_name: &str, pubkey: &Pubkey) -> Result<Vec<u8>> {
+        let data = self.program.sandbox().get_account_data(pubkey)?;
+        if data.len() < 8 || data[..8] != account_discriminator(account_name) {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("account {} is not a {}", pubkey, account_name),
+            )));
+        }
+        Ok(data[8..].to_vec())
+    }
+}
+
+/// Reads and parses an IDL file.
+pub fn load_idl(path: &Path) -> Result<Idl> {
+    let contents = fs::read_to_string(path)?;
+    serde_json::from_str(&contents)
+        .map_err(|err| Error::from(io::Error::new(io::ErrorKind::InvalidData, err)))
+}
+
+/// Returns the 8 byte discriminator Anchor prefixes instruction data with.
+pub fn instruction_discriminator(name: &str) -> [u8; 8] {
+    discriminator(&format!("global:{}", to_snake_case(name)))
+}
+
+/// Returns the 8 byte discriminator Anchor prefixes account data with.
+pub fn account_discriminator(name: &str) -> [u8; 8] {
+    discriminator(&format!("account:{}", name))
+}
+
+fn discriminator(preimage: &str) -> [u8; 8] {
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash::hash(preimage.as_bytes()).to_bytes()[..8]);
+    discriminator
+}
+
+/// IDL names are camelCase while Anchor hashes the Rust snake_case name.
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if index > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+fn account_metas(
+    items: &[IdlAccountItem],
+    accounts: &HashMap<&str, Pubkey>,
+    metas: &mut Vec<AccountMeta>,
+) -> Result<()> {
+    for item in items.iter() {
+        match item {
+            IdlAccountItem::Accounts {
+                accounts: nested, ..
+            } => account_metas(nested, accounts, metas)?,
+            IdlAccountItem::Account {
+                name,
+                is_mut,
+                is_signer,
+            } => {
+                let pubkey = accounts
+                    .get(name.as_str())
+                    .ok_or_else(|| invalid_input(format!("missing account {}", name)))?;
+                metas.push(if *is_mut {
+                    AccountMeta::new(*pubkey, *is_signer)
+                } else {
+                    AccountMeta::new_readonly(*pubkey, *is_signer)
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Borsh-encodes a JSON value as the given IDL type.
+fn encode(idl: &Idl, ty: &Value, value: &Value, data: &mut Vec<u8>) -> Result<()> {
+    match ty {
+        Value::String(primitive) => encode_primitive(primitive, value, data),
+        Value::Object(map) => {
+            if let Some(inner) = map.get("vec") {
+                let values = as_array(value)?;
+                data.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                for value in values.iter() {
+                    encode(idl, inner, value, data)?;
+                }
+                Ok(())
+            } else if let Some(inner) = map.get("option").or_else(|| map.get("coption")) {
+                // COption is encoded with a four byte tag.
+                let tag_len = if map.contains_key("coption") { 4 } else { 1 };
+                if value.is_null() {
+                    data.extend_from_slice(&[0u8; 4][..tag_len]);
+                    Ok(())
+                } else {
+                    data.extend_from_slice(&[1u8, 0, 0, 0][..tag_len]);
+                    encode(idl, inner, value, data)
+                }
+            } else if let Some(array) = map.get("array") {
+                let inner = &array[0];
+                let len = array[1]
+                    .as_u64()
+                    .ok_or_else(|| invalid_input("invalid array length in IDL"))?;
+                let values = as_array(value)?;
+                if values.len() as u64 != len {
+                    return Err(invalid_input(format!(
+                        "expected array of length {}, got {}",
+                        len,
+                        values.len()
+                    )));
+                }
+                for value in values.iter() {
+                    encode(idl, inner, value, data)?;
+                }
+                Ok(())
+            } else if let Some(Value::String(name)) = map.get("defined") {
+                encode_defined(idl, name, value, data)
+            } else {
+                Err(invalid_input(format!("unsupported IDL type {}", ty)))
+            }
+        }
+        _ => Err(invalid_input(format!("unsupported IDL type {}", ty))),
+    }
+}
+
+fn encode_primitive(primitive: &str, value: &Value, data: &mut Vec<u8>) -> Result<()> {
+    let mismatch = || invalid_input(format!("expected {}, got {}", primitive, value));
+    match primitive {
+        "bool" => data.push(value.as_bool().ok_or_else(mismatch)? as u8),
+        "u8" => data.extend_from_slice(&as_int::<u8>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "i8" => data.extend_from_slice(&as_int::<i8>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "u16" => data.extend_from_slice(&as_int::<u16>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "i16" => data.extend_from_slice(&as_int::<i16>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "u32" => data.extend_from_slice(&as_int::<u32>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "i32" => data.extend_from_slice(&as_int::<i32>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "u64" => data.extend_from_slice(&as_int::<u64>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "i64" => data.extend_from_slice(&as_int::<i64>(value).ok_or_else(mismatch)?.to_le_bytes()),
+        "u128" => {
+            data.extend_from_slice(&as_int::<u128>(value).ok_or_else(mismatch)?.to_le_bytes())
+        }
+        "i128" => {
+            data.extend_from_slice(&as_int::<i128>(value).ok_or_else(mismatch)?.to_le_bytes())
+        }
+        "f32" => {
+            data.extend_from_slice(&(value.as_f64().ok_or_else(mismatch)? as f32).to_le_bytes())
+        }
+        "f64" => data.extend_from_slice(&value.as_f64().ok_or_else(mismatch)?.to_le_bytes()),
+        "string" => {
+            let string = value.as_str().ok_or_else(mismatch)?;
+            data.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            data.extend_from_slice(string.as_bytes());
+        }
+        "bytes" => {
+            let bytes = as_array(value)?
+                .iter()
+                .map(|byte| as_int::<u8>(byte).ok_or_else(mismatch))
+                .collect::<Result<Vec<u8>>>()?;
+            data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            data.extend_from_slice(&bytes);
+        }
+        "publicKey" => {
+            let pubkey = value
+                .as_str()
+                .and_then(|string| Pubkey::from_str(string).ok())
+                .ok_or_else(mismatch)?;
+            data.extend_from_slice(pubkey.as_ref());
+        }
+        _ => return Err(invalid_input(format!("unsupported IDL type {}", primitive))),
+    }
+    Ok(())
+}
+
+fn encode_defined(idl: &Idl, name: &str, value: &Value, data: &mut Vec<u8>) -> Result<()> {
+    let definition = idl
+        .types
+        .iter()
+        .chain(idl.accounts.iter())
+        .find(|definition| definition.name == name)
+        .ok_or_else(|| invalid_input(format!("type {} not found in IDL", name)))?;
+
+    match &definition.ty {
+        IdlTypeDefinitionTy::Struct { fields } => encode_fields(idl, fields, value, data),
+        IdlTypeDefinitionTy::Enum { variants } => {
+            // Unit variants are given by name; other variants as an object
+            // with the variant name as its only key.
+            let (variant_name, fields_value) = match value {
+                Value::String(variant_name) => (variant_name.as_str(), &Value::Null),
+                Value::Object(map) if map.len() == 1 => {
+                    let (variant_name, fields_value) = map.iter().next().unwrap();
+                    (variant_name.as_str(), fields_value)
+                }
+                _ => return Err(invalid_input(format!("expected {} variant", name))),
+            };
+            let index = variants
+                .iter()
+                .position(|variant| variant.name == variant_name)
+                .ok_or_else(|| {
+                    invalid_input(format!("variant {} not found in {}", variant_name, name))
+                })?;
+            data.push(index as u8);
+
+            match &variants[index].fields {
+                None => Ok(()),
+                Some(fields) => {
+                    if fields.iter().all(|field| field.get("name").is_some()) {
+                        let fields = fields
+                            .iter()
+                            .map(|field| {
+                                serde_json::from_value(field.clone()).map_err(|err| {
+                                    Error::from(io::Error::new(io::ErrorKind::InvalidData, err))
+                                })
+                            })
+                            .collect::<Result<Vec<IdlField>>>()?;
+                        encode_fields(idl, &fields, fields_value, data)
+                    } else {
+                        let values = as_array(fields_value)?;
+                        if values.len() != fields.len() {
+                            return Err(invalid_input(format!(
+                                "expected {} fields for {}",
+                                fields.len(),
+                                variant_name
+                            )));
+                        }
+                        for (ty, value) in fields.iter().zip(values.iter()) {
+                            encode(idl, ty, value, data)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn encode_fields(idl: &Idl, fields: &[IdlField], value: &Value, data: &mut Vec<u8>) -> Result<()> {
+    for field in fields.iter() {
+        let field_value = value
+            .get(&field.name)
+            .ok_or_else(|| invalid_input(format!("missing field {}", field.name)))?;
+        encode(idl, &field.ty, field_value, data)?;
+    }
+    Ok(())
+}
+
+/// Reads an integer given either as a JSON number or, for values that do not
+/// fit in a JSON number, as a decimal string.
+fn as_int<T: TryFrom<i128> + FromStr>(value: &Value) -> Option<T> {
+    match value {
+        Value::Number(number) => number
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| number.as_u64().map(i128::from))
+            .and_then(|int| T::try_from(int).ok()),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_array(value: &Value) -> Result<&Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| invalid_input(format!("expected array, got {}", value)))
+}
+
+fn invalid_input<S: Into<String>>(message: S) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidInput, message.into()))
+}
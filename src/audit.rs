@@ -0,0 +1,282 @@
+use crate::errors::Result;
+use crate::sandbox::{Sandbox, MAX_ACCOUNTS_PER_REQUEST};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::UiTransactionEncoding;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// Transactions and airdrops seen by a Sandbox while a LamportAudit runs.
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    transactions: Vec<(Signature, Pubkey)>,
+    airdrops: Vec<(Pubkey, u64)>,
+}
+
+impl AuditLog {
+    /// Records a transaction send attempt and its fee payer.
+    pub(crate) fn record_transaction(&mut self, signature: Signature, payer: Pubkey) {
+        self.transactions.push((signature, payer));
+    }
+
+    /// Records an airdrop request.
+    pub(crate) fn record_airdrop(&mut self, pubkey: Pubkey, lamports: u64) {
+        self.airdrops.push((pubkey, lamports));
+    }
+}
+
+/// Accounts for every lamport held by the accounts of a Sandbox's Actors
+/// over the course of a test.
+///
+/// Starting an audit snapshots the lamports of every Actor created so far.
+/// Finishing it snapshots them again, together with the Actors created in
+/// between, and splits each account's change into airdrops, transaction
+/// fees, rent, and transfers. Lamports that moved to or from accounts that
+/// are not Actors, such as program-derived accounts, are reported as
+/// unaccounted, which catches programs that leak or mint lamports:
+///
+/// ```ignore
+/// let audit = LamportAudit::start(&sandbox)?;
+/// // ... run the scenario ...
+/// let report = audit.finish()?;
+/// assert!(report.is_balanced(), "{}", report);
+/// ```
+///
+/// Only transactions sent through the Sandbox and airdrops requested
+/// through it are seen. Lamports spent by commands it runs, such as the
+/// solana CLI deploying a program, are unaccounted.
+pub struct LamportAudit<'a> {
+    sandbox: &'a Sandbox,
+    before: HashMap<Pubkey, Option<(u64, usize)>>,
+}
+
+impl<'a> LamportAudit<'a> {
+    /// Snapshots the Sandbox's accounts and starts recording its
+    /// transactions and airdrops. An audit that is already running is
+    /// replaced.
+    pub fn start(sandbox: &'a Sandbox) -> Result<Self> {
+        let before = snapshot(sandbox, &sandbox.tracked_accounts())?;
+        sandbox.start_audit();
+        Ok(LamportAudit { sandbox, before })
+    }
+
+    /// Stops recording and reports where the lamports went. Transaction fees
+    /// are read from the confirmed transactions.
+    pub fn finish(self) -> Result<LamportReport> {
+        let log = self.sandbox.stop_audit().unwrap_or_default();
+
+        let mut fees: HashMap<Pubkey, u64> = HashMap::new();
+        let mut seen = HashSet::new();
+        for (signature, payer) in log.transactions.iter() {
+            if !seen.insert(*signature) {
+                continue;
+            }
+            // Transactions rejected before landing are not found and cost
+            // nothing.
+            let fee = self
+                .sandbox
+                .client()
+                .get_transaction(signature, UiTransactionEncoding::Json)
+                .ok()
+                .and_then(|transaction| transaction.transaction.meta)
+                .map_or(0, |meta| meta.fee);
+            *fees.entry(*payer).or_default() += fee;
+        }
+        let mut airdrops: HashMap<Pubkey, u64> = HashMap::new();
+        for (pubkey, lamports) in log.airdrops.iter() {
+            *airdrops.entry(*pubkey).or_default() += lamports;
+        }
+
+        let mut pubkeys = self.sandbox.tracked_accounts();
+        let mut known: HashSet<Pubkey> = pubkeys.iter().copied().collect();
+        for pubkey in self.before.keys().chain(fees.keys()).chain(airdrops.keys()) {
+            if known.insert(*pubkey) {
+                pubkeys.push(*pubkey);
+            }
+        }
+        let after = snapshot(self.sandbox, &pubkeys)?;
+
+        let mut rent_exempt: HashMap<usize, u64> = HashMap::new();
+        let mut accounts = Vec::with_capacity(pubkeys.len());
+        for pubkey in pubkeys.iter() {
+            let before = self.before.get(pubkey).copied().flatten();
+            let after = after.get(pubkey).copied().flatten();
+            let rent = match (before, after) {
+                (None, Some((lamports, len))) if len > 0 => {
+                    let minimum = match rent_exempt.get(&len) {
+                        Some(minimum) => *minimum,
+                        None => {
+                            let minimum = self
+                                .sandbox
+                                .client()
+                                .get_minimum_balance_for_rent_exemption(len)?;
+                            rent_exempt.insert(len, minimum);
+                            minimum
+                        }
+                    };
+                    lamports.min(minimum) as i128
+                }
+                (Some((lamports, len)), None) if len > 0 => -(lamports as i128),
+                _ => 0,
+            };
+            let before = before.map_or(0, |(lamports, _)| lamports);
+            let after = after.map_or(0, |(lamports, _)| lamports);
+            let airdropped = airdrops.get(pubkey).copied().unwrap_or(0);
+            let fees = fees.get(pubkey).copied().unwrap_or(0);
+            accounts.push(AccountLamports {
+                pubkey: *pubkey,
+                name: self.sandbox.label(pubkey),
+                before,
+                after,
+                airdropped,
+                fees,
+                rent,
+                transfers: after as i128 - before as i128 - airdropped as i128 + fees as i128
+                    - rent,
+            });
+        }
+        Ok(LamportReport { accounts })
+    }
+}
+
+impl<'a> Drop for LamportAudit<'a> {
+    /// Stops recording if the audit was not finished.
+    fn drop(&mut self) {
+        self.sandbox.stop_audit();
+    }
+}
+
+/// Fetches the lamports and data length of each account, or None for
+/// accounts that do not exist.
+fn snapshot(
+    sandbox: &Sandbox,
+    pubkeys: &[Pubkey],
+) -> Result<HashMap<Pubkey, Option<(u64, usize)>>> {
+    let mut snapshot = HashMap::with_capacity(pubkeys.len());
+    for chunk in pubkeys.chunks(MAX_ACCOUNTS_PER_REQUEST) {
+        let accounts = sandbox.run_rpc(|| sandbox.client().get_multiple_accounts(chunk))?;
+        for (pubkey, account) in chunk.iter().zip(accounts.into_iter()) {
+            snapshot.insert(
+                *pubkey,
+                account.map(|account| (account.lamports, account.data.len())),
+            );
+        }
+    }
+    Ok(snapshot)
+}
+
+/// How one account's lamports changed during a LamportAudit. The change
+/// from before to after is airdropped - fees + rent + transfers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountLamports {
+    pub pubkey: Pubkey,
+
+    /// Registered name of the account, or its address.
+    pub name: String,
+
+    /// Lamports held when the audit started, or zero if the account did not
+    /// exist.
+    pub before: u64,
+
+    /// Lamports held when the audit finished, or zero if the account no
+    /// longer exists.
+    pub after: u64,
+
+    /// Lamports airdropped to the account.
+    pub airdropped: u64,
+
+    /// Fees paid by transactions the account was the fee payer of.
+    pub fees: u64,
+
+    /// Rent deposited when the account was created with data, up to the
+    /// rent-exempt minimum, or minus its balance if it was closed.
+    pub rent: i128,
+
+    /// The rest of the change, moved by transfers.
+    pub transfers: i128,
+}
+
+impl AccountLamports {
+    /// Returns the change in lamports held.
+    pub fn change(&self) -> i128 {
+        self.after as i128 - self.before as i128
+    }
+}
+
+/// The result of a LamportAudit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LamportReport {
+    /// Every audited account, Actors in creation order first.
+    pub accounts: Vec<AccountLamports>,
+}
+
+impl LamportReport {
+    /// Returns the lamports airdropped to audited accounts.
+    pub fn airdropped(&self) -> u64 {
+        self.accounts.iter().map(|account| account.airdropped).sum()
+    }
+
+    /// Returns the transaction fees paid by audited accounts.
+    pub fn fees(&self) -> u64 {
+        self.accounts.iter().map(|account| account.fees).sum()
+    }
+
+    /// Returns the rent deposited into new accounts less the rent reclaimed
+    /// from closed ones.
+    pub fn rent(&self) -> i128 {
+        self.accounts.iter().map(|account| account.rent).sum()
+    }
+
+    /// Returns the change in lamports held by audited accounts.
+    pub fn net_change(&self) -> i128 {
+        self.accounts.iter().map(|account| account.change()).sum()
+    }
+
+    /// Returns the lamports that left the audited accounts for accounts
+    /// outside the audit, or entered from them if negative. This is the
+    /// airdrops less the fees and the net change.
+    pub fn unaccounted(&self) -> i128 {
+        self.airdropped() as i128 - self.fees() as i128 - self.net_change()
+    }
+
+    /// Returns true if every lamport is accounted for.
+    pub fn is_balanced(&self) -> bool {
+        self.unaccounted() == 0
+    }
+
+    /// Returns the accounts whose balance changed or that paid fees.
+    pub fn changed(&self) -> impl Iterator<Item = &AccountLamports> {
+        self.accounts
+            .iter()
+            .filter(|account| account.change() != 0 || account.fees != 0)
+    }
+}
+
+impl fmt::Display for LamportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "airdropped {}, fees {}, rent {}, net change {}, unaccounted {}",
+            self.airdropped(),
+            self.fees(),
+            self.rent(),
+            self.net_change(),
+            self.unaccounted()
+        )?;
+        for account in self.changed() {
+            writeln!(
+                f,
+                "  {}: {} -> {} (airdropped {}, fees {}, rent {}, transfers {})",
+                account.name,
+                account.before,
+                account.after,
+                account.airdropped,
+                account.fees,
+                account.rent,
+                account.transfers
+            )?;
+        }
+        Ok(())
+    }
+}
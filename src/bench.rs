@@ -0,0 +1,186 @@
+use crate::actor::Actor;
+use crate::errors::{Context, Result};
+use crate::serum::{Market, Participant};
+use serum_dex::matching::{OrderType, Side};
+use std::{fmt, num::NonZeroU64, time};
+
+/// Latencies measured for one kind of operation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    samples: Vec<time::Duration>,
+}
+
+impl LatencyStats {
+    /// Adds a measurement.
+    pub fn record(&mut self, latency: time::Duration) {
+        self.samples.push(latency);
+    }
+
+    /// Runs an operation, recording how long it took, and returns its
+    /// result. Failed operations are not recorded.
+    pub fn time<T, F: FnOnce() -> Result<T>>(&mut self, operation: F) -> Result<T> {
+        let start = time::Instant::now();
+        let result = operation()?;
+        self.record(start.elapsed());
+        Ok(result)
+    }
+
+    /// Returns the measurements in the order they were recorded.
+    pub fn samples(&self) -> &[time::Duration] {
+        &self.samples
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns the latency below which the given percentage of measurements
+    /// fall, using the nearest-rank method, or zero without measurements.
+    pub fn percentile(&self, percent: f64) -> time::Duration {
+        if self.samples.is_empty() {
+            return time::Duration::ZERO;
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+    }
+
+    pub fn min(&self) -> time::Duration {
+        self.samples.iter().min().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> time::Duration {
+        self.samples.iter().max().copied().unwrap_or_default()
+    }
+
+    pub fn mean(&self) -> time::Duration {
+        if self.samples.is_empty() {
+            return time::Duration::ZERO;
+        }
+        self.samples.iter().sum::<time::Duration>() / self.samples.len() as u32
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "n={} min={:?} p50={:?} p90={:?} p99={:?} max={:?} mean={:?}",
+            self.count(),
+            self.min(),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.max(),
+            self.mean()
+        )
+    }
+}
+
+/// Controls a market making benchmark.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Rounds of quoting, taking, cranking, and settling.
+    pub iterations: usize,
+
+    /// Price of every order, in quote lots per base lot.
+    pub price: u64,
+
+    /// Size of every order, in base lots.
+    pub size: u64,
+
+    /// Events consumed by each crank.
+    pub crank_limit: u16,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            iterations: 20,
+            price: 10,
+            size: 1,
+            crank_limit: 10,
+        }
+    }
+}
+
+/// Latencies measured by a market making benchmark.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BenchReport {
+    /// Time from sending a new order to its confirmation.
+    pub place_order: LatencyStats,
+
+    /// Time to consume the events of a round.
+    pub crank: LatencyStats,
+
+    /// Time to settle one participant.
+    pub settle: LatencyStats,
+
+    /// Time taken by the whole benchmark.
+    pub elapsed: time::Duration,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "place_order: {}", self.place_order)?;
+        writeln!(f, "crank:       {}", self.crank)?;
+        writeln!(f, "settle:      {}", self.settle)?;
+        write!(f, "elapsed:     {:?}", self.elapsed)
+    }
+}
+
+/// Measures the round trip latencies of market making against a Sandbox.
+///
+/// Each iteration the maker rests an ask, the taker fills it with a bid,
+/// the cranker consumes the fill, and both participants settle, so balances
+/// return to where they started less fees. Run it under different
+/// SandboxBuilder settings, transaction builders, or compute budgets to
+/// compare them:
+///
+/// ```ignore
+/// let report = bench::market_making(&market, &maker, &taker, &cranker, &BenchOptions {
+///     iterations: 100,
+///     ..BenchOptions::default()
+/// })?;
+/// println!("{}", report);
+/// assert!(report.place_order.percentile(99.0) < Duration::from_secs(2));
+/// ```
+///
+/// Both participants need enough base and quote for one order per
+/// iteration; any failure stops the benchmark.
+pub fn market_making(
+    market: &Market,
+    maker: &Participant,
+    taker: &Participant,
+    cranker: &Actor,
+    options: &BenchOptions,
+) -> Result<BenchReport> {
+    let start = time::Instant::now();
+    let price = NonZeroU64::new(options.price.max(1)).unwrap();
+    let size = NonZeroU64::new(options.size.max(1)).unwrap();
+
+    let mut report = BenchReport::default();
+    for i in 0..options.iterations {
+        for (participant, side) in [(maker, Side::Ask), (taker, Side::Bid)] {
+            report
+                .place_order
+                .time(|| {
+                    market.place_order(participant, side, price, size, OrderType::Limit, i as u64)
+                })
+                .with_context(|| format!("placing {:?} in benchmark round {}", side, i))?;
+        }
+        report
+            .crank
+            .time(|| market.crank_all(cranker, options.crank_limit))
+            .with_context(|| format!("cranking in benchmark round {}", i))?;
+        for participant in [maker, taker] {
+            report
+                .settle
+                .time(|| market.settle_funds(cranker, participant))
+                .with_context(|| format!("settling in benchmark round {}", i))?;
+        }
+    }
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
@@ -6,68 +6,31 @@
 //! serum-place-order examples. Please see the documentation under
 //! serum-market-maker for more details.
 //!
+//! Once the market is ready, an empty <output_file_name>.done file is written
+//! and the program serves the market until it receives SIGINT or SIGTERM, at
+//! which point it removes the .done file and shuts the validator down.
+//! Readiness and the market can also be queried over HTTP:
+//!
+//! ```bash
+//! create_serum_market --status-port 8080
+//! curl http://127.0.0.1:8080/ready
+//! curl http://127.0.0.1:8080/market.json
+//! ```
+//!
 use clap::Parser;
-use serde::Serialize;
 use solana_program::native_token::LAMPORTS_PER_SOL;
-use solarium::{actor::Actor, sandbox::Sandbox, serum::Participant, token::Mint};
-use std::convert::TryInto;
+use solarium::{
+    actor::Actor,
+    fixtures::MarketManifest,
+    sandbox::Sandbox,
+    serum::{MarketParams, Participant},
+    token::Mint,
+};
 use std::fs;
-
-// Represents a Serum market participant that was initialized by this binary.
-#[derive(Serialize, Debug)]
-struct TestMarketParticipant {
-    // Participant's private key.
-    keypair: String,
-
-    // Public key of participant's base token account.
-    base: String,
-
-    // Public key of participant's quote token account.
-    quote: String,
-
-    // Public key of participant's open orders account.
-    orders: String,
-}
-
-// Represents a Serum market that was initialized by this binary.
-#[derive(Serialize, Debug)]
-struct TestMarket {
-    // Solana test validator RPC endpoint.
-    url: String,
-
-    // Serum program public key.
-    program_id: String,
-
-    // Public key of the market initialized by this binary.
-    market: String,
-
-    // Public key of the market's bids slab initialized by this binary.
-    bids: String,
-
-    // Public key of the market's asks slab initialized by this binary.
-    asks: String,
-
-    // Public key of the market's request queue initialized by this binary.
-    request_queue: String,
-
-    // Public key of the market's event queue initialized by this binary.
-    event_queue: String,
-
-    // Public key of the market's base vault initialized by this binary.
-    base_vault: String,
-
-    // Public key of the market's quote vault initialized by this binary.
-    quote_vault: String,
-
-    // Public key of the market's base mint initialized by this binary.
-    base_mint: String,
-
-    // Public key of the market's quote mint initialized by this binary.
-    quote_mint: String,
-
-    // Market participants initialized by this binary.
-    participants: [TestMarketParticipant; NUM_PARTICIPANTS],
-}
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
 
 const NUM_PARTICIPANTS: usize = 4;
 
@@ -85,11 +48,46 @@ struct CliArgs {
     pub quote_lot_size: u64,
     #[clap(long, help="output_file_name", default_value_t = String::from("market.json"))]
     pub output_file_name: String,
+    #[clap(
+        long,
+        help = "port serving /ready and /market.json over HTTP on localhost"
+    )]
+    pub status_port: Option<u16>,
+}
+
+// Answers a single HTTP request for the readiness or market endpoints.
+fn serve_status(stream: TcpStream, market_json: &str) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, content_type, body) = match path {
+        "/ready" => ("200 OK", "text/plain", "ready\n"),
+        "/market.json" => ("200 OK", "application/json", market_json),
+        _ => ("404 Not Found", "text/plain", "not found\n"),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
 }
 
 fn main() {
     let args = CliArgs::parse();
 
+    // Install the handler before starting the validator so that a signal
+    // received during setup still shuts it down cleanly.
+    let (shutdown, shutdown_requested) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        shutdown.send(()).unwrap_or(());
+    })
+    .unwrap();
+
     println!("Creating solana-test-validator sandbox environment");
     let sandbox = Sandbox::new().unwrap();
     let market_creator = Actor::new(&sandbox).unwrap();
@@ -112,12 +110,11 @@ fn main() {
         &base_mint,
         &quote_mint,
         None,
-        args.base_lot_size,
-        args.quote_lot_size,
-        100,
-        128,
-        128,
-        256,
+        &MarketParams {
+            base_lot_size: args.base_lot_size,
+            quote_lot_size: args.quote_lot_size,
+            ..MarketParams::default_small()
+        },
     )
     .unwrap();
 
@@ -133,37 +130,30 @@ fn main() {
             100000,
         )
         .unwrap();
+        participants.push(p);
+    }
 
-        participants.push(TestMarketParticipant {
-            keypair: p.account().keypair().to_base58_string(),
-            base: p.base().pubkey().to_string(),
-            quote: p.quote().pubkey().to_string(),
-            orders: p.open_orders().pubkey().to_string(),
+    println!("Writing market.json");
+    let manifest = MarketManifest::from_market(&sandbox, &market, &participants);
+    manifest.save(&args.output_file_name).unwrap();
+    let market_json = serde_json::to_string(&manifest).unwrap();
+
+    if let Some(port) = args.status_port {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        println!("Serving status on http://127.0.0.1:{}", port);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                serve_status(stream, &market_json).unwrap_or(());
+            }
         });
     }
 
-    println!("Writing market.json");
-    let data = TestMarket {
-        url: sandbox.url(),
-        program_id: serum_program.pubkey().to_string(),
-        market: market.market().pubkey().to_string(),
-        bids: market.bids().pubkey().to_string(),
-        asks: market.asks().pubkey().to_string(),
-        request_queue: market.request_queue().pubkey().to_string(),
-        event_queue: market.event_queue().pubkey().to_string(),
-        base_vault: market.base_vault().account().pubkey().to_string(),
-        quote_vault: market.quote_vault().account().pubkey().to_string(),
-        base_mint: market.base_mint().actor().pubkey().to_string(),
-        quote_mint: market.quote_mint().actor().pubkey().to_string(),
-        participants: participants.try_into().unwrap(),
-    };
-    serde_json::to_writer(&fs::File::create(&args.output_file_name).unwrap(), &data).unwrap();
-    std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(args.output_file_name + ".done")
-        .unwrap();
+    let done_file = args.output_file_name + ".done";
+    fs::File::create(&done_file).unwrap();
 
     println!("Ready");
-    loop {}
+    shutdown_requested.recv().unwrap_or(());
+
+    println!("Shutting down");
+    fs::remove_file(&done_file).unwrap_or(());
 }
@@ -0,0 +1,208 @@
+//! Uses Solarium to run a Serum market pegged to a Pyth price feed in a local
+//! test validator.
+//!
+//! The program creates a market and a price account, then publishes a new
+//! price every interval by taking a random step from the last one. After each
+//! publish a market maker cancels its quotes, cranks and settles, and quotes
+//! again a fixed spread around the new price. Keys for the market, the price
+//! account, and the participants are saved to oracle_market.json, and the
+//! other participants can trade against the maker with serum_place_order:
+//!
+//! ```bash
+//! target/debug/oracle_pegged_market --start-price 100 --spread 2 --interval-ms 1000
+//! target/debug/serum_place_order --market-config oracle_market.json \
+//!     --side buy --price 101 --size 5 --participant 1
+//! ```
+//!
+//! Prices are published with an exponent of zero, so the published price is
+//! in quote lots per base lot, the same unit used to quote. The program runs
+//! until it receives SIGINT or SIGTERM. Pass --seed to replay a price path.
+//!
+use clap::Parser;
+use serde::Serialize;
+use serum_dex::matching::Side;
+use solana_program::native_token::LAMPORTS_PER_SOL;
+use solarium::{
+    actor::Actor,
+    fixtures::MarketManifest,
+    oracle::PeggedQuoter,
+    pyth::PriceAccount,
+    sandbox::SandboxBuilder,
+    serum::{Market, MarketParams, Participant},
+    token::Mint,
+};
+use std::fs;
+use std::num::NonZeroU64;
+use std::sync::mpsc;
+use std::time::Duration;
+
+// Represents the market and price feed initialized by this binary. The
+// market fields are those written by create_serum_market, and the first
+// participant is the market maker run by this binary.
+#[derive(Serialize, Debug)]
+struct OracleMarket {
+    #[serde(flatten)]
+    market: MarketManifest,
+
+    // Pyth program public key.
+    pyth_program_id: String,
+
+    // Public key of the price account the market is pegged to.
+    price_account: String,
+}
+
+const NUM_PARTICIPANTS: usize = 4;
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    #[clap(long, help = "serum dex's deploy url", default_value_t = String::from("https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so"))]
+    pub dex_url: String,
+    #[clap(long, help = "pyth's deploy url", default_value_t = String::from("https://github.com/foonetic/solarium-deps/raw/main/pyth_sim.so"))]
+    pub pyth_url: String,
+    #[clap(
+        long,
+        help = "first published price, in quote lots per base lot",
+        default_value_t = 100
+    )]
+    pub start_price: u64,
+    #[clap(long, help = "largest price change per publish", default_value_t = 2)]
+    pub max_step: u64,
+    #[clap(
+        long,
+        help = "distance of the maker's quotes from the price",
+        default_value_t = 1
+    )]
+    pub spread: u64,
+    #[clap(
+        long,
+        help = "size of each of the maker's quotes, in base lots",
+        default_value_t = 100
+    )]
+    pub size: u64,
+    #[clap(long, help = "milliseconds between publishes", default_value_t = 1000)]
+    pub interval_ms: u64,
+    #[clap(long, help = "seed for the price path")]
+    pub seed: Option<u64>,
+    #[clap(long, help="output_file_name", default_value_t = String::from("oracle_market.json"))]
+    pub output_file_name: String,
+}
+
+// Cancels the maker's resting orders, consumes every event, and settles, so
+// the maker requotes with all of its funds.
+fn pull_quotes(market: &Market, maker: &Participant) -> solarium::errors::Result<()> {
+    // The open orders account is initialized by the maker's first order.
+    let open_orders = maker.open_orders_view()?;
+    if open_orders.account_flags == 0 {
+        return Ok(());
+    }
+    for order in open_orders.orders.iter() {
+        let side = if order.is_bid { Side::Bid } else { Side::Ask };
+        market.cancel_order(maker.account(), maker, side, order.order_id)?;
+    }
+    market.crank_all(maker.account(), u16::MAX)?;
+    market.settle_funds(maker.account(), maker)
+}
+
+// Places a limit order for the maker that rests unless it crosses.
+fn quote(market: &Market, maker: &Participant, side: Side, price: u64, size: u64) {
+    let result = market.place_limit(
+        maker,
+        side,
+        NonZeroU64::new(price).unwrap(),
+        NonZeroU64::new(size).unwrap(),
+    );
+    if let Err(err) = result {
+        println!("Failed to quote {:?} {} @ {}: {:?}", side, size, price, err);
+    }
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    let (shutdown, shutdown_requested) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        shutdown.send(()).unwrap_or(());
+    })
+    .unwrap();
+
+    println!("Creating solana-test-validator sandbox environment");
+    let mut builder = SandboxBuilder::new();
+    if let Some(seed) = args.seed {
+        builder = builder.seed(seed);
+    }
+    let sandbox = builder.build().unwrap();
+    let market_creator = Actor::new(&sandbox).unwrap();
+    market_creator.airdrop(10000 * LAMPORTS_PER_SOL).unwrap();
+
+    println!("Deploying serum and pyth to the sandbox environment");
+    let serum_program = market_creator
+        .deploy_remote(&args.dex_url, "serum_dex.so")
+        .unwrap();
+    let pyth_program = market_creator
+        .deploy_remote(&args.pyth_url, "pyth_sim.so")
+        .unwrap();
+
+    println!("Creating the market and its price account");
+    let base_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+    let quote_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+    let market = Market::new(
+        &sandbox,
+        &market_creator,
+        serum_program.pubkey(),
+        &base_mint,
+        &quote_mint,
+        None,
+        &MarketParams::default_small(),
+    )
+    .unwrap();
+    let price_account =
+        PriceAccount::new(&sandbox, pyth_program.pubkey(), &market_creator).unwrap();
+
+    println!("Creating Serum market participants with large SOL and token balances for trading");
+    let participants = Participant::new_batch(
+        &sandbox,
+        &market_creator,
+        &market,
+        NUM_PARTICIPANTS,
+        10000 * LAMPORTS_PER_SOL,
+        1000000,
+        1000000000,
+    )
+    .unwrap();
+    let maker = &participants[0];
+
+    println!("Writing {}", args.output_file_name);
+    let data = OracleMarket {
+        market: MarketManifest::from_market(&sandbox, &market, &participants),
+        pyth_program_id: pyth_program.pubkey().to_string(),
+        price_account: price_account.account().pubkey().to_string(),
+    };
+    serde_json::to_writer(&fs::File::create(&args.output_file_name).unwrap(), &data).unwrap();
+
+    println!("Ready, price path seed {}", sandbox.rng().seed());
+    let interval = Duration::from_millis(args.interval_ms);
+    let mut quoter = PeggedQuoter::new(args.start_price, args.spread, args.max_step);
+    loop {
+        let price = quoter.price();
+        price_account
+            .publish_price(pyth_program.pubkey(), &market_creator, price as i64, 0)
+            .unwrap();
+        if let Err(err) = pull_quotes(&market, maker) {
+            println!("Failed to pull quotes: {:?}", err);
+        }
+        let (bid, ask) = quoter.quotes();
+        quote(&market, maker, Side::Bid, bid, args.size);
+        quote(&market, maker, Side::Ask, ask, args.size);
+        println!(
+            "Published {}, quoting {} @ {} / {}",
+            price, args.size, bid, ask
+        );
+
+        // Wait for the next publish, stopping early on a signal.
+        if shutdown_requested.recv_timeout(interval).is_ok() {
+            break;
+        }
+        quoter.random_step(&mut sandbox.rng());
+    }
+    println!("Shutting down");
+}
@@ -0,0 +1,96 @@
+//! Places an order on a market created by create_serum_market, as one of the
+//! participants listed in its market.json.
+//!
+//! Prices are in quote lots per base lot and sizes in base lots, as with
+//! solarium::serum::Market::place_order:
+//!
+//! ```bash
+//! target/debug/create_serum_market &
+//! target/debug/serum_place_order --side buy --price 100 --size 1 --participant 1
+//! ```
+//!
+//! The transaction signature is printed once the order is confirmed, together
+//! with the order id if any of the order rests on the book.
+//!
+use clap::{ArgEnum, Parser};
+use serum_dex::matching::{OrderType, Side};
+use solarium::{fixtures::MarketManifest, sandbox::Sandbox, serum::Market};
+use std::num::NonZeroU64;
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    #[clap(long, help = "market file written by create_serum_market", default_value_t = String::from("market.json"))]
+    pub market_config: String,
+    #[clap(
+        long,
+        help = "index of the participant placing the order",
+        default_value_t = 1
+    )]
+    pub participant: usize,
+    #[clap(long, arg_enum, help = "order direction")]
+    pub side: OrderSide,
+    #[clap(long, help = "limit price in quote lots per base lot")]
+    pub price: u64,
+    #[clap(long, help = "order size in base lots")]
+    pub size: u64,
+    #[clap(long, help = "client order id", default_value_t = 0)]
+    pub client_order_id: u64,
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    let manifest = MarketManifest::load(&args.market_config).unwrap();
+    let sandbox = Sandbox::connect(&manifest.url).unwrap();
+    let serum = manifest.program_id().unwrap();
+    let address = manifest
+        .market
+        .parse()
+        .expect("invalid market address in manifest");
+    let market = Market::new_from_existing(&sandbox, &serum, &address).unwrap();
+    let participants = manifest.participants(&market).unwrap();
+    let participant = participants
+        .get(args.participant)
+        .unwrap_or_else(|| panic!("market has no participant {}", args.participant));
+    let orders_before = participant.open_orders_view().unwrap().orders;
+
+    let side = match args.side {
+        OrderSide::Buy => Side::Bid,
+        OrderSide::Sell => Side::Ask,
+    };
+    let instruction = market
+        .place_order_ix(
+            participant,
+            side,
+            NonZeroU64::new(args.price).expect("price must be positive"),
+            NonZeroU64::new(args.size).expect("size must be positive"),
+            OrderType::Limit,
+            args.client_order_id,
+        )
+        .unwrap();
+    let signature = sandbox
+        .transaction()
+        .instruction(instruction)
+        .payer(participant.owner().try_keypair().unwrap())
+        .send()
+        .unwrap();
+    println!("signature: {}", signature);
+
+    // The open orders account lists the order only if part of it rests.
+    let resting = participant
+        .open_orders_view()
+        .unwrap()
+        .orders
+        .into_iter()
+        .find(|order| !orders_before.contains(order));
+    match resting {
+        Some(order) => println!("order id: {}", order.order_id),
+        None => println!("order id: none, the order did not rest on the book"),
+    }
+}
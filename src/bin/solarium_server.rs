@@ -0,0 +1,477 @@
+//! Serves sandbox operations over a small HTTP/JSON API, so that test
+//! harnesses written in other languages can orchestrate the same
+//! environment as Rust tests.
+//!
+//! The server starts a sandbox with the Serum DEX deployed and a funded
+//! payer that creates every account. Objects are created by name and later
+//! requests refer to them by that name:
+//!
+//! ```bash
+//! cargo run --features server --bin solarium-server -- --port 8080
+//! curl -X POST localhost:8080/mints -d '{"name": "base", "decimals": 0}'
+//! curl -X POST localhost:8080/mints -d '{"name": "quote", "decimals": 0}'
+//! curl -X POST localhost:8080/markets \
+//!     -d '{"name": "m", "base_mint": "base", "quote_mint": "quote"}'
+//! curl -X POST localhost:8080/participants \
+//!     -d '{"name": "alice", "market": "m", "base": 1000, "quote": 1000}'
+//! curl -X POST localhost:8080/orders \
+//!     -d '{"participant": "alice", "side": "sell", "price": 10, "size": 5}'
+//! curl localhost:8080/balances/alice
+//! ```
+//!
+//! Endpoints:
+//!
+//! - `GET /status`: the validator url and the Serum program id.
+//! - `POST /actors {name, lamports}`: creates and funds an actor.
+//! - `POST /airdrop {actor, lamports}`: airdrops to an actor.
+//! - `POST /mints {name, decimals}`: creates a mint.
+//! - `POST /markets {name, base_mint, quote_mint, base_lot_size,
+//!   quote_lot_size}`: creates a Serum market.
+//! - `POST /participants {name, market, lamports, base, quote}`: creates a
+//!   funded market participant.
+//! - `POST /orders {participant, side, price, size, client_order_id}`:
+//!   places a limit order, with price in quote lots and size in base lots.
+//! - `POST /crank {market}` and `POST /settle {participant}`.
+//! - `GET /balances/<participant>`: the participant's balances.
+//!
+//! Errors are returned as `{"error": "..."}` with a 4xx or 5xx status. The
+//! server handles one request at a time and runs until it receives SIGINT
+//! or SIGTERM. A client has REQUEST_TIMEOUT to send its request, whose body
+//! may hold at most MAX_BODY_BYTES.
+//!
+use clap::Parser;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serum_dex::matching::{OrderType, Side};
+use solana_program::native_token::LAMPORTS_PER_SOL;
+use solarium::{
+    actor::Actor,
+    errors::Error,
+    sandbox::Sandbox,
+    serum::{Market, MarketParams, Participant},
+    token::Mint,
+};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::num::NonZeroU64;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Time a client has to send each part of its request before the server
+/// drops the connection and moves on to the next.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request body the server reads.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    #[clap(long, help = "serum dex's deploy url", default_value_t = String::from("https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so"))]
+    pub dex_url: String,
+    #[clap(
+        long,
+        help = "port to serve the API on localhost",
+        default_value_t = 8080
+    )]
+    pub port: u16,
+}
+
+#[derive(Deserialize)]
+struct CreateActor {
+    name: String,
+    #[serde(default)]
+    lamports: u64,
+}
+
+#[derive(Deserialize)]
+struct Airdrop {
+    actor: String,
+    lamports: u64,
+}
+
+#[derive(Deserialize)]
+struct CreateMint {
+    name: String,
+    #[serde(default)]
+    decimals: u8,
+}
+
+#[derive(Deserialize)]
+struct CreateMarket {
+    name: String,
+    base_mint: String,
+    quote_mint: String,
+    #[serde(default)]
+    base_lot_size: Option<u64>,
+    #[serde(default)]
+    quote_lot_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct CreateParticipant {
+    name: String,
+    market: String,
+    #[serde(default)]
+    lamports: Option<u64>,
+    #[serde(default)]
+    base: u64,
+    #[serde(default)]
+    quote: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Deserialize)]
+struct PlaceOrder {
+    participant: String,
+    side: OrderSide,
+    price: u64,
+    size: u64,
+    #[serde(default)]
+    client_order_id: u64,
+}
+
+#[derive(Deserialize)]
+struct Crank {
+    market: String,
+}
+
+#[derive(Deserialize)]
+struct Settle {
+    participant: String,
+}
+
+// A failed request, answered with the given HTTP status.
+struct ApiError {
+    status: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: String) -> Self {
+        ApiError {
+            status: "400 Bad Request",
+            message,
+        }
+    }
+
+    fn not_found(message: String) -> Self {
+        ApiError {
+            status: "404 Not Found",
+            message,
+        }
+    }
+
+    fn payload_too_large(message: String) -> Self {
+        ApiError {
+            status: "413 Payload Too Large",
+            message,
+        }
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        // Input the library refuses before sending anything, such as an
+        // order whose quote amount overflows, is the client's mistake.
+        let status = match err.root() {
+            Error::InputOutputError(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                "400 Bad Request"
+            }
+            Error::InvalidMarketParams(_) => "400 Bad Request",
+            _ => "500 Internal Server Error",
+        };
+        ApiError {
+            status,
+            message: err.to_string(),
+        }
+    }
+}
+
+type ApiResult = Result<Value, ApiError>;
+
+// The objects created through the API, by name. Mints and markets are
+// leaked so that markets can borrow their mints and participants their
+// markets while the maps keep growing; they live until the server exits
+// anyway. Participants are kept with their market.
+struct Server<'a> {
+    sandbox: &'a Sandbox,
+    payer: &'a Actor<'a>,
+    serum: &'a Actor<'a>,
+    actors: HashMap<String, Actor<'a>>,
+    mints: HashMap<String, &'a Mint<'a>>,
+    markets: HashMap<String, &'a Market<'a>>,
+    participants: HashMap<String, (&'a Market<'a>, Participant<'a>)>,
+}
+
+fn lookup<'b, T>(
+    objects: &'b HashMap<String, T>,
+    kind: &str,
+    name: &str,
+) -> Result<&'b T, ApiError> {
+    objects
+        .get(name)
+        .ok_or_else(|| ApiError::not_found(format!("no {} named {}", kind, name)))
+}
+
+fn parse<'b, T: Deserialize<'b>>(body: &'b str) -> Result<T, ApiError> {
+    serde_json::from_str(body).map_err(|err| ApiError::bad_request(err.to_string()))
+}
+
+impl<'a> Server<'a> {
+    fn handle(&mut self, method: &str, path: &str, body: &str) -> ApiResult {
+        match (method, path) {
+            ("GET", "/status") => Ok(json!({
+                "url": self.sandbox.url(),
+                "program_id": self.serum.pubkey().to_string(),
+            })),
+            ("POST", "/actors") => self.create_actor(parse(body)?),
+            ("POST", "/airdrop") => {
+                let request: Airdrop = parse(body)?;
+                lookup(&self.actors, "actor", &request.actor)?.airdrop(request.lamports)?;
+                Ok(json!({}))
+            }
+            ("POST", "/mints") => self.create_mint(parse(body)?),
+            ("POST", "/markets") => self.create_market(parse(body)?),
+            ("POST", "/participants") => self.create_participant(parse(body)?),
+            ("POST", "/orders") => self.place_order(parse(body)?),
+            ("POST", "/crank") => {
+                let request: Crank = parse(body)?;
+                lookup(&self.markets, "market", &request.market)?
+                    .crank_all(self.payer, u16::MAX)?;
+                Ok(json!({}))
+            }
+            ("POST", "/settle") => {
+                let request: Settle = parse(body)?;
+                let (market, participant) =
+                    lookup(&self.participants, "participant", &request.participant)?;
+                market.settle_funds(self.payer, participant)?;
+                Ok(json!({}))
+            }
+            ("GET", path) if path.starts_with("/balances/") => {
+                let name = &path["/balances/".len()..];
+                let snapshot = lookup(&self.participants, "participant", name)?
+                    .1
+                    .snapshot()?;
+                Ok(json!({
+                    "lamports": snapshot.lamports,
+                    "base": snapshot.base,
+                    "quote": snapshot.quote,
+                    "base_locked": snapshot.base_locked,
+                    "quote_locked": snapshot.quote_locked,
+                    "base_unsettled": snapshot.base_unsettled,
+                    "quote_unsettled": snapshot.quote_unsettled,
+                }))
+            }
+            _ => Err(ApiError::not_found(format!(
+                "no endpoint {} {}",
+                method, path
+            ))),
+        }
+    }
+
+    fn create_actor(&mut self, request: CreateActor) -> ApiResult {
+        let actor = Actor::new_named(self.sandbox, &request.name)?;
+        if request.lamports > 0 {
+            actor.airdrop(request.lamports)?;
+        }
+        let response = json!({
+            "pubkey": actor.pubkey().to_string(),
+            "keypair": actor.keypair().to_base58_string(),
+        });
+        self.actors.insert(request.name, actor);
+        Ok(response)
+    }
+
+    fn create_mint(&mut self, request: CreateMint) -> ApiResult {
+        let mint = Mint::new(self.sandbox, self.payer, request.decimals, None, None)?;
+        self.sandbox
+            .register_name(mint.actor().pubkey(), &request.name);
+        let response = json!({ "pubkey": mint.actor().pubkey().to_string() });
+        self.mints.insert(request.name, Box::leak(Box::new(mint)));
+        Ok(response)
+    }
+
+    fn create_market(&mut self, request: CreateMarket) -> ApiResult {
+        let base_mint = *lookup(&self.mints, "mint", &request.base_mint)?;
+        let quote_mint = *lookup(&self.mints, "mint", &request.quote_mint)?;
+        let defaults = MarketParams::default_small();
+        let params = MarketParams {
+            base_lot_size: request.base_lot_size.unwrap_or(defaults.base_lot_size),
+            quote_lot_size: request.quote_lot_size.unwrap_or(defaults.quote_lot_size),
+            ..defaults
+        };
+        let market = Market::new_named(
+            self.sandbox,
+            self.payer,
+            self.serum.pubkey(),
+            base_mint,
+            quote_mint,
+            None,
+            &params,
+            &request.name,
+        )?;
+        let response = json!({
+            "market": market.market().pubkey().to_string(),
+            "bids": market.bids().pubkey().to_string(),
+            "asks": market.asks().pubkey().to_string(),
+            "request_queue": market.request_queue().pubkey().to_string(),
+            "event_queue": market.event_queue().pubkey().to_string(),
+            "base_vault": market.base_vault().account().pubkey().to_string(),
+            "quote_vault": market.quote_vault().account().pubkey().to_string(),
+        });
+        self.markets
+            .insert(request.name, Box::leak(Box::new(market)));
+        Ok(response)
+    }
+
+    fn create_participant(&mut self, request: CreateParticipant) -> ApiResult {
+        let market = *lookup(&self.markets, "market", &request.market)?;
+        let participant = Participant::new_named(
+            self.sandbox,
+            self.payer,
+            market,
+            request.lamports.unwrap_or(10 * LAMPORTS_PER_SOL),
+            request.base,
+            request.quote,
+            &request.name,
+        )?;
+        let response = json!({
+            "pubkey": participant.account().pubkey().to_string(),
+            "keypair": participant.account().keypair().to_base58_string(),
+            "base": participant.base().pubkey().to_string(),
+            "quote": participant.quote().pubkey().to_string(),
+            "orders": participant.open_orders().pubkey().to_string(),
+        });
+        self.participants
+            .insert(request.name, (market, participant));
+        Ok(response)
+    }
+
+    fn place_order(&mut self, request: PlaceOrder) -> ApiResult {
+        let (market, participant) =
+            lookup(&self.participants, "participant", &request.participant)?;
+        let side = match request.side {
+            OrderSide::Buy => Side::Bid,
+            OrderSide::Sell => Side::Ask,
+        };
+        let price = NonZeroU64::new(request.price)
+            .ok_or_else(|| ApiError::bad_request(String::from("price must be positive")))?;
+        let size = NonZeroU64::new(request.size)
+            .ok_or_else(|| ApiError::bad_request(String::from("size must be positive")))?;
+        market.place_order(
+            participant,
+            side,
+            price,
+            size,
+            OrderType::Limit,
+            request.client_order_id,
+        )?;
+        Ok(json!({}))
+    }
+}
+
+// Reads a request, dispatches it, and writes the JSON response.
+fn serve(server: &mut Server, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let result = if content_length > MAX_BODY_BYTES {
+        Err(ApiError::payload_too_large(format!(
+            "request body of {} bytes exceeds {} bytes",
+            content_length, MAX_BODY_BYTES
+        )))
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        server.handle(&method, &path, &String::from_utf8_lossy(&body))
+    };
+
+    let (status, response) = match result {
+        Ok(response) => ("200 OK", response),
+        Err(err) => (err.status, json!({ "error": err.message })),
+    };
+    let response = response.to_string();
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    )?;
+    stream.flush()
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    let (shutdown, shutdown_requested) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        shutdown.send(()).unwrap_or(());
+    })
+    .unwrap();
+
+    println!("Creating solana-test-validator sandbox environment");
+    let sandbox = Sandbox::new().unwrap();
+    let payer = Actor::new_named(&sandbox, "payer").unwrap();
+    payer.airdrop(10000 * LAMPORTS_PER_SOL).unwrap();
+
+    println!("Deploying serum to the sandbox environment");
+    let serum = payer.deploy_remote(&args.dex_url, "serum_dex.so").unwrap();
+
+    let mut server = Server {
+        sandbox: &sandbox,
+        payer: &payer,
+        serum: &serum,
+        actors: HashMap::new(),
+        mints: HashMap::new(),
+        markets: HashMap::new(),
+        participants: HashMap::new(),
+    };
+
+    // The listener is polled so that a signal can stop the server between
+    // requests.
+    let listener = TcpListener::bind(("127.0.0.1", args.port)).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    println!("Serving on http://127.0.0.1:{}", args.port);
+    while shutdown_requested.try_recv().is_err() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).unwrap_or(());
+                stream.set_read_timeout(Some(REQUEST_TIMEOUT)).unwrap_or(());
+                if let Err(err) = serve(&mut server, stream) {
+                    println!("Failed to serve request: {}", err);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => println!("Failed to accept connection: {}", err),
+        }
+    }
+    println!("Shutting down");
+}
@@ -0,0 +1,219 @@
+use crate::errors::{Error, Result};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    pubsub_client::{PubsubAccountClientSubscription, PubsubClient},
+    rpc_config::RpcAccountInfoConfig,
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, message::Message, pubkey::Pubkey,
+};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Keyed cache of account state in front of a Sandbox's account fetches.
+///
+/// The cache is disabled by default. Once enabled, Sandbox::get_account and
+/// Sandbox::get_account_data, and with them the Mint, TokenAccount, Market,
+/// and Participant loaders, serve repeated reads from memory. Accounts written
+/// by transactions sent through the Sandbox and accounts receiving airdrops
+/// are invalidated automatically. Changes made by anyone else, such as an
+/// external crank, are only seen after explicit invalidation or if the
+/// account is subscribed, in which case the cache is refreshed from the
+/// validator's account notifications.
+///
+/// Accounts that do not exist are cached as absent. A fetch that overlaps an
+/// invalidation is not cached, so a read racing a transaction cannot store
+/// state from before the transaction.
+#[derive(Default)]
+pub struct AccountCache {
+    enabled: AtomicBool,
+    entries: Arc<Mutex<HashMap<Pubkey, Option<Account>>>>,
+    generation: AtomicU64,
+    subscriptions: Mutex<HashMap<Pubkey, PubsubAccountClientSubscription>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl AccountCache {
+    /// Enables caching.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Disables caching and drops every cached account. Subscriptions are
+    /// kept and resume filling the cache if it is enabled again.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        self.invalidate_all();
+    }
+
+    /// Returns true if caching is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the cached state of an account, if it is cached and exists.
+    pub fn get(&self, pubkey: &Pubkey) -> Option<Account> {
+        self.lookup(pubkey).flatten()
+    }
+
+    /// Returns the cached state of an account, which is None inside the
+    /// Option if the account is cached as absent.
+    pub(crate) fn lookup(&self, pubkey: &Pubkey) -> Option<Option<Account>> {
+        let account = self
+            .entries
+            .lock()
+            .expect("account cache lock poisoned")
+            .get(pubkey)
+            .cloned();
+        match account {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        account
+    }
+
+    /// Stores the state of an account.
+    pub fn insert(&self, pubkey: &Pubkey, account: Account) {
+        self.entries
+            .lock()
+            .expect("account cache lock poisoned")
+            .insert(*pubkey, Some(account));
+    }
+
+    /// Returns a counter that changes whenever an account is invalidated.
+    /// Read it before fetching an account to be passed to fill.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Stores the fetched state of an account, None if it does not exist,
+    /// unless an account was invalidated since generation was read.
+    pub(crate) fn fill(&self, pubkey: &Pubkey, account: Option<Account>, generation: u64) {
+        let mut entries = self.entries.lock().expect("account cache lock poisoned");
+        if self.generation.load(Ordering::SeqCst) == generation {
+            entries.insert(*pubkey, account);
+        }
+    }
+
+    /// Drops the cached state of an account, so that the next read fetches
+    /// it from the validator.
+    pub fn invalidate(&self, pubkey: &Pubkey) {
+        let mut entries = self.entries.lock().expect("account cache lock poisoned");
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.remove(pubkey);
+    }
+
+    /// Drops every cached account.
+    pub fn invalidate_all(&self) {
+        let mut entries = self.entries.lock().expect("account cache lock poisoned");
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.clear();
+    }
+
+    /// Drops every account the message may write to.
+    pub(crate) fn invalidate_writable(&self, message: &Message) {
+        let mut entries = self.entries.lock().expect("account cache lock poisoned");
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        for (index, pubkey) in message.account_keys.iter().enumerate() {
+            if message.is_writable(index) {
+                entries.remove(pubkey);
+            }
+        }
+    }
+
+    /// Returns the number of reads served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of reads that had to be fetched.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Subscribes to notifications for an account on the given websocket
+    /// url and refreshes its cached state on every notification. Subscribing
+    /// to an account that is already subscribed does nothing.
+    pub(crate) fn subscribe(
+        &self,
+        ws_url: &str,
+        pubkey: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<()> {
+        let mut subscriptions = self
+            .subscriptions
+            .lock()
+            .expect("account cache lock poisoned");
+        if subscriptions.contains_key(pubkey) {
+            return Ok(());
+        }
+
+        let (subscription, receiver) = PubsubClient::account_subscribe(
+            ws_url,
+            pubkey,
+            Some(RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: None,
+                commitment: Some(commitment),
+            }),
+        )
+        .map_err(|err| Error::from(io::Error::new(io::ErrorKind::Other, err.to_string())))?;
+
+        // The receiver is closed when the subscription is shut down, which
+        // ends the thread.
+        let entries = self.entries.clone();
+        let pubkey = *pubkey;
+        thread::spawn(move || {
+            for response in receiver.iter() {
+                if let Some(account) = response.value.decode::<Account>() {
+                    entries
+                        .lock()
+                        .expect("account cache lock poisoned")
+                        .insert(pubkey, Some(account));
+                }
+            }
+        });
+
+        subscriptions.insert(pubkey, subscription);
+        Ok(())
+    }
+
+    /// Stops refreshing an account from notifications. Its cached state is
+    /// dropped.
+    pub fn unsubscribe(&self, pubkey: &Pubkey) {
+        let subscription = self
+            .subscriptions
+            .lock()
+            .expect("account cache lock poisoned")
+            .remove(pubkey);
+        if let Some(mut subscription) = subscription {
+            subscription.send_unsubscribe().unwrap_or(());
+            subscription.shutdown().unwrap_or(());
+        }
+        self.invalidate(pubkey);
+    }
+}
+
+impl Drop for AccountCache {
+    /// Shuts down all subscriptions.
+    fn drop(&mut self) {
+        let pubkeys: Vec<Pubkey> = self
+            .subscriptions
+            .lock()
+            .expect("account cache lock poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        for pubkey in pubkeys.iter() {
+            self.unsubscribe(pubkey);
+        }
+    }
+}
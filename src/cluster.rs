@@ -0,0 +1,459 @@
+use crate::errors::{Error, Result};
+use crate::sandbox;
+use portpicker;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{write_keypair_file, Keypair, Signer},
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+use tempfile;
+
+/// Number of consecutive ports reserved for each node's dynamic port range.
+const DYNAMIC_PORT_RANGE_WIDTH: u16 = 32;
+
+/// The upgradeable programs are not needed in a test cluster, so programs
+/// are loaded with the non-upgradeable loader.
+const BPF_LOADER: &str = "BPFLoader2111111111111111111111111111111111";
+
+/// Configures and starts a Cluster.
+///
+/// ```ignore
+/// let cluster = ClusterBuilder::new()
+///     .nodes(3)
+///     .program(&spl_token::id(), "spl_token.so")
+///     .build()?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClusterBuilder {
+    nodes: usize,
+    ticks_per_slot: u64,
+    slots_per_epoch: u64,
+    faucet_sol: u64,
+    programs: Vec<(Pubkey, PathBuf)>,
+}
+
+impl Default for ClusterBuilder {
+    fn default() -> Self {
+        ClusterBuilder {
+            nodes: 2,
+            ticks_per_slot: 8,
+            slots_per_epoch: 64,
+            faucet_sol: 1_000_000,
+            programs: Vec::new(),
+        }
+    }
+}
+
+impl ClusterBuilder {
+    /// Returns a builder for a bootstrap validator and one additional node.
+    pub fn new() -> Self {
+        ClusterBuilder::default()
+    }
+
+    /// Sets the total number of nodes, including the bootstrap validator.
+    pub fn nodes(mut self, nodes: usize) -> Self {
+        self.nodes = nodes;
+        self
+    }
+
+    /// Sets the number of ticks per slot.
+    pub fn ticks_per_slot(mut self, ticks: u64) -> Self {
+        self.ticks_per_slot = ticks;
+        self
+    }
+
+    /// Sets the number of slots per epoch.
+    pub fn slots_per_epoch(mut self, slots: u64) -> Self {
+        self.slots_per_epoch = slots;
+        self
+    }
+
+    /// Sets the amount of SOL held by the faucet keypair in genesis.
+    pub fn faucet_sol(mut self, sol: u64) -> Self {
+        self.faucet_sol = sol;
+        self
+    }
+
+    /// Adds a program to genesis. Unlike solana-test-validator, a cluster
+    /// does not come with the SPL programs preloaded.
+    pub fn program<P: AsRef<Path>>(mut self, program_id: &Pubkey, path: P) -> Self {
+        self.programs
+            .push((*program_id, path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Creates a shared genesis, starts every node, and blocks until all of
+    /// their RPC servers are ready to use.
+    pub fn build(self) -> Result<Cluster> {
+        if self.nodes == 0 {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a cluster needs at least one node",
+            )));
+        }
+
+        let tmp = tempfile::Builder::new()
+            .prefix("solarium-cluster")
+            .tempdir()?;
+        let faucet = Keypair::new();
+        let identities: Vec<Keypair> = (0..self.nodes).map(|_| Keypair::new()).collect();
+        let vote = Keypair::new();
+        let stake = Keypair::new();
+
+        let identity_paths = identities
+            .iter()
+            .enumerate()
+            .map(|(index, identity)| {
+                write_keypair(
+                    tmp.path(),
+                    &format!("node-{}-identity.json", index),
+                    identity,
+                )
+            })
+            .collect::<Result<Vec<PathBuf>>>()?;
+        let vote_path = write_keypair(tmp.path(), "node-0-vote.json", &vote)?;
+        let stake_path = write_keypair(tmp.path(), "node-0-stake.json", &stake)?;
+
+        let genesis_ledger = tmp.path().join("node-0-ledger");
+        let mut genesis = process::Command::new("solana-genesis");
+        genesis
+            .arg("--ledger")
+            .arg(&genesis_ledger)
+            .arg("--bootstrap-validator")
+            .arg(&identity_paths[0])
+            .arg(&vote_path)
+            .arg(&stake_path)
+            .args([
+                "--bootstrap-validator-lamports",
+                &(500 * LAMPORTS_PER_SOL).to_string(),
+                "--bootstrap-validator-stake-lamports",
+                &(500 * LAMPORTS_PER_SOL).to_string(),
+                "--faucet-pubkey",
+            ])
+            .arg(write_keypair(tmp.path(), "faucet.json", &faucet)?)
+            .args([
+                "--faucet-lamports",
+                &(self.faucet_sol * LAMPORTS_PER_SOL).to_string(),
+                "--hashes-per-tick",
+                "sleep",
+                "--ticks-per-slot",
+                &self.ticks_per_slot.to_string(),
+                "--slots-per-epoch",
+                &self.slots_per_epoch.to_string(),
+                "--cluster-type",
+                "development",
+            ]);
+        for (program_id, path) in self.programs.iter() {
+            genesis
+                .arg("--bpf-program")
+                .arg(program_id.to_string())
+                .arg(BPF_LOADER)
+                .arg(path);
+        }
+        let output = genesis.output()?;
+        if !output.status.success() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "solana-genesis failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            )));
+        }
+
+        let mut nodes: Vec<ClusterNode> = Vec::new();
+        for (index, (identity, identity_path)) in identities
+            .into_iter()
+            .zip(identity_paths.iter())
+            .enumerate()
+        {
+            let ledger = tmp.path().join(format!("node-{}-ledger", index));
+            if index > 0 {
+                // Every node starts from the same genesis rather than
+                // fetching it from the bootstrap validator.
+                fs::create_dir_all(&ledger)?;
+                for file in ["genesis.bin", "genesis.tar.bz2"] {
+                    fs::copy(genesis_ledger.join(file), ledger.join(file))?;
+                }
+            }
+            let entrypoint = nodes.first().map(|bootstrap| bootstrap.gossip_port);
+            nodes.push(ClusterNode::start(
+                tmp.path(),
+                index,
+                identity,
+                identity_path,
+                &ledger,
+                if index == 0 { Some(&vote_path) } else { None },
+                entrypoint,
+            )?);
+        }
+
+        Ok(Cluster { nodes, faucet, tmp })
+    }
+}
+
+/// Represents a local multi-node Solana cluster.
+///
+/// The first node is the bootstrap validator, which holds all stake and
+/// produces every block. The remaining nodes are non-voting RPC nodes that
+/// follow it from the same genesis. Each node has its own RPC client, so
+/// tests can exercise failover by stopping nodes and observe commitment
+/// levels diverging between the leader and lagging nodes.
+///
+/// Clusters have no faucet service. Accounts are funded by transferring from
+/// the faucet keypair, which genesis endows with the configured SOL.
+pub struct Cluster {
+    nodes: Vec<ClusterNode>,
+    faucet: Keypair,
+    tmp: tempfile::TempDir,
+}
+
+impl Cluster {
+    /// Starts a cluster of the given number of nodes with default settings.
+    pub fn new(nodes: usize) -> Result<Self> {
+        ClusterBuilder::new().nodes(nodes).build()
+    }
+
+    /// Returns all nodes, bootstrap validator first.
+    pub fn nodes(&self) -> &[ClusterNode] {
+        &self.nodes
+    }
+
+    /// Returns the node at the given index.
+    pub fn node(&self, index: usize) -> &ClusterNode {
+        &self.nodes[index]
+    }
+
+    /// Returns the bootstrap validator.
+    pub fn bootstrap(&self) -> &ClusterNode {
+        &self.nodes[0]
+    }
+
+    /// Returns the keypair holding the genesis faucet funds.
+    pub fn faucet(&self) -> &Keypair {
+        &self.faucet
+    }
+
+    /// Returns the directory holding the ledgers, keypairs, and logs of every
+    /// node.
+    pub fn tmpdir(&self) -> &Path {
+        self.tmp.path()
+    }
+}
+
+impl Drop for Cluster {
+    /// Stops every node before the directory holding their ledgers is
+    /// deleted.
+    fn drop(&mut self) {
+        for node in self.nodes.iter() {
+            node.stop().unwrap_or(());
+        }
+    }
+}
+
+/// One validator process of a Cluster.
+pub struct ClusterNode {
+    identity: Keypair,
+    validator: Mutex<Option<process::Child>>,
+    rpc_port: u16,
+    gossip_port: u16,
+    client: RpcClient,
+    log_path: PathBuf,
+}
+
+impl ClusterNode {
+    /// Starts a node and blocks until its RPC server is ready. Nodes with a
+    /// vote account vote; the others follow the gossip entrypoint.
+    fn start(
+        dir: &Path,
+        index: usize,
+        identity: Keypair,
+        identity_path: &Path,
+        ledger: &Path,
+        vote_path: Option<&Path>,
+        entrypoint: Option<u16>,
+    ) -> Result<Self> {
+        let rpc_port = pick_port()?;
+        let gossip_port = pick_port()?;
+        let dynamic_ports = pick_port_range(DYNAMIC_PORT_RANGE_WIDTH)?;
+        let log_path = dir.join(format!("node-{}.log", index));
+        let log = fs::File::create(&log_path)?;
+
+        let mut command = process::Command::new("solana-validator");
+        command
+            .arg("--identity")
+            .arg(identity_path)
+            .arg("--ledger")
+            .arg(ledger)
+            .args([
+                "--rpc-port",
+                &rpc_port.to_string(),
+                "--rpc-bind-address",
+                "127.0.0.1",
+                "--gossip-host",
+                "127.0.0.1",
+                "--gossip-port",
+                &gossip_port.to_string(),
+                "--dynamic-port-range",
+                &format!(
+                    "{}-{}",
+                    dynamic_ports,
+                    dynamic_ports + DYNAMIC_PORT_RANGE_WIDTH - 1
+                ),
+                "--full-rpc-api",
+                "--enable-rpc-transaction-history",
+                "--allow-private-addr",
+                "--no-os-network-limits-test",
+                "--limit-ledger-size",
+                "--log",
+                "-",
+            ]);
+        match vote_path {
+            Some(vote_path) => {
+                command
+                    .arg("--vote-account")
+                    .arg(vote_path)
+                    .arg("--no-wait-for-vote-to-start-leader");
+            }
+            None => {
+                command.arg("--no-voting");
+            }
+        }
+        if let Some(entrypoint) = entrypoint {
+            command.args([
+                "--entrypoint",
+                &format!("127.0.0.1:{}", entrypoint),
+                "--no-genesis-fetch",
+                "--no-snapshot-fetch",
+                "--no-port-check",
+            ]);
+        }
+        let mut validator = command
+            .stdout(process::Stdio::from(log.try_clone()?))
+            .stderr(process::Stdio::from(log))
+            .spawn()?;
+
+        let client = RpcClient::new_with_commitment(
+            format!("http://127.0.0.1:{}", rpc_port),
+            CommitmentConfig::confirmed(),
+        );
+        sandbox::wait_until_ready(&client, &mut validator, &log_path)?;
+
+        Ok(ClusterNode {
+            identity,
+            validator: Mutex::new(Some(validator)),
+            rpc_port,
+            gossip_port,
+            client,
+            log_path,
+        })
+    }
+
+    /// Returns the node's identity public key.
+    pub fn identity(&self) -> Pubkey {
+        self.identity.pubkey()
+    }
+
+    /// Returns the node's RPC service port.
+    pub fn port(&self) -> u16 {
+        self.rpc_port
+    }
+
+    /// Returns the node's RPC service url.
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.rpc_port)
+    }
+
+    /// Returns an RPC client connected to the node at confirmed commitment.
+    pub fn client(&self) -> &RpcClient {
+        &self.client
+    }
+
+    /// Returns a new RPC client connected to the node at the given
+    /// commitment.
+    pub fn client_with_commitment(&self, commitment: CommitmentConfig) -> RpcClient {
+        RpcClient::new_with_commitment(self.url(), commitment)
+    }
+
+    /// Returns the path of the file capturing the node's output.
+    pub fn log_path(&self) -> &Path {
+        &self.log_path
+    }
+
+    /// Returns true if the node's process has not exited or been stopped.
+    pub fn is_running(&self) -> bool {
+        match self
+            .validator
+            .lock()
+            .expect("validator lock poisoned")
+            .as_mut()
+        {
+            Some(validator) => matches!(validator.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    /// Kills the node's process. Its RPC client stops responding, which can
+    /// be used to test failover to the remaining nodes.
+    pub fn stop(&self) -> Result<()> {
+        let validator = self
+            .validator
+            .lock()
+            .expect("validator lock poisoned")
+            .take();
+        if let Some(mut validator) = validator {
+            validator.kill().unwrap_or(());
+            validator.wait()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ClusterNode {
+    /// Stops the node.
+    fn drop(&mut self) {
+        if let Ok(Some(validator)) = self.validator.get_mut() {
+            validator.kill().unwrap_or(());
+            let _ = validator.wait();
+        }
+    }
+}
+
+/// Writes a keypair to a file in the given directory and returns its path.
+fn write_keypair(dir: &Path, name: &str, keypair: &Keypair) -> Result<PathBuf> {
+    let path = dir.join(name);
+    write_keypair_file(keypair, &path)
+        .map_err(|err| Error::from(io::Error::new(io::ErrorKind::Other, err.to_string())))?;
+    Ok(path)
+}
+
+/// Returns an unused port.
+fn pick_port() -> Result<u16> {
+    portpicker::pick_unused_port()
+        .ok_or_else(|| Error::from(io::Error::from(io::ErrorKind::AddrNotAvailable)))
+}
+
+/// Returns the first of width consecutive unused ports. The range passed to
+/// the validator is inclusive, so it ends at start + width - 1.
+fn pick_port_range(width: u16) -> Result<u16> {
+    for _ in 0..100 {
+        let start = pick_port()?;
+        if start
+            .checked_add(width)
+            .map_or(false, |_| (start..start + width).all(portpicker::is_free))
+        {
+            return Ok(start);
+        }
+    }
+    Err(Error::from(io::Error::from(
+        io::ErrorKind::AddrNotAvailable,
+    )))
+}
@@ -0,0 +1,400 @@
+use crate::actor::Actor;
+use crate::errors::{Error, Result};
+use crate::serum::{self, Market};
+use serum_dex::state::EventView;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use std::{
+    collections::BTreeSet,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread, time,
+};
+
+#[cfg(feature = "fault-injection")]
+use std::sync::atomic::AtomicUsize;
+
+/// Open orders accounts passed to a single consume_events instruction, which
+/// keeps the transaction under the size limit.
+pub(crate) const MAX_OPEN_ORDERS_PER_CRANK: usize = 24;
+
+/// Queue depth samples kept by a CrankHandle. Older samples are dropped.
+const MAX_DEPTH_SAMPLES: usize = 10_000;
+
+/// The depth of a market's event queue at a point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueDepth {
+    /// Time since the crank started.
+    pub elapsed: time::Duration,
+
+    /// Number of events in the queue.
+    pub depth: usize,
+}
+
+/// Throughput and health of a running crank.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CrankMetrics {
+    /// Events removed from the queue since the crank started.
+    pub events_consumed: u64,
+
+    /// Consume events transactions sent successfully.
+    pub cranks: u64,
+
+    /// Descriptions of failed polls and sends, in the order they failed.
+    pub errors: Vec<String>,
+
+    /// Queue depth observed on each poll, oldest first.
+    pub queue_depth: Vec<QueueDepth>,
+
+    /// Time since the crank started.
+    pub elapsed: time::Duration,
+
+    /// Time since the crank started at which events were last consumed.
+    pub last_progress: Option<time::Duration>,
+
+    /// Time since the crank started at which the queue was last polled.
+    pub last_poll: Option<time::Duration>,
+}
+
+impl CrankMetrics {
+    /// Returns the average number of events consumed per second.
+    pub fn events_per_second(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.events_consumed as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Returns the deepest queue observed.
+    pub fn max_queue_depth(&self) -> usize {
+        self.queue_depth
+            .iter()
+            .map(|sample| sample.depth)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the most recently observed queue depth.
+    pub fn current_queue_depth(&self) -> usize {
+        self.queue_depth.last().map_or(0, |sample| sample.depth)
+    }
+
+    /// Returns how long events have been waiting in the queue without any
+    /// being consumed, or zero if the queue was last seen empty.
+    pub fn stalled_for(&self) -> time::Duration {
+        if self.current_queue_depth() == 0 {
+            return time::Duration::ZERO;
+        }
+        self.elapsed - self.last_progress.unwrap_or_default()
+    }
+}
+
+/// Consumes a market's events on a background thread and reports metrics.
+///
+/// The crank polls the event queue and sends consume_events for the open
+/// orders accounts at the front of the queue. On V1 and V2 markets, where
+/// orders wait in the request queue until matched, it first sends
+/// match_orders whenever requests are waiting. While events remain it cranks
+/// again immediately, so a backlog is drained as fast as transactions
+/// confirm; once the queue is empty it waits for the interval before
+/// polling again. Failures are recorded in the metrics rather than stopping
+/// the crank, so tests can assert on throughput and detect stalls:
+///
+/// ```ignore
+/// let crank = CrankHandle::start(&market, &cranker, Duration::from_millis(100), 10)?;
+/// // ... place orders ...
+/// let metrics = crank.stop()?;
+/// assert!(metrics.errors.is_empty());
+/// assert!(metrics.stalled_for() < Duration::from_secs(1));
+/// ```
+///
+/// A panic ends the crank until stop reports it. services::CrankService runs
+/// the same crank under a supervisor that restarts it and checks its health.
+pub struct CrankHandle {
+    metrics: Arc<Mutex<CrankMetrics>>,
+    start: time::Instant,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl CrankHandle {
+    /// Starts cranking a market. The payer pays for and signs every
+    /// transaction, and each transaction consumes up to num_events events.
+    pub fn start(
+        market: &Market,
+        payer: &Actor,
+        interval: time::Duration,
+        num_events: u16,
+    ) -> Result<Self> {
+        let crank = Crank::new(market, payer, num_events)?;
+        let metrics = Arc::new(Mutex::new(CrankMetrics::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let start = time::Instant::now();
+
+        let thread_metrics = metrics.clone();
+        let thread_stop = stop.clone();
+        let handle =
+            thread::spawn(move || crank.run(&thread_metrics, start, &thread_stop, interval));
+
+        Ok(CrankHandle {
+            metrics,
+            start,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns the metrics collected so far.
+    pub fn metrics(&self) -> CrankMetrics {
+        let mut metrics = lock(&self.metrics).clone();
+        metrics.elapsed = self.start.elapsed();
+        metrics
+    }
+
+    /// Returns true until the crank is stopped.
+    pub fn is_running(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Stops cranking, waiting for a crank in progress to finish, and
+    /// returns the final metrics.
+    pub fn stop(mut self) -> Result<CrankMetrics> {
+        self.join()?;
+        Ok(self.metrics())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => handle.join().map_err(|_| {
+                Error::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    "crank thread panicked",
+                ))
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CrankHandle {
+    /// Stops the background thread.
+    fn drop(&mut self) {
+        self.join().unwrap_or(());
+    }
+}
+
+/// State owned by a crank's background thread.
+pub(crate) struct Crank {
+    url: String,
+    commitment: CommitmentConfig,
+    client: RpcClient,
+    payer: Keypair,
+    serum: Pubkey,
+    market: Pubkey,
+    event_queue: Pubkey,
+    base_vault: Pubkey,
+    quote_vault: Pubkey,
+    num_events: u16,
+    /// The request queue and the match_orders instruction of a V1 or V2
+    /// market, which does not match orders as they are placed.
+    match_orders: Option<(Pubkey, Instruction)>,
+    /// Polls left that panic instead, shared by restarted cranks.
+    #[cfg(feature = "fault-injection")]
+    injected_panics: Arc<AtomicUsize>,
+}
+
+impl Crank {
+    /// Prepares to crank a market, paying with and signing as payer. Fails
+    /// if payer cannot sign.
+    pub(crate) fn new(market: &Market, payer: &Actor, num_events: u16) -> Result<Self> {
+        let sandbox = payer.sandbox();
+        let url = sandbox.url();
+        let commitment = sandbox.client().commitment();
+        Ok(Crank {
+            client: RpcClient::new_with_commitment(url.clone(), commitment),
+            url,
+            commitment,
+            payer: Keypair::from_bytes(&payer.try_keypair()?.to_bytes())
+                .expect("keypair bytes round trip"),
+            serum: *market.serum(),
+            market: *market.market().pubkey(),
+            event_queue: *market.event_queue().pubkey(),
+            base_vault: *market.base_vault().account().pubkey(),
+            quote_vault: *market.quote_vault().account().pubkey(),
+            num_events,
+            match_orders: if market.version().matches_immediately() {
+                None
+            } else {
+                Some((
+                    *market.request_queue().pubkey(),
+                    market.match_orders_ix(num_events),
+                ))
+            },
+            #[cfg(feature = "fault-injection")]
+            injected_panics: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Returns a crank of the same market with its own RPC client, so that
+    /// a crank can be restarted after its thread panicked.
+    pub(crate) fn reconnect(&self) -> Self {
+        Crank {
+            url: self.url.clone(),
+            commitment: self.commitment,
+            client: RpcClient::new_with_commitment(self.url.clone(), self.commitment),
+            payer: Keypair::from_bytes(&self.payer.to_bytes()).expect("keypair bytes round trip"),
+            match_orders: self.match_orders.clone(),
+            #[cfg(feature = "fault-injection")]
+            injected_panics: self.injected_panics.clone(),
+            ..*self
+        }
+    }
+
+    /// Returns the number of upcoming polls that panic, which tests set to
+    /// exercise restarts.
+    #[cfg(feature = "fault-injection")]
+    pub(crate) fn injected_panics(&self) -> Arc<AtomicUsize> {
+        self.injected_panics.clone()
+    }
+
+    /// Returns the market being cranked.
+    pub(crate) fn market(&self) -> &Pubkey {
+        &self.market
+    }
+
+    /// Cranks until stop is set, recording failed polls in the metrics.
+    pub(crate) fn run(
+        &self,
+        metrics: &Mutex<CrankMetrics>,
+        start: time::Instant,
+        stop: &AtomicBool,
+        interval: time::Duration,
+    ) {
+        let mut consumed_before = None;
+        while !stop.load(Ordering::Relaxed) {
+            #[cfg(feature = "fault-injection")]
+            {
+                let injected =
+                    self.injected_panics
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+                if injected.is_ok() {
+                    panic!("injected crank panic");
+                }
+            }
+            let drained = match self.poll(metrics, start, &mut consumed_before) {
+                Ok(drained) => drained,
+                Err(err) => {
+                    tracing::warn!(market = %self.market, error = ?err, "crank failed");
+                    lock(metrics).errors.push(format!("{:?}", err));
+                    true
+                }
+            };
+            if drained {
+                thread::sleep(interval);
+            }
+        }
+    }
+
+    /// Matches waiting requests, then samples the queue and consumes events
+    /// from its front. Returns true if the queue was empty.
+    fn poll(
+        &self,
+        metrics: &Mutex<CrankMetrics>,
+        start: time::Instant,
+        consumed_before: &mut Option<u64>,
+    ) -> Result<bool> {
+        if let Some((request_queue, match_orders)) = &self.match_orders {
+            let requests = self.client.get_account_data(request_queue)?;
+            if serum::request_queue_len(&requests)? > 0 {
+                self.send(match_orders.clone())?;
+            }
+        }
+
+        let data = self.client.get_account_data(&self.event_queue)?;
+        let (seq_num, events) = serum::load_event_queue(&data)?;
+
+        // Every event ever pushed has either been consumed or is still in
+        // the queue.
+        let consumed = seq_num - events.len() as u64;
+        {
+            let mut metrics = lock(metrics);
+            let elapsed = start.elapsed();
+            metrics.last_poll = Some(elapsed);
+            if let Some(before) = *consumed_before {
+                if consumed > before {
+                    metrics.events_consumed += consumed - before;
+                    metrics.last_progress = Some(elapsed);
+                }
+            }
+            if metrics.queue_depth.len() == MAX_DEPTH_SAMPLES {
+                metrics.queue_depth.remove(0);
+            }
+            metrics.queue_depth.push(QueueDepth {
+                elapsed,
+                depth: events.len(),
+            });
+        }
+        *consumed_before = Some(consumed);
+        if events.is_empty() {
+            return Ok(true);
+        }
+
+        // Serum looks owners up by binary search, so the accounts are sorted
+        // in the same order as the packed keys in the events.
+        let mut owners = BTreeSet::new();
+        for event in events.iter().take(self.num_events as usize) {
+            if owners.len() == MAX_OPEN_ORDERS_PER_CRANK {
+                break;
+            }
+            let owner = match event.as_view()? {
+                EventView::Fill { owner, .. } | EventView::Out { owner, .. } => owner,
+            };
+            owners.insert(owner);
+        }
+        let owners: Vec<Pubkey> = owners
+            .iter()
+            .map(|owner| Pubkey::new(bytemuck::bytes_of(owner)))
+            .collect();
+
+        let instruction = serum_dex::instruction::consume_events(
+            &self.serum,
+            owners.iter().collect(),
+            &self.market,
+            &self.event_queue,
+            &self.base_vault,
+            &self.quote_vault,
+            self.num_events,
+        )?;
+        self.send(instruction)?;
+        lock(metrics).cranks += 1;
+        Ok(false)
+    }
+
+    /// Sends an instruction paid for and signed by the payer.
+    fn send(&self, instruction: Instruction) -> Result<()> {
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&self.payer.pubkey()));
+        transaction
+            .try_sign(&[&self.payer], self.client.get_latest_blockhash()?)
+            .map_err(ClientError::from)?;
+        self.client.send_and_confirm_transaction(&transaction)?;
+        Ok(())
+    }
+}
+
+/// Locks state shared with crank threads, such as metrics. The state stays
+/// usable after a thread panicked while holding the lock, so that a
+/// restarted crank and its supervisor can carry on.
+pub(crate) fn lock<T>(state: &Mutex<T>) -> MutexGuard<T> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,232 @@
+//! Decodes the instructions of known programs into readable descriptions.
+//!
+//! A DecoderRegistry maps program ids to decoders. It starts with the system,
+//! spl-token, and associated token account programs, whose ids are fixed.
+//! Serum and the Pyth simulator are deployed at a new address in each
+//! Sandbox, so Market and PriceAccount register their program's decoder when
+//! they are created. Decoders for other programs can be registered the same
+//! way:
+//!
+//! ```ignore
+//! sandbox.register_decoder(program.pubkey(), |data: &[u8]| {
+//!     let instruction = MyInstruction::try_from_slice(data).ok()?;
+//!     Some(DecodedInstruction::from_debug("my-program", &instruction))
+//! });
+//! ```
+//!
+//! Decoded instructions appear in trace entries and are returned by
+//! Sandbox::decode_transaction.
+
+use crate::token;
+use pyth_sim::{
+    instruction::{
+        CreateMappingAccountInstruction, CreatePriceAccountInstruction,
+        CreateProductAccountInstruction, MakeStaleInstruction, PublishPriceConfInstruction,
+        PublishPriceInstruction, PythInstructionId, SetExponentInstruction, SetStatusInstruction,
+    },
+    pack::PythPack,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{message::Message, pubkey::Pubkey, system_instruction::SystemInstruction};
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+/// A readable description of an instruction.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    /// Name of the program, such as "spl-token".
+    pub program: String,
+
+    /// Name of the instruction, such as "Transfer".
+    pub name: String,
+
+    /// The instruction and its arguments.
+    pub details: String,
+}
+
+impl DecodedInstruction {
+    /// Describes an instruction by its Debug representation. The name is the
+    /// leading identifier, which is the variant name of an instruction enum.
+    pub fn from_debug<T: Debug>(program: &str, instruction: &T) -> Self {
+        let details = format!("{:?}", instruction);
+        let name = details
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        DecodedInstruction {
+            program: program.to_string(),
+            name,
+            details,
+        }
+    }
+}
+
+/// Decodes the data of instructions sent to a single program. Returns None
+/// if the data is not an instruction the decoder recognizes.
+///
+/// Implemented for closures taking the instruction data.
+pub trait InstructionDecoder: Send + Sync {
+    fn decode(&self, data: &[u8]) -> Option<DecodedInstruction>;
+}
+
+impl<F> InstructionDecoder for F
+where
+    F: Fn(&[u8]) -> Option<DecodedInstruction> + Send + Sync,
+{
+    fn decode(&self, data: &[u8]) -> Option<DecodedInstruction> {
+        self(data)
+    }
+}
+
+/// Maps program ids to the decoders for their instructions.
+#[derive(Clone)]
+pub struct DecoderRegistry {
+    decoders: HashMap<Pubkey, Arc<dyn InstructionDecoder>>,
+}
+
+impl Default for DecoderRegistry {
+    fn default() -> Self {
+        DecoderRegistry::new()
+    }
+}
+
+impl DecoderRegistry {
+    /// Returns a registry with decoders for the system, spl-token, and
+    /// associated token account programs.
+    pub fn new() -> Self {
+        let mut registry = DecoderRegistry::empty();
+        registry.register(&solana_sdk::system_program::id(), decode_system);
+        registry.register(&spl_token::id(), decode_spl_token);
+        registry.register(
+            &token::associated_token_program_id(),
+            decode_associated_token_account,
+        );
+        registry
+    }
+
+    /// Returns a registry without any decoders.
+    pub fn empty() -> Self {
+        DecoderRegistry {
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Registers the decoder for a program, replacing any decoder registered
+    /// for it before.
+    pub fn register<D: InstructionDecoder + 'static>(&mut self, program_id: &Pubkey, decoder: D) {
+        self.decoders.insert(*program_id, Arc::new(decoder));
+    }
+
+    /// Returns true if a decoder is registered for the program.
+    pub fn contains(&self, program_id: &Pubkey) -> bool {
+        self.decoders.contains_key(program_id)
+    }
+
+    /// Decodes an instruction sent to the given program. Returns None if no
+    /// decoder is registered for the program or the data is not recognized.
+    pub fn decode(&self, program_id: &Pubkey, data: &[u8]) -> Option<DecodedInstruction> {
+        self.decoders.get(program_id)?.decode(data)
+    }
+
+    /// Decodes each instruction of a message, in order.
+    pub fn decode_message(&self, message: &Message) -> Vec<Option<DecodedInstruction>> {
+        message
+            .instructions
+            .iter()
+            .map(|instruction| {
+                self.decode(
+                    &message.account_keys[instruction.program_id_index as usize],
+                    &instruction.data,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Decodes system program instructions.
+pub fn decode_system(data: &[u8]) -> Option<DecodedInstruction> {
+    let instruction: SystemInstruction = bincode::deserialize(data).ok()?;
+    Some(DecodedInstruction::from_debug("system", &instruction))
+}
+
+/// Decodes spl-token instructions.
+pub fn decode_spl_token(data: &[u8]) -> Option<DecodedInstruction> {
+    let instruction = spl_token::instruction::TokenInstruction::unpack(data).ok()?;
+    Some(DecodedInstruction::from_debug("spl-token", &instruction))
+}
+
+/// Decodes associated token account program instructions. Older versions of
+/// the program take no data to create an account.
+pub fn decode_associated_token_account(data: &[u8]) -> Option<DecodedInstruction> {
+    let name = match data {
+        [] | [0] => "Create",
+        [1] => "CreateIdempotent",
+        [2] => "RecoverNested",
+        _ => return None,
+    };
+    Some(DecodedInstruction {
+        program: "associated-token-account".to_string(),
+        name: name.to_string(),
+        details: name.to_string(),
+    })
+}
+
+/// Decodes Serum dex instructions.
+pub fn decode_serum_dex(data: &[u8]) -> Option<DecodedInstruction> {
+    let instruction = serum_dex::instruction::MarketInstruction::unpack(data)?;
+    Some(DecodedInstruction::from_debug("serum-dex", &instruction))
+}
+
+/// Decodes Pyth simulator instructions.
+pub fn decode_pyth_sim(data: &[u8]) -> Option<DecodedInstruction> {
+    let (id, data) = data.split_first()?;
+    let id = PythInstructionId::try_from(*id).ok()?;
+    let details = match id {
+        PythInstructionId::CreatePriceAccount => {
+            format!("{:?}", unpack_pyth::<CreatePriceAccountInstruction>(data)?)
+        }
+        PythInstructionId::CreateProductAccount => {
+            let instruction = unpack_pyth::<CreateProductAccountInstruction>(data)?;
+            format!(
+                "CreateProductAccountInstruction {{ px_acc: {}, attr_len: {} }}",
+                Pubkey::new_from_array(instruction.px_acc),
+                instruction.attr_len
+            )
+        }
+        PythInstructionId::CreateMappingAccount => {
+            format!(
+                "{:?}",
+                unpack_pyth::<CreateMappingAccountInstruction>(data)?
+            )
+        }
+        PythInstructionId::PublishPrice => {
+            format!("{:?}", unpack_pyth::<PublishPriceInstruction>(data)?)
+        }
+        PythInstructionId::SetStatus => {
+            format!("{:?}", unpack_pyth::<SetStatusInstruction>(data)?)
+        }
+        PythInstructionId::SetExponent => {
+            format!("{:?}", unpack_pyth::<SetExponentInstruction>(data)?)
+        }
+        PythInstructionId::MakeStale => {
+            format!("{:?}", unpack_pyth::<MakeStaleInstruction>(data)?)
+        }
+        PythInstructionId::PublishPriceConf => {
+            format!("{:?}", unpack_pyth::<PublishPriceConfInstruction>(data)?)
+        }
+    };
+    Some(DecodedInstruction {
+        program: "pyth-sim".to_string(),
+        name: format!("{:?}", id),
+        details,
+    })
+}
+
+/// Unpacks a Pyth simulator instruction body, checking its length first
+/// since unpacking panics on short data.
+fn unpack_pyth<T: PythPack>(data: &[u8]) -> Option<T> {
+    if data.len() < T::LEN {
+        return None;
+    }
+    T::unpack_from_slice(data).ok()
+}
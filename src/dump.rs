@@ -0,0 +1,103 @@
+use crate::errors::{Error, Result};
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// An account as written by `solana account --output json <ADDRESS>` and
+/// read by `solana-test-validator --account <ADDRESS> <FILE>`:
+///
+/// ```json
+/// {
+///   "pubkey": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+///   "account": {
+///     "lamports": 1461600,
+///     "data": ["AQAAAJj+huiNm+Lqi8HMpIeLKYjCQPUrhCS/tA7Rot3LXhmbSAkuwh5cAQAGAQEA...", "base64"],
+///     "owner": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+///     "executable": false,
+///     "rentEpoch": 0
+///   }
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AccountFile {
+    /// Base58 address of the account.
+    pub pubkey: String,
+
+    /// The account, with its data encoded as base64.
+    pub account: UiAccount,
+}
+
+impl AccountFile {
+    /// Encodes an account.
+    pub fn new(pubkey: &Pubkey, account: &Account) -> Self {
+        AccountFile {
+            pubkey: pubkey.to_string(),
+            account: UiAccount::encode(pubkey, account, UiAccountEncoding::Base64, None, None),
+        }
+    }
+
+    /// Reads an account file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(invalid_data)
+    }
+
+    /// Writes the account file, replacing any existing file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(invalid_data)?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Decodes the account's address and contents.
+    pub fn decode(&self) -> Result<(Pubkey, Account)> {
+        let pubkey = Pubkey::from_str(&self.pubkey).map_err(invalid_data)?;
+        let account = self
+            .account
+            .decode()
+            .ok_or_else(|| invalid_data(format!("account {} has undecodable data", self.pubkey)))?;
+        Ok((pubkey, account))
+    }
+}
+
+/// Writes each account to dir as <pubkey>.json, creating dir if needed.
+pub fn write_account_dir<P: AsRef<Path>>(dir: P, accounts: &[(Pubkey, Account)]) -> Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    for (pubkey, account) in accounts.iter() {
+        AccountFile::new(pubkey, account).save(dir.join(format!("{}.json", pubkey)))?;
+    }
+    Ok(())
+}
+
+/// Returns the address and path of every .json account file in dir, sorted
+/// by path.
+pub fn read_account_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<(Pubkey, PathBuf)>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "json")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let (pubkey, _) = AccountFile::load(&path)?.decode()?;
+            Ok((pubkey, path))
+        })
+        .collect()
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, err))
+}
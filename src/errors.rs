@@ -1,9 +1,200 @@
+use solana_sdk::transaction::TransactionError;
+use std::fmt;
+
 #[derive(Debug, foonetic_macros::From)]
 pub enum Error {
     SolanaClientError(solana_client::client_error::ClientError),
     SolanaProgramError(solana_sdk::program_error::ProgramError),
     InputOutputError(std::io::Error),
     SerumDexError(serum_dex::error::DexError),
+    InvalidMarketParams(MarketParamsError),
+    Context(Box<ContextError>),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Returns the underlying error, beneath any operation labels.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context(context) => context.error.root(),
+            err => err,
+        }
+    }
+
+    /// Returns the labels of the operations that failed, outermost first.
+    pub fn contexts(&self) -> Vec<&str> {
+        let mut contexts = Vec::new();
+        let mut err = self;
+        while let Error::Context(context) = err {
+            contexts.push(context.context.as_str());
+            err = &context.error;
+        }
+        contexts
+    }
+
+    /// Returns true if a program rejected the transaction, as opposed to the
+    /// transaction failing to be built, sent or confirmed.
+    pub fn is_program_error(&self) -> bool {
+        match self.root() {
+            Error::SolanaClientError(err) => matches!(
+                err.get_transaction_error(),
+                Some(TransactionError::InstructionError(..))
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SolanaClientError(err) => write!(f, "solana client error: {}", err),
+            Error::SolanaProgramError(err) => write!(f, "solana program error: {}", err),
+            Error::InputOutputError(err) => write!(f, "i/o error: {}", err),
+            // DexError implements neither Display nor std::error::Error.
+            Error::SerumDexError(err) => write!(f, "serum dex error: {:?}", err),
+            Error::InvalidMarketParams(err) => write!(f, "invalid market parameters: {}", err),
+            Error::Context(context) => context.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::SolanaClientError(err) => Some(err),
+            Error::SolanaProgramError(err) => Some(err),
+            Error::InputOutputError(err) => Some(err),
+            Error::SerumDexError(_) => None,
+            Error::InvalidMarketParams(err) => Some(err),
+            Error::Context(context) => Some(&context.error),
+        }
+    }
+}
+
+/// An error labeled with the operation that produced it, such as which of
+/// the transactions creating a participant failed.
+#[derive(Debug)]
+pub struct ContextError {
+    context: String,
+    error: Error,
+}
+
+impl ContextError {
+    /// Returns the label of the operation that failed.
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    /// Returns the error the operation failed with.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.error)
+    }
+}
+
+impl std::error::Error for ContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Labels the error of a failed operation.
+///
+/// ```ignore
+/// sandbox
+///     .airdrop_many(&airdrops)
+///     .with_context(|| format!("airdropping to {} participants", n))?;
+/// ```
+pub trait Context<T> {
+    /// Labels the error, if any, with the given operation.
+    fn context<C: Into<String>>(self, context: C) -> Result<T>;
+
+    /// Labels the error, if any, with an operation that is only formatted on
+    /// failure.
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, context: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T> {
+        self.with_context(|| context)
+    }
+
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.map_err(|err| {
+            Error::Context(Box::new(ContextError {
+                context: context().into(),
+                error: err.into(),
+            }))
+        })
+    }
+}
+
+/// A reason Serum would reject a market's parameters, found before any
+/// account is created for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketParamsError {
+    /// The request queue has fewer entries than Serum requires.
+    RequestQueueTooSmall { given: usize, minimum: usize },
+    /// The event queue has fewer entries than Serum requires.
+    EventQueueTooSmall { given: usize, minimum: usize },
+    /// The bids and asks accounts have fewer slab nodes than Serum requires.
+    BookTooSmall { given: usize, minimum: usize },
+    /// A lot size is zero.
+    ZeroLotSize {
+        base_lot_size: u64,
+        quote_lot_size: u64,
+    },
+    /// The dust threshold would sweep amounts of a whole quote lot.
+    DustThresholdTooLarge {
+        dust_threshold: u64,
+        quote_lot_size: u64,
+    },
+}
+
+impl fmt::Display for MarketParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarketParamsError::RequestQueueTooSmall { given, minimum } => write!(
+                f,
+                "request_queue_size is {} but Serum requires at least {} entries",
+                given, minimum
+            ),
+            MarketParamsError::EventQueueTooSmall { given, minimum } => write!(
+                f,
+                "event_queue_size is {} but Serum requires at least {} entries",
+                given, minimum
+            ),
+            MarketParamsError::BookTooSmall { given, minimum } => write!(
+                f,
+                "book_size is {} but Serum requires at least {} slab nodes per side",
+                given, minimum
+            ),
+            MarketParamsError::ZeroLotSize {
+                base_lot_size,
+                quote_lot_size,
+            } => write!(
+                f,
+                "base_lot_size is {} and quote_lot_size is {} but both must be at least 1",
+                base_lot_size, quote_lot_size
+            ),
+            MarketParamsError::DustThresholdTooLarge {
+                dust_threshold,
+                quote_lot_size,
+            } => write!(
+                f,
+                "dust_threshold is {} but must be below quote_lot_size {}, or whole lots \
+                 would be swept as dust",
+                dust_threshold, quote_lot_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MarketParamsError {}
@@ -0,0 +1,140 @@
+use crate::rng::SandboxRng;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use std::{io, time};
+
+/// Describes which faults to inject into transaction sends and how often.
+/// Probabilities are checked in order for each send: a simulated timeout
+/// fails the send without submitting the transaction, a dropped confirmation
+/// submits and confirms the transaction but reports a timeout, and a delay
+/// postpones the send. At most one fault is injected per send.
+///
+/// ```ignore
+/// sandbox.inject_faults(
+///     FaultPolicy::new()
+///         .timeout(0.1)
+///         .drop_confirmation(0.1)
+///         .delay(0.5, Duration::from_millis(200)),
+///     Some(42),
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaultPolicy {
+    /// Probability that a send fails with a timeout before reaching the
+    /// validator.
+    pub timeout_probability: f64,
+
+    /// Probability that a send reaches the validator but its confirmation is
+    /// reported as a timeout.
+    pub drop_confirmation_probability: f64,
+
+    /// Probability that a send is delayed.
+    pub delay_probability: f64,
+
+    /// Longest delay applied to a delayed send. Delays are uniform between
+    /// zero and this duration.
+    pub max_delay: time::Duration,
+}
+
+impl FaultPolicy {
+    /// Returns a policy that injects no faults.
+    pub fn new() -> Self {
+        FaultPolicy::default()
+    }
+
+    /// Fails sends with a timeout with the given probability.
+    pub fn timeout(mut self, probability: f64) -> Self {
+        self.timeout_probability = probability;
+        self
+    }
+
+    /// Drops confirmations of landed transactions with the given
+    /// probability.
+    pub fn drop_confirmation(mut self, probability: f64) -> Self {
+        self.drop_confirmation_probability = probability;
+        self
+    }
+
+    /// Delays sends by up to max_delay with the given probability.
+    pub fn delay(mut self, probability: f64, max_delay: time::Duration) -> Self {
+        self.delay_probability = probability;
+        self.max_delay = max_delay;
+        self
+    }
+}
+
+/// Counts of the faults injected so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    /// Sends seen by the injector, faulted or not.
+    pub sends: u64,
+    pub timeouts: u64,
+    pub dropped_confirmations: u64,
+    pub delays: u64,
+}
+
+/// A fault chosen for a single send.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fault {
+    None,
+    Timeout,
+    DropConfirmation,
+    Delay(time::Duration),
+}
+
+/// Draws faults from a policy with its own seeded generator, so that the
+/// sequence of faults is reproducible independently of other randomness.
+pub(crate) struct FaultInjector {
+    policy: FaultPolicy,
+    rng: SandboxRng,
+    stats: FaultStats,
+}
+
+impl FaultInjector {
+    pub(crate) fn new(policy: FaultPolicy, rng: SandboxRng) -> Self {
+        FaultInjector {
+            policy,
+            rng,
+            stats: FaultStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    /// Chooses the fault for the next send.
+    pub(crate) fn next(&mut self) -> Fault {
+        self.stats.sends += 1;
+        if self.chance(self.policy.timeout_probability) {
+            self.stats.timeouts += 1;
+            return Fault::Timeout;
+        }
+        if self.chance(self.policy.drop_confirmation_probability) {
+            self.stats.dropped_confirmations += 1;
+            return Fault::DropConfirmation;
+        }
+        if self.chance(self.policy.delay_probability) {
+            self.stats.delays += 1;
+            let max = self.policy.max_delay.as_micros() as u64;
+            return Fault::Delay(time::Duration::from_micros(
+                self.rng.gen_range(0, max.max(1)),
+            ));
+        }
+        Fault::None
+    }
+
+    /// Returns true with the given probability, clamped to [0, 1].
+    fn chance(&mut self, probability: f64) -> bool {
+        self.rng.gen_bool(probability.clamp(0.0, 1.0))
+    }
+}
+
+/// Returns the error reported for an injected timeout. It is an I/O error,
+/// as a real network timeout would be, so retry policies treat it as
+/// transient.
+pub(crate) fn timeout_error(message: &str) -> ClientError {
+    ClientError::from(ClientErrorKind::Io(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("injected fault: {}", message),
+    )))
+}
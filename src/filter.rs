@@ -0,0 +1,89 @@
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+
+/// Selects the accounts of a program returned by
+/// Sandbox::find_program_accounts. Every condition must hold for an account
+/// to match:
+///
+/// ```ignore
+/// let filter = AccountFilter::new()
+///     .data_size(165)
+///     .memcmp_pubkey(0, mint.pubkey())
+///     .keys_only();
+/// let accounts = sandbox.find_program_accounts(&spl_token::id(), &filter)?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AccountFilter {
+    filters: Vec<RpcFilterType>,
+    data_slice: Option<UiDataSliceConfig>,
+}
+
+impl AccountFilter {
+    /// Returns a filter that matches every account of the program.
+    pub fn new() -> Self {
+        AccountFilter::default()
+    }
+
+    /// Matches accounts whose data is exactly size bytes long.
+    pub fn data_size(mut self, size: u64) -> Self {
+        self.filters.push(RpcFilterType::DataSize(size));
+        self
+    }
+
+    /// Matches accounts whose data contains bytes at offset.
+    pub fn memcmp(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.filters.push(RpcFilterType::Memcmp(Memcmp {
+            offset,
+            bytes: MemcmpEncodedBytes::Base64(base64::encode(bytes)),
+            encoding: None,
+        }));
+        self
+    }
+
+    /// Matches accounts whose data contains the address at offset, such as
+    /// an owner or market field.
+    pub fn memcmp_pubkey(mut self, offset: usize, pubkey: &Pubkey) -> Self {
+        self.filters.push(RpcFilterType::Memcmp(Memcmp {
+            offset,
+            bytes: MemcmpEncodedBytes::Base58(pubkey.to_string()),
+            encoding: None,
+        }));
+        self
+    }
+
+    /// Returns only len bytes of each matching account's data, starting at
+    /// offset.
+    pub fn data_slice(mut self, offset: usize, len: usize) -> Self {
+        self.data_slice = Some(UiDataSliceConfig {
+            offset,
+            length: len,
+        });
+        self
+    }
+
+    /// Returns matching accounts without their data, for when only the
+    /// addresses are needed.
+    pub fn keys_only(self) -> Self {
+        self.data_slice(0, 0)
+    }
+
+    pub(crate) fn config(&self, commitment: CommitmentConfig) -> RpcProgramAccountsConfig {
+        RpcProgramAccountsConfig {
+            filters: if self.filters.is_empty() {
+                None
+            } else {
+                Some(self.filters.clone())
+            },
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: self.data_slice,
+                commitment: Some(commitment),
+            },
+            with_context: None,
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::actor::Actor;
+use crate::errors::{Context, Error, Result};
+use crate::sandbox::Sandbox;
+use crate::serum::{Market, Participant};
+use crate::token::Mint;
+use serde::{Deserialize, Serialize};
+use solana_program::{bs58, program_option::COption, program_pack::Pack};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use std::{fs, io, path::Path, str::FromStr};
+
+/// A Serum market participant listed in a MarketManifest.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ParticipantManifest {
+    /// Base58 private key of the participant's account.
+    pub keypair: String,
+
+    /// Address of the participant's base token account.
+    pub base: String,
+
+    /// Address of the participant's quote token account.
+    pub quote: String,
+
+    /// Address of the participant's open orders account.
+    pub orders: String,
+}
+
+/// The accounts of a Serum market and its participants, as written to
+/// market.json by create_serum_market and read by serum_place_order.
+///
+/// Addresses are base58 strings, so the file can be read by tools in other
+/// languages. A manifest reconnects to its market in stages, since the
+/// market borrows its mints and the participants borrow the market:
+///
+/// ```ignore
+/// let manifest = MarketManifest::load("market.json")?;
+/// let serum = manifest.program_id()?;
+/// let authority = Actor::new(&sandbox)?;
+/// let (base_mint, quote_mint) = manifest.mints(&sandbox, &authority)?;
+/// let market = manifest.market(&sandbox, &serum, &base_mint, &quote_mint)?;
+/// let participants = manifest.participants(&market)?;
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketManifest {
+    /// RPC endpoint of the validator running the market.
+    pub url: String,
+
+    /// Address of the Serum program.
+    pub program_id: String,
+
+    /// Address of the market.
+    pub market: String,
+
+    /// Address of the market's bids slab.
+    pub bids: String,
+
+    /// Address of the market's asks slab.
+    pub asks: String,
+
+    /// Address of the market's request queue.
+    pub request_queue: String,
+
+    /// Address of the market's event queue.
+    pub event_queue: String,
+
+    /// Address of the market's base vault.
+    pub base_vault: String,
+
+    /// Address of the market's quote vault.
+    pub quote_vault: String,
+
+    /// Address of the market's base mint.
+    pub base_mint: String,
+
+    /// Address of the market's quote mint.
+    pub quote_mint: String,
+
+    /// Participants of the market, whose keys are stored in the clear.
+    #[serde(default)]
+    pub participants: Vec<ParticipantManifest>,
+}
+
+impl MarketManifest {
+    /// Describes a market and its participants running in the Sandbox.
+    pub fn from_market(sandbox: &Sandbox, market: &Market, participants: &[Participant]) -> Self {
+        MarketManifest {
+            url: sandbox.url(),
+            program_id: market.serum().to_string(),
+            market: market.market().pubkey().to_string(),
+            bids: market.bids().pubkey().to_string(),
+            asks: market.asks().pubkey().to_string(),
+            request_queue: market.request_queue().pubkey().to_string(),
+            event_queue: market.event_queue().pubkey().to_string(),
+            base_vault: market.base_vault().account().pubkey().to_string(),
+            quote_vault: market.quote_vault().account().pubkey().to_string(),
+            base_mint: market.base_mint().actor().pubkey().to_string(),
+            quote_mint: market.quote_mint().actor().pubkey().to_string(),
+            participants: participants
+                .iter()
+                .map(|participant| ParticipantManifest {
+                    keypair: participant.account().keypair().to_base58_string(),
+                    base: participant.base().pubkey().to_string(),
+                    quote: participant.quote().pubkey().to_string(),
+                    orders: participant.open_orders().pubkey().to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Loads a manifest from a JSON file. Fields the manifest does not know,
+    /// such as those oracle_pegged_market adds, are ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|err| Error::from(io::Error::new(io::ErrorKind::InvalidData, err)))
+            .with_context(|| format!("loading market manifest {}", path.display()))
+    }
+
+    /// Writes the manifest to a JSON file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string(self)
+            .map_err(|err| Error::from(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Returns the address of the Serum program.
+    pub fn program_id(&self) -> Result<Pubkey> {
+        parse(&self.program_id)
+    }
+
+    /// Attaches to the market's base and quote mints, signing mints with
+    /// authority. The manifest does not record the mints' authority, so the
+    /// caller must hold it; fails if authority is not the authority of both
+    /// mints. Use Market::new_from_existing to attach without it.
+    pub fn mints<'a>(
+        &self,
+        sandbox: &'a Sandbox,
+        authority: &'a Actor,
+    ) -> Result<(Mint<'a>, Mint<'a>)> {
+        let mut mints = Vec::new();
+        for address in [&self.base_mint, &self.quote_mint] {
+            let pubkey = parse(address)?;
+            let state = spl_token::state::Mint::unpack(&sandbox.get_account_data(&pubkey)?)?;
+            if state.mint_authority != COption::Some(*authority.pubkey()) {
+                return Err(Error::from(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} is not the mint authority of {}",
+                        authority.pubkey(),
+                        address
+                    ),
+                )));
+            }
+            mints.push(Mint::from_existing(sandbox, &pubkey, authority)?);
+        }
+        let quote = mints.pop().expect("quote mint attached");
+        let base = mints.pop().expect("base mint attached");
+        Ok((base, quote))
+    }
+
+    /// Attaches to the market, checking that the Sandbox runs it and that the
+    /// accounts recorded in the market's state match the manifest.
+    pub fn market<'a>(
+        &self,
+        sandbox: &'a Sandbox,
+        serum: &'a Pubkey,
+        base_mint: &'a Mint,
+        quote_mint: &'a Mint,
+    ) -> Result<Market<'a>> {
+        if sandbox.url() != self.url {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "manifest describes a market at {}, not at {}",
+                    self.url,
+                    sandbox.url()
+                ),
+            )));
+        }
+        let market = Market::new_from_existing_with_mints(
+            sandbox,
+            serum,
+            &parse(&self.market)?,
+            base_mint,
+            quote_mint,
+        )
+        .with_context(|| format!("attaching to market {}", self.market))?;
+        for (name, expected, actual) in [
+            ("bids", &self.bids, market.bids().pubkey()),
+            ("asks", &self.asks, market.asks().pubkey()),
+            (
+                "request_queue",
+                &self.request_queue,
+                market.request_queue().pubkey(),
+            ),
+            (
+                "event_queue",
+                &self.event_queue,
+                market.event_queue().pubkey(),
+            ),
+            (
+                "base_vault",
+                &self.base_vault,
+                market.base_vault().account().pubkey(),
+            ),
+            (
+                "quote_vault",
+                &self.quote_vault,
+                market.quote_vault().account().pubkey(),
+            ),
+        ] {
+            if parse(expected)? != *actual {
+                return Err(Error::from(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "manifest lists {} {} for market {}, but the market uses {}",
+                        name, expected, self.market, actual
+                    ),
+                )));
+            }
+        }
+        Ok(market)
+    }
+
+    /// Attaches to the manifest's participants of the market, in order.
+    pub fn participants<'a>(&self, market: &'a Market<'a>) -> Result<Vec<Participant<'a>>> {
+        let sandbox = market.market().sandbox();
+        self.participants
+            .iter()
+            .enumerate()
+            .map(|(i, participant)| {
+                Participant::from_existing(
+                    market,
+                    Actor::from_keypair(sandbox, parse_keypair(&participant.keypair)?)?,
+                    &parse(&participant.base)?,
+                    &parse(&participant.quote)?,
+                    &parse(&participant.orders)?,
+                )
+                .with_context(|| format!("attaching to participant {}", i))
+            })
+            .collect()
+    }
+}
+
+// Errors leave the key out, since it is secret.
+fn parse_keypair(keypair: &str) -> Result<Keypair> {
+    let invalid = |err: String| {
+        Error::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid keypair in manifest: {}", err),
+        ))
+    };
+    let bytes = bs58::decode(keypair)
+        .into_vec()
+        .map_err(|err| invalid(err.to_string()))?;
+    Keypair::from_bytes(&bytes).map_err(|err| invalid(err.to_string()))
+}
+
+fn parse(address: &str) -> Result<Pubkey> {
+    Pubkey::from_str(address).map_err(|err| {
+        Error::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid address {} in manifest: {}", address, err),
+        ))
+    })
+}
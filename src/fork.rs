@@ -0,0 +1,92 @@
+use crate::errors::{Error, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{fs, io, path::Path, str::FromStr};
+
+/// An account to clone into a forked Sandbox.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ForkAccount {
+    /// Base58 address of the account.
+    pub address: String,
+
+    /// Name registered for the account in the Sandbox, if any.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// Lists the programs and accounts that a forked Sandbox clones from a remote
+/// cluster, for example the Serum DEX program, Pyth feeds, and token mints a
+/// test depends on.
+///
+/// Manifests are usually loaded from JSON files of the form:
+///
+/// ```json
+/// {
+///   "programs": [
+///     { "address": "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin", "label": "serum_dex" }
+///   ],
+///   "accounts": [
+///     { "address": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v", "label": "usdc" }
+///   ]
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ForkManifest {
+    /// Executable programs to clone.
+    #[serde(default)]
+    pub programs: Vec<ForkAccount>,
+
+    /// Data accounts to clone, such as mints, markets, and price feeds.
+    #[serde(default)]
+    pub accounts: Vec<ForkAccount>,
+}
+
+impl ForkManifest {
+    /// Returns an empty manifest.
+    pub fn new() -> Self {
+        ForkManifest::default()
+    }
+
+    /// Loads a manifest from a JSON file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|err| Error::from(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+
+    /// Adds a program to clone.
+    pub fn program(mut self, address: &Pubkey, label: Option<&str>) -> Self {
+        self.programs.push(ForkAccount {
+            address: address.to_string(),
+            label: label.map(String::from),
+        });
+        self
+    }
+
+    /// Adds a data account to clone.
+    pub fn account(mut self, address: &Pubkey, label: Option<&str>) -> Self {
+        self.accounts.push(ForkAccount {
+            address: address.to_string(),
+            label: label.map(String::from),
+        });
+        self
+    }
+
+    /// Returns the address and label of every program and account in the
+    /// manifest, failing on the first address that is not valid base58.
+    pub fn entries(&self) -> Result<Vec<(Pubkey, Option<String>)>> {
+        self.programs
+            .iter()
+            .chain(self.accounts.iter())
+            .map(|entry| {
+                let address = Pubkey::from_str(&entry.address).map_err(|err| {
+                    Error::from(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid address {} in manifest: {}", entry.address, err),
+                    ))
+                })?;
+                Ok((address, entry.label.clone()))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,222 @@
+use crate::actor::Actor;
+use crate::errors::{Error, Result};
+use crate::sandbox::Sandbox;
+use crate::token::{Mint, TokenAccount};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+};
+use spl_token::{self, instruction as spl_instruction};
+use std::{collections::HashMap, io};
+
+/// Maximum number of mint_to instructions packed into a single transaction.
+/// Each distinct mint authority adds a signature, so this is kept well below
+/// the packet size limit.
+const MINTS_PER_TRANSACTION: usize = 8;
+
+/// Where a Sandbox gets the lamports for Actor::airdrop and
+/// Sandbox::airdrop_many. Set with Sandbox::set_funding_source.
+///
+/// Airdrops are requested from the cluster's faucet by default, which
+/// devnet rate limits and some validators do not run. A funded keypair can
+/// pay for airdrops instead, by transferring from its own balance.
+#[derive(Debug)]
+pub enum FundingSource {
+    /// Requests airdrops from the cluster's faucet.
+    Faucet,
+
+    /// Transfers every airdrop from the keypair.
+    Funder(Keypair),
+
+    /// Requests airdrops from the cluster's faucet, transferring from the
+    /// keypair those the faucet refuses.
+    FaucetOrFunder(Keypair),
+}
+
+impl Default for FundingSource {
+    fn default() -> Self {
+        FundingSource::Faucet
+    }
+}
+
+impl FundingSource {
+    /// Returns the keypair paying for airdrops, if any.
+    pub fn funder(&self) -> Option<&Keypair> {
+        match self {
+            FundingSource::Faucet => None,
+            FundingSource::Funder(funder) | FundingSource::FaucetOrFunder(funder) => Some(funder),
+        }
+    }
+
+    /// Returns whether airdrops are first requested from the faucet.
+    pub fn uses_faucet(&self) -> bool {
+        !matches!(self, FundingSource::Funder(_))
+    }
+}
+
+/// Collects SOL and token top-ups for many accounts and executes them in as
+/// few transactions as possible.
+///
+/// SOL is funded through batched airdrops that are confirmed together. Token
+/// balances are funded by packing mint_to instructions from any number of
+/// mints into shared transactions.
+pub struct FundingPlan<'a> {
+    sandbox: &'a Sandbox,
+    lamports: Vec<(&'a Actor<'a>, u64)>,
+    tokens: Vec<(&'a Mint<'a>, Pubkey, u64)>,
+}
+
+impl<'a> FundingPlan<'a> {
+    /// Creates an empty funding plan for the given Sandbox.
+    pub fn new(sandbox: &'a Sandbox) -> Self {
+        FundingPlan {
+            sandbox,
+            lamports: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Adds the given number of lamports to the actor's balance.
+    pub fn lamports(&mut self, actor: &'a Actor<'a>, lamports: u64) -> &mut Self {
+        self.lamports.push((actor, lamports));
+        self
+    }
+
+    /// Mints the given amount into the token account. The mint's authority
+    /// signs the resulting transaction.
+    pub fn tokens(
+        &mut self,
+        mint: &'a Mint<'a>,
+        destination: &TokenAccount,
+        amount: u64,
+    ) -> &mut Self {
+        self.tokens
+            .push((mint, *destination.account().pubkey(), amount));
+        self
+    }
+
+    /// Executes the plan. The payer funds all token transactions.
+    pub fn execute(&self, payer: &Actor) -> Result<()> {
+        if !self.lamports.is_empty() {
+            let targets: Vec<(&Actor, u64)> = self
+                .lamports
+                .iter()
+                .map(|(actor, lamports)| (*actor, *lamports))
+                .collect();
+            self.sandbox.airdrop_many(&targets)?;
+        }
+
+        for chunk in self.tokens.chunks(MINTS_PER_TRANSACTION) {
+            let mut instructions = Vec::with_capacity(chunk.len());
+            let mut signers: Vec<&Keypair> = vec![payer.try_keypair()?];
+            for (mint, destination, amount) in chunk.iter() {
+                instructions.push(spl_instruction::mint_to(
+                    &spl_token::id(),
+                    mint.actor().pubkey(),
+                    destination,
+                    mint.authority().pubkey(),
+                    &[],
+                    *amount,
+                )?);
+
+                let authority = mint.authority().try_keypair()?;
+                if !signers
+                    .iter()
+                    .any(|signer| signer.pubkey() == authority.pubkey())
+                {
+                    signers.push(authority);
+                }
+            }
+
+            self.sandbox
+                .transaction()
+                .instructions(&instructions)
+                .payer(payer.try_keypair()?)
+                .signers(&signers)
+                .send()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A funded actor that hands out SOL and tokens on request.
+///
+/// Tokens are registered by symbol, so long-running scenario tests can
+/// replenish balances mid-test by asking for "USDC" without plumbing mints
+/// through every helper. Tokens are minted on request, signed by each mint's
+/// authority; SOL is transferred from the faucet actor's own balance rather
+/// than airdropped.
+///
+/// ```ignore
+/// let faucet = Faucet::new(&sandbox, &bank)
+///     .with_token("BTC", &base_mint)
+///     .with_token("USDC", &quote_mint);
+/// faucet.give("USDC", &alice_usdc, 1_000)?;
+/// ```
+pub struct Faucet<'a> {
+    sandbox: &'a Sandbox,
+    actor: &'a Actor<'a>,
+    mints: HashMap<String, &'a Mint<'a>>,
+}
+
+impl<'a> Faucet<'a> {
+    /// Creates a faucet backed by the given actor, which pays for every
+    /// request.
+    pub fn new(sandbox: &'a Sandbox, actor: &'a Actor<'a>) -> Self {
+        Faucet {
+            sandbox,
+            actor,
+            mints: HashMap::new(),
+        }
+    }
+
+    /// Registers a mint under a symbol such as "USDC".
+    pub fn with_token(mut self, symbol: &str, mint: &'a Mint<'a>) -> Self {
+        self.mints.insert(String::from(symbol), mint);
+        self
+    }
+
+    /// Returns the actor backing the faucet.
+    pub fn actor(&self) -> &Actor {
+        self.actor
+    }
+
+    /// Returns the mint registered under a symbol.
+    pub fn mint(&self, symbol: &str) -> Result<&'a Mint<'a>> {
+        self.mints.get(symbol).cloned().ok_or_else(|| {
+            Error::from(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no token registered for symbol {}", symbol),
+            ))
+        })
+    }
+
+    /// Returns the registered symbols, sorted.
+    pub fn symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = self.mints.keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+
+    /// Gives an amount of the token registered under symbol to the
+    /// destination account, which must be a token account of that mint.
+    pub fn give(&self, symbol: &str, destination: &TokenAccount, amount: u64) -> Result<()> {
+        self.mint(symbol)?.mint_to(self.actor, destination, amount)
+    }
+
+    /// Gives lamports to an account from the faucet actor's balance.
+    pub fn give_lamports(&self, destination: &Pubkey, lamports: u64) -> Result<()> {
+        self.sandbox
+            .transaction()
+            .instruction(system_instruction::transfer(
+                self.actor.pubkey(),
+                destination,
+                lamports,
+            ))
+            .payer(self.actor.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,414 @@
+use crate::actor::Actor;
+use crate::errors::{Error, Result};
+use crate::rng::SandboxRng;
+use crate::serum::{Market, Participant};
+use crate::token::TokenAccount;
+use crate::views::{OpenOrdersView, SlabNodeView};
+use serum_dex::matching::{OrderType, Side};
+use std::{io, num::NonZeroU64};
+
+/// Events consumed by each crank operation.
+const CRANK_EVENTS: u16 = 10;
+
+/// An operation applied to a market by a MarketHarness. Participants and
+/// orders are chosen by index modulo the number available, so any generated
+/// value refers to something valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Places an order with price in quote lots and quantity in base lots.
+    /// Zero prices and quantities are raised to one.
+    NewOrder {
+        participant: usize,
+        side: Side,
+        price: u64,
+        quantity: u64,
+        order_type: OrderType,
+    },
+    /// Cancels one of the participant's resting orders, if it has any.
+    Cancel { participant: usize, order: usize },
+    /// Consumes events for every open orders account on the market.
+    Crank,
+    /// Settles the participant's free balances into its wallets.
+    Settle { participant: usize },
+}
+
+/// Applies random sequences of operations to a Serum market and checks
+/// invariants after each one:
+///
+/// - Conservation: the base and quote held by the participants' wallets and
+///   the market vaults never change in total.
+/// - No negative free balances: no open orders account has more free than
+///   total funds.
+/// - Vault coverage: once every event is consumed, the vaults hold at least
+///   what the open orders accounts are owed.
+/// - Book consistency: the book is not crossed, every resting order has a
+///   quantity, and every order placed by a participant is listed in its open
+///   orders account.
+///
+/// Operations that the DEX program rejects, such as orders the participant
+/// cannot afford, are counted and skipped. Tokens moved into or out of the
+/// participants' wallets or the vaults other than by the operations, for
+/// example with Participant::top_up or Market::sweep_fees, break the
+/// conservation check.
+///
+/// The harness works with any source of operations. With the proptest
+/// feature enabled, operations generates them as a proptest strategy:
+///
+/// ```ignore
+/// proptest! {
+///     #[test]
+///     fn market_invariants(ops in operations(100, 50, 32)) {
+///         let mut harness = MarketHarness::new(&cranker, &market, participants()).unwrap();
+///         harness.run(&ops).unwrap();
+///     }
+/// }
+/// ```
+pub struct MarketHarness<'a> {
+    cranker: &'a Actor<'a>,
+    market: &'a Market<'a>,
+    participants: Vec<Participant<'a>>,
+    base_total: u64,
+    quote_total: u64,
+    next_client_order_id: u64,
+}
+
+impl<'a> MarketHarness<'a> {
+    /// Creates a harness for the given participants of a market. The
+    /// cranker pays for cranking and settling. Current balances are recorded
+    /// as the totals to conserve.
+    pub fn new(
+        cranker: &'a Actor<'a>,
+        market: &'a Market<'a>,
+        participants: Vec<Participant<'a>>,
+    ) -> Result<Self> {
+        if participants.is_empty() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "market harness needs at least one participant",
+            )));
+        }
+        let mut harness = MarketHarness {
+            cranker,
+            market,
+            participants,
+            base_total: 0,
+            quote_total: 0,
+            next_client_order_id: 1,
+        };
+        let (base_total, quote_total) = harness.token_totals()?;
+        harness.base_total = base_total;
+        harness.quote_total = quote_total;
+        Ok(harness)
+    }
+
+    /// Returns the participants trading through the harness.
+    pub fn participants(&self) -> &[Participant<'a>] {
+        &self.participants
+    }
+
+    /// Applies a single operation. Fails if the transaction fails, including
+    /// when the DEX program rejects it.
+    pub fn apply(&mut self, operation: &Operation) -> Result<()> {
+        match *operation {
+            Operation::NewOrder {
+                participant,
+                side,
+                price,
+                quantity,
+                order_type,
+            } => {
+                let participant = self.participant(participant);
+                let client_order_id = self.next_client_order_id;
+                self.next_client_order_id += 1;
+                self.market.place_order(
+                    participant,
+                    side,
+                    NonZeroU64::new(price.max(1)).unwrap(),
+                    NonZeroU64::new(quantity.max(1)).unwrap(),
+                    order_type,
+                    client_order_id,
+                )
+            }
+            Operation::Cancel { participant, order } => {
+                let participant = self.participant(participant);
+                let orders = participant.open_orders_view()?.orders;
+                if orders.is_empty() {
+                    return Ok(());
+                }
+                let order = orders[order % orders.len()];
+                let side = if order.is_bid { Side::Bid } else { Side::Ask };
+                self.market
+                    .cancel_order(self.cranker, participant, side, order.order_id)
+            }
+            Operation::Crank => self.market.crank_all(self.cranker, CRANK_EVENTS),
+            Operation::Settle { participant } => {
+                let participant = self.participant(participant);
+                self.market.settle_funds(self.cranker, participant)
+            }
+        }
+    }
+
+    /// Applies each operation in turn and checks the invariants after each
+    /// one. Returns the number of operations the DEX program rejected, or
+    /// an error describing the first violated invariant and the operations
+    /// leading up to it.
+    pub fn run(&mut self, operations: &[Operation]) -> Result<usize> {
+        let mut rejected = 0;
+        for (i, operation) in operations.iter().enumerate() {
+            if self.apply(operation).is_err() {
+                // A rejected operation is expected, a dead validator is not.
+                self.cranker.sandbox().check_alive()?;
+                rejected += 1;
+            }
+            let violations = self.violations()?;
+            if !violations.is_empty() {
+                let applied: Vec<String> = operations[..=i]
+                    .iter()
+                    .map(|operation| format!("  {:?}", operation))
+                    .collect();
+                return Err(Error::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "invariants violated after operation {}:\n{}\noperations:\n{}",
+                        i,
+                        violations.join("\n"),
+                        applied.join("\n")
+                    ),
+                )));
+            }
+        }
+        Ok(rejected)
+    }
+
+    /// Fails with every violated invariant, if any.
+    pub fn check(&self) -> Result<()> {
+        let violations = self.violations()?;
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(Error::from(io::Error::new(
+            io::ErrorKind::Other,
+            format!("invariants violated:\n{}", violations.join("\n")),
+        )))
+    }
+
+    /// Returns a description of every violated invariant.
+    pub fn violations(&self) -> Result<Vec<String>> {
+        let mut violations = Vec::new();
+
+        let (base_total, quote_total) = self.token_totals()?;
+        if base_total != self.base_total {
+            violations.push(format!(
+                "base not conserved: expected {}, got {}",
+                self.base_total, base_total
+            ));
+        }
+        if quote_total != self.quote_total {
+            violations.push(format!(
+                "quote not conserved: expected {}, got {}",
+                self.quote_total, quote_total
+            ));
+        }
+
+        let open_orders = self
+            .participants
+            .iter()
+            .map(|participant| participant.open_orders_view())
+            .collect::<Result<Vec<OpenOrdersView>>>()?;
+        for (participant, view) in self.participants.iter().zip(open_orders.iter()) {
+            let address = participant.open_orders().pubkey();
+            if view.native_coin_free > view.native_coin_total {
+                violations.push(format!(
+                    "open orders {} has {} base free of {} total",
+                    address, view.native_coin_free, view.native_coin_total
+                ));
+            }
+            if view.native_pc_free > view.native_pc_total {
+                violations.push(format!(
+                    "open orders {} has {} quote free of {} total",
+                    address, view.native_pc_free, view.native_pc_total
+                ));
+            }
+        }
+
+        // Fills credit the taker immediately and the maker when the event is
+        // consumed, so vaults only cover every open orders account once the
+        // event queue is empty.
+        if self.market.event_queue_len()? == 0 {
+            let owed_base: u64 = open_orders.iter().map(|view| view.native_coin_total).sum();
+            let owed_quote: u64 = open_orders.iter().map(|view| view.native_pc_total).sum();
+            let vault_base = self.market.base_vault().get_account_info()?.amount;
+            let vault_quote = self.market.quote_vault().get_account_info()?.amount;
+            if vault_base < owed_base {
+                violations.push(format!(
+                    "base vault holds {} but open orders are owed {}",
+                    vault_base, owed_base
+                ));
+            }
+            if vault_quote < owed_quote {
+                violations.push(format!(
+                    "quote vault holds {} but open orders are owed {}",
+                    vault_quote, owed_quote
+                ));
+            }
+        }
+
+        violations.extend(self.book_violations(&open_orders)?);
+        Ok(violations)
+    }
+
+    fn book_violations(&self, open_orders: &[OpenOrdersView]) -> Result<Vec<String>> {
+        let mut violations = Vec::new();
+        let mut best_bid = None;
+        let mut best_ask = None;
+        for side in [Side::Bid, Side::Ask] {
+            for node in self.market.book_nodes(side)? {
+                let (key, owner, quantity) = match node {
+                    SlabNodeView::Leaf {
+                        key,
+                        owner,
+                        quantity,
+                        ..
+                    } => (key, owner, quantity),
+                    _ => continue,
+                };
+                let price = (key >> 64) as u64;
+                match side {
+                    Side::Bid => best_bid = best_bid.max(Some(price)),
+                    Side::Ask => best_ask = Some(best_ask.map_or(price, |ask: u64| ask.min(price))),
+                }
+                if quantity == 0 {
+                    violations.push(format!("{:?} order {} has no quantity", side, key));
+                }
+
+                let listed = self
+                    .participants
+                    .iter()
+                    .zip(open_orders.iter())
+                    .find(|(participant, _)| *participant.open_orders().pubkey() == owner)
+                    .map(|(_, view)| {
+                        view.orders.iter().any(|order| {
+                            order.order_id == key && order.is_bid == matches!(side, Side::Bid)
+                        })
+                    });
+                if listed == Some(false) {
+                    violations.push(format!(
+                        "{:?} order {} is not listed in open orders {}",
+                        side, key, owner
+                    ));
+                }
+            }
+        }
+        if let (Some(bid), Some(ask)) = (best_bid, best_ask) {
+            if bid >= ask {
+                violations.push(format!(
+                    "book is crossed: best bid {} >= best ask {}",
+                    bid, ask
+                ));
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Sums the base and quote held by the participants' wallets and the
+    /// market vaults.
+    fn token_totals(&self) -> Result<(u64, u64)> {
+        let mut base = self.market.base_vault().get_account_info()?.amount;
+        let mut quote = self.market.quote_vault().get_account_info()?.amount;
+        let sandbox = self.cranker.sandbox();
+        for participant in self.participants.iter() {
+            let wallets = [participant.base(), participant.quote()]
+                .map(|wallet| TokenAccount::from_pubkey(sandbox, wallet.pubkey()));
+            base += wallets[0].get_account_info()?.amount;
+            quote += wallets[1].get_account_info()?.amount;
+        }
+        Ok((base, quote))
+    }
+
+    fn participant(&self, index: usize) -> &Participant<'a> {
+        &self.participants[index % self.participants.len()]
+    }
+}
+
+/// Generates count random operations from a Sandbox's seeded generator, so
+/// that a failing sequence can be replayed with the same seed. Prices and
+/// quantities are drawn from [1, max_price] and [1, max_quantity].
+pub fn random_operations(
+    rng: &mut SandboxRng,
+    count: usize,
+    max_price: u64,
+    max_quantity: u64,
+) -> Vec<Operation> {
+    (0..count)
+        .map(|_| {
+            let participant = rng.gen_range(0, u64::MAX) as usize;
+            match rng.gen_range(0, 8) {
+                0..=3 => Operation::NewOrder {
+                    participant,
+                    side: if rng.gen_bool(0.5) {
+                        Side::Bid
+                    } else {
+                        Side::Ask
+                    },
+                    price: rng.gen_range(1, max_price.max(1) + 1),
+                    quantity: rng.gen_range(1, max_quantity.max(1) + 1),
+                    order_type: match rng.gen_range(0, 3) {
+                        0 => OrderType::Limit,
+                        1 => OrderType::ImmediateOrCancel,
+                        _ => OrderType::PostOnly,
+                    },
+                },
+                4 | 5 => Operation::Cancel {
+                    participant,
+                    order: rng.gen_range(0, u64::MAX) as usize,
+                },
+                6 => Operation::Crank,
+                _ => Operation::Settle { participant },
+            }
+        })
+        .collect()
+}
+
+/// Returns a proptest strategy generating a single operation. Prices and
+/// quantities are drawn from [1, max_price] and [1, max_quantity].
+#[cfg(feature = "proptest")]
+pub fn operation(
+    max_price: u64,
+    max_quantity: u64,
+) -> impl proptest::strategy::Strategy<Value = Operation> {
+    use proptest::prelude::*;
+
+    let side = prop_oneof![Just(Side::Bid), Just(Side::Ask)];
+    let order_type = prop_oneof![
+        Just(OrderType::Limit),
+        Just(OrderType::ImmediateOrCancel),
+        Just(OrderType::PostOnly),
+    ];
+    prop_oneof![
+        4 => (any::<usize>(), side, 1..=max_price.max(1), 1..=max_quantity.max(1), order_type)
+            .prop_map(|(participant, side, price, quantity, order_type)| {
+                Operation::NewOrder {
+                    participant,
+                    side,
+                    price,
+                    quantity,
+                    order_type,
+                }
+            }),
+        2 => (any::<usize>(), any::<usize>())
+            .prop_map(|(participant, order)| Operation::Cancel { participant, order }),
+        1 => Just(Operation::Crank),
+        1 => any::<usize>().prop_map(|participant| Operation::Settle { participant }),
+    ]
+}
+
+/// Returns a proptest strategy generating sequences of up to max_len
+/// operations.
+#[cfg(feature = "proptest")]
+pub fn operations(
+    max_price: u64,
+    max_quantity: u64,
+    max_len: usize,
+) -> impl proptest::strategy::Strategy<Value = Vec<Operation>> {
+    proptest::collection::vec(operation(max_price, max_quantity), 1..=max_len.max(1))
+}
@@ -0,0 +1,170 @@
+use crate::errors::Result;
+use crate::recorder::MarketRecorder;
+use crate::serum::{Market, Participant, ParticipantFill, PortfolioSnapshot};
+
+/// Tracks a market maker's net position, cash, and profit and loss across
+/// its fills on a Serum market.
+///
+/// Position and cash are in native base and quote units, starting from zero
+/// when the inventory is created. Profit is measured against the average
+/// price of the open position: fills that reduce the position realize the
+/// difference between their price and the average, while the rest of the
+/// position is marked to a reference price by unrealized_pnl. Fees paid and
+/// rebates received count towards profit.
+///
+/// Fills are read from the event queue, so update must be called before
+/// they are consumed, or a MarketRecorder must be running and
+/// update_with_history used instead:
+///
+/// ```ignore
+/// let mut inventory = Inventory::new(&market, &maker)?;
+/// // ... trade ...
+/// inventory.update()?;
+/// market.crank_all(&cranker, 10)?;
+/// assert_eq!(inventory.position(), 1_500);
+/// assert!(inventory.unrealized_pnl(21)? > 0);
+/// ```
+pub struct Inventory<'a> {
+    market: &'a Market<'a>,
+    participant: &'a Participant<'a>,
+    start: PortfolioSnapshot,
+    last_seq_num: Option<u64>,
+    fills: usize,
+    position: i128,
+    cost_basis: i128,
+    cash: i128,
+    realized_pnl: i128,
+}
+
+impl<'a> Inventory<'a> {
+    /// Starts tracking a participant with a flat position. Fills already in
+    /// the event queue are ignored.
+    pub fn new(market: &'a Market<'a>, participant: &'a Participant<'a>) -> Result<Self> {
+        let last_seq_num = market
+            .load_fills_for(participant)?
+            .last()
+            .map(|fill| fill.seq_num);
+        Ok(Inventory {
+            market,
+            participant,
+            start: participant.snapshot()?,
+            last_seq_num,
+            fills: 0,
+            position: 0,
+            cost_basis: 0,
+            cash: 0,
+            realized_pnl: 0,
+        })
+    }
+
+    /// Applies the participant's fills in the event queue that have not been
+    /// applied yet. Returns the number of fills applied.
+    pub fn update(&mut self) -> Result<usize> {
+        let fills = self.market.load_fills_for(self.participant)?;
+        Ok(self.apply_new(&fills))
+    }
+
+    /// Applies the participant's fills recorded by a MarketRecorder or still
+    /// in the event queue that have not been applied yet. Returns the number
+    /// of fills applied.
+    pub fn update_with_history(&mut self, recorder: &MarketRecorder) -> Result<usize> {
+        let fills = self
+            .market
+            .load_fills_with_history(self.participant, recorder)?;
+        Ok(self.apply_new(&fills))
+    }
+
+    fn apply_new(&mut self, fills: &[ParticipantFill]) -> usize {
+        let mut applied = 0;
+        for fill in fills.iter() {
+            if self.last_seq_num.map_or(true, |last| fill.seq_num > last) {
+                self.apply(fill);
+                self.last_seq_num = Some(fill.seq_num);
+                applied += 1;
+            }
+        }
+        applied
+    }
+
+    /// Applies a single fill, regardless of whether it was applied before.
+    pub fn apply(&mut self, fill: &ParticipantFill) {
+        let base = fill.base_change();
+        let quote = fill.quote_change();
+        self.fills += 1;
+        self.cash += quote;
+
+        // The part of the fill that reduces the position realizes profit
+        // against the average price; the rest opens a position the other way.
+        let closed = if self.position.signum() == -base.signum() {
+            base.abs().min(self.position.abs())
+        } else {
+            0
+        };
+        if closed > 0 {
+            let released = self.cost_basis * closed / self.position.abs();
+            let proceeds = quote * closed / base.abs();
+            self.realized_pnl += proceeds - released;
+            self.cost_basis -= released;
+            self.position += closed * base.signum();
+        }
+        let opened = base.abs() - closed;
+        if opened > 0 {
+            self.cost_basis -= quote * opened / base.abs();
+            self.position += opened * base.signum();
+        }
+    }
+
+    /// Returns the net base bought, in native units. Negative when short.
+    pub fn position(&self) -> i128 {
+        self.position
+    }
+
+    /// Returns the net quote received, in native units, including fees and
+    /// rebates.
+    pub fn cash(&self) -> i128 {
+        self.cash
+    }
+
+    /// Returns the quote paid for the open position, or received for it when
+    /// short, in native units.
+    pub fn cost_basis(&self) -> i128 {
+        self.cost_basis
+    }
+
+    /// Returns the number of fills applied.
+    pub fn fills(&self) -> usize {
+        self.fills
+    }
+
+    /// Returns the profit locked in by reducing the position, in native
+    /// quote units.
+    pub fn realized_pnl(&self) -> i128 {
+        self.realized_pnl
+    }
+
+    /// Returns the profit the open position would make if closed at the
+    /// given price, in quote lots per base lot, without fees. The result is
+    /// in native quote units.
+    pub fn unrealized_pnl(&self, oracle_price: u64) -> Result<i128> {
+        let view = self.market.view()?;
+        let value = self.position * oracle_price as i128 * view.pc_lot_size as i128
+            / view.coin_lot_size.max(1) as i128;
+        Ok(value - self.cost_basis)
+    }
+
+    /// Returns the change in the participant's base and quote since the
+    /// inventory was created, summing the wallets and the open orders
+    /// account. Once every fill is consumed, this matches position and cash
+    /// unless tokens were moved by other means, such as top ups.
+    pub fn balance_changes(&self) -> Result<(i128, i128)> {
+        let total = |snapshot: &PortfolioSnapshot| {
+            (
+                (snapshot.base + snapshot.base_locked + snapshot.base_unsettled) as i128,
+                (snapshot.quote + snapshot.quote_locked + snapshot.quote_unsettled) as i128,
+            )
+        };
+        let (base_before, quote_before) = total(&self.start);
+        let (base_after, quote_after) = total(&self.participant.snapshot()?);
+        Ok((base_after - base_before, quote_after - quote_before))
+    }
+}
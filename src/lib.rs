@@ -1,6 +1,50 @@
 pub mod actor;
+pub mod anchor;
+pub mod audit;
+pub mod bench;
+pub mod cache;
+pub mod cluster;
+pub mod crank;
+pub mod decoder;
+pub mod dump;
 pub mod errors;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod filter;
+pub mod fixtures;
+pub mod fork;
+pub mod funding;
+pub mod harness;
+pub mod inventory;
+pub mod logging;
+pub mod matching;
+pub mod multisig;
+pub mod oracle;
+pub mod pda;
+pub mod performance;
+pub mod profile;
+pub mod proxy;
 pub mod pyth;
+pub mod ratelimit;
+pub mod recorder;
+pub mod replay;
+pub mod retry;
+pub mod rng;
 pub mod sandbox;
+pub mod scenario;
+pub mod scheduler;
 pub mod serum;
+pub mod services;
+pub mod session;
+pub mod signer;
+pub mod snapshot;
+pub mod swap;
 pub mod token;
+pub mod token_faucet;
+pub mod trace;
+pub mod trader;
+pub mod transaction;
+pub mod versioned;
+pub mod views;
+
+pub use logging::init_test_logging;
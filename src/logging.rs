@@ -0,0 +1,30 @@
+use tracing_subscriber::EnvFilter;
+
+/// Filter used when RUST_LOG is not set.
+const DEFAULT_FILTER: &str = "warn";
+
+/// Routes solarium's tracing output to the test harness, filtered by the
+/// RUST_LOG environment variable, for example RUST_LOG=solarium=debug to see
+/// every transaction and market operation. Without RUST_LOG only warnings
+/// and errors are shown.
+///
+/// Output goes through the test writer, so it is captured per test and only
+/// shown for failing tests unless --nocapture is given. Calling this more
+/// than once, or after another subscriber was installed, has no effect.
+///
+/// ```ignore
+/// #[test]
+/// fn integration() {
+///     solarium::init_test_logging();
+///     let sandbox = Sandbox::new().unwrap();
+/// }
+/// ```
+pub fn init_test_logging() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_test_writer()
+        .try_init()
+        .unwrap_or(());
+}
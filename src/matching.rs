@@ -0,0 +1,293 @@
+use crate::views::{pubkey_string, u128_string, SlabNodeView};
+use serde::{Deserialize, Serialize};
+use serum_dex::{
+    fees::FeeTier,
+    matching::{OrderType, Side},
+};
+use solana_sdk::pubkey::Pubkey;
+
+/// A resting order on a Serum order book. Prices are in quote lots per base
+/// lot and quantities in base lots. Serializes the order id as a decimal
+/// string and the owner as a base58 string.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BookOrder {
+    #[serde(with = "u128_string")]
+    pub order_id: u128,
+    pub price: u64,
+    pub quantity: u64,
+    /// Open orders account that placed the order.
+    #[serde(with = "pubkey_string")]
+    pub owner: Pubkey,
+    pub client_order_id: u64,
+    pub fee_tier: u8,
+}
+
+/// Both sides of a Serum order book at a point in time, best orders first.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BookSnapshot {
+    /// Native base units per base lot.
+    pub base_lot_size: u64,
+    /// Native quote units per quote lot.
+    pub quote_lot_size: u64,
+    /// Bids from the highest price down. Orders at the same price are in
+    /// time priority.
+    pub bids: Vec<BookOrder>,
+    /// Asks from the lowest price up. Orders at the same price are in time
+    /// priority.
+    pub asks: Vec<BookOrder>,
+}
+
+impl BookSnapshot {
+    /// Builds a snapshot from the nodes of the bids and asks slabs, as
+    /// returned by Market::book_nodes.
+    pub fn from_nodes(
+        base_lot_size: u64,
+        quote_lot_size: u64,
+        bids: &[SlabNodeView],
+        asks: &[SlabNodeView],
+    ) -> Self {
+        // Order ids hold the price in their upper 64 bits and a sequence
+        // number below it, inverted for bids, so sorting by id yields price
+        // then time priority.
+        let mut bids = leaves(bids);
+        bids.sort_by(|a, b| b.order_id.cmp(&a.order_id));
+        let mut asks = leaves(asks);
+        asks.sort_by(|a, b| a.order_id.cmp(&b.order_id));
+        BookSnapshot {
+            base_lot_size,
+            quote_lot_size,
+            bids,
+            asks,
+        }
+    }
+
+    /// Aggregates up to n_levels prices of one side, best first.
+    pub fn levels(&self, side: Side, n_levels: usize) -> Vec<PriceLevel> {
+        let orders = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let mut levels: Vec<PriceLevel> = Vec::new();
+        for order in orders.iter() {
+            if let Some(level) = levels.last_mut() {
+                if level.price == order.price {
+                    level.quantity += order.quantity;
+                    level.orders += 1;
+                    continue;
+                }
+            }
+            if levels.len() == n_levels {
+                break;
+            }
+            levels.push(PriceLevel {
+                price: order.price,
+                quantity: order.quantity,
+                orders: 1,
+            });
+        }
+        levels
+    }
+
+    /// Returns the highest bid level, if any.
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.levels(Side::Bid, 1).pop()
+    }
+
+    /// Returns the lowest ask level, if any.
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.levels(Side::Ask, 1).pop()
+    }
+
+    /// Aggregates up to n_levels prices of each side.
+    pub fn depth(&self, n_levels: usize) -> Depth {
+        Depth {
+            bids: self.levels(Side::Bid, n_levels),
+            asks: self.levels(Side::Ask, n_levels),
+        }
+    }
+}
+
+/// The resting orders at a single price. Prices are in quote lots per base
+/// lot and quantities in base lots.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub price: u64,
+    /// Total quantity of the orders at the price.
+    pub quantity: u64,
+    /// Number of orders at the price.
+    pub orders: usize,
+}
+
+/// The best price levels of both sides of an order book.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Depth {
+    /// Bids from the highest price down.
+    pub bids: Vec<PriceLevel>,
+    /// Asks from the lowest price up.
+    pub asks: Vec<PriceLevel>,
+}
+
+impl Depth {
+    /// Returns the difference between the best ask and best bid prices, if
+    /// both sides have orders.
+    pub fn spread(&self) -> Option<u64> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => Some(ask.price.saturating_sub(bid.price)),
+            _ => None,
+        }
+    }
+}
+
+fn leaves(nodes: &[SlabNodeView]) -> Vec<BookOrder> {
+    nodes
+        .iter()
+        .filter_map(|node| match node {
+            SlabNodeView::Leaf {
+                fee_tier,
+                key,
+                owner,
+                quantity,
+                client_order_id,
+                ..
+            } => Some(BookOrder {
+                order_id: *key,
+                price: (key >> 64) as u64,
+                quantity: *quantity,
+                owner: *owner,
+                client_order_id: *client_order_id,
+                fee_tier: *fee_tier,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// An order about to be placed, with the same units as Market::new_order:
+/// price in quote lots per base lot, quantity in base lots, and the quote
+/// budget in native units including fees.
+#[derive(Clone, Copy, Debug)]
+pub struct IncomingOrder {
+    pub side: Side,
+    pub limit_price: u64,
+    pub max_base_qty: u64,
+    pub max_native_quote_including_fees: u64,
+    pub order_type: OrderType,
+    /// Fee tier of the taker, Base unless it holds SRM or MSRM.
+    pub fee_tier: FeeTier,
+}
+
+/// A fill the incoming order is expected to produce against one resting
+/// order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExpectedFill {
+    /// Open orders account of the resting order.
+    pub maker: Pubkey,
+    pub maker_order_id: u128,
+    /// Price in quote lots per base lot.
+    pub price: u64,
+    pub base_lots: u64,
+    pub native_base: u64,
+    /// Quote exchanged before fees.
+    pub native_quote: u64,
+    /// The fill's share of the taker fee, in native quote units. Serum
+    /// charges the fee once on the order's total quote, so the shares of an
+    /// order's fills add up to the fee on their summed quote.
+    pub taker_fee: u64,
+    /// Rebate credited to the maker, in native quote units.
+    pub maker_rebate: u64,
+}
+
+/// Computes the fills Serum's matching engine produces for an incoming order
+/// against a book snapshot: price-time priority, whole lots, and the taker's
+/// and makers' fee tiers. Self-trade handling is not modelled, so the order
+/// should not cross orders of the same open orders account.
+///
+/// ```ignore
+/// let book = market.book_snapshot()?;
+/// let fills = expected_fills(&book, &order);
+/// let (base, quote) = taker_changes(Side::Bid, &fills);
+/// ```
+pub fn expected_fills(book: &BookSnapshot, order: &IncomingOrder) -> Vec<ExpectedFill> {
+    let opposite = match order.side {
+        Side::Bid => &book.asks,
+        Side::Ask => &book.bids,
+    };
+    let crosses = |price: u64| match order.side {
+        Side::Bid => price <= order.limit_price,
+        Side::Ask => price >= order.limit_price,
+    };
+    if let OrderType::PostOnly = order.order_type {
+        // Crossing post only orders are rejected rather than matched.
+        return Vec::new();
+    }
+
+    // Bids are limited by their quote budget net of the taker fee.
+    let mut quote_lots_left = order
+        .fee_tier
+        .remove_taker_fee(order.max_native_quote_including_fees)
+        / book.quote_lot_size.max(1);
+    let mut base_lots_left = order.max_base_qty;
+    let mut total_quote = 0;
+    let mut total_fee = 0;
+
+    let mut fills = Vec::new();
+    for resting in opposite.iter() {
+        if base_lots_left == 0 || !crosses(resting.price) {
+            break;
+        }
+        let mut base_lots = base_lots_left.min(resting.quantity);
+        if let Side::Bid = order.side {
+            base_lots = base_lots.min(quote_lots_left / resting.price.max(1));
+        }
+        if base_lots == 0 {
+            break;
+        }
+
+        let quote_lots = base_lots * resting.price;
+        let native_quote = quote_lots * book.quote_lot_size;
+        let maker_tier = FeeTier::try_from(resting.fee_tier).unwrap_or(FeeTier::Base);
+        total_quote += native_quote;
+        let fee = order.fee_tier.taker_fee(total_quote);
+        fills.push(ExpectedFill {
+            maker: resting.owner,
+            maker_order_id: resting.order_id,
+            price: resting.price,
+            base_lots,
+            native_base: base_lots * book.base_lot_size,
+            native_quote,
+            taker_fee: fee - total_fee,
+            maker_rebate: maker_tier.maker_rebate(native_quote),
+        });
+        total_fee = fee;
+        base_lots_left -= base_lots;
+        quote_lots_left = quote_lots_left.saturating_sub(quote_lots);
+    }
+    fills
+}
+
+/// Returns the taker's change in base and quote, in native units, once the
+/// fills are settled. Bids pay quote including fees; asks receive quote net
+/// of fees.
+pub fn taker_changes(side: Side, fills: &[ExpectedFill]) -> (i128, i128) {
+    let base: i128 = fills.iter().map(|fill| fill.native_base as i128).sum();
+    let quote: i128 = fills.iter().map(|fill| fill.native_quote as i128).sum();
+    let fees: i128 = fills.iter().map(|fill| fill.taker_fee as i128).sum();
+    match side {
+        Side::Bid => (base, -(quote + fees)),
+        Side::Ask => (-base, quote - fees),
+    }
+}
+
+/// Returns a maker's change in base and quote, in native units, once the
+/// fills against its orders are settled. The maker's side is the opposite
+/// of the taker's.
+pub fn maker_changes(taker_side: Side, maker: &Pubkey, fills: &[ExpectedFill]) -> (i128, i128) {
+    let fills: Vec<&ExpectedFill> = fills.iter().filter(|fill| fill.maker == *maker).collect();
+    let base: i128 = fills.iter().map(|fill| fill.native_base as i128).sum();
+    let quote: i128 = fills.iter().map(|fill| fill.native_quote as i128).sum();
+    let rebates: i128 = fills.iter().map(|fill| fill.maker_rebate as i128).sum();
+    match taker_side {
+        Side::Bid => (-base, quote + rebates),
+        Side::Ask => (base, -quote + rebates),
+    }
+}
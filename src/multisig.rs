@@ -0,0 +1,266 @@
+use crate::actor::Actor;
+use crate::errors::{Error, Result};
+use crate::sandbox::Sandbox;
+use crate::token::{Mint, TokenAccount};
+use solana_program::program_pack::Pack;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use spl_token::{
+    self,
+    instruction::{self as spl_instruction, AuthorityType},
+    state as spl_state,
+};
+use std::io;
+
+/// Represents an m-of-n spl_token multisig account.
+///
+/// A multisig can be used wherever spl_token expects an owner or authority,
+/// for example as a mint authority or a token account owner. Instructions
+/// authorized by the multisig must be signed by at least threshold members;
+/// the helpers below take the approving members, check that they are enough,
+/// and add their keypairs as signers.
+pub struct Multisig<'a> {
+    sandbox: &'a Sandbox,
+    account: Actor<'a>,
+    members: Vec<Pubkey>,
+    threshold: u8,
+}
+
+impl<'a> Multisig<'a> {
+    /// Creates a multisig requiring threshold of the given members. The payer
+    /// funds the account.
+    pub fn new(
+        sandbox: &'a Sandbox,
+        payer: &Actor,
+        members: &[&Actor],
+        threshold: u8,
+    ) -> Result<Self> {
+        if threshold == 0
+            || threshold as usize > members.len()
+            || members.len() > spl_token::instruction::MAX_SIGNERS
+        {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid {}-of-{} multisig, at most {} members are supported",
+                    threshold,
+                    members.len(),
+                    spl_token::instruction::MAX_SIGNERS
+                ),
+            )));
+        }
+
+        let account = Actor::new(sandbox)?;
+        let member_pubkeys: Vec<&Pubkey> = members.iter().map(|member| member.pubkey()).collect();
+
+        let create_account =
+            payer.create_account(account.pubkey(), spl_state::Multisig::LEN, &spl_token::id())?;
+        let initialize_multisig = spl_instruction::initialize_multisig(
+            &spl_token::id(),
+            account.pubkey(),
+            &member_pubkeys,
+            threshold,
+        )?;
+
+        sandbox
+            .transaction()
+            .instruction(create_account)
+            .instruction(initialize_multisig)
+            .payer(payer.try_keypair()?)
+            .signer(account.try_keypair()?)
+            .send()?;
+
+        Ok(Multisig {
+            sandbox,
+            account,
+            members: members.iter().map(|member| *member.pubkey()).collect(),
+            threshold,
+        })
+    }
+
+    /// Returns the Actor representing the multisig account. Its public key is
+    /// the one to use as an authority or owner.
+    pub fn account(&self) -> &Actor {
+        &self.account
+    }
+
+    /// Returns the public key of the multisig account.
+    pub fn pubkey(&self) -> &Pubkey {
+        self.account.pubkey()
+    }
+
+    /// Returns the public keys of the members of the multisig.
+    pub fn members(&self) -> &[Pubkey] {
+        &self.members
+    }
+
+    /// Returns the number of members required to sign.
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Returns the keypairs of the approving members, failing if any of them
+    /// is not a member or if fewer than threshold distinct members approve.
+    pub fn signers<'b>(&self, approvers: &[&'b Actor]) -> Result<Vec<&'b Keypair>> {
+        self.approvals(approvers)?
+            .iter()
+            .map(|approver| approver.try_keypair())
+            .collect()
+    }
+
+    /// Deduplicates the approving members and checks them against the
+    /// multisig.
+    fn approvals<'b, 'c>(&self, approvers: &[&'b Actor<'c>]) -> Result<Vec<&'b Actor<'c>>> {
+        let mut approvals: Vec<&'b Actor<'c>> = Vec::new();
+        for approver in approvers.iter() {
+            if !self.members.contains(approver.pubkey()) {
+                return Err(Error::from(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is not a member of the multisig", approver.pubkey()),
+                )));
+            }
+            if !approvals
+                .iter()
+                .any(|approval| approval.pubkey() == approver.pubkey())
+            {
+                approvals.push(approver);
+            }
+        }
+
+        if approvals.len() < self.threshold as usize {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} approvals given but the multisig requires {}",
+                    approvals.len(),
+                    self.threshold
+                ),
+            )));
+        }
+        Ok(approvals)
+    }
+
+    /// Mints tokens from a mint whose authority is this multisig.
+    pub fn mint_to(
+        &self,
+        payer: &Actor,
+        mint: &Mint,
+        destination: &TokenAccount,
+        amount: u64,
+        approvers: &[&Actor],
+    ) -> Result<()> {
+        let approvals = self.approvals(approvers)?;
+        let signer_pubkeys: Vec<&Pubkey> = approvals.iter().map(|a| a.pubkey()).collect();
+        let signers = approvals
+            .iter()
+            .map(|a| a.try_keypair())
+            .collect::<Result<Vec<&Keypair>>>()?;
+        let instruction = spl_instruction::mint_to(
+            &spl_token::id(),
+            mint.actor().pubkey(),
+            destination.account().pubkey(),
+            self.pubkey(),
+            &signer_pubkeys,
+            amount,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(payer.try_keypair()?)
+            .signers(&signers)
+            .send()?;
+        Ok(())
+    }
+
+    /// Transfers tokens out of a token account owned by this multisig.
+    pub fn transfer(
+        &self,
+        payer: &Actor,
+        source: &TokenAccount,
+        destination: &TokenAccount,
+        amount: u64,
+        approvers: &[&Actor],
+    ) -> Result<()> {
+        let approvals = self.approvals(approvers)?;
+        let signer_pubkeys: Vec<&Pubkey> = approvals.iter().map(|a| a.pubkey()).collect();
+        let signers = approvals
+            .iter()
+            .map(|a| a.try_keypair())
+            .collect::<Result<Vec<&Keypair>>>()?;
+        let instruction = spl_instruction::transfer(
+            &spl_token::id(),
+            source.account().pubkey(),
+            destination.account().pubkey(),
+            self.pubkey(),
+            &signer_pubkeys,
+            amount,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(payer.try_keypair()?)
+            .signers(&signers)
+            .send()?;
+        Ok(())
+    }
+
+    /// Rotates an authority held by this multisig over a mint or token
+    /// account to a new authority, or removes it if new_authority is None.
+    pub fn set_authority(
+        &self,
+        payer: &Actor,
+        target: &Pubkey,
+        authority_type: AuthorityType,
+        new_authority: Option<&Pubkey>,
+        approvers: &[&Actor],
+    ) -> Result<()> {
+        let approvals = self.approvals(approvers)?;
+        let signer_pubkeys: Vec<&Pubkey> = approvals.iter().map(|a| a.pubkey()).collect();
+        let signers = approvals
+            .iter()
+            .map(|a| a.try_keypair())
+            .collect::<Result<Vec<&Keypair>>>()?;
+        let instruction = spl_instruction::set_authority(
+            &spl_token::id(),
+            target,
+            new_authority,
+            authority_type,
+            self.pubkey(),
+            &signer_pubkeys,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(payer.try_keypair()?)
+            .signers(&signers)
+            .send()?;
+        Ok(())
+    }
+
+    /// Hands an authority currently held by a single actor over to this
+    /// multisig.
+    pub fn take_authority(
+        &self,
+        current_authority: &Actor,
+        target: &Pubkey,
+        authority_type: AuthorityType,
+    ) -> Result<()> {
+        let instruction = spl_instruction::set_authority(
+            &spl_token::id(),
+            target,
+            Some(self.pubkey()),
+            authority_type,
+            current_authority.pubkey(),
+            &[],
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(current_authority.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::errors::Result;
+use crate::rng::SandboxRng;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+/// The trading status of a price feed, common to the simulated oracles.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OracleStatus {
+    /// The feed is not updating for an unknown reason.
+    Unknown,
+    /// The feed is updating as expected.
+    Trading,
+    /// The feed is not updating because trading has been halted.
+    Halted,
+    /// The feed is not updating because an auction is setting the price.
+    Auction,
+}
+
+/// A simulated price feed that a program under test reads prices from.
+///
+/// Scenario utilities such as scripted feeds and market makers can be
+/// written once against this trait and run against any simulator:
+///
+/// ```ignore
+/// fn replay<O: Oracle>(oracle: &O, path: &[(i64, u64)]) -> Result<()> {
+///     for (price, conf) in path.iter() {
+///         oracle.publish(*price, *conf, -2)?;
+///     }
+///     oracle.set_status(OracleStatus::Halted)
+/// }
+/// ```
+pub trait Oracle {
+    /// Returns the address of the account holding the price.
+    fn pubkey(&self) -> &Pubkey;
+
+    /// Publishes a price and confidence interval, both scaled by
+    /// 10^expo.
+    fn publish(&self, price: i64, conf: u64, expo: i32) -> Result<()>;
+
+    /// Sets the trading status of the feed.
+    fn set_status(&self, status: OracleStatus) -> Result<()>;
+}
+
+/// Follows a price that takes bounded random steps and quotes a fixed spread
+/// around it, as a market maker pegged to an oracle does. Prices are in
+/// quote lots per base lot.
+///
+/// The price never falls below spread + 1, so that the bid stays positive.
+///
+/// ```ignore
+/// let mut quoter = PeggedQuoter::new(100, 1, 2);
+/// loop {
+///     price_account.publish_price(pyth, &publisher, quoter.price() as i64, 0)?;
+///     let (bid, ask) = quoter.quotes();
+///     // ... requote at bid and ask ...
+///     quoter.random_step(&mut sandbox.rng());
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeggedQuoter {
+    price: u64,
+    spread: u64,
+    max_step: u64,
+}
+
+impl PeggedQuoter {
+    /// Starts at the given price, raised to the floor if below it. Each step
+    /// moves the price by at most max_step.
+    pub fn new(start_price: u64, spread: u64, max_step: u64) -> Self {
+        let mut quoter = PeggedQuoter {
+            price: start_price,
+            spread,
+            max_step,
+        };
+        quoter.price = quoter.price.max(quoter.floor());
+        quoter
+    }
+
+    /// Returns the current price.
+    pub fn price(&self) -> u64 {
+        self.price
+    }
+
+    /// Returns the lowest price the quoter follows.
+    pub fn floor(&self) -> u64 {
+        self.spread.saturating_add(1)
+    }
+
+    /// Returns the bid and ask prices, spread below and above the price.
+    pub fn quotes(&self) -> (u64, u64) {
+        (
+            self.price - self.spread,
+            self.price.saturating_add(self.spread),
+        )
+    }
+
+    /// Moves the price by step - max_step, so that steps from 0 to
+    /// 2 * max_step cover every move from -max_step to +max_step. Larger
+    /// steps are clamped. Returns the new price.
+    pub fn step(&mut self, step: u64) -> u64 {
+        let step = step.min(self.max_step.saturating_mul(2));
+        self.price = self
+            .price
+            .saturating_add(step)
+            .saturating_sub(self.max_step)
+            .max(self.floor());
+        self.price
+    }
+
+    /// Moves the price by a uniformly random step drawn from rng. Returns
+    /// the new price.
+    pub fn random_step(&mut self, rng: &mut SandboxRng) -> u64 {
+        let step = rng.gen_range(0, self.max_step.saturating_mul(2).saturating_add(1));
+        self.step(step)
+    }
+}
@@ -0,0 +1,121 @@
+use crate::actor::Actor;
+use crate::errors::Result;
+use crate::sandbox::Sandbox;
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey};
+
+/// Finds the program derived address for the given seeds and its bump seed,
+/// searching bumps from 255 down as Pubkey::find_program_address does.
+///
+/// ```ignore
+/// let (vault, bump) = find_pda(&[b"vault", owner.as_ref()], &program_id);
+/// ```
+pub fn find_pda(seeds: &[&[u8]], program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(seeds, program_id)
+}
+
+/// Returns the vault signer of a Serum market and its nonce. Serum derives
+/// the address from the market key and a u64 nonce counting up from zero,
+/// so find_pda cannot be used.
+pub fn serum_vault_pda(serum: &Pubkey, market: &Pubkey) -> (Pubkey, u64) {
+    let mut nonce: u64 = 0;
+    loop {
+        let seeds = [market.as_ref(), bytemuck::bytes_of(&nonce)];
+        match Pubkey::create_program_address(&seeds, serum) {
+            Ok(key) => return (key, nonce),
+            _ => nonce += 1,
+        }
+    }
+}
+
+/// Returns the mint authority of the SPL token faucet program and its bump
+/// seed. Every faucet of a deployment shares it.
+pub fn token_faucet_pda(program: &Pubkey) -> (Pubkey, u8) {
+    find_pda(&[b"faucet"], program)
+}
+
+/// A program derived account, addressed by its seeds and owning program
+/// rather than by a keypair.
+///
+/// Only the owning program can sign for a PDA, so tests cannot create one
+/// with create_account the way they create keypair accounts. Instead
+/// Actor::create_pda_account funds the address with the rent the program
+/// needs, after which the program's own initialization only has to allocate
+/// and assign it, as Anchor's init and most SPL programs do for prefunded
+/// addresses. A PdaActor can be passed wherever a read-only account
+/// reference is needed through actor or meta:
+///
+/// ```ignore
+/// let vault = payer.create_pda_account(&[b"vault", owner.pubkey().as_ref()], 165, &program)?;
+/// let init = Instruction::new_with_bytes(program, &[0, vault.bump()], vec![vault.meta(true)]);
+/// ```
+pub struct PdaActor<'a> {
+    actor: Actor<'a>,
+    program: Pubkey,
+    seeds: Vec<Vec<u8>>,
+    bump: u8,
+}
+
+impl<'a> PdaActor<'a> {
+    /// Derives the address of the given seeds for the program. Nothing is
+    /// created or funded.
+    pub fn new(sandbox: &'a Sandbox, seeds: &[&[u8]], program: &Pubkey) -> Self {
+        let (pubkey, bump) = find_pda(seeds, program);
+        PdaActor {
+            actor: Actor::from_pubkey(sandbox, &pubkey),
+            program: *program,
+            seeds: seeds.iter().map(|seed| seed.to_vec()).collect(),
+            bump,
+        }
+    }
+
+    /// Returns a watch-only Actor for the address, for APIs that take one.
+    pub fn actor(&self) -> &Actor<'a> {
+        &self.actor
+    }
+
+    pub fn pubkey(&self) -> &Pubkey {
+        self.actor.pubkey()
+    }
+
+    /// Returns the program the address is derived from, which owns the
+    /// account once it is initialized.
+    pub fn program(&self) -> &Pubkey {
+        &self.program
+    }
+
+    /// Returns the seeds the address is derived from, without the bump.
+    pub fn seeds(&self) -> Vec<&[u8]> {
+        self.seeds.iter().map(|seed| seed.as_slice()).collect()
+    }
+
+    /// Returns the bump seed found for the address, which programs usually
+    /// take as an instruction argument or store in the account.
+    pub fn bump(&self) -> u8 {
+        self.bump
+    }
+
+    /// Returns an account reference for an instruction. PDAs never sign
+    /// transactions, so only writability is chosen.
+    pub fn meta(&self, writable: bool) -> AccountMeta {
+        if writable {
+            AccountMeta::new(*self.pubkey(), false)
+        } else {
+            AccountMeta::new_readonly(*self.pubkey(), false)
+        }
+    }
+
+    /// Returns true if the account holds lamports.
+    pub fn exists(&self) -> Result<bool> {
+        let sandbox = self.actor.sandbox();
+        Ok(sandbox
+            .client()
+            .get_account_with_commitment(self.pubkey(), sandbox.client().commitment())?
+            .value
+            .is_some())
+    }
+
+    /// Fetches the account's data.
+    pub fn get_account_data(&self) -> Result<Vec<u8>> {
+        self.actor.sandbox().get_account_data(self.pubkey())
+    }
+}
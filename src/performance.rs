@@ -0,0 +1,159 @@
+use crate::errors::Result;
+use crate::sandbox::Sandbox;
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_response::RpcPerfSample;
+use std::time;
+
+/// Transactions and slots processed by the validator over one sampling
+/// period, as reported by getRecentPerformanceSamples. Counts include the
+/// validator's vote transactions.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PerformanceSample {
+    /// Slot at which the sample was taken.
+    pub slot: u64,
+
+    /// Transactions processed during the period.
+    pub transactions: u64,
+
+    /// Slots produced during the period.
+    pub slots: u64,
+
+    /// Length of the period.
+    pub period: time::Duration,
+}
+
+impl PerformanceSample {
+    /// Returns the mean transactions processed per second.
+    pub fn transactions_per_second(&self) -> f64 {
+        rate(self.transactions, self.period)
+    }
+
+    /// Returns the mean slots produced per second.
+    pub fn slots_per_second(&self) -> f64 {
+        rate(self.slots, self.period)
+    }
+}
+
+impl From<RpcPerfSample> for PerformanceSample {
+    fn from(sample: RpcPerfSample) -> Self {
+        PerformanceSample {
+            slot: sample.slot,
+            transactions: sample.num_transactions,
+            slots: sample.num_slots,
+            period: time::Duration::from_secs(sample.sample_period_secs as u64),
+        }
+    }
+}
+
+/// Measures the validator's throughput over a stretch of a load test, from
+/// its transaction count and slot at the start and end.
+///
+/// The validator only records a performance sample every minute, so this is
+/// the way to measure windows shorter than that:
+///
+/// ```ignore
+/// let meter = PerformanceMeter::start(&sandbox)?;
+/// // ... generate load ...
+/// let report = meter.finish()?;
+/// report.assert_throughput(50.0);
+/// report.assert_slot_rate(1.0);
+/// ```
+pub struct PerformanceMeter<'a> {
+    sandbox: &'a Sandbox,
+    start: time::Instant,
+    slot: u64,
+    transactions: u64,
+}
+
+impl<'a> PerformanceMeter<'a> {
+    /// Reads the validator's counters to start the window.
+    pub fn start(sandbox: &'a Sandbox) -> Result<Self> {
+        let (slot, transactions) = counters(sandbox)?;
+        Ok(PerformanceMeter {
+            sandbox,
+            start: time::Instant::now(),
+            slot,
+            transactions,
+        })
+    }
+
+    /// Reads the validator's counters again and reports the window, which
+    /// can keep running for further reports.
+    pub fn report(&self) -> Result<PerformanceReport> {
+        let (slot, transactions) = counters(self.sandbox)?;
+        Ok(PerformanceReport {
+            elapsed: self.start.elapsed(),
+            slots: slot.saturating_sub(self.slot),
+            transactions: transactions.saturating_sub(self.transactions),
+        })
+    }
+
+    /// Ends the window and reports it.
+    pub fn finish(self) -> Result<PerformanceReport> {
+        self.report()
+    }
+}
+
+/// Transactions and slots processed during a PerformanceMeter's window.
+/// Counts include the validator's vote transactions.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PerformanceReport {
+    /// Wall clock length of the window.
+    pub elapsed: time::Duration,
+
+    /// Slots produced during the window.
+    pub slots: u64,
+
+    /// Transactions processed during the window.
+    pub transactions: u64,
+}
+
+impl PerformanceReport {
+    /// Returns the mean transactions processed per second.
+    pub fn transactions_per_second(&self) -> f64 {
+        rate(self.transactions, self.elapsed)
+    }
+
+    /// Returns the mean slots produced per second.
+    pub fn slots_per_second(&self) -> f64 {
+        rate(self.slots, self.elapsed)
+    }
+
+    /// Panics unless the validator processed at least the given mean number
+    /// of transactions per second.
+    pub fn assert_throughput(&self, min_transactions_per_second: f64) {
+        assert!(
+            self.transactions_per_second() >= min_transactions_per_second,
+            "validator processed {:.1} transactions per second, expected at least {:.1}: {:?}",
+            self.transactions_per_second(),
+            min_transactions_per_second,
+            self
+        );
+    }
+
+    /// Panics unless the validator produced at least the given mean number
+    /// of slots per second, which catches a validator stalled by load.
+    pub fn assert_slot_rate(&self, min_slots_per_second: f64) {
+        assert!(
+            self.slots_per_second() >= min_slots_per_second,
+            "validator produced {:.2} slots per second, expected at least {:.2}: {:?}",
+            self.slots_per_second(),
+            min_slots_per_second,
+            self
+        );
+    }
+}
+
+/// Returns the validator's current slot and transaction count.
+fn counters(sandbox: &Sandbox) -> Result<(u64, u64)> {
+    let slot = sandbox.run_rpc(|| sandbox.client().get_slot())?;
+    let transactions = sandbox.run_rpc(|| sandbox.client().get_transaction_count())?;
+    Ok((slot, transactions))
+}
+
+fn rate(count: u64, period: time::Duration) -> f64 {
+    if period.is_zero() {
+        return 0.0;
+    }
+    count as f64 / period.as_secs_f64()
+}
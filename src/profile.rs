@@ -0,0 +1,235 @@
+use crate::errors::{Error, Result};
+use crate::sandbox::Sandbox;
+use crate::trace;
+use crate::transaction::TransactionBuilder;
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::UiTransactionEncoding;
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+/// A transaction sent while a ComputeProfiler runs: its signature and the
+/// program and data of each top-level instruction.
+#[derive(Clone, Debug)]
+pub(crate) struct ProfiledTransaction {
+    pub(crate) signature: Signature,
+    pub(crate) instructions: Vec<(Pubkey, Vec<u8>)>,
+}
+
+/// Collects the compute units consumed by every transaction a Sandbox
+/// sends, and by simulated transactions, aggregated by program and
+/// instruction:
+///
+/// ```ignore
+/// let profiler = ComputeProfiler::start(&sandbox);
+/// // ... run the scenario ...
+/// let profile = profiler.finish()?;
+/// println!("{}", profile);
+/// let regressions = profile.regressions(&ComputeProfile::load("baseline.json")?, 0.05);
+/// assert!(regressions.is_empty(), "{}", regressions.join("\n"));
+/// ```
+///
+/// Units are read from the program logs, so each top-level instruction is
+/// charged for the programs it invokes. Instructions are named by the
+/// decoder registered for their program, or "unknown". Native programs such
+/// as the system program do not report units and are left out.
+pub struct ComputeProfiler<'a> {
+    sandbox: &'a Sandbox,
+    profile: ComputeProfile,
+}
+
+impl<'a> ComputeProfiler<'a> {
+    /// Starts recording the Sandbox's transactions. A profiler that is
+    /// already running is replaced.
+    pub fn start(sandbox: &'a Sandbox) -> Self {
+        sandbox.start_profile();
+        ComputeProfiler {
+            sandbox,
+            profile: ComputeProfile::default(),
+        }
+    }
+
+    /// Simulates a transaction without sending it and adds its compute units
+    /// to the profile.
+    pub fn simulate(
+        &mut self,
+        transaction: TransactionBuilder,
+    ) -> Result<RpcSimulateTransactionResult> {
+        let instructions = transaction.get_instructions().to_vec();
+        let result = transaction.simulate()?;
+        self.profile.add(
+            self.sandbox,
+            &top_level(&instructions),
+            result.logs.as_deref().unwrap_or_default(),
+        );
+        Ok(result)
+    }
+
+    /// Stops recording and aggregates the compute units of the recorded
+    /// transactions, read from the validator. Transactions that never landed
+    /// are skipped.
+    pub fn finish(mut self) -> Result<ComputeProfile> {
+        let transactions = self.sandbox.stop_profile().unwrap_or_default();
+        for transaction in transactions.iter() {
+            let logs = match self
+                .sandbox
+                .client()
+                .get_transaction(&transaction.signature, UiTransactionEncoding::Json)
+            {
+                Ok(confirmed) => confirmed
+                    .transaction
+                    .meta
+                    .and_then(|meta| meta.log_messages)
+                    .unwrap_or_default(),
+                Err(_) => continue,
+            };
+            self.profile
+                .add(self.sandbox, &transaction.instructions, &logs);
+        }
+        Ok(self.profile)
+    }
+}
+
+/// Compute units consumed by one instruction of one program.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ComputeStats {
+    /// Number of executions measured.
+    pub count: u64,
+    pub total: u64,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl ComputeStats {
+    /// Adds an execution.
+    pub fn record(&mut self, units: u64) {
+        self.min = if self.count == 0 {
+            units
+        } else {
+            self.min.min(units)
+        };
+        self.max = self.max.max(units);
+        self.total += units;
+        self.count += 1;
+    }
+
+    /// Returns the mean units per execution, or zero if none were measured.
+    pub fn mean(&self) -> u64 {
+        self.total.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+/// Compute units aggregated by program and instruction, as collected by a
+/// ComputeProfiler. Profiles can be saved as JSON and compared across runs
+/// to catch regressions.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ComputeProfile {
+    /// Statistics keyed by program name, then instruction name.
+    pub programs: BTreeMap<String, BTreeMap<String, ComputeStats>>,
+}
+
+impl ComputeProfile {
+    /// Returns the statistics of an instruction, if it was measured.
+    pub fn get(&self, program: &str, instruction: &str) -> Option<&ComputeStats> {
+        self.programs.get(program)?.get(instruction)
+    }
+
+    /// Returns the units consumed by every measured instruction.
+    pub fn total(&self) -> u64 {
+        self.programs
+            .values()
+            .flat_map(|instructions| instructions.values())
+            .map(|stats| stats.total)
+            .sum()
+    }
+
+    /// Loads a profile saved with save.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|err| Error::from(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+
+    /// Writes the profile as JSON.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| Error::from(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Describes every instruction whose mean units grew by more than the
+    /// tolerance, a fraction of its mean in the baseline. Instructions
+    /// missing from the baseline are not regressions.
+    pub fn regressions(&self, baseline: &ComputeProfile, tolerance: f64) -> Vec<String> {
+        let mut regressions = Vec::new();
+        for (program, instructions) in self.programs.iter() {
+            for (instruction, stats) in instructions.iter() {
+                let before = match baseline.get(program, instruction) {
+                    Some(before) => before.mean(),
+                    None => continue,
+                };
+                if stats.mean() as f64 > before as f64 * (1.0 + tolerance) {
+                    regressions.push(format!(
+                        "{} {}: mean {} units, baseline {}",
+                        program,
+                        instruction,
+                        stats.mean(),
+                        before
+                    ));
+                }
+            }
+        }
+        regressions
+    }
+
+    /// Charges each top-level instruction with the units its logs report.
+    fn add(&mut self, sandbox: &Sandbox, instructions: &[(Pubkey, Vec<u8>)], logs: &[String]) {
+        let units = trace::instruction_compute_units(logs);
+        for ((program_id, data), units) in instructions.iter().zip(units) {
+            let units = match units {
+                Some(units) => units,
+                None => continue,
+            };
+            let name = sandbox
+                .decode_instruction(program_id, data)
+                .map_or_else(|| String::from("unknown"), |decoded| decoded.name);
+            self.programs
+                .entry(sandbox.label(program_id))
+                .or_default()
+                .entry(name)
+                .or_default()
+                .record(units);
+        }
+    }
+}
+
+impl fmt::Display for ComputeProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} compute units", self.total())?;
+        for (program, instructions) in self.programs.iter() {
+            writeln!(f, "  {}", program)?;
+            for (instruction, stats) in instructions.iter() {
+                writeln!(
+                    f,
+                    "    {}: {} calls, mean {}, min {}, max {}, total {}",
+                    instruction,
+                    stats.count,
+                    stats.mean(),
+                    stats.min,
+                    stats.max,
+                    stats.total
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the program and data of each instruction.
+fn top_level(instructions: &[Instruction]) -> Vec<(Pubkey, Vec<u8>)> {
+    instructions
+        .iter()
+        .map(|instruction| (instruction.program_id, instruction.data.clone()))
+        .collect()
+}
@@ -0,0 +1,103 @@
+use crate::actor::Actor;
+use crate::errors::Result;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+use std::path::Path;
+
+/// A middleware program that Serum instructions are routed through, such as
+/// a program built on Anchor's MarketProxy for permissioned markets.
+///
+/// MarketProxy programs take the DEX instruction's data unchanged. Their
+/// accounts are the DEX program, then any accounts their middleware needs,
+/// such as an identity token for an identity check, then the DEX
+/// instruction's accounts. The proxy runs its middleware and forwards the
+/// instruction to the DEX, signing for open orders accounts it owns.
+///
+/// Once attached to a Market with Market::with_proxy, the instructions
+/// participants send, to initialize open orders, place and cancel orders,
+/// settle, close open orders, and prune, go through the proxy:
+///
+/// ```ignore
+/// let proxy = MarketProxy::deploy_local(&payer, serum.pubkey(), Path::new("proxy.so"))?
+///     .middleware_account(AccountMeta::new_readonly(*identity.pubkey(), false));
+/// let market = Market::new(&sandbox, &payer, serum.pubkey(), &base, &quote, None, &params)?
+///     .with_proxy(proxy);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MarketProxy {
+    program: Pubkey,
+    dex: Pubkey,
+    middleware_accounts: Vec<AccountMeta>,
+}
+
+impl MarketProxy {
+    /// Routes instructions for the given DEX through an already deployed
+    /// proxy program.
+    pub fn new(program: &Pubkey, dex: &Pubkey) -> Self {
+        MarketProxy {
+            program: *program,
+            dex: *dex,
+            middleware_accounts: Vec::new(),
+        }
+    }
+
+    /// Deploys a proxy program from a local file.
+    pub fn deploy_local(payer: &Actor, dex: &Pubkey, program_location: &Path) -> Result<Self> {
+        let program = payer.deploy_local(program_location)?;
+        Ok(MarketProxy::new(program.pubkey(), dex))
+    }
+
+    /// Downloads and deploys a proxy program.
+    pub fn deploy_remote(
+        payer: &Actor,
+        dex: &Pubkey,
+        remote_location: &str,
+        file_name: &str,
+    ) -> Result<Self> {
+        let program = payer.deploy_remote(remote_location, file_name)?;
+        Ok(MarketProxy::new(program.pubkey(), dex))
+    }
+
+    /// Adds an account that the proxy's middleware reads, passed after the
+    /// DEX program and before the DEX instruction's accounts, in the order
+    /// added.
+    pub fn middleware_account(mut self, account: AccountMeta) -> Self {
+        self.middleware_accounts.push(account);
+        self
+    }
+
+    /// Returns the proxy program's address.
+    pub fn program(&self) -> &Pubkey {
+        &self.program
+    }
+
+    /// Returns the address of the DEX the proxy forwards to.
+    pub fn dex(&self) -> &Pubkey {
+        &self.dex
+    }
+
+    /// Returns the accounts passed to the middleware.
+    pub fn middleware_accounts(&self) -> &[AccountMeta] {
+        &self.middleware_accounts
+    }
+
+    /// Rewrites a DEX instruction to be sent through the proxy. Instructions
+    /// for other programs are returned unchanged.
+    pub fn wrap(&self, instruction: Instruction) -> Instruction {
+        if instruction.program_id != self.dex {
+            return instruction;
+        }
+        let mut accounts =
+            Vec::with_capacity(1 + self.middleware_accounts.len() + instruction.accounts.len());
+        accounts.push(AccountMeta::new_readonly(self.dex, false));
+        accounts.extend(self.middleware_accounts.iter().cloned());
+        accounts.extend(instruction.accounts);
+        Instruction {
+            program_id: self.program,
+            accounts,
+            data: instruction.data,
+        }
+    }
+}
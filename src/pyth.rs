@@ -1,9 +1,20 @@
+//! Price and product accounts of the Pyth simulator, pyth_sim.
+//!
+//! Product accounts, publishing with a confidence interval, and the admin
+//! instructions such as make_stale require the pyth_sim built from this
+//! repository; the published pyth_sim.so only creates and publishes prices.
+
 use crate::actor::Actor;
+use crate::decoder;
 use crate::errors::{Error, Result};
+use crate::oracle::{Oracle, OracleStatus};
 use crate::sandbox::Sandbox;
 use crate::token::{Mint, TokenAccount};
+use crate::views::pubkey_string;
 use bytemuck;
-use pyth_sim::state::Price;
+use pyth_sim::pack::PythPack;
+use pyth_sim::state::{Price, PriceStatus, PRICE_ACCOUNT_SIZE};
+use serde::{Deserialize, Serialize};
 use solana_sdk::pubkey::Pubkey;
 use std::mem::size_of;
 use std::num::NonZeroU64;
@@ -11,18 +22,24 @@ use std::thread;
 use std::thread::sleep;
 use std::time::Duration;
 
-use pyth_sim::instruction::CreatePriceAccountInstruction;
+use pyth_sim::instruction::{
+    CreatePriceAccountInstruction, PROD_ACCT_SIZE, PROD_ATTR_SIZE, PROD_HDR_SIZE,
+};
 
 pub struct PriceAccount<'a> {
     sandbox: &'a Sandbox,
     account: Actor<'a>,
+    /// Program and payer used by the Oracle implementation.
+    pyth: &'a Pubkey,
+    payer: &'a Actor<'a>,
 }
 
 impl<'a> PriceAccount<'a> {
     pub fn new(sandbox: &'a Sandbox, pyth: &'a Pubkey, payer: &'a Actor) -> Result<Self> {
+        sandbox.register_decoder(pyth, decoder::decode_pyth_sim);
         let acc = Actor::new(sandbox)?;
 
-        let sized_accounts = vec![(acc.pubkey(), 3312)];
+        let sized_accounts = vec![(acc.pubkey(), PRICE_ACCOUNT_SIZE)];
 
         let mut instructions = Vec::new();
 
@@ -35,15 +52,18 @@ impl<'a> PriceAccount<'a> {
 
         instructions.push(create_instr);
 
-        sandbox.send_signed_transaction_with_payers(
-            &instructions,
-            Some(payer.pubkey()),
-            vec![payer.keypair(), acc.keypair()],
-        )?;
+        sandbox
+            .transaction()
+            .instructions(&instructions)
+            .payer(payer.try_keypair()?)
+            .signer(acc.try_keypair()?)
+            .send()?;
 
         Ok(PriceAccount {
             sandbox,
             account: acc,
+            pyth,
+            payer,
         })
     }
 
@@ -67,16 +87,333 @@ impl<'a> PriceAccount<'a> {
 
         instructions.push(publish_instr);
 
-        &self.sandbox.send_signed_transaction_with_payers(
-            &instructions,
-            Some(payer.pubkey()),
-            vec![payer.keypair()],
-        )?;
+        self.sandbox
+            .transaction()
+            .instructions(&instructions)
+            .payer(payer.try_keypair()?)
+            .send()?;
+
+        Ok(())
+    }
+
+    /// Sets the status of the aggregate price, such as Halted or Auction, to
+    /// exercise how consumers handle a price that is not trading. Unlike
+    /// Oracle::set_status, signs with the given program and payer.
+    pub fn set_price_status(
+        &self,
+        pyth: &Pubkey,
+        payer: &Actor,
+        status: PriceStatus,
+    ) -> Result<()> {
+        let instruction =
+            pyth_sim::instruction::set_status(pyth, payer.pubkey(), self.account.pubkey(), status)
+                .map_err(invalid_instruction)?;
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(payer.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Changes the exponent of the price without publishing a new price.
+    pub fn set_exponent(&self, pyth: &Pubkey, payer: &Actor, expo: i32) -> Result<()> {
+        let instruction =
+            pyth_sim::instruction::set_exponent(pyth, payer.pubkey(), self.account.pubkey(), expo)
+                .map_err(invalid_instruction)?;
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(payer.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
 
+    /// Moves the slots at which the price was published and last valid back
+    /// to slots_behind slots before the current slot, so that consumers see
+    /// a stale price without waiting for slots to pass.
+    pub fn make_stale(&self, pyth: &Pubkey, payer: &Actor, slots_behind: u64) -> Result<()> {
+        let instruction = pyth_sim::instruction::make_stale(
+            pyth,
+            payer.pubkey(),
+            self.account.pubkey(),
+            slots_behind,
+        )
+        .map_err(invalid_instruction)?;
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(payer.try_keypair()?)
+            .send()?;
         Ok(())
     }
 
     pub fn account(&self) -> &Actor {
         &self.account
     }
+
+    /// Fetches the aggregate state of the price account.
+    pub fn view(&self) -> Result<PriceView> {
+        PriceView::load(&self.sandbox.get_account_data(self.account.pubkey())?)
+    }
+}
+
+/// Typed copy of the aggregate state of a Pyth price account. Serializes
+/// addresses as base58 strings.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PriceView {
+    /// Aggregate price, scaled by 10^expo.
+    pub price: i64,
+    /// Aggregate confidence interval, scaled by 10^expo.
+    pub conf: u64,
+    pub expo: i32,
+    pub status: OracleStatus,
+    /// Slot at which the aggregate price was published.
+    pub publish_slot: u64,
+    /// Slot up to which the aggregate price is valid.
+    pub valid_slot: u64,
+    /// Slot of the last aggregate price that was not unknown.
+    pub last_slot: u64,
+    /// Time-weighted average price.
+    pub twap: i64,
+    /// Time-weighted average confidence interval.
+    pub twac: i64,
+    /// Number of publishers that make up the aggregate.
+    pub num_quoters: u32,
+    #[serde(with = "pubkey_string")]
+    pub product: Pubkey,
+    /// Next price account of the product, or the default address.
+    #[serde(with = "pubkey_string")]
+    pub next: Pubkey,
+}
+
+impl PriceView {
+    /// Parses a price account from raw account data.
+    pub fn load(data: &[u8]) -> Result<Self> {
+        if data.len() < Price::LEN {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "price account holds {} bytes, expected at least {}",
+                    data.len(),
+                    Price::LEN
+                ),
+            )));
+        }
+        let price = Price::unpack_from_slice(data).map_err(|err| {
+            Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                err.to_string(),
+            ))
+        })?;
+        Ok(PriceView {
+            price: price.agg.price,
+            conf: price.agg.conf,
+            expo: price.expo,
+            status: match price.agg.status {
+                PriceStatus::Unknown => OracleStatus::Unknown,
+                PriceStatus::Trading => OracleStatus::Trading,
+                PriceStatus::Halted => OracleStatus::Halted,
+                PriceStatus::Auction => OracleStatus::Auction,
+            },
+            publish_slot: price.agg.pub_slot,
+            valid_slot: price.valid_slot,
+            last_slot: price.last_slot,
+            twap: price.twap.val,
+            twac: price.twac.val,
+            num_quoters: price.num_qt,
+            product: Pubkey::new_from_array(price.prod.val),
+            next: Pubkey::new_from_array(price.next.val),
+        })
+    }
+}
+
+/// Publishes and sets the status through the pyth program and payer the
+/// account was created with.
+impl<'a> Oracle for PriceAccount<'a> {
+    fn pubkey(&self) -> &Pubkey {
+        self.account.pubkey()
+    }
+
+    fn publish(&self, price: i64, conf: u64, expo: i32) -> Result<()> {
+        let instruction = pyth_sim::instruction::publish_price_conf(
+            self.pyth,
+            self.payer.pubkey(),
+            self.account.pubkey(),
+            price,
+            conf,
+            expo,
+        )
+        .map_err(invalid_instruction)?;
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(self.payer.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    fn set_status(&self, status: OracleStatus) -> Result<()> {
+        let status = match status {
+            OracleStatus::Unknown => PriceStatus::Unknown,
+            OracleStatus::Trading => PriceStatus::Trading,
+            OracleStatus::Halted => PriceStatus::Halted,
+            OracleStatus::Auction => PriceStatus::Auction,
+        };
+        self.set_price_status(self.pyth, self.payer, status)
+    }
+}
+
+fn invalid_instruction(err: pyth_sim::error::PythError) -> Error {
+    Error::from(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        err.to_string(),
+    ))
+}
+
+/// Represents a Pyth product account holding reference attributes such as
+/// the symbol and asset type of a price feed.
+pub struct ProductAccount<'a> {
+    sandbox: &'a Sandbox,
+    account: Actor<'a>,
+}
+
+impl<'a> ProductAccount<'a> {
+    /// Returns a builder for a product account's attributes.
+    pub fn builder() -> ProductAccountBuilder {
+        ProductAccountBuilder::default()
+    }
+
+    pub fn account(&self) -> &Actor {
+        &self.account
+    }
+
+    /// Reads the attributes back from the account, in order.
+    pub fn attrs(&self) -> Result<Vec<(String, String)>> {
+        let data = self.sandbox.get_account_data(self.account.pubkey())?;
+        unpack_attrs(&data)
+    }
+}
+
+/// Collects the key-value attributes of a product account.
+///
+/// ```ignore
+/// let product = ProductAccount::builder()
+///     .attr("symbol", "BTC/USD")
+///     .attr("asset_type", "Crypto")
+///     .price_account(price.account().pubkey())
+///     .build(&sandbox, pyth.pubkey(), &payer)?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ProductAccountBuilder {
+    attrs: Vec<(String, String)>,
+    price_account: Option<Pubkey>,
+}
+
+impl ProductAccountBuilder {
+    /// Adds an attribute. Attributes are stored in the order they are added.
+    pub fn attr(mut self, key: &str, value: &str) -> Self {
+        self.attrs.push((String::from(key), String::from(value)));
+        self
+    }
+
+    /// Links the product to its price account.
+    pub fn price_account(mut self, pubkey: &Pubkey) -> Self {
+        self.price_account = Some(*pubkey);
+        self
+    }
+
+    /// Packs the attributes in Pyth's format: each key and value is a
+    /// string prefixed by its length in one byte.
+    pub fn pack(&self) -> Result<Vec<u8>> {
+        let mut packed = Vec::new();
+        for (key, value) in self.attrs.iter() {
+            for field in [key, value] {
+                if field.len() > u8::MAX as usize {
+                    return Err(Error::from(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("product attribute {} exceeds 255 bytes", field),
+                    )));
+                }
+                packed.push(field.len() as u8);
+                packed.extend_from_slice(field.as_bytes());
+            }
+        }
+        if packed.len() > PROD_ATTR_SIZE {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "product attributes take {} bytes but at most {} fit",
+                    packed.len(),
+                    PROD_ATTR_SIZE
+                ),
+            )));
+        }
+        Ok(packed)
+    }
+
+    /// Creates the product account with the pyth program.
+    pub fn build<'a>(
+        self,
+        sandbox: &'a Sandbox,
+        pyth: &Pubkey,
+        payer: &Actor,
+    ) -> Result<ProductAccount<'a>> {
+        let attr = self.pack()?;
+        sandbox.register_decoder(pyth, decoder::decode_pyth_sim);
+        let acc = Actor::new(sandbox)?;
+
+        let create_instr = pyth_sim::instruction::create_product_acc(
+            pyth,
+            payer.pubkey(),
+            acc.pubkey(),
+            self.price_account.as_ref(),
+            &attr,
+        )
+        .map_err(invalid_instruction)?;
+
+        sandbox
+            .transaction()
+            .instruction(payer.create_account(acc.pubkey(), PROD_ACCT_SIZE, pyth)?)
+            .instruction(create_instr)
+            .payer(payer.try_keypair()?)
+            .signer(acc.try_keypair()?)
+            .send()?;
+
+        Ok(ProductAccount {
+            sandbox,
+            account: acc,
+        })
+    }
+}
+
+/// Unpacks the attributes of a product account's data.
+pub fn unpack_attrs(data: &[u8]) -> Result<Vec<(String, String)>> {
+    let invalid = || {
+        Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "malformed product account",
+        ))
+    };
+    if data.len() < PROD_HDR_SIZE {
+        return Err(invalid());
+    }
+    let size = u32::from_le_bytes(data[12..16].try_into().expect("slice of 4 bytes")) as usize;
+    let attr = data.get(PROD_HDR_SIZE..size).ok_or_else(invalid)?;
+
+    let mut fields = Vec::new();
+    let mut offset = 0;
+    while offset < attr.len() {
+        let len = attr[offset] as usize;
+        let field = attr.get(offset + 1..offset + 1 + len).ok_or_else(invalid)?;
+        fields.push(String::from_utf8_lossy(field).into_owned());
+        offset += 1 + len;
+    }
+    if fields.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    Ok(fields
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
 }
@@ -0,0 +1,114 @@
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use std::{io, time};
+
+/// Throttles the RPC requests a Sandbox makes, to resemble the limits public
+/// RPC nodes impose rather than the unlimited local validator.
+///
+/// Requests draw from a bucket of burst tokens that refills at
+/// requests_per_second. When the bucket is empty a request either waits for
+/// its token or, with reject set, fails the way a throttled node answers:
+/// with an HTTP 429 error, which retry policies treat as transient.
+///
+/// ```ignore
+/// let sandbox = SandboxBuilder::fast()
+///     .rate_limit(RateLimit::new(40.0, 100).reject())
+///     .build()?;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Sustained requests allowed per second.
+    pub requests_per_second: f64,
+
+    /// Requests allowed at once after a quiet period.
+    pub burst: u32,
+
+    /// Whether requests over the limit fail instead of waiting.
+    pub reject: bool,
+}
+
+impl RateLimit {
+    /// Returns a limit that delays requests over the given rate.
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        RateLimit {
+            requests_per_second,
+            burst,
+            reject: false,
+        }
+    }
+
+    /// Fails requests over the limit instead of delaying them.
+    pub fn reject(mut self) -> Self {
+        self.reject = true;
+        self
+    }
+}
+
+/// Counts of the requests seen by a rate limiter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitStats {
+    /// Requests seen, throttled or not.
+    pub requests: u64,
+
+    /// Requests that waited for a token.
+    pub delayed: u64,
+
+    /// Requests that failed for lack of a token.
+    pub rejected: u64,
+
+    /// Total time requests spent waiting.
+    pub total_delay: time::Duration,
+}
+
+/// A token bucket enforcing a RateLimit.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: time::Instant,
+    stats: RateLimitStats,
+}
+
+impl RateLimiter {
+    /// Returns a limiter whose bucket starts full.
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            tokens: limit.burst as f64,
+            refilled: time::Instant::now(),
+            stats: RateLimitStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> RateLimitStats {
+        self.stats
+    }
+
+    /// Takes a token for a request. Returns how long the request must wait
+    /// for it, or an error if the request is rejected. A delayed request
+    /// takes its token in advance, so that later requests queue behind it.
+    pub(crate) fn acquire(&mut self) -> Result<time::Duration, ClientError> {
+        let now = time::Instant::now();
+        let rate = self.limit.requests_per_second.max(f64::MIN_POSITIVE);
+        let burst = self.limit.burst.max(1) as f64;
+        self.tokens =
+            (self.tokens + now.duration_since(self.refilled).as_secs_f64() * rate).min(burst);
+        self.refilled = now;
+        self.stats.requests += 1;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(time::Duration::ZERO);
+        }
+        if self.limit.reject {
+            self.stats.rejected += 1;
+            return Err(ClientError::from(ClientErrorKind::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "rate limited: 429 Too Many Requests",
+            ))));
+        }
+        let delay = time::Duration::from_secs_f64((1.0 - self.tokens) / rate);
+        self.tokens -= 1.0;
+        self.stats.delayed += 1;
+        self.stats.total_delay += delay;
+        Ok(delay)
+    }
+}
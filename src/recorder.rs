@@ -0,0 +1,617 @@
+use crate::errors::{Error, Result};
+use crate::sandbox::Sandbox;
+use crate::serum::{self, Market};
+use serde::{Deserialize, Serialize};
+use serum_dex::{matching::Side, state::EventView};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread, time,
+};
+
+/// getMultipleAccounts accepts at most 100 accounts per request.
+const MAX_ACCOUNTS_PER_REQUEST: usize = 100;
+
+/// A snapshot of an account observed by an AccountRecorder.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccountUpdate {
+    /// Base58 public key of the account.
+    pub pubkey: String,
+
+    /// Slot at which the snapshot was observed.
+    pub slot: u64,
+
+    /// Lamport balance of the account. Zero if the account does not exist.
+    pub lamports: u64,
+
+    /// Account data. Empty if the account does not exist.
+    pub data: Vec<u8>,
+}
+
+/// Records every observed change to a set of accounts for the duration of a
+/// test.
+///
+/// The recorder polls the validator on a background thread with its own RPC
+/// client. The first observation of each account is recorded as its initial
+/// state, and every subsequent change in lamports or data is appended.
+/// Updates are kept in memory and optionally streamed to a JSONL file, one
+/// AccountUpdate per line.
+pub struct AccountRecorder {
+    updates: Arc<Mutex<Vec<AccountUpdate>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl AccountRecorder {
+    /// Starts recording the given accounts. If an output path is given then
+    /// updates are also appended to that file as JSON lines.
+    pub fn start(sandbox: &Sandbox, pubkeys: &[Pubkey], output: Option<&Path>) -> Result<Self> {
+        let client = RpcClient::new_with_commitment(sandbox.url(), sandbox.client().commitment());
+        let mut output = match output {
+            Some(path) => Some(fs::File::create(path)?),
+            None => None,
+        };
+        let pubkeys = pubkeys.to_vec();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_updates = updates.clone();
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || -> Result<()> {
+            let mut last: Vec<Option<(u64, Vec<u8>)>> = vec![None; pubkeys.len()];
+            while !thread_stop.load(Ordering::Relaxed) {
+                for (chunk_index, chunk) in pubkeys.chunks(MAX_ACCOUNTS_PER_REQUEST).enumerate() {
+                    let response =
+                        client.get_multiple_accounts_with_commitment(chunk, client.commitment())?;
+                    let slot = response.context.slot;
+                    for (offset, account) in response.value.into_iter().enumerate() {
+                        let index = chunk_index * MAX_ACCOUNTS_PER_REQUEST + offset;
+                        let state = match account {
+                            Some(account) => (account.lamports, account.data),
+                            None => (0, Vec::new()),
+                        };
+                        if last[index].as_ref() == Some(&state) {
+                            continue;
+                        }
+
+                        let update = AccountUpdate {
+                            pubkey: pubkeys[index].to_string(),
+                            slot,
+                            lamports: state.0,
+                            data: state.1.clone(),
+                        };
+                        if let Some(file) = output.as_mut() {
+                            serde_json::to_writer(&mut *file, &update).map_err(io::Error::from)?;
+                            file.write_all(b"\n")?;
+                        }
+                        thread_updates
+                            .lock()
+                            .expect("recorder lock poisoned")
+                            .push(update);
+                        last[index] = Some(state);
+                    }
+                }
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            Ok(())
+        });
+
+        Ok(AccountRecorder {
+            updates,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns all updates recorded so far, in the order they were observed.
+    pub fn updates(&self) -> Vec<AccountUpdate> {
+        self.updates.lock().expect("recorder lock poisoned").clone()
+    }
+
+    /// Returns the updates recorded so far for a single account.
+    pub fn updates_for(&self, pubkey: &Pubkey) -> Vec<AccountUpdate> {
+        let pubkey = pubkey.to_string();
+        self.updates
+            .lock()
+            .expect("recorder lock poisoned")
+            .iter()
+            .filter(|update| update.pubkey == pubkey)
+            .cloned()
+            .collect()
+    }
+
+    /// Stops recording and returns every recorded update. Returns the error
+    /// that stopped the background thread, if any.
+    pub fn stop(mut self) -> Result<Vec<AccountUpdate>> {
+        self.join()?;
+        Ok(self.updates())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => match handle.join() {
+                Ok(result) => result,
+                Err(_) => Err(Error::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    "account recorder thread panicked",
+                ))),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AccountRecorder {
+    /// Stops the background thread.
+    fn drop(&mut self) {
+        self.join().unwrap_or(());
+    }
+}
+
+/// File format written by a MarketRecorder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma separated values with a header row.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+/// A fill or out event observed on a market's event queue.
+///
+/// Fields that do not apply to the event kind are zero: fills have no
+/// unlocked quantities and outs have no paid, received, or fee quantities.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketEvent {
+    /// Either "fill" or "out".
+    pub kind: String,
+
+    /// Slot at which the event was observed.
+    pub slot: u64,
+
+    /// Wall clock time at which the event was observed, in milliseconds
+    /// since the Unix epoch.
+    pub timestamp_ms: u64,
+
+    /// Position of the event in the queue's history.
+    pub seq_num: u64,
+
+    /// Either "bid" or "ask".
+    pub side: String,
+
+    /// Base58 public key of the owning open orders account.
+    pub owner: String,
+
+    /// Name registered for the owner in the Sandbox, or the owner itself.
+    pub owner_label: String,
+
+    /// Serum order id.
+    pub order_id: u128,
+
+    /// Client order id, or zero if none was given.
+    pub client_order_id: u64,
+
+    /// Whether the owner was the maker of a fill.
+    pub maker: bool,
+
+    pub native_qty_paid: u64,
+    pub native_qty_received: u64,
+    pub native_fee_or_rebate: u64,
+    pub native_qty_unlocked: u64,
+    pub native_qty_still_locked: u64,
+}
+
+impl MarketEvent {
+    const CSV_HEADER: &'static str = "kind,slot,timestamp_ms,seq_num,side,owner,owner_label,\
+        order_id,client_order_id,maker,native_qty_paid,native_qty_received,\
+        native_fee_or_rebate,native_qty_unlocked,native_qty_still_locked";
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.kind,
+            self.slot,
+            self.timestamp_ms,
+            self.seq_num,
+            self.side,
+            self.owner,
+            csv_field(&self.owner_label),
+            self.order_id,
+            self.client_order_id,
+            self.maker,
+            self.native_qty_paid,
+            self.native_qty_received,
+            self.native_fee_or_rebate,
+            self.native_qty_unlocked,
+            self.native_qty_still_locked
+        )
+    }
+
+    pub(crate) fn from_view(
+        view: EventView,
+        slot: u64,
+        timestamp_ms: u64,
+        seq_num: u64,
+        names: &HashMap<Pubkey, String>,
+    ) -> Self {
+        let kind = match view {
+            EventView::Fill { .. } => "fill",
+            EventView::Out { .. } => "out",
+        };
+        let (side, owner, order_id, client_order_id) = match &view {
+            EventView::Fill {
+                side,
+                owner,
+                order_id,
+                client_order_id,
+                ..
+            }
+            | EventView::Out {
+                side,
+                owner,
+                order_id,
+                client_order_id,
+                ..
+            } => (
+                *side,
+                Pubkey::new(bytemuck::bytes_of(owner)),
+                *order_id,
+                client_order_id.map(|id| id.get()).unwrap_or(0),
+            ),
+        };
+
+        let mut event = MarketEvent {
+            kind: String::from(kind),
+            slot,
+            timestamp_ms,
+            seq_num,
+            side: String::from(match side {
+                Side::Bid => "bid",
+                Side::Ask => "ask",
+            }),
+            owner: owner.to_string(),
+            owner_label: names
+                .get(&owner)
+                .cloned()
+                .unwrap_or_else(|| owner.to_string()),
+            order_id,
+            client_order_id,
+            maker: false,
+            native_qty_paid: 0,
+            native_qty_received: 0,
+            native_fee_or_rebate: 0,
+            native_qty_unlocked: 0,
+            native_qty_still_locked: 0,
+        };
+        match view {
+            EventView::Fill {
+                maker,
+                native_qty_paid,
+                native_qty_received,
+                native_fee_or_rebate,
+                ..
+            } => {
+                event.maker = maker;
+                event.native_qty_paid = native_qty_paid;
+                event.native_qty_received = native_qty_received;
+                event.native_fee_or_rebate = native_fee_or_rebate;
+            }
+            EventView::Out {
+                native_qty_unlocked,
+                native_qty_still_locked,
+                ..
+            } => {
+                event.native_qty_unlocked = native_qty_unlocked;
+                event.native_qty_still_locked = native_qty_still_locked;
+            }
+        }
+        event
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote, or newline.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/// Polls a market's event queue and returns the events pushed since the
+/// previous poll, using the queue's sequence number to skip events already
+/// seen. On the first poll everything in the queue is new.
+pub(crate) struct EventQueuePoller {
+    client: RpcClient,
+    event_queue: Pubkey,
+    names: Arc<Mutex<HashMap<Pubkey, String>>>,
+    last_seq_num: Option<u64>,
+}
+
+impl EventQueuePoller {
+    pub(crate) fn new(
+        client: RpcClient,
+        event_queue: Pubkey,
+        names: Arc<Mutex<HashMap<Pubkey, String>>>,
+    ) -> Self {
+        EventQueuePoller {
+            client,
+            event_queue,
+            names,
+            last_seq_num: None,
+        }
+    }
+
+    pub(crate) fn poll(&mut self) -> Result<Vec<MarketEvent>> {
+        let response = self
+            .client
+            .get_account_with_commitment(&self.event_queue, self.client.commitment())?;
+        let slot = response.context.slot;
+        let account = match response.value {
+            Some(account) => account,
+            None => return Ok(Vec::new()),
+        };
+        let (seq_num, queue) = serum::load_event_queue(&account.data)?;
+        // Events pushed since the last poll are at the end of the queue.
+        let new = match self.last_seq_num {
+            Some(last) => seq_num.saturating_sub(last).min(queue.len() as u64) as usize,
+            None => queue.len(),
+        };
+        let timestamp_ms = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+        let names = self
+            .names
+            .lock()
+            .expect("name registry lock poisoned")
+            .clone();
+
+        let first_seq_num = seq_num - new as u64;
+        let mut events = Vec::with_capacity(new);
+        for (i, event) in queue[queue.len() - new..].iter().enumerate() {
+            events.push(MarketEvent::from_view(
+                event.as_view()?,
+                slot,
+                timestamp_ms,
+                first_seq_num + i as u64,
+                &names,
+            ));
+        }
+        self.last_seq_num = Some(seq_num);
+        Ok(events)
+    }
+}
+
+/// Records every fill and out event pushed to a market's event queue.
+///
+/// The recorder polls the event queue on a background thread and uses the
+/// queue's sequence number to pick up only events it has not seen. Events are
+/// kept in memory and optionally streamed to a CSV or JSON lines file. Owners
+/// are labelled with the names registered in the Sandbox, for example through
+/// Participant::new_named.
+///
+/// Events that are pushed and consumed between two polls are missed, so the
+/// recorder should be started before orders are placed and cranking should
+/// not outpace it.
+pub struct MarketRecorder {
+    events: Arc<Mutex<Vec<MarketEvent>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl MarketRecorder {
+    /// Starts recording the market's events, optionally writing them to the
+    /// given file in the given format.
+    pub fn start(
+        sandbox: &Sandbox,
+        market: &Market,
+        output: Option<(&Path, ExportFormat)>,
+    ) -> Result<Self> {
+        let client = RpcClient::new_with_commitment(sandbox.url(), sandbox.client().commitment());
+        let names = sandbox.shared_names();
+        let event_queue = *market.event_queue().pubkey();
+        let mut output = match output {
+            Some((path, format)) => {
+                let mut file = fs::File::create(path)?;
+                if format == ExportFormat::Csv {
+                    writeln!(file, "{}", MarketEvent::CSV_HEADER)?;
+                }
+                Some((file, format))
+            }
+            None => None,
+        };
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_events = events.clone();
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || -> Result<()> {
+            let mut poller = EventQueuePoller::new(client, event_queue, names);
+            while !thread_stop.load(Ordering::Relaxed) {
+                for event in poller.poll()? {
+                    if let Some((file, format)) = output.as_mut() {
+                        match format {
+                            ExportFormat::Csv => writeln!(file, "{}", event.to_csv())?,
+                            ExportFormat::JsonLines => {
+                                serde_json::to_writer(&mut *file, &event)
+                                    .map_err(io::Error::from)?;
+                                file.write_all(b"\n")?;
+                            }
+                        }
+                    }
+                    thread_events
+                        .lock()
+                        .expect("recorder lock poisoned")
+                        .push(event);
+                }
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            Ok(())
+        });
+
+        Ok(MarketRecorder {
+            events,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns all events recorded so far, in queue order.
+    pub fn events(&self) -> Vec<MarketEvent> {
+        self.events.lock().expect("recorder lock poisoned").clone()
+    }
+
+    /// Returns the fills recorded so far.
+    pub fn fills(&self) -> Vec<MarketEvent> {
+        self.events
+            .lock()
+            .expect("recorder lock poisoned")
+            .iter()
+            .filter(|event| event.kind == "fill")
+            .cloned()
+            .collect()
+    }
+
+    /// Stops recording and returns every recorded event. Returns the error
+    /// that stopped the background thread, if any.
+    pub fn stop(mut self) -> Result<Vec<MarketEvent>> {
+        self.join()?;
+        Ok(self.events())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => match handle.join() {
+                Ok(result) => result,
+                Err(_) => Err(Error::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    "market recorder thread panicked",
+                ))),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MarketRecorder {
+    /// Stops the background thread.
+    fn drop(&mut self) {
+        self.join().unwrap_or(());
+    }
+}
+
+/// Calls a function for each fill or out event pushed to a market's event
+/// queue after the subscription starts. Obtained from Market::on_fill and
+/// Market::on_out.
+///
+/// The callback runs on a background thread that polls the event queue, so
+/// it must own what it uses. To react on the test thread, send the events
+/// over a channel:
+///
+/// ```ignore
+/// let (fills, filled) = mpsc::channel();
+/// let subscription = market.on_fill(move |event| {
+///     fills.send(event.clone()).unwrap_or(());
+/// })?;
+/// // ... place an order that fills ...
+/// let fill = filled.recv_timeout(Duration::from_secs(10)).unwrap();
+/// // ... place the hedge ...
+/// ```
+///
+/// Like a MarketRecorder, the subscription misses events that are pushed
+/// and consumed between two polls.
+pub struct EventSubscription {
+    delivered: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl EventSubscription {
+    /// Starts calling callback for every new event of the given kind, "fill"
+    /// or "out". Events already in the queue are skipped.
+    pub(crate) fn start<F>(
+        sandbox: &Sandbox,
+        market: &Market,
+        kind: &'static str,
+        mut callback: F,
+    ) -> Result<Self>
+    where
+        F: FnMut(&MarketEvent) + Send + 'static,
+    {
+        let client = RpcClient::new_with_commitment(sandbox.url(), sandbox.client().commitment());
+        let mut poller = EventQueuePoller::new(
+            client,
+            *market.event_queue().pubkey(),
+            sandbox.shared_names(),
+        );
+        poller.poll()?;
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_delivered = delivered.clone();
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || -> Result<()> {
+            while !thread_stop.load(Ordering::Relaxed) {
+                for event in poller.poll()? {
+                    if event.kind == kind {
+                        callback(&event);
+                        thread_delivered.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            Ok(())
+        });
+
+        Ok(EventSubscription {
+            delivered,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns the number of events passed to the callback so far.
+    pub fn delivered(&self) -> usize {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    /// Stops watching, waiting for a callback in progress to return, and
+    /// returns the number of events delivered. Returns the error that
+    /// stopped the background thread, if any.
+    pub fn stop(mut self) -> Result<usize> {
+        self.join()?;
+        Ok(self.delivered())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => match handle.join() {
+                Ok(result) => result,
+                Err(_) => Err(Error::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    "event subscription thread panicked",
+                ))),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for EventSubscription {
+    /// Stops the background thread.
+    fn drop(&mut self) {
+        self.join().unwrap_or(());
+    }
+}
@@ -0,0 +1,339 @@
+use crate::actor::Actor;
+use crate::errors::{Context, Error, Result};
+use crate::scenario::{ScenarioOrderType, ScenarioSide};
+use crate::serum::{Market, Participant};
+use serde::{Deserialize, Serialize};
+use serum_dex::matching::{OrderType, Side};
+use std::{collections::HashMap, fs, io, num::NonZeroU64, path::Path, thread, time};
+
+/// Events consumed by each crank unless the options set a limit.
+const CRANK_EVENTS: u16 = 100;
+
+/// Order flow captured from a Serum market, such as mainnet, to be replayed
+/// against a sandbox market.
+///
+/// A capture is a list of records in time order. Each record places or
+/// cancels an order on behalf of an owner, which is any string identifying
+/// the trader in the captured data, such as its open orders address. Prices
+/// are in quote lots per base lot and sizes in base lots of the sandbox
+/// market, so captured prices must be converted to its lot sizes first.
+///
+/// Captures are read from JSON, an array of records, or from CSV with a
+/// header row naming the columns:
+///
+/// ```text
+/// timestamp_ms,kind,owner,side,price,size,order_type,client_order_id
+/// 1650000000000,new,9xQe...,sell,20,10,limit,1
+/// 1650000000250,new,5ZpR...,buy,20,4,immediate_or_cancel,7
+/// 1650000001000,cancel,9xQe...,sell,,,,1
+/// ```
+///
+/// kind defaults to new, order_type to limit, and client_order_id to 0.
+/// Cancels refer to an earlier order of the same owner by client order id.
+///
+/// Owners are assigned to the given sandbox participants in order of first
+/// appearance, wrapping around if there are more owners than participants:
+///
+/// ```ignore
+/// let capture = Capture::load("captures/sol_usdc.csv")?;
+/// let stats = capture.replay(&market, &participants, &cranker, &ReplayOptions {
+///     speed: Some(10.0),
+///     ..ReplayOptions::default()
+/// })?;
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capture {
+    pub records: Vec<CaptureRecord>,
+}
+
+/// Whether a capture record places or cancels an order.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureKind {
+    New,
+    Cancel,
+}
+
+impl Default for CaptureKind {
+    fn default() -> Self {
+        CaptureKind::New
+    }
+}
+
+/// A single order or cancel of a capture.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Time of the record in milliseconds. Only differences between records
+    /// matter.
+    pub timestamp_ms: u64,
+
+    #[serde(default)]
+    pub kind: CaptureKind,
+
+    /// Identifies the trader in the captured data.
+    pub owner: String,
+
+    pub side: ScenarioSide,
+
+    /// Limit price in quote lots per base lot. Unused by cancels.
+    #[serde(default)]
+    pub price: u64,
+
+    /// Size in base lots. Unused by cancels.
+    #[serde(default)]
+    pub size: u64,
+
+    #[serde(default)]
+    pub order_type: ScenarioOrderType,
+
+    /// The owner's id for the order, which cancels refer to.
+    #[serde(default)]
+    pub client_order_id: u64,
+}
+
+/// Controls how a capture is replayed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayOptions {
+    /// Multiple of captured time to replay at, so 2.0 replays twice as
+    /// fast. Records are sent as fast as possible if unset.
+    pub speed: Option<f64>,
+
+    /// Cranks the market and settles every participant after this many
+    /// records, so that participants keep trading with their proceeds.
+    pub crank_every: Option<usize>,
+
+    /// Events consumed by each crank. Defaults to 100.
+    pub crank_limit: Option<u16>,
+}
+
+/// What happened to the records of a replayed capture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// Orders accepted by the market.
+    pub orders_placed: usize,
+
+    /// Orders the market rejected, such as for lack of funds.
+    pub orders_rejected: usize,
+
+    /// Orders cancelled.
+    pub cancels: usize,
+
+    /// Cancels skipped because their order was never placed or no longer
+    /// rests on the book.
+    pub cancels_skipped: usize,
+
+    /// Times the market was cranked and settled.
+    pub cranks: usize,
+
+    /// Time taken by the replay.
+    pub elapsed: time::Duration,
+}
+
+/// Capture columns holding numbers, parsed as such from CSV.
+const NUMERIC_COLUMNS: [&str; 4] = ["timestamp_ms", "price", "size", "client_order_id"];
+
+impl Capture {
+    /// Parses a capture from a JSON array of records.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let records = serde_json::from_str(json).map_err(invalid_data)?;
+        Ok(Capture { records })
+    }
+
+    /// Parses a capture from CSV with a header row. Fields are not quoted,
+    /// and empty fields take their default.
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let mut lines = csv
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let header: Vec<&str> = match lines.next() {
+            Some((_, header)) => header.split(',').map(str::trim).collect(),
+            None => return Ok(Capture::default()),
+        };
+
+        let mut records = Vec::new();
+        for (number, line) in lines {
+            let mut record = serde_json::Map::new();
+            for (column, field) in header.iter().zip(line.split(',').map(str::trim)) {
+                if field.is_empty() {
+                    continue;
+                }
+                let value = if NUMERIC_COLUMNS.contains(column) {
+                    let parsed = field.parse::<u64>().map_err(|err| {
+                        invalid_data_message(format!("line {}: {}: {}", number + 1, column, err))
+                    })?;
+                    serde_json::Value::from(parsed)
+                } else {
+                    serde_json::Value::from(field)
+                };
+                record.insert(column.to_string(), value);
+            }
+            records.push(
+                serde_json::from_value(serde_json::Value::Object(record))
+                    .map_err(|err| invalid_data_message(format!("line {}: {}", number + 1, err)))?,
+            );
+        }
+        Ok(Capture { records })
+    }
+
+    /// Loads a capture from a file, parsed as CSV if its extension is .csv
+    /// and as JSON otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let capture = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Self::from_csv(&contents),
+            _ => Self::from_json(&contents),
+        };
+        capture.with_context(|| format!("loading capture {}", path.display()))
+    }
+
+    /// Replays the capture's records in order through the participants. The
+    /// payer cancels orders and pays for cranking and settling. Orders the
+    /// market rejects are counted rather than failing the replay, since
+    /// sandbox balances rarely match the captured traders'; other failures
+    /// stop the replay.
+    pub fn replay(
+        &self,
+        market: &Market,
+        participants: &[Participant],
+        payer: &Actor,
+        options: &ReplayOptions,
+    ) -> Result<ReplayStats> {
+        if participants.is_empty() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a replay needs at least one participant",
+            )));
+        }
+
+        let start = time::Instant::now();
+        let mut stats = ReplayStats::default();
+        let mut assigned: HashMap<&str, usize> = HashMap::new();
+        // Sandbox client order ids are assigned in sequence, since captured
+        // ids are only unique per owner and owners share participants.
+        let mut placed: HashMap<(&str, u64), (usize, u64)> = HashMap::new();
+        let mut next_client_order_id = 1;
+        let mut previous_ms = self.records.first().map_or(0, |record| record.timestamp_ms);
+
+        for (i, record) in self.records.iter().enumerate() {
+            if let Some(speed) = options.speed.filter(|speed| *speed > 0.0) {
+                let gap = record.timestamp_ms.saturating_sub(previous_ms);
+                thread::sleep(time::Duration::from_secs_f64(gap as f64 / 1000.0 / speed));
+            }
+            previous_ms = previous_ms.max(record.timestamp_ms);
+
+            let owners = assigned.len();
+            let index = *assigned
+                .entry(record.owner.as_str())
+                .or_insert(owners % participants.len());
+            let participant = &participants[index];
+            match record.kind {
+                CaptureKind::New => {
+                    let client_order_id = next_client_order_id;
+                    next_client_order_id += 1;
+                    match place(market, participant, record, client_order_id) {
+                        Ok(()) => {
+                            stats.orders_placed += 1;
+                            placed.insert(
+                                (record.owner.as_str(), record.client_order_id),
+                                (index, client_order_id),
+                            );
+                        }
+                        Err(err) if err.is_program_error() => {
+                            tracing::debug!(record = i, error = ?err, "replayed order rejected");
+                            stats.orders_rejected += 1;
+                        }
+                        Err(err) => {
+                            return Err(err).with_context(|| format!("replaying order {}", i));
+                        }
+                    }
+                }
+                CaptureKind::Cancel => {
+                    let resting = match placed.get(&(record.owner.as_str(), record.client_order_id))
+                    {
+                        Some((index, client_order_id)) => participants[*index]
+                            .open_orders_view()?
+                            .orders
+                            .into_iter()
+                            .find(|order| order.client_order_id == *client_order_id)
+                            .map(|order| (*index, order)),
+                        None => None,
+                    };
+                    match resting {
+                        Some((index, order)) => {
+                            let side = if order.is_bid { Side::Bid } else { Side::Ask };
+                            market
+                                .cancel_order(payer, &participants[index], side, order.order_id)
+                                .with_context(|| format!("replaying cancel {}", i))?;
+                            stats.cancels += 1;
+                        }
+                        None => stats.cancels_skipped += 1,
+                    }
+                }
+            }
+
+            if let Some(every) = options.crank_every.filter(|every| *every > 0) {
+                if (i + 1) % every == 0 {
+                    crank_and_settle(market, participants, payer, options)?;
+                    stats.cranks += 1;
+                }
+            }
+        }
+
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+}
+
+/// Places a record's order for a participant.
+fn place(
+    market: &Market,
+    participant: &Participant,
+    record: &CaptureRecord,
+    client_order_id: u64,
+) -> Result<()> {
+    let side = match record.side {
+        ScenarioSide::Buy => Side::Bid,
+        ScenarioSide::Sell => Side::Ask,
+    };
+    let order_type = match record.order_type {
+        ScenarioOrderType::Limit => OrderType::Limit,
+        ScenarioOrderType::ImmediateOrCancel => OrderType::ImmediateOrCancel,
+        ScenarioOrderType::PostOnly => OrderType::PostOnly,
+    };
+    let price = NonZeroU64::new(record.price)
+        .ok_or_else(|| invalid_data_message(String::from("price must be positive")))?;
+    let size = NonZeroU64::new(record.size)
+        .ok_or_else(|| invalid_data_message(String::from("size must be positive")))?;
+    market.place_order(participant, side, price, size, order_type, client_order_id)
+}
+
+fn crank_and_settle(
+    market: &Market,
+    participants: &[Participant],
+    payer: &Actor,
+    options: &ReplayOptions,
+) -> Result<()> {
+    market
+        .crank_all(payer, options.crank_limit.unwrap_or(CRANK_EVENTS))
+        .context("cranking replayed market")?;
+    for participant in participants.iter() {
+        // Open orders accounts are initialized by their first order.
+        if participant.open_orders_view()?.account_flags == 0 {
+            continue;
+        }
+        market
+            .settle_funds(payer, participant)
+            .context("settling replayed participant")?;
+    }
+    Ok(())
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn invalid_data_message(message: String) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, message))
+}
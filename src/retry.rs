@@ -0,0 +1,116 @@
+use crate::errors::Result;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_request::{RpcError, RpcResponseErrorData},
+};
+use solana_sdk::transaction::TransactionError;
+use std::{thread, time::Duration};
+
+/// Controls how RPC operations are retried when they fail transiently.
+///
+/// A Sandbox applies its policy to transaction sends and account fetches.
+/// Transient failures are IO and HTTP errors (such as timeouts), unhealthy
+/// node responses and expired blockhashes. Before a TransactionBuilder
+/// retries a send, it checks whether the failed attempt landed after all,
+/// so that a transaction is never applied twice. When resign_on_expiry is
+/// set, a transaction the cluster has not seen is signed again with a fresh
+/// blockhash once its old blockhash has expired.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: usize,
+
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+
+    /// Factor applied to the delay after every retry.
+    pub backoff_multiplier: u32,
+
+    /// Upper bound on the delay between retries.
+    pub max_backoff: Duration,
+
+    /// Whether transactions that never landed are signed again with a
+    /// fresh blockhash when their blockhash has expired.
+    pub resign_on_expiry: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            backoff_multiplier: 2,
+            max_backoff: Duration::from_secs(2),
+            resign_on_expiry: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns a policy that never retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Returns the delay to wait before the given retry, counting from zero.
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let mut delay = self.initial_backoff;
+        for _ in 0..retry {
+            delay = delay.saturating_mul(self.backoff_multiplier);
+            if delay >= self.max_backoff {
+                return self.max_backoff;
+            }
+        }
+        std::cmp::min(delay, self.max_backoff)
+    }
+
+    /// Runs the operation, retrying transient failures according to this
+    /// policy. The last error is returned once retries are exhausted.
+    pub fn run<T, F>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> ClientResult<T>,
+    {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(err) if retry < self.max_retries && is_transient(&err) => {
+                    thread::sleep(self.backoff(retry));
+                    retry += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Returns true if the error is worth retrying.
+pub(crate) fn is_transient(err: &ClientError) -> bool {
+    match err.kind() {
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => true,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::NodeUnhealthy { .. },
+            ..
+        }) => true,
+        _ => is_blockhash_expired(err),
+    }
+}
+
+/// Returns true if the error indicates that the transaction's blockhash is no
+/// longer valid.
+pub(crate) fn is_blockhash_expired(err: &ClientError) -> bool {
+    match err.kind() {
+        // send_and_confirm_transaction gives up with this error once the
+        // blockhash expires before the transaction is confirmed.
+        ClientErrorKind::RpcError(RpcError::ForUser(message)) => {
+            message.starts_with(CONFIRMATION_TIMEOUT)
+        }
+        _ => err.get_transaction_error() == Some(TransactionError::BlockhashNotFound),
+    }
+}
+
+/// Prefix of the error send_and_confirm_transaction returns when it gives up.
+const CONFIRMATION_TIMEOUT: &str = "unable to confirm transaction";
@@ -0,0 +1,79 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, RngCore, SeedableRng};
+use solana_sdk::signature::Keypair;
+use std::env;
+
+/// Environment variable holding the seed used by Sandbox randomness.
+pub const SEED_ENV_VAR: &str = "SOLARIUM_SEED";
+
+/// Seeded source of randomness shared by a Sandbox's helpers.
+///
+/// Every randomized helper, including Actor keypair generation, draws from the
+/// Sandbox's SandboxRng. Running a test again with the same seed, for example
+/// by setting SOLARIUM_SEED to the seed printed when the Sandbox started,
+/// replays the same keypairs and random choices.
+pub struct SandboxRng {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl SandboxRng {
+    /// Creates a generator with the given seed.
+    pub fn from_seed(seed: u64) -> Self {
+        SandboxRng {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Creates a generator seeded from SOLARIUM_SEED if it is set to a valid
+    /// u64, or from a random seed otherwise.
+    pub fn from_env() -> Self {
+        let seed = env::var(SEED_ENV_VAR)
+            .ok()
+            .and_then(|seed| seed.trim().parse().ok())
+            .unwrap_or_else(rand::random);
+        SandboxRng::from_seed(seed)
+    }
+
+    /// Returns the seed this generator was created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Generates a keypair.
+    pub fn keypair(&mut self) -> Keypair {
+        Keypair::generate(&mut self.rng)
+    }
+
+    /// Generates a name with the given prefix and a random suffix, such as
+    /// "trader-3fa1".
+    pub fn name(&mut self, prefix: &str) -> String {
+        format!("{}-{:04x}", prefix, self.rng.gen::<u16>())
+    }
+
+    /// Returns a value in [low, high).
+    pub fn gen_range(&mut self, low: u64, high: u64) -> u64 {
+        self.rng.gen_range(low, high)
+    }
+
+    /// Returns true with the given probability.
+    pub fn gen_bool(&mut self, probability: f64) -> bool {
+        self.rng.gen_bool(probability)
+    }
+
+    /// Shuffles the items in place.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        items.shuffle(&mut self.rng);
+    }
+
+    /// Picks one of the items, or None if there are none.
+    pub fn choose<'b, T>(&mut self, items: &'b [T]) -> Option<&'b T> {
+        items.choose(&mut self.rng)
+    }
+
+    /// Returns the underlying generator, for use with APIs that take an
+    /// RngCore.
+    pub fn rng(&mut self) -> &mut impl RngCore {
+        &mut self.rng
+    }
+}
@@ -1,27 +1,443 @@
-use crate::errors::{Error, Result};
+use crate::actor::{self, Actor};
+use crate::audit::AuditLog;
+use crate::cache::AccountCache;
+use crate::decoder::{DecodedInstruction, DecoderRegistry, InstructionDecoder};
+use crate::dump;
+use crate::errors::{Context, Error, Result};
+#[cfg(feature = "fault-injection")]
+use crate::fault::{self, Fault, FaultInjector, FaultPolicy, FaultStats};
+use crate::filter::AccountFilter;
+use crate::fork::ForkManifest;
+use crate::funding::FundingSource;
+use crate::performance::PerformanceSample;
+use crate::profile::ProfiledTransaction;
+use crate::ratelimit::{RateLimit, RateLimitStats, RateLimiter};
+use crate::retry::RetryPolicy;
+use crate::rng::{self, SandboxRng};
+use crate::trace::TraceEntry;
+use crate::transaction::{self, TransactionBuilder};
+use crate::versioned::{self, AddressLookupTable};
 use portpicker;
-use solana_client::rpc_client;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    client_error::{ClientError, Result as ClientResult},
+    rpc_client,
+    rpc_config::RpcAccountInfoConfig,
+    rpc_request::RpcRequest,
+};
 use solana_sdk::{
-    instruction::Instruction, pubkey::Pubkey, signer::keypair::Keypair, transaction::Transaction,
+    account::Account,
+    hash::Hash,
+    instruction::Instruction,
+    message::Message,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Signature, Signer},
+    signer::keypair::Keypair,
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use solana_transaction_status::UiTransactionEncoding;
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, Write},
+    net,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread, time,
 };
-use std::{io, path::Path, process, thread, time};
 use tempfile;
 
+/// getMultipleAccounts accepts at most 100 accounts per request.
+pub(crate) const MAX_ACCOUNTS_PER_REQUEST: usize = 100;
+
+/// Time a validator is given to exit after SIGTERM before it is killed.
+const VALIDATOR_SHUTDOWN_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// Image of the Solana release the crate is built against, which provides
+/// solana-test-validator and the solana CLI.
+pub const DEFAULT_DOCKER_IMAGE: &str = "solanalabs/solana:v1.9.4";
+
+/// Environment variable naming the RPC url of a validator for
+/// Sandbox::from_env to connect to instead of starting one.
+pub const URL_ENV_VAR: &str = "SOLARIUM_URL";
+
+/// Environment variable naming a keyfile that Sandbox::from_env funds
+/// airdrops from when the connected cluster's faucet refuses them.
+pub const FUNDER_ENV_VAR: &str = "SOLARIUM_FUNDER";
+
 /// Represents a Solana test environment.
 ///
-/// A Sandbox wraps a solana-test-validator instance. A Sandbox facilitates the
-/// creation of Actors, which represent keypairs known to this environment.
+/// A Sandbox wraps a solana-test-validator instance, or connects to a
+/// validator managed elsewhere with Sandbox::connect. A Sandbox facilitates
+/// the creation of Actors, which represent keypairs known to this
+/// environment.
 pub struct Sandbox {
-    tmp: tempfile::TempDir,
-    validator: process::Child,
+    tmp: Workdir,
+    /// The validator process, or None if the Sandbox is connected to a
+    /// validator it did not start.
+    validator: Option<LocalValidator>,
+    url: String,
     port: u16,
     client: rpc_client::RpcClient,
+    funding: FundingSource,
+    log_path: PathBuf,
+    retry_policy: RetryPolicy,
+    names: Arc<Mutex<HashMap<Pubkey, String>>>,
+    trace: Mutex<Option<fs::File>>,
+    decoders: Mutex<DecoderRegistry>,
+    tracked_accounts: Mutex<Vec<Pubkey>>,
+    audit: Mutex<Option<AuditLog>>,
+    profile: Mutex<Option<Vec<ProfiledTransaction>>>,
+    rng: Mutex<SandboxRng>,
+    account_cache: AccountCache,
+    ledger_snapshots: AtomicUsize,
+    rate_limiter: Mutex<Option<RateLimiter>>,
+    lazy_keyfiles: bool,
+    #[cfg(feature = "fault-injection")]
+    faults: Mutex<Option<FaultInjector>>,
 }
 
-impl Sandbox {
-    /// Creates a Sandbox and blocks until the RPC server is ready to use.
-    pub fn new() -> Result<Self> {
-        let tmp = tempfile::Builder::new().prefix("solarium").tempdir()?;
+/// Configures and starts a Sandbox.
+///
+/// Each option maps to a solana-test-validator flag and is left at the
+/// validator's default unless set. The fast preset trades realism for
+/// throughput and is what Sandbox::new uses.
+///
+/// ```ignore
+/// let sandbox = SandboxBuilder::fast().slots_per_epoch(128).seed(42).build()?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SandboxBuilder {
+    ticks_per_slot: Option<u64>,
+    slots_per_epoch: Option<u64>,
+    no_bpf_jit: bool,
+    faucet_sol: Option<f64>,
+    faucet_time_slice_secs: Option<u64>,
+    faucet_per_request_sol_cap: Option<f64>,
+    faucet_per_time_sol_cap: Option<f64>,
+    seed: Option<u64>,
+    retry_policy: Option<RetryPolicy>,
+    rate_limit: Option<RateLimit>,
+    lazy_keyfiles: bool,
+    workdir: Option<PathBuf>,
+    keep_artifacts: bool,
+    clone_url: Option<String>,
+    clone_accounts: Vec<Pubkey>,
+    warp_slot: Option<u64>,
+    names: Vec<(Pubkey, String)>,
+    account_files: Vec<(Pubkey, PathBuf)>,
+    genesis_accounts: Vec<(Pubkey, u64)>,
+    genesis_actors: Vec<(String, u64)>,
+    deactivated_features: Vec<Pubkey>,
+    extra_args: Vec<String>,
+    docker_image: Option<String>,
+}
+
+impl SandboxBuilder {
+    /// Returns a builder with every option at the validator's default.
+    pub fn new() -> Self {
+        SandboxBuilder::default()
+    }
+
+    /// Returns a builder tuned for throughput: short slots and epochs, and a
+    /// large faucet without rate limits so tests can airdrop freely.
+    pub fn fast() -> Self {
+        SandboxBuilder::new()
+            .ticks_per_slot(8)
+            .slots_per_epoch(64)
+            .faucet_sol(10_000_000.0)
+    }
+
+    /// Sets the number of ticks per slot. Fewer ticks make slots, and with
+    /// them confirmations, shorter.
+    pub fn ticks_per_slot(mut self, ticks: u64) -> Self {
+        self.ticks_per_slot = Some(ticks);
+        self
+    }
+
+    /// Sets the number of slots per epoch.
+    pub fn slots_per_epoch(mut self, slots: u64) -> Self {
+        self.slots_per_epoch = Some(slots);
+        self
+    }
+
+    /// Runs programs in the BPF interpreter rather than the JIT.
+    pub fn no_bpf_jit(mut self) -> Self {
+        self.no_bpf_jit = true;
+        self
+    }
+
+    /// Sets the amount of SOL held by the faucet.
+    pub fn faucet_sol(mut self, sol: f64) -> Self {
+        self.faucet_sol = Some(sol);
+        self
+    }
+
+    /// Sets the length of the faucet's rate limiting window.
+    pub fn faucet_time_slice_secs(mut self, secs: u64) -> Self {
+        self.faucet_time_slice_secs = Some(secs);
+        self
+    }
+
+    /// Caps the SOL handed out by a single airdrop.
+    pub fn faucet_per_request_sol_cap(mut self, sol: f64) -> Self {
+        self.faucet_per_request_sol_cap = Some(sol);
+        self
+    }
+
+    /// Caps the SOL handed out to one address per time slice.
+    pub fn faucet_per_time_sol_cap(mut self, sol: f64) -> Self {
+        self.faucet_per_time_sol_cap = Some(sol);
+        self
+    }
+
+    /// Seeds the Sandbox's random number generator, overriding the
+    /// SOLARIUM_SEED environment variable.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the retry policy applied to transaction sends and account
+    /// fetches.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Throttles the RPC requests the Sandbox makes. See Sandbox::set_rate_limit.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Writes an Actor's keyfile only when Actor::keyfile is first called,
+    /// such as by a CLI deploy, rather than when the Actor is created.
+    /// Scenarios creating many Actors then avoid a file write for each.
+    pub fn lazy_keyfiles(mut self) -> Self {
+        self.lazy_keyfiles = true;
+        self
+    }
+
+    /// Creates the Sandbox's directory, which holds the ledger, validator
+    /// log, keyfiles, ledger snapshots, and downloaded programs, inside the
+    /// given directory rather than the system temporary directory.
+    pub fn workdir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.workdir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Keeps the Sandbox's directory after the Sandbox is dropped, for
+    /// inspecting the ledger and logs of a failed test. The directory is
+    /// logged when the Sandbox is dropped and is returned by Sandbox::tmpdir.
+    pub fn keep_artifacts(mut self, keep: bool) -> Self {
+        self.keep_artifacts = keep;
+        self
+    }
+
+    /// Sets the url of the cluster that cloned accounts are copied from.
+    pub fn clone_url(mut self, url: &str) -> Self {
+        self.clone_url = Some(String::from(url));
+        self
+    }
+
+    /// Copies an account, or a program along with its program data, from the
+    /// clone url into the Sandbox's genesis.
+    pub fn clone_account(mut self, pubkey: &Pubkey) -> Self {
+        self.clone_accounts.push(*pubkey);
+        self
+    }
+
+    /// Starts the ledger at the given slot.
+    pub fn warp_slot(mut self, slot: u64) -> Self {
+        self.warp_slot = Some(slot);
+        self
+    }
+
+    /// Registers a name for an address once the Sandbox has started.
+    pub fn name(mut self, pubkey: &Pubkey, name: &str) -> Self {
+        self.names.push((*pubkey, String::from(name)));
+        self
+    }
+
+    /// Approximates a fork of a remote cluster: every program and account in
+    /// the manifest is cloned from rpc_url, labelled, and the ledger starts at
+    /// the given slot.
+    ///
+    /// Accounts are cloned in their state at the time the Sandbox starts, not
+    /// as of the slot, since the validator can only fetch current state.
+    pub fn fork_from(mut self, rpc_url: &str, slot: u64, manifest: &ForkManifest) -> Result<Self> {
+        self = self.clone_url(rpc_url).warp_slot(slot);
+        for (pubkey, label) in manifest.entries()? {
+            self = self.clone_account(&pubkey);
+            if let Some(label) = label {
+                self = self.name(&pubkey, &label);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Loads an account into the Sandbox's genesis from a file in the format
+    /// described by AccountFile.
+    pub fn account_file<P: AsRef<Path>>(mut self, pubkey: &Pubkey, path: P) -> Self {
+        self.account_files
+            .push((*pubkey, path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Funds an account with lamports at genesis, so it needs no airdrop.
+    pub fn genesis_account(mut self, pubkey: &Pubkey, lamports: u64) -> Self {
+        self.genesis_accounts.push((*pubkey, lamports));
+        self
+    }
+
+    /// Funds the Actor that Actor::from_path creates for the path with
+    /// lamports at genesis. Tests that need many funded actors skip the
+    /// airdrops and their confirmations:
+    ///
+    /// ```ignore
+    /// let mut builder = SandboxBuilder::fast();
+    /// for i in 0..50 {
+    ///     builder = builder.genesis_actor(&format!("trader-{}", i), LAMPORTS_PER_SOL);
+    /// }
+    /// let sandbox = builder.build()?;
+    /// let trader = Actor::from_path(&sandbox, "trader-0")?;
+    /// ```
+    pub fn genesis_actor(mut self, path: &str, lamports: u64) -> Self {
+        self.genesis_actors.push((String::from(path), lamports));
+        self
+    }
+
+    /// Loads every account file in a directory, such as one written by
+    /// Sandbox::dump_accounts, into the Sandbox's genesis.
+    pub fn import_accounts<P: AsRef<Path>>(mut self, dir: P) -> Result<Self> {
+        for (pubkey, path) in dump::read_account_dir(dir)? {
+            self = self.account_file(&pubkey, path);
+        }
+        Ok(self)
+    }
+
+    /// Starts the Sandbox with a runtime feature deactivated, so that
+    /// programs can be tested under the behavior from before the feature was
+    /// activated. Every feature known to the validator is active by default.
+    /// Feature ids are listed by `solana feature status`.
+    pub fn deactivate_feature(mut self, feature: &Pubkey) -> Self {
+        self.deactivated_features.push(*feature);
+        self
+    }
+
+    /// Runs solana-test-validator inside a docker container started from the
+    /// given image, such as DEFAULT_DOCKER_IMAGE, for machines where the
+    /// Solana toolchain can't be installed natively. Only docker is needed
+    /// on the host: programs are deployed with the solana CLI inside the
+    /// container.
+    ///
+    /// The Sandbox's directory is mounted into the container at the same
+    /// path, so account files must be inside it, and the RPC, websocket, and
+    /// faucet ports are published on the host. The container is removed when
+    /// the Sandbox is dropped.
+    pub fn docker(mut self, image: &str) -> Self {
+        self.docker_image = Some(String::from(image));
+        self
+    }
+
+    /// Passes an additional argument to solana-test-validator.
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.extra_args.push(String::from(arg));
+        self
+    }
+
+    /// Returns the tuning flags passed to solana-test-validator.
+    fn validator_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(ticks) = self.ticks_per_slot {
+            args.extend([String::from("--ticks-per-slot"), ticks.to_string()]);
+        }
+        if let Some(slots) = self.slots_per_epoch {
+            args.extend([String::from("--slots-per-epoch"), slots.to_string()]);
+        }
+        if self.no_bpf_jit {
+            args.push(String::from("--no-bpf-jit"));
+        }
+        if let Some(sol) = self.faucet_sol {
+            args.extend([String::from("--faucet-sol"), sol.to_string()]);
+        }
+        if let Some(secs) = self.faucet_time_slice_secs {
+            args.extend([String::from("--faucet-time-slice-secs"), secs.to_string()]);
+        }
+        if let Some(sol) = self.faucet_per_request_sol_cap {
+            args.extend([
+                String::from("--faucet-per-request-sol-cap"),
+                sol.to_string(),
+            ]);
+        }
+        if let Some(sol) = self.faucet_per_time_sol_cap {
+            args.extend([String::from("--faucet-per-time-sol-cap"), sol.to_string()]);
+        }
+        if let Some(url) = &self.clone_url {
+            args.extend([String::from("--url"), url.clone()]);
+        }
+        for pubkey in self.clone_accounts.iter() {
+            args.extend([String::from("--clone"), pubkey.to_string()]);
+        }
+        for (pubkey, path) in self.account_files.iter() {
+            args.extend([
+                String::from("--account"),
+                pubkey.to_string(),
+                path.to_string_lossy().into_owned(),
+            ]);
+        }
+        for feature in self.deactivated_features.iter() {
+            args.extend([String::from("--deactivate-feature"), feature.to_string()]);
+        }
+        if let Some(slot) = self.warp_slot {
+            args.extend([String::from("--warp-slot"), slot.to_string()]);
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    /// Writes an account file for each account funded at genesis into the
+    /// Sandbox's directory and returns the flags loading them.
+    fn genesis_args(&self, dir: &Path, seed: u64) -> Result<Vec<String>> {
+        let mut accounts = self.genesis_accounts.clone();
+        for (path, lamports) in self.genesis_actors.iter() {
+            accounts.push((actor::path_keypair(seed, path)?.pubkey(), *lamports));
+        }
+        if accounts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let accounts: Vec<(Pubkey, Account)> = accounts
+            .into_iter()
+            .map(|(pubkey, lamports)| {
+                let account = Account::new(lamports, 0, &solana_sdk::system_program::id());
+                (pubkey, account)
+            })
+            .collect();
+        let genesis_dir = dir.join("genesis");
+        dump::write_account_dir(&genesis_dir, &accounts)?;
+        let mut args = Vec::with_capacity(3 * accounts.len());
+        for (pubkey, _) in accounts.iter() {
+            let path = genesis_dir.join(format!("{}.json", pubkey));
+            args.extend([
+                String::from("--account"),
+                pubkey.to_string(),
+                path.to_string_lossy().into_owned(),
+            ]);
+        }
+        Ok(args)
+    }
+
+    /// Starts the validator and blocks until the RPC server is ready to use.
+    #[tracing::instrument(name = "sandbox", skip_all, fields(port))]
+    pub fn build(self) -> Result<Sandbox> {
+        let tmp = self.workdir()?;
         let port = portpicker::pick_unused_port();
         let faucet = portpicker::pick_unused_port();
         if port.is_none() {
@@ -38,41 +454,303 @@ impl Sandbox {
 
         let port = port.expect("could not get port");
         let faucet = faucet.expect("could not get faucet");
-        let validator = process::Command::new("solana-test-validator")
-            .args([
-                "--ledger",
-                &tmp.path()
-                    .join("solana-test-validator-ledger")
-                    .into_os_string()
-                    .into_string()
-                    .expect("could not get tmp path"),
-                "--rpc-port",
-                &port.to_string(),
-                "--faucet-port",
-                &faucet.to_string(),
-            ])
-            .stdout(std::process::Stdio::null())
-            .spawn()?;
+        tracing::Span::current().record("port", &port);
 
-        let commitment_level = solana_sdk::commitment_config::CommitmentConfig::confirmed();
-        let client = rpc_client::RpcClient::new_with_commitment(
-            String::from("http://127.0.0.1:") + &port.to_string(),
-            commitment_level,
-        );
+        let rng = self.rng();
 
-        // Wait for the cluster to come online and respond to basic commands.
-        while client.get_latest_blockhash().is_err() {
-            thread::sleep(time::Duration::from_millis(10));
-        }
+        // Validator output is streamed to a log file so that it can be
+        // inspected when a test fails.
+        let log_path = tmp.path().join("validator.log");
+        let mut validator_args = self.validator_args();
+        validator_args.extend(self.genesis_args(tmp.path(), rng.seed())?);
+        let docker = self.docker_image.clone().map(|image| Docker {
+            image,
+            container: format!("solarium-{}", port),
+        });
+        let process = spawn_validator(
+            tmp.path(),
+            port,
+            faucet,
+            &validator_args,
+            &log_path,
+            docker.as_ref(),
+        )?;
+        let validator = LocalValidator {
+            process: Mutex::new(process),
+            args: validator_args,
+            docker,
+            faucet_port: faucet,
+        };
+
+        let url = String::from("http://127.0.0.1:") + &port.to_string();
+        let sandbox = self.assemble(tmp, rng, url, port, Some(validator));
+        sandbox.wait_for_validator()?;
+        tracing::info!(log = %sandbox.log_path.display(), "validator ready");
+        Ok(sandbox)
+    }
 
-        Ok(Self {
+    /// Connects to a validator that is already running, such as one started
+    /// by hand, a CI service, or devnet, instead of starting one. Blocks
+    /// until the validator responds.
+    ///
+    /// Options applying to the Sandbox itself, such as the seed, retry
+    /// policy, rate limit, workdir, and names, take effect. Options
+    /// configuring the validator are ignored, and operations that need the
+    /// validator process, such as restart and save_ledger, fail.
+    #[tracing::instrument(name = "sandbox", skip(self))]
+    pub fn connect(self, url: &str) -> Result<Sandbox> {
+        let port = url_port(url).ok_or_else(|| {
+            Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("could not determine the RPC port of {}", url),
+            ))
+        })?;
+        let tmp = self.workdir()?;
+        let rng = self.rng();
+        let sandbox = self.assemble(tmp, rng, String::from(url), port, None);
+        sandbox
+            .run_rpc(|| sandbox.client.get_latest_blockhash())
+            .with_context(|| format!("connecting to {}", url))?;
+        tracing::info!("connected to validator");
+        Ok(sandbox)
+    }
+
+    /// Creates the Sandbox's directory.
+    fn workdir(&self) -> Result<Workdir> {
+        let mut tmp_builder = tempfile::Builder::new();
+        tmp_builder.prefix("solarium");
+        let tmp = match &self.workdir {
+            Some(workdir) => {
+                fs::create_dir_all(workdir)?;
+                tmp_builder.tempdir_in(workdir)?
+            }
+            None => tmp_builder.tempdir()?,
+        };
+        Ok(if self.keep_artifacts {
+            Workdir::Kept(tmp.into_path())
+        } else {
+            Workdir::Temporary(tmp)
+        })
+    }
+
+    /// Returns the Sandbox's random number generator, printing its seed so
+    /// that a failing run can be replayed. The seed is printed to stdout
+    /// rather than only traced, so that it shows up in the output of every
+    /// failing test, whether or not it installed a subscriber.
+    fn rng(&self) -> SandboxRng {
+        let rng = match self.seed {
+            Some(seed) => SandboxRng::from_seed(seed),
+            None => SandboxRng::from_env(),
+        };
+        println!(
+            "solarium seed: {} (set {}={} to replay)",
+            rng.seed(),
+            rng::SEED_ENV_VAR,
+            rng.seed()
+        );
+        tracing::debug!(seed = rng.seed(), "seeded sandbox");
+        rng
+    }
+
+    /// Returns a Sandbox using the validator at the url.
+    fn assemble(
+        self,
+        tmp: Workdir,
+        rng: SandboxRng,
+        url: String,
+        port: u16,
+        validator: Option<LocalValidator>,
+    ) -> Sandbox {
+        let commitment_level = solana_sdk::commitment_config::CommitmentConfig::confirmed();
+        let client = rpc_client::RpcClient::new_with_commitment(url.clone(), commitment_level);
+        let log_path = tmp.path().join("validator.log");
+        Sandbox {
             tmp,
             validator,
+            url,
             port,
             client,
+            funding: FundingSource::default(),
+            log_path,
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            names: Arc::new(Mutex::new(
+                [
+                    (
+                        solana_sdk::system_program::id(),
+                        String::from("system_program"),
+                    ),
+                    (spl_token::id(), String::from("spl_token")),
+                ]
+                .into_iter()
+                .chain(self.names.into_iter())
+                .collect(),
+            )),
+            trace: Mutex::new(None),
+            decoders: Mutex::new(DecoderRegistry::new()),
+            tracked_accounts: Mutex::new(Vec::new()),
+            audit: Mutex::new(None),
+            profile: Mutex::new(None),
+            rng: Mutex::new(rng),
+            account_cache: AccountCache::default(),
+            ledger_snapshots: AtomicUsize::new(0),
+            rate_limiter: Mutex::new(self.rate_limit.map(RateLimiter::new)),
+            lazy_keyfiles: self.lazy_keyfiles,
+            #[cfg(feature = "fault-injection")]
+            faults: Mutex::new(None),
+        }
+    }
+}
+
+impl Sandbox {
+    /// Creates a Sandbox with the fast preset and blocks until the RPC server
+    /// is ready to use. Use SandboxBuilder for other validator settings.
+    pub fn new() -> Result<Self> {
+        SandboxBuilder::fast().build()
+    }
+
+    /// Connects to a validator that is already running at the url instead of
+    /// starting one. See SandboxBuilder::connect.
+    pub fn connect(url: &str) -> Result<Self> {
+        SandboxBuilder::new().connect(url)
+    }
+
+    /// Connects to the validator named by the SOLARIUM_URL environment
+    /// variable if it is set, funding refused airdrops from the keyfile named
+    /// by SOLARIUM_FUNDER if that is set too. Otherwise starts a validator as
+    /// Sandbox::new does, so that a suite can run against either.
+    pub fn from_env() -> Result<Self> {
+        let url = match env::var(URL_ENV_VAR) {
+            Ok(url) => url,
+            Err(_) => return Sandbox::new(),
+        };
+        let mut sandbox = Sandbox::connect(&url)?;
+        if let Ok(path) = env::var(FUNDER_ENV_VAR) {
+            let funder = read_keypair_file(&path).map_err(|err| {
+                Error::from(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("reading funder keyfile {}: {}", path, err),
+                ))
+            })?;
+            sandbox.set_funding_source(FundingSource::FaucetOrFunder(funder));
+        }
+        Ok(sandbox)
+    }
+
+    /// Creates a Sandbox with the fast preset that clones the programs and
+    /// accounts in the manifest from a remote cluster, approximating a fork
+    /// of it at the given slot. See SandboxBuilder::fork_from.
+    pub fn fork_from(rpc_url: &str, slot: u64, manifest: &ForkManifest) -> Result<Self> {
+        SandboxBuilder::fast()
+            .fork_from(rpc_url, slot, manifest)?
+            .build()
+    }
+
+    /// Creates a Sandbox with the fast preset whose genesis holds every
+    /// account dumped to a directory, for example by dump_accounts on another
+    /// Sandbox or checked into a repository as a test fixture.
+    ///
+    /// Accounts can only be loaded at genesis, so importing always starts a
+    /// new validator. See SandboxBuilder::import_accounts.
+    pub fn import_accounts<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        SandboxBuilder::fast().import_accounts(dir)?.build()
+    }
+
+    /// Writes the given accounts to a directory, one <pubkey>.json file per
+    /// account, in the format read by `solana-test-validator --account` and
+    /// written by `solana account --output json`. Fails if an account does
+    /// not exist.
+    pub fn dump_accounts<P: AsRef<Path>>(&self, dir: P, pubkeys: &[Pubkey]) -> Result<()> {
+        let accounts = pubkeys
+            .iter()
+            .map(|pubkey| Ok((*pubkey, self.get_account(pubkey)?)))
+            .collect::<Result<Vec<(Pubkey, Account)>>>()?;
+        dump::write_account_dir(dir, &accounts)
+    }
+
+    /// Returns the given accounts that still exist, for asserting that a
+    /// scenario closed every account it created and reclaimed its rent.
+    pub fn leaked_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Pubkey>> {
+        let mut leaked = Vec::new();
+        for chunk in pubkeys.chunks(MAX_ACCOUNTS_PER_REQUEST) {
+            let accounts = self.run_rpc(|| self.client.get_multiple_accounts(chunk))?;
+            leaked.extend(
+                chunk
+                    .iter()
+                    .zip(accounts.iter())
+                    .filter(|(_, account)| account.is_some())
+                    .map(|(pubkey, _)| *pubkey),
+            );
+        }
+        Ok(leaked)
+    }
+
+    /// Returns the slot at which a runtime feature was activated, or None if
+    /// the feature is inactive, including when it was deactivated with
+    /// SandboxBuilder::deactivate_feature.
+    pub fn feature_activation_slot(&self, feature: &Pubkey) -> Result<Option<u64>> {
+        let accounts = self.run_rpc(|| self.client.get_multiple_accounts(&[*feature]))?;
+        Ok(accounts
+            .into_iter()
+            .flatten()
+            .next()
+            .and_then(|account| solana_sdk::feature::from_account(&account))
+            .and_then(|feature| feature.activated_at))
+    }
+
+    /// Returns true if a runtime feature is active.
+    pub fn is_feature_active(&self, feature: &Pubkey) -> Result<bool> {
+        Ok(self.feature_activation_slot(feature)?.is_some())
+    }
+
+    /// Returns the directory holding the validator's ledger.
+    pub fn ledger_path(&self) -> PathBuf {
+        ledger_path(self.tmp.path())
+    }
+
+    /// Returns the name of the docker container running the validator, if
+    /// the Sandbox was built in docker mode.
+    pub fn docker_container(&self) -> Option<&str> {
+        self.docker().map(|docker| docker.container.as_str())
+    }
+
+    /// Returns whether the Sandbox started its validator, rather than
+    /// connecting to one with connect.
+    pub fn is_local(&self) -> bool {
+        self.validator.is_some()
+    }
+
+    /// Returns the validator process, failing on a connected Sandbox.
+    fn local(&self, operation: &str) -> Result<&LocalValidator> {
+        self.validator.as_ref().ok_or_else(|| {
+            Error::from(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "cannot {} a validator the Sandbox did not start ({})",
+                    operation, self.url
+                ),
+            ))
         })
     }
 
+    fn docker(&self) -> Option<&Docker> {
+        self.validator
+            .as_ref()
+            .and_then(|validator| validator.docker.as_ref())
+    }
+
+    /// Returns a command running the solana CLI, inside the validator's
+    /// container in docker mode.
+    pub(crate) fn solana_command(&self) -> process::Command {
+        match self.docker() {
+            Some(docker) => {
+                let mut command = process::Command::new("docker");
+                command.args(["exec", &docker.container, "solana"]);
+                command
+            }
+            None => process::Command::new("solana"),
+        }
+    }
+
     /// Returns the validator's RPC service port.
     pub fn port(&self) -> u16 {
         self.port
@@ -80,7 +758,51 @@ impl Sandbox {
 
     /// Returns the validator's RPC service url.
     pub fn url(&self) -> String {
-        String::from("http://127.0.0.1:") + &self.port.to_string()
+        self.url.clone()
+    }
+
+    /// Returns the port of the validator's faucet, or None if the Sandbox is
+    /// connected to a validator it did not start, whose faucet it does not
+    /// know.
+    pub fn faucet_port(&self) -> Option<u16> {
+        self.validator
+            .as_ref()
+            .map(|validator| validator.faucet_port)
+    }
+
+    /// Returns the address of the validator's faucet, in the form the
+    /// solana CLI's --faucet-host and --faucet-port options or a JS test's
+    /// faucet client expect, or None as faucet_port does.
+    pub fn faucet_url(&self) -> Option<String> {
+        self.faucet_port()
+            .map(|port| String::from("127.0.0.1:") + &port.to_string())
+    }
+
+    /// Returns the validator's websocket url for subscriptions. A validator
+    /// serves it on the port after its RPC port; a url without a port, such
+    /// as a public cluster's, keeps the scheme's default port.
+    pub fn ws_url(&self) -> String {
+        let (scheme, rest) = match self.url.split_once("://") {
+            Some(("https", rest)) => ("wss", rest),
+            Some((_, rest)) => ("ws", rest),
+            None => ("ws", self.url.as_str()),
+        };
+        let host = rest.split('/').next().unwrap_or(rest);
+        match host.rsplit_once(':') {
+            Some((host, _)) => format!("{}://{}:{}", scheme, host, self.port + 1),
+            None => format!("{}://{}", scheme, host),
+        }
+    }
+
+    /// Sets where airdrops get their lamports, for clusters such as devnet
+    /// or a validator without a faucet, where airdrops fail.
+    pub fn set_funding_source(&mut self, source: FundingSource) {
+        self.funding = source;
+    }
+
+    /// Returns where airdrops get their lamports.
+    pub fn funding_source(&self) -> &FundingSource {
+        &self.funding
     }
 
     /// Returns an RPC client that is connected to the validator.
@@ -90,38 +812,1370 @@ impl Sandbox {
 
     /// Returns a temporary directory associated with this Sandbox.
     pub fn tmpdir(&self) -> &Path {
-        self.tmp.as_ref()
+        self.tmp.path()
+    }
+
+    /// Returns whether Actors write their keyfiles only when first needed.
+    /// See SandboxBuilder::lazy_keyfiles.
+    pub fn lazy_keyfiles(&self) -> bool {
+        self.lazy_keyfiles
+    }
+
+    /// Returns the Sandbox's random number generator. Randomized helpers draw
+    /// from it so that a run can be replayed from its seed.
+    pub fn rng(&self) -> MutexGuard<SandboxRng> {
+        self.rng.lock().expect("rng lock poisoned")
+    }
+
+    /// Returns the seed of the Sandbox's random number generator.
+    pub fn seed(&self) -> u64 {
+        self.rng().seed()
     }
 
-    /// Create & send signed transaction with payers from instructions
-    pub fn send_signed_transaction_with_payers(
+    /// Restarts the Sandbox's random number generator from the given seed.
+    pub fn set_seed(&self, seed: u64) {
+        *self.rng() = SandboxRng::from_seed(seed);
+    }
+
+    /// Returns the retry policy applied to transaction sends and account
+    /// fetches.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Sets the retry policy applied to transaction sends and account fetches.
+    /// Individual transactions may override it through TransactionBuilder.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Fetches an account, retrying transient failures according to the
+    /// retry policy. Fails if the account does not exist. Served from the
+    /// account cache when it is enabled.
+    pub fn get_account(&self, pubkey: &Pubkey) -> Result<Account> {
+        self.get_account_if_exists(pubkey)?.ok_or_else(|| {
+            Error::from(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("account {} not found", self.label(pubkey)),
+            ))
+        })
+    }
+
+    /// Fetches an account, or None if it does not exist, retrying transient
+    /// failures according to the retry policy. Served from the account cache
+    /// when it is enabled, which also records accounts that do not exist.
+    pub fn get_account_if_exists(&self, pubkey: &Pubkey) -> Result<Option<Account>> {
+        let fetch = || {
+            self.run_rpc(|| {
+                self.client
+                    .get_account_with_commitment(pubkey, self.client.commitment())
+            })
+            .map(|response| response.value)
+        };
+        if !self.account_cache.is_enabled() {
+            return fetch();
+        }
+        if let Some(account) = self.account_cache.lookup(pubkey) {
+            return Ok(account);
+        }
+        let generation = self.account_cache.generation();
+        let account = fetch()?;
+        self.account_cache.fill(pubkey, account.clone(), generation);
+        Ok(account)
+    }
+
+    /// Fetches an account's data, retrying transient failures according to
+    /// the retry policy. Served from the account cache when it is enabled.
+    pub fn get_account_data(&self, pubkey: &Pubkey) -> Result<Vec<u8>> {
+        if self.account_cache.is_enabled() {
+            return Ok(self.get_account(pubkey)?.data);
+        }
+        self.run_rpc(|| self.client.get_account_data(pubkey))
+    }
+
+    /// Fetches up to len bytes of an account's data starting at offset, so
+    /// that headers and single fields of large accounts such as order books
+    /// and queues can be read without transferring the whole account. The
+    /// result is shorter than len if the account ends first. Served from the
+    /// account cache when the account is cached.
+    pub fn get_account_slice(&self, pubkey: &Pubkey, offset: usize, len: usize) -> Result<Vec<u8>> {
+        if self.account_cache.is_enabled() {
+            if let Some(account) = self.account_cache.lookup(pubkey) {
+                let account = account.ok_or_else(|| {
+                    Error::from(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("account {} not found", self.label(pubkey)),
+                    ))
+                })?;
+                let start = offset.min(account.data.len());
+                let end = offset.saturating_add(len).min(account.data.len());
+                return Ok(account.data[start..end].to_vec());
+            }
+        }
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            data_slice: Some(UiDataSliceConfig {
+                offset,
+                length: len,
+            }),
+            commitment: Some(self.client.commitment()),
+        };
+        self.run_rpc(|| self.client.get_account_with_config(pubkey, config.clone()))?
+            .value
+            .map(|account| account.data)
+            .ok_or_else(|| {
+                Error::from(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("account {} not found", self.label(pubkey)),
+                ))
+            })
+    }
+
+    /// Returns the accounts owned by a program that match the filter, sorted
+    /// by address, retrying transient failures according to the retry
+    /// policy. The account cache is bypassed.
+    pub fn find_program_accounts(
         &self,
-        instructions: &[Instruction],
-        payer: Option<&Pubkey>,
-        signers: Vec<&Keypair>,
-    ) -> Result<()> {
-        let recent_hash = self.client.get_latest_blockhash()?;
-        let transaction =
-            Transaction::new_signed_with_payer(instructions, payer, &signers, recent_hash);
-        self.client.send_and_confirm_transaction(&transaction)?;
+        program_id: &Pubkey,
+        filter: &AccountFilter,
+    ) -> Result<Vec<(Pubkey, Account)>> {
+        let config = filter.config(self.client.commitment());
+        let mut accounts = self.run_rpc(|| {
+            self.client
+                .get_program_accounts_with_config(program_id, config.clone())
+        })?;
+        accounts.sort_by_key(|(pubkey, _)| *pubkey);
+        Ok(accounts)
+    }
+
+    /// Returns the cache in front of get_account and get_account_data.
+    pub fn account_cache(&self) -> &AccountCache {
+        &self.account_cache
+    }
+
+    /// Keeps the cached state of an account up to date from the validator's
+    /// account notifications, so that changes made outside this Sandbox are
+    /// seen without invalidating it.
+    pub fn subscribe_account(&self, pubkey: &Pubkey) -> Result<()> {
+        self.account_cache
+            .subscribe(&self.ws_url(), pubkey, self.client.commitment())
+    }
+
+    /// Associates a human-readable name with an address. Registered names are
+    /// substituted for addresses in program logs and rendered errors.
+    /// Registering an address again replaces its name.
+    pub fn register_name(&self, pubkey: &Pubkey, name: &str) {
+        self.names
+            .lock()
+            .expect("name registry lock poisoned")
+            .insert(*pubkey, String::from(name));
+    }
+
+    /// Returns the name registered for an address, if any.
+    pub fn name_of(&self, pubkey: &Pubkey) -> Option<String> {
+        self.names
+            .lock()
+            .expect("name registry lock poisoned")
+            .get(pubkey)
+            .cloned()
+    }
+
+    /// Returns the name registered for an address, or the address itself.
+    pub fn label(&self, pubkey: &Pubkey) -> String {
+        self.name_of(pubkey).unwrap_or_else(|| pubkey.to_string())
+    }
+
+    /// Replaces every registered address appearing in the text with its name.
+    pub fn render(&self, text: &str) -> String {
+        let names = self.names.lock().expect("name registry lock poisoned");
+        let mut rendered = String::from(text);
+        for (pubkey, name) in names.iter() {
+            let address = pubkey.to_string();
+            if rendered.contains(&address) {
+                rendered = rendered.replace(&address, name);
+            }
+        }
+        rendered
+    }
+
+    /// Returns the shared name registry, for background threads that render
+    /// names after the Sandbox has been borrowed elsewhere.
+    pub(crate) fn shared_names(&self) -> Arc<Mutex<HashMap<Pubkey, String>>> {
+        self.names.clone()
+    }
+
+    /// Renders an error with registered names substituted for addresses.
+    pub fn describe_error(&self, err: &Error) -> String {
+        self.render(&format!("{:?}", err))
+    }
+
+    /// Registers the decoder used to describe instructions sent to a program
+    /// in traces and decoded transactions, replacing any decoder registered
+    /// for it before. See DecoderRegistry.
+    pub fn register_decoder<D: InstructionDecoder + 'static>(
+        &self,
+        program_id: &Pubkey,
+        decoder: D,
+    ) {
+        self.decoders
+            .lock()
+            .expect("decoder registry lock poisoned")
+            .register(program_id, decoder);
+    }
+
+    /// Decodes an instruction sent to the given program with the registered
+    /// decoders. Returns None if the program or the data is not recognized.
+    pub fn decode_instruction(
+        &self,
+        program_id: &Pubkey,
+        data: &[u8],
+    ) -> Option<DecodedInstruction> {
+        self.decoders
+            .lock()
+            .expect("decoder registry lock poisoned")
+            .decode(program_id, data)
+    }
+
+    /// Returns a copy of the registered decoders.
+    pub fn decoders(&self) -> DecoderRegistry {
+        self.decoders
+            .lock()
+            .expect("decoder registry lock poisoned")
+            .clone()
+    }
+
+    /// Enables trace mode. Every transaction send attempt is then
+    /// appended to the given file as a JSON line describing its instructions,
+    /// signers, outcome, compute units, and logs. See TraceEntry.
+    pub fn enable_trace(&self, path: &Path) -> Result<()> {
+        let file = fs::File::create(path)?;
+        *self.trace.lock().expect("trace lock poisoned") = Some(file);
         Ok(())
     }
 
-    /// Create & send transaction with payers from instructions
-    pub fn send_transaction_with_payer(
+    /// Disables trace mode.
+    pub fn disable_trace(&self) {
+        *self.trace.lock().expect("trace lock poisoned") = None;
+    }
+
+    /// Returns true if trace mode is enabled.
+    pub fn is_tracing(&self) -> bool {
+        self.trace.lock().expect("trace lock poisoned").is_some()
+    }
+
+    /// Appends a send attempt to the trace file if trace mode is enabled,
+    /// and records it for a running LamportAudit. Tracing is best effort and
+    /// never fails the send.
+    pub(crate) fn trace_transaction(
         &self,
-        instructions: &[Instruction],
-        payer: Option<&Pubkey>,
-    ) -> Result<()> {
-        let transaction = Transaction::new_with_payer(instructions, payer);
-        self.client.send_and_confirm_transaction(&transaction)?;
+        transaction: &Transaction,
+        result: &std::result::Result<Signature, ClientError>,
+    ) {
+        self.audit_transaction(
+            &transaction.signatures[0],
+            &transaction.message.account_keys[0],
+        );
+        self.profile_transaction(transaction);
+        if !self.is_tracing() {
+            return;
+        }
+        let entry = TraceEntry::new(self, transaction, result);
+        if let Some(file) = self.trace.lock().expect("trace lock poisoned").as_mut() {
+            if let Ok(line) = serde_json::to_string(&entry) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    /// Records an account created through an Actor, for LamportAudit.
+    pub(crate) fn track_account(&self, pubkey: &Pubkey) {
+        self.tracked_accounts
+            .lock()
+            .expect("tracked accounts lock poisoned")
+            .push(*pubkey);
+    }
+
+    /// Returns the accounts of the Actors created in this Sandbox, in the
+    /// order they were created.
+    pub fn tracked_accounts(&self) -> Vec<Pubkey> {
+        let mut seen = std::collections::HashSet::new();
+        self.tracked_accounts
+            .lock()
+            .expect("tracked accounts lock poisoned")
+            .iter()
+            .filter(|pubkey| seen.insert(**pubkey))
+            .copied()
+            .collect()
+    }
+
+    /// Starts recording transactions and airdrops for a LamportAudit.
+    pub(crate) fn start_audit(&self) {
+        *self.audit.lock().expect("audit lock poisoned") = Some(AuditLog::default());
+    }
+
+    /// Stops recording and returns what was recorded, if an audit was
+    /// running.
+    pub(crate) fn stop_audit(&self) -> Option<AuditLog> {
+        self.audit.lock().expect("audit lock poisoned").take()
+    }
+
+    /// Records a transaction send attempt if an audit is running.
+    pub(crate) fn audit_transaction(&self, signature: &Signature, payer: &Pubkey) {
+        if let Some(log) = self.audit.lock().expect("audit lock poisoned").as_mut() {
+            log.record_transaction(*signature, *payer);
+        }
+    }
+
+    /// Starts recording transactions for a ComputeProfiler.
+    pub(crate) fn start_profile(&self) {
+        *self.profile.lock().expect("profile lock poisoned") = Some(Vec::new());
+    }
+
+    /// Stops recording and returns the recorded transactions, if a profiler
+    /// was running.
+    pub(crate) fn stop_profile(&self) -> Option<Vec<ProfiledTransaction>> {
+        self.profile.lock().expect("profile lock poisoned").take()
+    }
+
+    /// Records a transaction send attempt if a profiler is running.
+    fn profile_transaction(&self, transaction: &Transaction) {
+        if let Some(log) = self.profile.lock().expect("profile lock poisoned").as_mut() {
+            let keys = &transaction.message.account_keys;
+            log.push(ProfiledTransaction {
+                signature: transaction.signatures[0],
+                instructions: transaction
+                    .message
+                    .instructions
+                    .iter()
+                    .map(|instruction| {
+                        (
+                            keys[instruction.program_id_index as usize],
+                            instruction.data.clone(),
+                        )
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    /// Records an airdrop if an audit is running.
+    pub(crate) fn audit_airdrop(&self, pubkey: &Pubkey, lamports: u64) {
+        if let Some(log) = self.audit.lock().expect("audit lock poisoned").as_mut() {
+            log.record_airdrop(*pubkey, lamports);
+        }
+    }
+
+    /// Reports whether the validator process is running, its current slot,
+    /// and how long the slot request took.
+    /// A connected Sandbox reports its validator running while it responds.
+    pub fn health(&self) -> Result<Health> {
+        let exit_status = match &self.validator {
+            Some(validator) => validator
+                .process
+                .lock()
+                .expect("validator lock poisoned")
+                .try_wait()?,
+            None => None,
+        };
+        let start = time::Instant::now();
+        let slot = self.client.get_slot().ok();
+        let rpc_latency = slot.map(|_| start.elapsed());
+        Ok(Health {
+            running: exit_status.is_none() && (self.is_local() || slot.is_some()),
+            exit_status: exit_status.map(|status| status.to_string()),
+            slot,
+            rpc_latency,
+        })
+    }
+
+    /// Returns up to limit of the validator's performance samples, newest
+    /// first, or all it keeps if limit is None. The validator takes a sample
+    /// every minute; PerformanceMeter measures shorter windows.
+    pub fn performance_samples(&self, limit: Option<usize>) -> Result<Vec<PerformanceSample>> {
+        let samples = self.run_rpc(|| self.client.get_recent_performance_samples(limit))?;
+        Ok(samples.into_iter().map(PerformanceSample::from).collect())
+    }
+
+    /// Fails with a descriptive error if the validator process has exited.
+    /// Always succeeds on a connected Sandbox.
+    pub fn check_alive(&self) -> Result<()> {
+        let validator = match &self.validator {
+            Some(validator) => validator,
+            None => return Ok(()),
+        };
+        match validator
+            .process
+            .lock()
+            .expect("validator lock poisoned")
+            .try_wait()?
+        {
+            Some(status) => Err(validator_exited(&self.log_path, status)),
+            None => Ok(()),
+        }
+    }
+
+    /// Blocks until the validator the Sandbox started is ready to use.
+    fn wait_for_validator(&self) -> Result<()> {
+        let validator = self.local("wait for")?;
+        let mut process = validator.process.lock().expect("validator lock poisoned");
+        wait_until_ready(&self.client, &mut process, &self.log_path)
+    }
+
+    /// Stops the validator and starts it again on the same ports from the
+    /// preserved ledger, blocking until the RPC server is ready to use.
+    ///
+    /// Accounts, programs, and balances survive the restart. Clients
+    /// connected to the old process, including subscriptions and background
+    /// threads such as recorders, observe the interruption and must
+    /// reconnect on their own, which makes this useful for testing recovery
+    /// logic. Cached accounts are dropped.
+    pub fn restart(&self) -> Result<()> {
+        self.restart_with(|_| Ok(()))
+    }
+
+    /// Saves a copy of the ledger that restore_ledger can later restore, so
+    /// that a suite can set up shared state once and reset to it between
+    /// cases without creating a new Sandbox.
+    ///
+    /// This is not cheap. The validator offers no way to overwrite accounts
+    /// over RPC, and the ledger can only be copied consistently while the
+    /// validator is stopped, so saving and restoring both restart the
+    /// validator and copy the whole ledger, which takes seconds rather than
+    /// milliseconds. It still avoids funding actors, deploying programs, and
+    /// creating markets again. Connected clients are affected as by restart.
+    /// Snapshots are deleted with the Sandbox.
+    pub fn save_ledger(&self) -> Result<LedgerSnapshot> {
+        let slot = self.run_rpc(|| self.client.get_slot())?;
+        let id = self.ledger_snapshots.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .tmp
+            .path()
+            .join("ledger-snapshots")
+            .join(id.to_string());
+        self.restart_with(|ledger| copy_dir(ledger, &path))?;
+        Ok(LedgerSnapshot { path, slot })
+    }
+
+    /// Restores every account to its state when the snapshot was saved,
+    /// discarding all later transactions. The validator is restarted from a
+    /// copy of the saved ledger, at the cost described in save_ledger. A
+    /// snapshot can be restored any number of times.
+    pub fn restore_ledger(&self, snapshot: &LedgerSnapshot) -> Result<()> {
+        if !snapshot.path.starts_with(self.tmp.path()) {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ledger snapshot was saved from a different sandbox",
+            )));
+        }
+        self.restart_with(|ledger| {
+            fs::remove_dir_all(ledger)?;
+            copy_dir(&snapshot.path, ledger)
+        })
+    }
+
+    /// Restarts the validator at a later slot, skipping the slots in
+    /// between, so that logic that depends on the slot, epoch, or clock can
+    /// be tested without waiting for it. Accounts survive the warp, with the
+    /// same effect on connected clients as restart. The slot must be ahead
+    /// of the validator's current slot.
+    pub fn warp_to_slot(&self, slot: u64) -> Result<()> {
+        let current = self.run_rpc(|| self.client.get_slot())?;
+        if slot <= current {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot warp to slot {} from slot {}", slot, current),
+            )));
+        }
+        self.restart_with_args(&[String::from("--warp-slot"), slot.to_string()], |_| Ok(()))
+    }
+
+    /// Stops the validator, runs the given operation on its ledger
+    /// directory, and starts the validator again. The validator is started
+    /// even if the operation fails, in which case its error is returned.
+    fn restart_with<F>(&self, operation: F) -> Result<()>
+    where
+        F: FnOnce(&Path) -> Result<()>,
+    {
+        self.restart_with_args(&[], operation)
+    }
+
+    /// Like restart_with, but starts the validator with extra arguments for
+    /// this start only. The validator only accepts a warp slot ahead of its
+    /// ledger, so the one it was built with is not passed again.
+    #[tracing::instrument(skip_all, fields(port = self.port))]
+    fn restart_with_args<F>(&self, extra_args: &[String], operation: F) -> Result<()>
+    where
+        F: FnOnce(&Path) -> Result<()>,
+    {
+        let local = self.local("restart")?;
+        let mut args = Vec::new();
+        let mut built_args = local.args.iter();
+        while let Some(arg) = built_args.next() {
+            if arg == "--warp-slot" {
+                built_args.next();
+            } else {
+                args.push(arg.clone());
+            }
+        }
+        args.extend_from_slice(extra_args);
+
+        let mut validator = local.process.lock().expect("validator lock poisoned");
+        stop_validator(&mut validator, local.docker.as_ref());
+
+        let result = operation(&ledger_path(self.tmp.path()));
+        *validator = spawn_validator(
+            self.tmp.path(),
+            self.port,
+            local.faucet_port,
+            &args,
+            &self.log_path,
+            local.docker.as_ref(),
+        )?;
+        self.account_cache.invalidate_all();
+        wait_until_ready(&self.client, &mut validator, &self.log_path)?;
+        result
+    }
+
+    /// Injects faults into every transaction sent through the Sandbox
+    /// according to the policy, replacing any previous policy. Faults are
+    /// drawn from a generator with the given seed, or with a seed drawn from
+    /// the Sandbox's generator, so that the same seed injects the same
+    /// sequence of faults.
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_faults(&self, policy: FaultPolicy, seed: Option<u64>) {
+        let seed = seed.unwrap_or_else(|| self.rng().gen_range(0, u64::MAX));
+        *self.faults.lock().expect("fault injector lock poisoned") =
+            Some(FaultInjector::new(policy, SandboxRng::from_seed(seed)));
+    }
+
+    /// Stops injecting faults and returns the faults injected under the
+    /// removed policy.
+    #[cfg(feature = "fault-injection")]
+    pub fn clear_faults(&self) -> FaultStats {
+        self.faults
+            .lock()
+            .expect("fault injector lock poisoned")
+            .take()
+            .map(|injector| injector.stats())
+            .unwrap_or_default()
+    }
+
+    /// Returns the faults injected so far under the current policy.
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_stats(&self) -> FaultStats {
+        self.faults
+            .lock()
+            .expect("fault injector lock poisoned")
+            .as_ref()
+            .map(|injector| injector.stats())
+            .unwrap_or_default()
+    }
+
+    /// Throttles the RPC requests made through the Sandbox: account fetches
+    /// and transaction sends, including those of TransactionBuilder. Calls
+    /// made directly on Sandbox::client are not throttled. None removes the
+    /// limit.
+    pub fn set_rate_limit(&self, limit: Option<RateLimit>) {
+        *self
+            .rate_limiter
+            .lock()
+            .expect("rate limiter lock poisoned") = limit.map(RateLimiter::new);
+    }
+
+    /// Returns the requests throttled so far under the current rate limit.
+    pub fn rate_limit_stats(&self) -> RateLimitStats {
+        self.rate_limiter
+            .lock()
+            .expect("rate limiter lock poisoned")
+            .as_ref()
+            .map(|limiter| limiter.stats())
+            .unwrap_or_default()
+    }
+
+    /// Waits for the rate limiter to admit a request, or fails if it rejects
+    /// the request. The lock is released before waiting so that other
+    /// threads can queue behind this request.
+    pub(crate) fn throttle(&self) -> ClientResult<()> {
+        let delay = match self
+            .rate_limiter
+            .lock()
+            .expect("rate limiter lock poisoned")
+            .as_mut()
+        {
+            Some(limiter) => limiter.acquire()?,
+            None => return Ok(()),
+        };
+        if !delay.is_zero() {
+            tracing::debug!(delay = ?delay, "rate limited");
+            thread::sleep(delay);
+        }
         Ok(())
     }
+
+    /// Runs a transaction send, subject to the injected fault policy when
+    /// fault injection is enabled.
+    pub(crate) fn send_with_faults<F>(&self, send: F) -> ClientResult<Signature>
+    where
+        F: FnOnce() -> ClientResult<Signature>,
+    {
+        #[cfg(feature = "fault-injection")]
+        {
+            let fault = self
+                .faults
+                .lock()
+                .expect("fault injector lock poisoned")
+                .as_mut()
+                .map_or(Fault::None, |injector| injector.next());
+            match fault {
+                Fault::Timeout => return Err(fault::timeout_error("transaction was not sent")),
+                Fault::DropConfirmation => {
+                    send()?;
+                    return Err(fault::timeout_error("confirmation was dropped"));
+                }
+                Fault::Delay(delay) => thread::sleep(delay),
+                Fault::None => {}
+            }
+        }
+        send()
+    }
+
+    /// Runs an RPC operation under the retry policy. If it fails because the
+    /// validator has died, the failure is replaced by a descriptive error.
+    pub(crate) fn run_rpc<T, F>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> ClientResult<T>,
+    {
+        self.retry_policy
+            .run(|| {
+                self.throttle()?;
+                operation()
+            })
+            .map_err(|err| self.check_alive().err().unwrap_or(err))
+    }
+
+    /// Returns the path of the file capturing the validator's output.
+    pub fn validator_log_path(&self) -> &Path {
+        &self.log_path
+    }
+
+    /// Returns up to the last n lines of validator output.
+    pub fn tail_logs(&self, n: usize) -> Result<Vec<String>> {
+        let contents = fs::read_to_string(&self.log_path)?;
+        let lines: Vec<&str> = contents.lines().collect();
+        let start = lines.len().saturating_sub(n);
+        Ok(lines[start..].iter().map(|line| line.to_string()).collect())
+    }
+
+    /// Returns the program log messages emitted by a confirmed transaction,
+    /// with registered names substituted for addresses.
+    pub fn program_logs(&self, signature: &Signature) -> Result<Vec<String>> {
+        let transaction = self
+            .client
+            .get_transaction(signature, UiTransactionEncoding::Json)?;
+        Ok(transaction
+            .transaction
+            .meta
+            .and_then(|meta| meta.log_messages)
+            .unwrap_or_default()
+            .iter()
+            .map(|line| self.render(line))
+            .collect())
+    }
+
+    /// Fetches a confirmed transaction and decodes each of its instructions
+    /// with the registered decoders, in order. Instructions of unknown
+    /// programs are None.
+    pub fn decode_transaction(
+        &self,
+        signature: &Signature,
+    ) -> Result<Vec<Option<DecodedInstruction>>> {
+        let confirmed = self
+            .client
+            .get_transaction(signature, UiTransactionEncoding::Base64)?;
+        let transaction = confirmed.transaction.transaction.decode().ok_or_else(|| {
+            Error::from(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("could not decode transaction {}", signature),
+            ))
+        })?;
+        Ok(self
+            .decoders
+            .lock()
+            .expect("decoder registry lock poisoned")
+            .decode_message(&transaction.message))
+    }
+
+    /// Polls the given account until the predicate accepts its data, and
+    /// returns the value produced by the predicate. The predicate is expected
+    /// to decode the account data and return None while the desired state has
+    /// not been reached. Accounts that do not exist yet are not passed to the
+    /// predicate. Fails with a TimedOut error if the timeout elapses first.
+    pub fn wait_for_account_change<T, F>(
+        &self,
+        pubkey: &Pubkey,
+        mut predicate: F,
+        timeout: time::Duration,
+    ) -> Result<T>
+    where
+        F: FnMut(&[u8]) -> Option<T>,
+    {
+        let deadline = time::Instant::now() + timeout;
+        loop {
+            let account = self
+                .run_rpc(|| {
+                    self.client
+                        .get_account_with_commitment(pubkey, self.client.commitment())
+                })?
+                .value;
+            if let Some(account) = account {
+                if let Some(value) = predicate(&account.data) {
+                    return Ok(value);
+                }
+            }
+            if time::Instant::now() >= deadline {
+                return Err(Error::from(io::Error::from(io::ErrorKind::TimedOut)));
+            }
+            self.check_alive()?;
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    /// Airdrops lamports to each of the given actors. All airdrop requests are
+    /// sent up front and then confirmed together, so funding N actors waits on
+    /// a single confirmation loop rather than N sequential ones.
+    pub fn airdrop_many(&self, targets: &[(&Actor, u64)]) -> Result<()> {
+        let pending = targets
+            .iter()
+            .map(|(actor, lamports)| actor.request_airdrop(*lamports))
+            .collect::<Result<Vec<_>>>()?;
+        self.confirm_all(&pending)
+    }
+
+    /// Blocks until every pending airdrop is confirmed, polling their
+    /// signatures together rather than one at a time.
+    pub fn confirm_all(&self, pending: &[PendingAirdrop]) -> Result<()> {
+        let signatures: Vec<Signature> = pending.iter().map(|airdrop| airdrop.signature).collect();
+        self.confirm_signatures(&signatures)
+    }
+
+    /// Funds an account by requesting an airdrop transaction from the faucet
+    /// directly and sending it, the way the solana CLI does, rather than
+    /// through the RPC service's requestAirdrop. This exercises the same path
+    /// as external tools pointed at faucet_url, and is subject to the
+    /// faucet's rate limits.
+    pub fn fund_with_faucet(&self, pubkey: &Pubkey, lamports: u64) -> Result<Signature> {
+        let faucet_port = self.local("use the faucet of")?.faucet_port;
+        let faucet = net::SocketAddr::from(([127, 0, 0, 1], faucet_port));
+        let blockhash = self.run_rpc(|| self.client.get_latest_blockhash())?;
+        let transaction = solana_faucet::faucet::request_airdrop_transaction(
+            &faucet, pubkey, lamports, blockhash,
+        )
+        .map_err(|err| Error::from(io::Error::new(io::ErrorKind::Other, err)))?;
+        // Sent like an airdrop rather than through send_transaction, so that
+        // audits see lamports arriving instead of a fee paid by the faucet.
+        let signature = self.run_rpc(|| self.client.send_and_confirm_transaction(&transaction))?;
+        self.audit_airdrop(pubkey, lamports);
+        self.account_cache.invalidate(pubkey);
+        Ok(signature)
+    }
+
+    /// Requests an airdrop without waiting for it, from the faucet or the
+    /// funder as the funding source directs.
+    pub(crate) fn request_airdrop(&self, pubkey: &Pubkey, lamports: u64) -> Result<Signature> {
+        if self.funding.uses_faucet() {
+            match self.run_rpc(|| self.client.request_airdrop(pubkey, lamports)) {
+                Ok(signature) => return Ok(signature),
+                Err(err) if self.funding.funder().is_some() => {
+                    tracing::debug!(error = %err, "faucet refused airdrop, using funder");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let funder = self
+            .funding
+            .funder()
+            .expect("funding sources without a faucet have a funder");
+        let blockhash = self.run_rpc(|| self.client.get_latest_blockhash())?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[system_instruction::transfer(
+                &funder.pubkey(),
+                pubkey,
+                lamports,
+            )],
+            Some(&funder.pubkey()),
+            &[funder],
+            blockhash,
+        );
+        let signature = self
+            .run_rpc(|| self.client.send_transaction(&transaction))
+            .with_context(|| format!("transferring from funder {}", funder.pubkey()))?;
+        self.account_cache.invalidate(&funder.pubkey());
+        Ok(signature)
+    }
+
+    /// Blocks until every given signature is confirmed. Returns the first
+    /// transaction error encountered, if any.
+    pub(crate) fn confirm_signatures(&self, signatures: &[Signature]) -> Result<()> {
+        for outcome in self.signature_outcomes(signatures)? {
+            outcome.map_err(ClientError::from)?;
+        }
+        Ok(())
+    }
+
+    /// Waits until every transaction has either failed or been confirmed,
+    /// and returns the outcome of each, in order.
+    fn signature_outcomes(
+        &self,
+        signatures: &[Signature],
+    ) -> Result<Vec<std::result::Result<(), TransactionError>>> {
+        // getSignatureStatuses accepts at most 256 signatures per request.
+        const MAX_SIGNATURES_PER_REQUEST: usize = 256;
+
+        let mut outcomes = vec![Ok(()); signatures.len()];
+        let mut pending: Vec<usize> = (0..signatures.len()).collect();
+        while !pending.is_empty() {
+            let mut still_pending = Vec::new();
+            for chunk in pending.chunks(MAX_SIGNATURES_PER_REQUEST) {
+                let chunk_signatures: Vec<Signature> =
+                    chunk.iter().map(|index| signatures[*index]).collect();
+                let statuses = self
+                    .run_rpc(|| self.client.get_signature_statuses(&chunk_signatures))?
+                    .value;
+                for (index, status) in chunk.iter().zip(statuses.into_iter()) {
+                    match status {
+                        Some(status) => {
+                            if let Some(err) = status.err {
+                                outcomes[*index] = Err(err);
+                            } else if !status.satisfies_commitment(self.client.commitment()) {
+                                still_pending.push(*index);
+                            }
+                        }
+                        None => still_pending.push(*index),
+                    }
+                }
+            }
+            if !still_pending.is_empty() {
+                self.check_alive()?;
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            pending = still_pending;
+        }
+        Ok(outcomes)
+    }
+
+    /// Returns a builder for a transaction sent to this Sandbox.
+    pub fn transaction(&self) -> TransactionBuilder {
+        TransactionBuilder::new(self)
+    }
+
+    /// Sends the instructions and signers added by build as a single
+    /// transaction, for asserting that a protocol's steps apply together or
+    /// not at all. Instructions are typically gathered from several helpers,
+    /// and signers they share are only counted once:
+    ///
+    /// ```ignore
+    /// sandbox.atomic(|transaction| {
+    ///     Ok(transaction
+    ///         .instruction(spl_token::instruction::transfer(/* ... */)?)
+    ///         .instruction(market.new_order_ix(/* ... */)?)
+    ///         .instruction(my_program_call)
+    ///         .payer(trader.keypair())
+    ///         .signer(participant.owner().keypair()))
+    /// })?;
+    /// ```
+    ///
+    /// Nothing is sent if build fails. See TransactionBuilder::send_atomic.
+    pub fn atomic<'s, F>(&'s self, build: F) -> Result<Signature>
+    where
+        F: FnOnce(TransactionBuilder<'s>) -> Result<TransactionBuilder<'s>>,
+    {
+        build(self.transaction())?.send_atomic()
+    }
+
+    /// Sends a signed transaction and blocks until it is confirmed. Transient
+    /// failures are retried according to the retry policy. The transaction is
+    /// already signed, so it is never signed again with a fresh blockhash.
+    #[tracing::instrument(
+        name = "transaction",
+        skip_all,
+        fields(signature = %transaction.signatures[0])
+    )]
+    pub fn send_transaction(&self, transaction: &Transaction) -> Result<Signature> {
+        self.run_rpc(|| {
+            let result =
+                self.send_with_faults(|| self.client.send_and_confirm_transaction(transaction));
+            self.account_cache.invalidate_writable(&transaction.message);
+            self.trace_transaction(transaction, &result);
+            result
+        })
+    }
+
+    /// Packs groups of instructions into as few transactions as the packet
+    /// size allows, sends them all without waiting in between, and then
+    /// blocks until every transaction is confirmed. Each group is kept whole
+    /// and in order within a single transaction, together with the signers
+    /// it requires besides the payer. Groups must not depend on each other,
+    /// since the transactions may land in any order.
+    #[tracing::instrument(skip_all, fields(groups = groups.len()))]
+    pub fn send_batch<'b>(
+        &self,
+        payer: &'b Keypair,
+        groups: &[(Vec<Instruction>, Vec<&'b Keypair>)],
+    ) -> Result<Vec<Signature>> {
+        let fits = |instructions: &[Instruction]| transaction::fits(&payer.pubkey(), instructions);
+
+        let mut batches: Vec<(Vec<Instruction>, Vec<&Keypair>)> = Vec::new();
+        let mut instructions: Vec<Instruction> = Vec::new();
+        let mut signers: Vec<&Keypair> = vec![payer];
+        for (group_instructions, group_signers) in groups.iter() {
+            if !fits(group_instructions) {
+                return Err(Error::from(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "instruction group does not fit in a single transaction, \
+                     use TransactionBuilder::send_split",
+                )));
+            }
+            let mut candidate = instructions.clone();
+            candidate.extend_from_slice(group_instructions);
+            if !fits(&candidate) {
+                batches.push((instructions, signers));
+                candidate = group_instructions.clone();
+                signers = vec![payer];
+            }
+            instructions = candidate;
+            for signer in group_signers.iter() {
+                if !signers
+                    .iter()
+                    .any(|known| known.pubkey() == signer.pubkey())
+                {
+                    signers.push(*signer);
+                }
+            }
+        }
+        if !instructions.is_empty() {
+            batches.push((instructions, signers));
+        }
+
+        tracing::debug!(transactions = batches.len(), "sending batch");
+        let blockhash = self.run_rpc(|| self.client.get_latest_blockhash())?;
+        let mut transactions = Vec::with_capacity(batches.len());
+        for (instructions, signers) in batches.iter() {
+            let message = Message::new(instructions, Some(&payer.pubkey()));
+            let mut transaction = Transaction::new_unsigned(message);
+            transaction
+                .try_sign(signers, blockhash)
+                .map_err(ClientError::from)?;
+            transactions.push(transaction);
+        }
+
+        // Sends and confirmations are traced like TransactionBuilder::send,
+        // including those that fail.
+        let mut signatures = Vec::with_capacity(transactions.len());
+        for transaction in transactions.iter() {
+            let signature = transaction.signatures[0];
+            let sent = self.run_rpc(|| {
+                self.send_with_faults(|| {
+                    already_processed(self.client.send_transaction(transaction), signature)
+                })
+            });
+            self.account_cache.invalidate_writable(&transaction.message);
+            let sent = client_result(sent)?;
+            if sent.is_err() {
+                self.trace_transaction(transaction, &sent);
+            }
+            signatures.push(sent?);
+        }
+        let mut failure = None;
+        let outcomes = self.signature_outcomes(&signatures)?;
+        for ((transaction, signature), outcome) in
+            transactions.iter().zip(signatures.iter()).zip(outcomes)
+        {
+            let result = outcome.map(|()| *signature).map_err(ClientError::from);
+            self.trace_transaction(transaction, &result);
+            if failure.is_none() {
+                failure = result.err();
+            }
+        }
+        match failure {
+            Some(err) => Err(err.into()),
+            None => Ok(signatures),
+        }
+    }
+
+    /// Completes a partially signed transaction with the given signers and
+    /// sends it. Signatures that are already present are kept, so each party
+    /// only needs to provide their own keypairs.
+    pub fn send_partially_signed_transaction(
+        &self,
+        mut transaction: Transaction,
+        signers: &[&Keypair],
+    ) -> Result<Signature> {
+        let blockhash = transaction.message.recent_blockhash;
+        transaction
+            .try_partial_sign(&signers.to_vec(), blockhash)
+            .map_err(ClientError::from)?;
+        self.send_transaction(&transaction)
+    }
+
+    /// Returns the blockhash currently stored in a durable nonce account.
+    pub fn nonce_blockhash(&self, nonce_account: &Pubkey) -> Result<Hash> {
+        let data = self.get_account_data(nonce_account)?;
+        transaction::parse_nonce_blockhash(&data)
+    }
+
+    /// Create, sign & send a v0 transaction from instructions. Accounts found
+    /// in the given lookup tables are loaded from them instead of being
+    /// stored in the message. Blocks until the transaction is confirmed.
+    pub fn send_versioned_transaction(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        signers: Vec<&Keypair>,
+        lookup_tables: &[&AddressLookupTable],
+    ) -> Result<Signature> {
+        let recent_hash = self.run_rpc(|| self.client.get_latest_blockhash())?;
+        let message =
+            versioned::compile_v0_message(payer, instructions, lookup_tables, recent_hash)?;
+        let transaction = versioned::sign_v0_message(message, &signers)?;
+        let signature = transaction.signatures[0];
+        let data = bincode::serialize(&transaction)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let params = serde_json::json!([
+            base64::encode(data),
+            {
+                "encoding": "base64",
+                "preflightCommitment": self.client.commitment().commitment,
+            }
+        ]);
+
+        // The RPC client only sends legacy transactions, so the encoded
+        // transaction is submitted through a raw sendTransaction request.
+        let result = self
+            .run_rpc(|| {
+                self.send_with_faults(|| {
+                    let sent = self
+                        .client
+                        .send::<String>(RpcRequest::SendTransaction, params.clone());
+                    already_processed(sent.map(|_| signature), signature)
+                })
+            })
+            .and_then(|signature| {
+                self.confirm_signatures(&[signature])?;
+                Ok(signature)
+            });
+
+        // Writable accounts may be loaded from lookup tables, so the whole
+        // cache is dropped rather than resolving them.
+        self.account_cache.invalidate_all();
+        let result = client_result(result)?;
+        self.trace_transaction(
+            &versioned::resolve_transaction(&transaction, lookup_tables),
+            &result,
+        );
+        Ok(result?)
+    }
+}
+
+/// Treats a resent transaction that was rejected because an earlier send
+/// already landed as sent, since its signature is unchanged.
+fn already_processed(
+    result: ClientResult<Signature>,
+    signature: Signature,
+) -> ClientResult<Signature> {
+    match result {
+        Err(err) if err.get_transaction_error() == Some(TransactionError::AlreadyProcessed) => {
+            Ok(signature)
+        }
+        result => result,
+    }
+}
+
+/// Separates the client errors of a send, which are traced, from errors
+/// such as a dead validator, which are returned at once.
+fn client_result<T>(result: Result<T>) -> Result<ClientResult<T>> {
+    match result {
+        Ok(value) => Ok(Ok(value)),
+        Err(Error::SolanaClientError(err)) => Ok(Err(err)),
+        Err(err) => Err(err),
+    }
 }
 
 impl Drop for Sandbox {
     /// Stops the validator.
     fn drop(&mut self) {
-        self.validator.kill().unwrap_or(());
+        if let Some(validator) = self.validator.as_mut() {
+            if let Ok(process) = validator.process.get_mut() {
+                stop_validator(process, validator.docker.as_ref());
+            }
+        }
+        if let Workdir::Kept(path) = &self.tmp {
+            tracing::info!(workdir = %path.display(), "kept sandbox artifacts");
+        }
     }
 }
+
+/// The directory holding a Sandbox's ledger, logs, and keyfiles.
+enum Workdir {
+    /// Deleted when the Sandbox is dropped.
+    Temporary(tempfile::TempDir),
+
+    /// Left in place for post-mortem inspection.
+    Kept(PathBuf),
+}
+
+impl Workdir {
+    fn path(&self) -> &Path {
+        match self {
+            Workdir::Temporary(dir) => dir.path(),
+            Workdir::Kept(path) => path,
+        }
+    }
+}
+
+/// A saved copy of a Sandbox's ledger, taken with Sandbox::save_ledger and
+/// restored with Sandbox::restore_ledger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LedgerSnapshot {
+    path: PathBuf,
+    slot: u64,
+}
+
+impl LedgerSnapshot {
+    /// Returns the slot the validator had reached when the snapshot was
+    /// saved.
+    pub fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    /// Returns the directory holding the saved ledger.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// An airdrop that was requested with Actor::request_airdrop and may not be
+/// confirmed yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PendingAirdrop {
+    pub(crate) signature: Signature,
+    pub(crate) pubkey: Pubkey,
+    pub(crate) lamports: u64,
+}
+
+impl PendingAirdrop {
+    /// Returns the signature of the airdrop transaction.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Returns the account receiving the airdrop.
+    pub fn pubkey(&self) -> &Pubkey {
+        &self.pubkey
+    }
+
+    /// Returns the lamports requested.
+    pub fn lamports(&self) -> u64 {
+        self.lamports
+    }
+}
+
+/// Liveness information about a Sandbox's validator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Health {
+    /// Whether the validator process is still running.
+    pub running: bool,
+
+    /// The validator's exit status, if it has exited.
+    pub exit_status: Option<String>,
+
+    /// The validator's current slot, if it responded.
+    pub slot: Option<u64>,
+
+    /// Round trip time of the slot request, if the validator responded.
+    pub rpc_latency: Option<time::Duration>,
+}
+
+/// A validator process started by a Sandbox.
+struct LocalValidator {
+    process: Mutex<process::Child>,
+    /// Arguments the validator is started and restarted with.
+    args: Vec<String>,
+    docker: Option<Docker>,
+    faucet_port: u16,
+}
+
+/// A validator run inside a docker container.
+#[derive(Clone, Debug)]
+struct Docker {
+    image: String,
+    container: String,
+}
+
+impl Docker {
+    /// Returns a command running solana-test-validator in a new container
+    /// that mounts the Sandbox's directory and publishes the validator's
+    /// ports. The container is named so that it can be removed even if the
+    /// docker client is killed.
+    fn command(&self, dir: &Path, port: u16, faucet_port: u16) -> process::Command {
+        let dir = dir.to_str().expect("could not get tmp path");
+        let mut command = process::Command::new("docker");
+        command.args(["run", "--rm", "--init", "--name", &self.container]);
+        // Files written by the validator must be owned by the host user so
+        // that the Sandbox's directory can be deleted.
+        #[cfg(unix)]
+        if let Ok(metadata) = fs::metadata(dir) {
+            use std::os::unix::fs::MetadataExt;
+            command.args(["--user", &format!("{}:{}", metadata.uid(), metadata.gid())]);
+        }
+        command.args(["--volume", &format!("{0}:{0}", dir)]);
+        for port in [port, port + 1, faucet_port] {
+            command.args(["--publish", &format!("{0}:{0}", port)]);
+        }
+        command.args(["--entrypoint", "solana-test-validator", &self.image]);
+        command
+    }
+
+    /// Removes the container, stopping it if it is running.
+    fn remove(&self) {
+        let output = process::Command::new("docker")
+            .args(["rm", "--force", &self.container])
+            .output();
+        if let Err(err) = output {
+            tracing::warn!(container = %self.container, error = %err, "could not remove container");
+        }
+    }
+}
+
+/// Starts solana-test-validator with its ledger in the given directory.
+/// Output is appended to the log file, so that it spans restarts.
+fn spawn_validator(
+    dir: &Path,
+    port: u16,
+    faucet_port: u16,
+    args: &[String],
+    log_path: &Path,
+    docker: Option<&Docker>,
+) -> Result<process::Child> {
+    let ledger = ledger_path(dir);
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    let mut command = match docker {
+        Some(docker) => {
+            // A container left behind by a killed Sandbox would hold the
+            // name and ports.
+            docker.remove();
+            docker.command(dir, port, faucet_port)
+        }
+        None => process::Command::new("solana-test-validator"),
+    };
+    Ok(command
+        .args([
+            "--ledger",
+            ledger.to_str().expect("could not get tmp path"),
+            "--rpc-port",
+            &port.to_string(),
+            "--faucet-port",
+            &faucet_port.to_string(),
+            "--log",
+        ])
+        .args(args)
+        .stdout(process::Stdio::from(log.try_clone()?))
+        .stderr(process::Stdio::from(log))
+        .spawn()?)
+}
+
+/// Stops the validator and waits for it to exit. The validator is sent
+/// SIGTERM so that it can close its ledger cleanly, and is only killed if
+/// it is still running after the shutdown timeout, since a killed validator
+/// can leave a ledger that restart cannot start from. In docker mode,
+/// stopping the docker client leaves the container running, so the
+/// container is removed as well.
+fn stop_validator(validator: &mut process::Child, docker: Option<&Docker>) {
+    let terminated = process::Command::new("kill")
+        .args(["-TERM", &validator.id().to_string()])
+        .status()
+        .map_or(false, |status| status.success());
+    if terminated {
+        let deadline = time::Instant::now() + VALIDATOR_SHUTDOWN_TIMEOUT;
+        while matches!(validator.try_wait(), Ok(None)) && time::Instant::now() < deadline {
+            thread::sleep(time::Duration::from_millis(50));
+        }
+    }
+    validator.kill().unwrap_or(());
+    let _ = validator.wait();
+    if let Some(docker) = docker {
+        docker.remove();
+    }
+}
+
+/// Returns the port in an RPC url, or the scheme's default port.
+fn url_port(url: &str) -> Option<u16> {
+    let (scheme, rest) = url.split_once("://")?;
+    let host = rest.split('/').next()?;
+    match host.rsplit_once(':') {
+        Some((_, port)) => port.parse().ok(),
+        None => match scheme {
+            "http" => Some(80),
+            "https" => Some(443),
+            _ => None,
+        },
+    }
+}
+
+/// Returns the validator's ledger directory within the Sandbox's directory.
+fn ledger_path(dir: &Path) -> PathBuf {
+    dir.join("solana-test-validator-ledger")
+}
+
+/// Recursively copies a directory, creating the destination.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Waits for the validator to come online and respond to basic commands. A
+/// validator that exits during startup would otherwise be waited on forever.
+pub(crate) fn wait_until_ready(
+    client: &rpc_client::RpcClient,
+    validator: &mut process::Child,
+    log_path: &Path,
+) -> Result<()> {
+    while client.get_latest_blockhash().is_err() {
+        if let Some(status) = validator.try_wait()? {
+            return Err(validator_exited(log_path, status));
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Returns a descriptive error for a validator that exited unexpectedly,
+/// including the end of its log.
+pub(crate) fn validator_exited(log_path: &Path, status: process::ExitStatus) -> Error {
+    let tail = fs::read_to_string(log_path)
+        .map(|contents| {
+            let lines: Vec<&str> = contents.lines().collect();
+            lines[lines.len().saturating_sub(20)..].join("\n")
+        })
+        .unwrap_or_default();
+    Error::from(io::Error::new(
+        io::ErrorKind::NotConnected,
+        format!(
+            "validator exited unexpectedly ({}), see {}:\n{}",
+            status,
+            log_path.display(),
+            tail
+        ),
+    ))
+}
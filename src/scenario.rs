@@ -0,0 +1,474 @@
+use crate::actor::Actor;
+use crate::errors::{Context, Error, Result};
+use crate::sandbox::Sandbox;
+use crate::serum::{Market, MarketParams, Participant, PortfolioSnapshot};
+use crate::token::Mint;
+use serde::{Deserialize, Serialize};
+use serum_dex::matching::{OrderType, Side};
+use solana_program::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::BTreeMap, fs, io, num::NonZeroU64, path::Path};
+
+/// Events consumed by a crank step unless it sets its own limit.
+const CRANK_EVENTS: u16 = 100;
+
+/// A Serum market test case described declaratively, so that integration
+/// cases can be written without Rust changes.
+///
+/// A scenario names its participants and refers to them by name in its
+/// steps and expectations. Prices are in quote lots per base lot, sizes in
+/// base lots, and balances in native units:
+///
+/// ```yaml
+/// market:
+///   base_lot_size: 100
+///   quote_lot_size: 10
+/// participants:
+///   - { name: maker, base: 1000000, quote: 1000000 }
+///   - { name: taker, base: 1000000, quote: 1000000 }
+/// steps:
+///   - { action: order, participant: maker, side: sell, price: 20, size: 10 }
+///   - { action: order, participant: taker, side: buy, price: 20, size: 10 }
+///   - { action: crank }
+///   - { action: settle, participant: maker }
+/// expect:
+///   participants:
+///     maker: { base: 999000, quote_locked: 0 }
+///   ask_orders: 0
+/// ```
+///
+/// Scenarios are loaded from YAML or JSON and run against a Sandbox with
+/// the Serum DEX deployed:
+///
+/// ```ignore
+/// let scenario = Scenario::load("tests/scenarios/cross.yaml")?;
+/// scenario.run(&sandbox, serum.pubkey(), &payer)?;
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scenario {
+    /// Decimals of the base mint.
+    #[serde(default)]
+    pub base_decimals: u8,
+
+    /// Decimals of the quote mint.
+    #[serde(default)]
+    pub quote_decimals: u8,
+
+    /// Overrides of MarketParams::default_small.
+    #[serde(default)]
+    pub market: MarketSpec,
+
+    /// Participants created before the first step.
+    #[serde(default)]
+    pub participants: Vec<ParticipantSpec>,
+
+    /// Operations applied in order.
+    #[serde(default)]
+    pub steps: Vec<Step>,
+
+    /// Outcomes checked after the last step.
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+/// Market parameters of a scenario. Unset fields keep the values of
+/// MarketParams::default_small.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MarketSpec {
+    #[serde(default)]
+    pub base_lot_size: Option<u64>,
+    #[serde(default)]
+    pub quote_lot_size: Option<u64>,
+    #[serde(default)]
+    pub dust_threshold: Option<u64>,
+    #[serde(default)]
+    pub request_queue_size: Option<usize>,
+    #[serde(default)]
+    pub event_queue_size: Option<usize>,
+    #[serde(default)]
+    pub book_size: Option<usize>,
+}
+
+impl MarketSpec {
+    /// Returns the market parameters the spec describes.
+    pub fn params(&self) -> MarketParams {
+        let mut params = MarketParams::default_small();
+        params.base_lot_size = self.base_lot_size.unwrap_or(params.base_lot_size);
+        params.quote_lot_size = self.quote_lot_size.unwrap_or(params.quote_lot_size);
+        params.dust_threshold = self.dust_threshold.unwrap_or(params.dust_threshold);
+        params.request_queue_size = self.request_queue_size.unwrap_or(params.request_queue_size);
+        params.event_queue_size = self.event_queue_size.unwrap_or(params.event_queue_size);
+        params.book_size = self.book_size.unwrap_or(params.book_size);
+        params
+    }
+}
+
+/// A participant of a scenario and its starting balances.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ParticipantSpec {
+    pub name: String,
+
+    /// Lamports airdropped to the participant. Defaults to 10 SOL.
+    #[serde(default = "default_lamports")]
+    pub lamports: u64,
+
+    /// Base minted into the participant's wallet.
+    #[serde(default)]
+    pub base: u64,
+
+    /// Quote minted into the participant's wallet.
+    #[serde(default)]
+    pub quote: u64,
+}
+
+fn default_lamports() -> u64 {
+    10 * LAMPORTS_PER_SOL
+}
+
+/// The direction of an order step.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScenarioSide {
+    Buy,
+    Sell,
+}
+
+/// The order type of an order step.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScenarioOrderType {
+    Limit,
+    ImmediateOrCancel,
+    PostOnly,
+}
+
+impl Default for ScenarioOrderType {
+    fn default() -> Self {
+        ScenarioOrderType::Limit
+    }
+}
+
+/// An operation applied by a scenario.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Step {
+    /// Places an order. A rejected order fails the scenario unless the step
+    /// expects the rejection. Only the market can reject an order, so an
+    /// order that fails to be sent fails the scenario either way.
+    Order {
+        participant: String,
+        side: ScenarioSide,
+        price: u64,
+        size: u64,
+        #[serde(default)]
+        order_type: ScenarioOrderType,
+        #[serde(default)]
+        client_order_id: u64,
+        #[serde(default)]
+        rejected: bool,
+    },
+    /// Cancels the participant's resting order with the given client order
+    /// id.
+    Cancel {
+        participant: String,
+        client_order_id: u64,
+    },
+    /// Consumes events for every open orders account on the market.
+    Crank {
+        #[serde(default)]
+        limit: Option<u16>,
+    },
+    /// Settles the participant's free balances into its wallets.
+    Settle { participant: String },
+}
+
+/// Outcomes checked after a scenario's last step. Unset fields are not
+/// checked.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Expectations {
+    /// Expected balances of participants, by name.
+    #[serde(default)]
+    pub participants: BTreeMap<String, BalanceExpectation>,
+
+    /// Expected price of the highest bid, or zero for no bids.
+    #[serde(default)]
+    pub best_bid: Option<u64>,
+
+    /// Expected price of the lowest ask, or zero for no asks.
+    #[serde(default)]
+    pub best_ask: Option<u64>,
+
+    /// Expected number of resting bids.
+    #[serde(default)]
+    pub bid_orders: Option<usize>,
+
+    /// Expected number of resting asks.
+    #[serde(default)]
+    pub ask_orders: Option<usize>,
+}
+
+/// Expected balances of a participant, in native units. Unset fields are
+/// not checked.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BalanceExpectation {
+    #[serde(default)]
+    pub base: Option<u64>,
+    #[serde(default)]
+    pub quote: Option<u64>,
+    #[serde(default)]
+    pub base_locked: Option<u64>,
+    #[serde(default)]
+    pub quote_locked: Option<u64>,
+    #[serde(default)]
+    pub base_unsettled: Option<u64>,
+    #[serde(default)]
+    pub quote_unsettled: Option<u64>,
+    /// Expected number of the participant's resting orders.
+    #[serde(default)]
+    pub open_orders: Option<usize>,
+}
+
+/// The state of a scenario's participants after its last step.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScenarioOutcome {
+    /// Balances of each participant, by name.
+    pub snapshots: BTreeMap<String, PortfolioSnapshot>,
+}
+
+impl Scenario {
+    /// Parses a scenario from JSON.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(invalid_data)
+    }
+
+    /// Parses a scenario from YAML.
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        serde_yaml::from_str(yaml).map_err(invalid_data)
+    }
+
+    /// Loads a scenario from a file, parsed as JSON if its extension is
+    /// .json and as YAML otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let scenario = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&contents),
+            _ => Self::from_yaml(&contents),
+        };
+        scenario.with_context(|| format!("loading scenario {}", path.display()))
+    }
+
+    /// Creates the scenario's mints, market, and participants, applies its
+    /// steps, and checks its expectations. The payer creates every account
+    /// and pays for cranking and settling, so it needs enough lamports for
+    /// all of them. Fails on the first step that fails, or with every unmet
+    /// expectation.
+    pub fn run(&self, sandbox: &Sandbox, serum: &Pubkey, payer: &Actor) -> Result<ScenarioOutcome> {
+        let base_mint = Mint::new(sandbox, payer, self.base_decimals, None, None)?;
+        let quote_mint = Mint::new(sandbox, payer, self.quote_decimals, None, None)?;
+        let market = Market::new(
+            sandbox,
+            payer,
+            serum,
+            &base_mint,
+            &quote_mint,
+            None,
+            &self.market.params(),
+        )?;
+
+        let mut participants = BTreeMap::new();
+        for spec in self.participants.iter() {
+            let participant = Participant::new_named(
+                sandbox,
+                payer,
+                &market,
+                spec.lamports,
+                spec.base,
+                spec.quote,
+                &spec.name,
+            )
+            .with_context(|| format!("creating participant {}", spec.name))?;
+            if participants
+                .insert(spec.name.clone(), participant)
+                .is_some()
+            {
+                return Err(invalid_input(format!(
+                    "participant {} is listed twice",
+                    spec.name
+                )));
+            }
+        }
+
+        for (i, step) in self.steps.iter().enumerate() {
+            apply(&market, payer, &participants, step)
+                .with_context(|| format!("scenario step {}: {:?}", i, step))?;
+        }
+
+        let mut outcome = ScenarioOutcome::default();
+        for (name, participant) in participants.iter() {
+            outcome
+                .snapshots
+                .insert(name.clone(), participant.snapshot()?);
+        }
+        let failures = self.unmet_expectations(&market, &participants, &outcome)?;
+        if !failures.is_empty() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::Other,
+                format!("scenario expectations not met:\n{}", failures.join("\n")),
+            )));
+        }
+        Ok(outcome)
+    }
+
+    fn unmet_expectations(
+        &self,
+        market: &Market,
+        participants: &BTreeMap<String, Participant>,
+        outcome: &ScenarioOutcome,
+    ) -> Result<Vec<String>> {
+        let mut failures = Vec::new();
+        let mut check = |what: String, expected: Option<u64>, actual: u64| {
+            if let Some(expected) = expected {
+                if expected != actual {
+                    failures.push(format!("  {}: expected {}, got {}", what, expected, actual));
+                }
+            }
+        };
+
+        for (name, expected) in self.expect.participants.iter() {
+            let snapshot = outcome
+                .snapshots
+                .get(name)
+                .ok_or_else(|| invalid_input(format!("no participant named {}", name)))?;
+            let balances = [
+                ("base", expected.base, snapshot.base),
+                ("quote", expected.quote, snapshot.quote),
+                ("base_locked", expected.base_locked, snapshot.base_locked),
+                ("quote_locked", expected.quote_locked, snapshot.quote_locked),
+                (
+                    "base_unsettled",
+                    expected.base_unsettled,
+                    snapshot.base_unsettled,
+                ),
+                (
+                    "quote_unsettled",
+                    expected.quote_unsettled,
+                    snapshot.quote_unsettled,
+                ),
+            ];
+            for (balance, expected, actual) in balances {
+                check(format!("{}.{}", name, balance), expected, actual);
+            }
+            if expected.open_orders.is_some() {
+                let open_orders = participants[name].open_orders_view()?.orders.len();
+                check(
+                    format!("{}.open_orders", name),
+                    expected.open_orders.map(|n| n as u64),
+                    open_orders as u64,
+                );
+            }
+        }
+
+        let book = market.book_snapshot()?;
+        let best_price = |level: Option<u64>| level.unwrap_or(0);
+        check(
+            String::from("best_bid"),
+            self.expect.best_bid,
+            best_price(book.best_bid().map(|level| level.price)),
+        );
+        check(
+            String::from("best_ask"),
+            self.expect.best_ask,
+            best_price(book.best_ask().map(|level| level.price)),
+        );
+        check(
+            String::from("bid_orders"),
+            self.expect.bid_orders.map(|n| n as u64),
+            book.bids.len() as u64,
+        );
+        check(
+            String::from("ask_orders"),
+            self.expect.ask_orders.map(|n| n as u64),
+            book.asks.len() as u64,
+        );
+        Ok(failures)
+    }
+}
+
+/// Applies a single step.
+fn apply(
+    market: &Market,
+    payer: &Actor,
+    participants: &BTreeMap<String, Participant>,
+    step: &Step,
+) -> Result<()> {
+    let participant = |name: &str| {
+        participants
+            .get(name)
+            .ok_or_else(|| invalid_input(format!("no participant named {}", name)))
+    };
+    match step {
+        Step::Order {
+            participant: name,
+            side,
+            price,
+            size,
+            order_type,
+            client_order_id,
+            rejected,
+        } => {
+            let participant = participant(name)?;
+            let side = match side {
+                ScenarioSide::Buy => Side::Bid,
+                ScenarioSide::Sell => Side::Ask,
+            };
+            let order_type = match order_type {
+                ScenarioOrderType::Limit => OrderType::Limit,
+                ScenarioOrderType::ImmediateOrCancel => OrderType::ImmediateOrCancel,
+                ScenarioOrderType::PostOnly => OrderType::PostOnly,
+            };
+            let price = NonZeroU64::new(*price)
+                .ok_or_else(|| invalid_input(String::from("price must be positive")))?;
+            let size = NonZeroU64::new(*size)
+                .ok_or_else(|| invalid_input(String::from("size must be positive")))?;
+            let result =
+                market.place_order(participant, side, price, size, order_type, *client_order_id);
+            match (result, rejected) {
+                (Ok(()), true) => Err(invalid_input(String::from(
+                    "order was expected to be rejected but was accepted",
+                ))),
+                (Err(err), true) if err.is_program_error() => Ok(()),
+                (result, false) => result,
+            }
+        }
+        Step::Cancel {
+            participant: name,
+            client_order_id,
+        } => {
+            let participant = participant(name)?;
+            let order = participant
+                .open_orders_view()?
+                .orders
+                .into_iter()
+                .find(|order| order.client_order_id == *client_order_id)
+                .ok_or_else(|| {
+                    invalid_input(format!(
+                        "{} has no resting order with client order id {}",
+                        name, client_order_id
+                    ))
+                })?;
+            let side = if order.is_bid { Side::Bid } else { Side::Ask };
+            market.cancel_order(payer, participant, side, order.order_id)
+        }
+        Step::Crank { limit } => market.crank_all(payer, limit.unwrap_or(CRANK_EVENTS)),
+        Step::Settle { participant: name } => market.settle_funds(payer, participant(name)?),
+    }
+}
+
+fn invalid_input(message: String) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,273 @@
+use crate::errors::Result;
+use crate::sandbox::Sandbox;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread, time,
+};
+
+/// How often a scheduled job runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Runs the job every given duration, measured from the start of one run
+    /// to the start of the next. A run that takes longer than the duration
+    /// is followed immediately by the next one.
+    Every(time::Duration),
+    /// Runs the job once for every new slot observed.
+    EverySlot,
+}
+
+/// A failed run of a scheduled job.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobError {
+    /// Name of the job.
+    pub job: String,
+
+    /// Number of the failed run, starting at zero.
+    pub run: u64,
+
+    /// Description of the failure.
+    pub error: String,
+}
+
+type JobFn = Box<dyn FnMut(&RpcClient) -> Result<Vec<Instruction>> + Send>;
+
+/// A registered job, owned by its thread while the scheduler is running.
+struct Job {
+    name: String,
+    schedule: Schedule,
+    payer: Keypair,
+    signers: Vec<Keypair>,
+    run: JobFn,
+    runs: u64,
+}
+
+/// Runs recurring jobs against a Sandbox, such as cranking a market every
+/// 500ms or publishing a price every slot.
+///
+/// A job is a closure that returns the instructions to send on each run. It
+/// receives an RPC client so that it can read state first, and may return no
+/// instructions to skip a run. Each job runs on its own background thread
+/// with its own RPC client. Failed runs, whether the closure or the send
+/// failed, are collected rather than stopping the job, and can be inspected
+/// with errors or turned into a test failure with check.
+///
+/// ```ignore
+/// let mut scheduler = Scheduler::new(&sandbox);
+/// scheduler.job("publish", Schedule::EverySlot, payer.keypair(), &[], move |_| {
+///     Ok(vec![publish_price_instruction()])
+/// });
+/// scheduler.start();
+/// // ... exercise the system ...
+/// scheduler.stop();
+/// scheduler.check()?;
+/// ```
+pub struct Scheduler {
+    url: String,
+    commitment: CommitmentConfig,
+    jobs: Vec<Job>,
+    stop: Arc<AtomicBool>,
+    handles: Vec<(String, thread::JoinHandle<Job>)>,
+    runs: Arc<Mutex<HashMap<String, u64>>>,
+    errors: Arc<Mutex<Vec<JobError>>>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler with no jobs, connected to the Sandbox's validator.
+    pub fn new(sandbox: &Sandbox) -> Self {
+        Scheduler {
+            url: sandbox.url(),
+            commitment: sandbox.client().commitment(),
+            jobs: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            handles: Vec::new(),
+            runs: Arc::new(Mutex::new(HashMap::new())),
+            errors: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Registers a job. The payer pays for and signs every transaction the
+    /// job sends, along with any additional signers. Jobs registered while
+    /// the scheduler is running start on the next call to start.
+    pub fn job<F>(
+        &mut self,
+        name: &str,
+        schedule: Schedule,
+        payer: &Keypair,
+        signers: &[&Keypair],
+        job: F,
+    ) -> &mut Self
+    where
+        F: FnMut(&RpcClient) -> Result<Vec<Instruction>> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name: String::from(name),
+            schedule,
+            payer: clone_keypair(payer),
+            signers: signers.iter().map(|signer| clone_keypair(signer)).collect(),
+            run: Box::new(job),
+            runs: 0,
+        });
+        self
+    }
+
+    /// Starts every job that is not already running.
+    pub fn start(&mut self) {
+        self.stop.store(false, Ordering::Relaxed);
+        for job in self.jobs.drain(..) {
+            let client = RpcClient::new_with_commitment(self.url.clone(), self.commitment);
+            let stop = self.stop.clone();
+            let runs = self.runs.clone();
+            let errors = self.errors.clone();
+            let name = job.name.clone();
+            let handle = thread::spawn(move || run_job(job, client, stop, runs, errors));
+            self.handles.push((name, handle));
+        }
+    }
+
+    /// Stops every job, waiting for runs in progress to finish. Stopped jobs
+    /// resume on the next call to start.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for (name, handle) in self.handles.drain(..) {
+            match handle.join() {
+                Ok(job) => self.jobs.push(job),
+                Err(_) => {
+                    let run = self.runs(&name);
+                    self.errors
+                        .lock()
+                        .expect("scheduler lock poisoned")
+                        .push(job_error(&name, run, "job panicked and was removed"));
+                }
+            }
+        }
+    }
+
+    /// Returns true if any job is running.
+    pub fn is_running(&self) -> bool {
+        !self.handles.is_empty()
+    }
+
+    /// Returns the number of completed runs of a job, successful or not.
+    pub fn runs(&self, name: &str) -> u64 {
+        self.runs
+            .lock()
+            .expect("scheduler lock poisoned")
+            .get(name)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Returns every failed run so far, in the order they failed.
+    pub fn errors(&self) -> Vec<JobError> {
+        self.errors.lock().expect("scheduler lock poisoned").clone()
+    }
+
+    /// Fails with the first recorded error, if any run failed.
+    pub fn check(&self) -> Result<()> {
+        match self.errors().first() {
+            Some(err) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("job {} failed on run {}: {}", err.job, err.run, err.error),
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    /// Stops every job.
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Runs a job on its schedule until stopped, then hands it back.
+fn run_job(
+    mut job: Job,
+    client: RpcClient,
+    stop: Arc<AtomicBool>,
+    runs: Arc<Mutex<HashMap<String, u64>>>,
+    errors: Arc<Mutex<Vec<JobError>>>,
+) -> Job {
+    let mut last_slot = None;
+    let mut next_run = time::Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        let due = match job.schedule {
+            Schedule::Every(interval) => {
+                let now = time::Instant::now();
+                if now >= next_run {
+                    next_run = now + interval;
+                    true
+                } else {
+                    false
+                }
+            }
+            Schedule::EverySlot => match client.get_slot() {
+                Ok(slot) if Some(slot) != last_slot => {
+                    last_slot = Some(slot);
+                    true
+                }
+                _ => false,
+            },
+        };
+        if !due {
+            thread::sleep(time::Duration::from_millis(10));
+            continue;
+        }
+
+        if let Err(err) = run_once(&mut job, &client) {
+            errors
+                .lock()
+                .expect("scheduler lock poisoned")
+                .push(job_error(&job.name, job.runs, &format!("{:?}", err)));
+        }
+        job.runs += 1;
+        runs.lock()
+            .expect("scheduler lock poisoned")
+            .insert(job.name.clone(), job.runs);
+    }
+    job
+}
+
+/// Builds and sends the job's instructions once.
+fn run_once(job: &mut Job, client: &RpcClient) -> Result<()> {
+    let instructions = (job.run)(client)?;
+    if instructions.is_empty() {
+        return Ok(());
+    }
+
+    let mut signers: Vec<&Keypair> = vec![&job.payer];
+    signers.extend(job.signers.iter());
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&job.payer.pubkey()));
+    transaction
+        .try_sign(&signers, client.get_latest_blockhash()?)
+        .map_err(ClientError::from)?;
+    client.send_and_confirm_transaction(&transaction)?;
+    Ok(())
+}
+
+fn job_error(job: &str, run: u64, error: &str) -> JobError {
+    JobError {
+        job: String::from(job),
+        run,
+        error: String::from(error),
+    }
+}
+
+/// Copies a keypair so that a job thread can own it.
+fn clone_keypair(keypair: &Keypair) -> Keypair {
+    Keypair::from_bytes(&keypair.to_bytes()).expect("keypair bytes round trip")
+}
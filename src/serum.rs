@@ -1,26 +1,264 @@
 use crate::actor::Actor;
-use crate::errors::{Error, Result};
+use crate::crank;
+use crate::decoder;
+use crate::errors::{Context, Error, MarketParamsError, Result};
+use crate::filter::AccountFilter;
+use crate::matching::{BookSnapshot, Depth, PriceLevel};
+use crate::pda;
+use crate::proxy::MarketProxy;
+use crate::recorder::{EventSubscription, MarketEvent, MarketRecorder};
 use crate::sandbox::Sandbox;
-use crate::token::{Mint, TokenAccount};
+use crate::token::{BaseOrQuote, MaybeOwned, Mint, TokenAccount};
+use crate::transaction;
+use crate::views::{self, MarketView, OpenOrdersView, SlabNodeView};
 use bytemuck;
+use serde::{Deserialize, Serialize};
 use serum_dex::{
-    instruction::SelfTradeBehavior,
+    critbit::AnyNode,
+    fees::FeeTier,
+    instruction::{
+        CancelOrderInstruction, MarketInstruction, NewOrderInstructionV1, NewOrderInstructionV2,
+        SelfTradeBehavior,
+    },
     matching::{OrderType, Side},
     state as serum_state,
 };
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
 use std::num::NonZeroU64;
+use std::sync::Mutex;
 use std::thread;
 use std::thread::sleep;
 use std::time::Duration;
 
-/// Represents a Serum market. This is a V2 market if there is an authority
-/// specified, otherwise a V1 market.
+pub mod slab;
+
+/// Fewest request queue entries Serum accepts.
+const MIN_REQUEST_QUEUE_SIZE: usize = 1;
+
+/// Fewest event queue entries Serum accepts.
+const MIN_EVENT_QUEUE_SIZE: usize = 128;
+
+/// Fewest slab nodes Serum accepts for each of the bids and asks.
+const MIN_BOOK_SIZE: usize = 201;
+
+/// Most orders an open orders account holds at once.
+const MAX_ORDERS_PER_OPEN_ORDERS: usize = 128;
+
+/// Byte offset of the market public key within an open orders account: the
+/// account head padding followed by the account flags.
+const OPEN_ORDERS_MARKET_OFFSET: usize = 5 + 8;
+
+/// Resting orders an order placed with Market::place_order may match
+/// before the rest of it is posted or cancelled.
+const PLACE_ORDER_MATCH_LIMIT: u16 = 10;
+
+/// The version of the Serum DEX program a market is traded on, which
+/// decides the instructions used to trade it and the sizes of its accounts.
+///
+/// V1 and V2 programs queue new orders and cancels in the request queue,
+/// where they wait for a match_orders crank, and V2 adds self trade
+/// behavior to new orders. V3 programs match orders and cancel them
+/// immediately, leaving the request queue unused, and support permissioned
+/// markets. The serum_dex.so deployed by the tests is a V3 program; V1 and
+/// V2 markets need an older build of Serum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DexVersion {
+    V1,
+    V2,
+    V3,
+}
+
+impl DexVersion {
+    /// Returns true if orders are matched when they are placed rather than
+    /// by a match_orders crank.
+    pub fn matches_immediately(&self) -> bool {
+        *self == DexVersion::V3
+    }
+
+    /// Returns true if markets can have an authority.
+    pub fn supports_authority(&self) -> bool {
+        *self == DexVersion::V3
+    }
+
+    /// Returns the size in bytes of a market account. Only markets with an
+    /// authority store the permissioned market state.
+    pub fn market_bytes(&self, has_authority: bool) -> usize {
+        Market::market_size(has_authority && self.supports_authority())
+    }
+
+    /// Returns the number of request queue entries allocated for a market
+    /// that asked for requested entries. V3 markets never queue requests, so
+    /// they get the smallest queue Serum accepts.
+    pub fn request_queue_entries(&self, requested: usize) -> usize {
+        if self.matches_immediately() {
+            MIN_REQUEST_QUEUE_SIZE
+        } else {
+            requested
+        }
+    }
+}
+
+impl Default for DexVersion {
+    fn default() -> Self {
+        DexVersion::V3
+    }
+}
+
+/// Sizing parameters for a Serum market.
+///
+/// Lot sizes are expressed in native token units. Queue and book sizes are
+/// expressed in number of entries; the corresponding account sizes in bytes
+/// are derived when the market is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketParams {
+    /// Native base units per base lot.
+    pub base_lot_size: u64,
+
+    /// Native quote units per quote lot.
+    pub quote_lot_size: u64,
+
+    /// Minimum native quote amount that is swept to fees on settlement. It
+    /// must be below quote_lot_size, since an amount of a whole lot can
+    /// still be traded and is not dust.
+    pub dust_threshold: u64,
+
+    /// Number of entries in the request queue. Only V1 and V2 markets use
+    /// the queue; see DexVersion::request_queue_entries.
+    pub request_queue_size: usize,
+
+    /// Number of entries in the event queue.
+    pub event_queue_size: usize,
+
+    /// Number of slab nodes in each of the bids and asks accounts.
+    pub book_size: usize,
+
+    /// Version of the Serum program the market is created on.
+    pub version: DexVersion,
+}
+
+impl MarketParams {
+    /// Returns the smallest parameters that Serum accepts, with unit lot
+    /// sizes. Suitable for tests with tokens that have no decimals.
+    pub fn default_small() -> Self {
+        MarketParams {
+            base_lot_size: 1,
+            quote_lot_size: 1,
+            dust_threshold: 0,
+            request_queue_size: 128,
+            event_queue_size: 128,
+            book_size: 256,
+            version: DexVersion::V3,
+        }
+    }
+
+    /// Returns parameters resembling a production market. The tick size is
+    /// the minimum price increment and the min size is the minimum order
+    /// size, both in UI units (e.g. a tick size of 0.01 USDC per SOL).
+    pub fn realistic(
+        base_decimals: u8,
+        quote_decimals: u8,
+        tick_size: f64,
+        min_size: f64,
+    ) -> Result<Self> {
+        let base_lot_size = (min_size * 10f64.powi(base_decimals as i32)).round();
+        let quote_lot_size = (tick_size * min_size * 10f64.powi(quote_decimals as i32)).round();
+        let valid = |lot_size: f64| lot_size.is_finite() && lot_size >= 1.0;
+        if !valid(base_lot_size) || !valid(quote_lot_size) {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "tick size {} and min size {} produce a lot size below one native unit",
+                    tick_size, min_size
+                ),
+            )));
+        }
+
+        Ok(MarketParams {
+            base_lot_size: base_lot_size as u64,
+            quote_lot_size: quote_lot_size as u64,
+            dust_threshold: 100.min(quote_lot_size as u64 - 1),
+            request_queue_size: 64,
+            event_queue_size: 2048,
+            book_size: 1024,
+            version: DexVersion::V3,
+        })
+    }
+
+    /// Checks the parameters against Serum's requirements, so that invalid
+    /// parameters are reported before any account is created.
+    pub fn validate(&self) -> std::result::Result<(), MarketParamsError> {
+        if self.base_lot_size == 0 || self.quote_lot_size == 0 {
+            return Err(MarketParamsError::ZeroLotSize {
+                base_lot_size: self.base_lot_size,
+                quote_lot_size: self.quote_lot_size,
+            });
+        }
+        if self.dust_threshold >= self.quote_lot_size {
+            return Err(MarketParamsError::DustThresholdTooLarge {
+                dust_threshold: self.dust_threshold,
+                quote_lot_size: self.quote_lot_size,
+            });
+        }
+        if self.request_queue_size < MIN_REQUEST_QUEUE_SIZE {
+            return Err(MarketParamsError::RequestQueueTooSmall {
+                given: self.request_queue_size,
+                minimum: MIN_REQUEST_QUEUE_SIZE,
+            });
+        }
+        if self.event_queue_size < MIN_EVENT_QUEUE_SIZE {
+            return Err(MarketParamsError::EventQueueTooSmall {
+                given: self.event_queue_size,
+                minimum: MIN_EVENT_QUEUE_SIZE,
+            });
+        }
+        if self.book_size < MIN_BOOK_SIZE {
+            return Err(MarketParamsError::BookTooSmall {
+                given: self.book_size,
+                minimum: MIN_BOOK_SIZE,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the size in bytes of the request queue account, as sized for
+    /// the market's version.
+    pub fn request_queue_bytes(&self) -> usize {
+        Market::request_queue_size(self.version.request_queue_entries(self.request_queue_size))
+    }
+
+    /// Returns the size in bytes of the event queue account.
+    pub fn event_queue_bytes(&self) -> usize {
+        Market::event_queue_size(self.event_queue_size)
+    }
+
+    /// Returns the size in bytes of each of the bids and asks accounts.
+    pub fn book_bytes(&self) -> usize {
+        Market::side_size(self.book_size)
+    }
+}
+
+impl Default for MarketParams {
+    fn default() -> Self {
+        MarketParams::default_small()
+    }
+}
+
+/// Represents a Serum market. Markets with an authority are permissioned and
+/// store the authority after the unpermissioned market state. The market's
+/// DexVersion selects the instructions used to trade it.
 pub struct Market<'a> {
     sandbox: &'a Sandbox,
     serum: &'a Pubkey,
     market: Actor<'a>,
-    authority: Option<&'a Pubkey>,
+    version: DexVersion,
+    proxy: Option<MarketProxy>,
+    authority: Option<Pubkey>,
     request_queue: Actor<'a>,
     event_queue: Actor<'a>,
     bids: Actor<'a>,
@@ -28,15 +266,16 @@ pub struct Market<'a> {
     base_vault: TokenAccount<'a>,
     quote_vault: TokenAccount<'a>,
     vault_signer_key: Pubkey,
-    base_mint: &'a Mint<'a>,
-    quote_mint: &'a Mint<'a>,
+    quote_lot_size: u64,
+    base_mint: MaybeOwned<'a, Mint<'a>>,
+    quote_mint: MaybeOwned<'a, Mint<'a>>,
     pub open_orders_accounts: Vec<&'a Pubkey>,
 }
 
 impl<'a> Market<'a> {
     /// Creates and initializes a serum market. Creation is funded by the given
-    /// actor. If an authority is provided then a V2 market is created.
-    /// Otherwise, a V1 market is created.
+    /// actor. If an authority is provided then a permissioned market is
+    /// created, which requires a V3 program.
     pub fn new(
         sandbox: &'a Sandbox,
         actor: &'a Actor,
@@ -44,113 +283,318 @@ impl<'a> Market<'a> {
         base_mint: &'a Mint,
         quote_mint: &'a Mint,
         authority: Option<&'a Pubkey>,
-        base_lot_size: u64,
-        quote_lot_size: u64,
-        dust_threshold: u64,
-        request_queue_size: usize,
-        event_queue_size: usize,
-        book_size: usize,
+        params: &MarketParams,
     ) -> Result<Self> {
-        // Make sure that certain accounts meet the minimum size requirements for allocation
-        if request_queue_size == 0 {
-            return Err(Error::from(serum_dex::error::DexError::from(
-                serum_dex::error::DexErrorCode::RequestQueueEmpty,
-            )));
-        }
-        if event_queue_size < 128 {
-            return Err(Error::from(serum_dex::error::DexError::from(
-                serum_dex::error::DexErrorCode::EventQueueTooSmall,
-            )));
-        }
-        if book_size <= 200 {
-            return Err(Error::from(serum_dex::error::DexError::from(
-                serum_dex::error::DexErrorCode::SlabTooSmall,
+        let mut markets = Self::new_batch(
+            sandbox,
+            actor,
+            serum,
+            &[(base_mint, quote_mint)],
+            authority,
+            params,
+        )?;
+        Ok(markets.remove(0))
+    }
+
+    /// Creates a market like new and registers readable names for its
+    /// accounts in the Sandbox, such as "SOL/USDC.bids" and
+    /// "SOL/USDC.base_vault".
+    pub fn new_named(
+        sandbox: &'a Sandbox,
+        actor: &'a Actor,
+        serum: &'a Pubkey,
+        base_mint: &'a Mint,
+        quote_mint: &'a Mint,
+        authority: Option<&'a Pubkey>,
+        params: &MarketParams,
+        label: &str,
+    ) -> Result<Self> {
+        let market = Self::new(
+            sandbox, actor, serum, base_mint, quote_mint, authority, params,
+        )?;
+        market.register_labels(label);
+        Ok(market)
+    }
+
+    /// Creates and initializes one market per pair of base and quote mints,
+    /// all sharing the same authority and parameters. The vaults of every
+    /// market are created together, and then every market is initialized
+    /// together, packing instructions into as few concurrently submitted
+    /// transactions as possible.
+    #[tracing::instrument(skip_all, fields(markets = mints.len()))]
+    pub fn new_batch(
+        sandbox: &'a Sandbox,
+        actor: &'a Actor,
+        serum: &'a Pubkey,
+        mints: &[(&'a Mint<'a>, &'a Mint<'a>)],
+        authority: Option<&'a Pubkey>,
+        params: &MarketParams,
+    ) -> Result<Vec<Self>> {
+        params.validate()?;
+        if authority.is_some() && !params.version.supports_authority() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} markets cannot have an authority", params.version),
             )));
         }
+        sandbox.register_decoder(serum, decoder::decode_serum_dex);
 
-        let market = Actor::new(sandbox)?;
-        let request_queue = Actor::new(sandbox)?;
-        let event_queue = Actor::new(sandbox)?;
-        let bids = Actor::new(sandbox)?;
-        let asks = Actor::new(sandbox)?;
-
-        let (vault_address, vault_nonce) = Self::create_vault_address(serum, market.pubkey());
-        let base_vault = TokenAccount::new(sandbox, actor, base_mint, Some(&vault_address))?;
-        let quote_vault = TokenAccount::new(sandbox, actor, quote_mint, Some(&vault_address))?;
         let has_authority = authority.is_some();
+        let book_size = params.book_bytes();
+        let mut prepared = Vec::with_capacity(mints.len());
+        for (base_mint, quote_mint) in mints.iter() {
+            let market = Actor::new(sandbox)?;
+            let request_queue = Actor::new(sandbox)?;
+            let event_queue = Actor::new(sandbox)?;
+            let bids = Actor::new(sandbox)?;
+            let asks = Actor::new(sandbox)?;
+
+            let (vault_address, vault_nonce) = pda::serum_vault_pda(serum, market.pubkey());
+            let (base_vault, mut vault_instructions) = TokenAccount::prepare(
+                sandbox,
+                actor,
+                base_mint.actor().pubkey(),
+                Some(&vault_address),
+            )?;
+            let (quote_vault, quote_vault_instructions) = TokenAccount::prepare(
+                sandbox,
+                actor,
+                quote_mint.actor().pubkey(),
+                Some(&vault_address),
+            )?;
+            vault_instructions.extend(quote_vault_instructions);
 
-        // Fetch the size of serum accounts so that we can send create_account
-        // instructions with the appropriate sizes.
-        let book_size = Self::side_size(book_size);
-        let sized_accounts = vec![
-            (market.pubkey(), Self::market_size(has_authority)),
-            (
+            // Fetch the size of serum accounts so that we can send create_account
+            // instructions with the appropriate sizes.
+            let sized_accounts = vec![
+                (market.pubkey(), params.version.market_bytes(has_authority)),
+                (request_queue.pubkey(), params.request_queue_bytes()),
+                (event_queue.pubkey(), params.event_queue_bytes()),
+                (bids.pubkey(), book_size),
+                (asks.pubkey(), book_size),
+            ];
+
+            // Bundle create_account instructions
+            let mut instructions = Vec::new();
+            for (pubkey, len) in sized_accounts.iter() {
+                instructions.push(actor.create_account(pubkey, *len, serum)?);
+            }
+
+            // Trail with market initialization
+            instructions.push(serum_dex::instruction::initialize_market(
+                market.pubkey(),
+                serum,
+                base_mint.actor().pubkey(),
+                quote_mint.actor().pubkey(),
+                base_vault.account().pubkey(),
+                quote_vault.account().pubkey(),
+                authority,
+                authority,
+                authority,
+                bids.pubkey(),
+                asks.pubkey(),
                 request_queue.pubkey(),
-                Self::request_queue_size(request_queue_size),
-            ),
-            (
                 event_queue.pubkey(),
-                Self::event_queue_size(event_queue_size),
-            ),
-            (bids.pubkey(), book_size),
-            (asks.pubkey(), book_size),
-        ];
+                params.base_lot_size,
+                params.quote_lot_size,
+                vault_nonce,
+                params.dust_threshold,
+            )?);
 
-        // Bundle create_account instructions
-        let mut instructions = Vec::new();
-        for (pubkey, len) in sized_accounts.iter() {
-            instructions.push(actor.create_account(pubkey, *len, serum)?);
+            let vault_signer_key =
+                serum_dex::state::gen_vault_signer_key(vault_nonce, market.pubkey(), serum)?;
+
+            let market = Market {
+                sandbox,
+                serum,
+                market,
+                version: params.version,
+                proxy: None,
+                authority: authority.copied(),
+                request_queue,
+                event_queue,
+                bids,
+                asks,
+                base_vault,
+                quote_vault,
+                vault_signer_key,
+                quote_lot_size: params.quote_lot_size,
+                base_mint: MaybeOwned::Borrowed(*base_mint),
+                quote_mint: MaybeOwned::Borrowed(*quote_mint),
+                open_orders_accounts: Vec::new(),
+            };
+            prepared.push((market, vault_instructions, instructions));
         }
 
-        // Trail with market initialization
-        instructions.push(serum_dex::instruction::initialize_market(
-            market.pubkey(),
+        // Markets can only be initialized once their vaults exist.
+        let vault_groups: Vec<_> = prepared
+            .iter()
+            .map(|(market, vault_instructions, _)| {
+                (
+                    vault_instructions.clone(),
+                    vec![
+                        market.base_vault.account().keypair(),
+                        market.quote_vault.account().keypair(),
+                    ],
+                )
+            })
+            .collect();
+        sandbox
+            .send_batch(actor.try_keypair()?, &vault_groups)
+            .with_context(|| format!("creating vaults for {} markets", prepared.len()))?;
+
+        let market_groups: Vec<_> = prepared
+            .iter()
+            .map(|(market, _, instructions)| {
+                (
+                    instructions.clone(),
+                    vec![
+                        market.market.keypair(),
+                        market.request_queue.keypair(),
+                        market.event_queue.keypair(),
+                        market.bids.keypair(),
+                        market.asks.keypair(),
+                    ],
+                )
+            })
+            .collect();
+        // Markets whose accounts and initialization do not fit in a single
+        // transaction are created in order across several instead.
+        let (market_groups, oversized): (Vec<_>, Vec<_>) = market_groups
+            .into_iter()
+            .partition(|(instructions, _)| transaction::fits(actor.pubkey(), instructions));
+        sandbox
+            .send_batch(actor.try_keypair()?, &market_groups)
+            .with_context(|| format!("initializing {} markets", market_groups.len()))?;
+        for (instructions, signers) in oversized.iter() {
+            sandbox
+                .transaction()
+                .instructions(instructions)
+                .payer(actor.try_keypair()?)
+                .signers(signers)
+                .send_split()
+                .with_context(|| {
+                    format!(
+                        "initializing market {}",
+                        sandbox.label(&signers[0].pubkey())
+                    )
+                })?;
+        }
+
+        Ok(prepared.into_iter().map(|(market, _, _)| market).collect())
+    }
+
+    /// Attaches to a market that already exists on chain, such as one created
+    /// by create_serum_market or cloned from another cluster. The market
+    /// state is loaded to recover the queue, slab, vault, and mint accounts.
+    ///
+    /// Accounts owned by the market are attached as watch-only actors, so the
+    /// returned handle can trade, crank, and settle but cannot sign for the
+    /// market's own accounts. The mints' authorities are watch-only too, so
+    /// participants must bring their own tokens; use
+    /// new_from_existing_with_mints to fund them by minting. The program
+    /// version cannot be read from the market, so it is taken to be V3; use
+    /// with_version otherwise.
+    pub fn new_from_existing(
+        sandbox: &'a Sandbox,
+        serum: &'a Pubkey,
+        market: &Pubkey,
+    ) -> Result<Self> {
+        let data = sandbox.get_account_data(market)?;
+        let state = Self::load_market_state(&data)?;
+        let coin_mint = state.coin_mint;
+        let pc_mint = state.pc_mint;
+        let base_mint = Mint::watch_only(sandbox, &Self::to_pubkey(&coin_mint))?;
+        let quote_mint = Mint::watch_only(sandbox, &Self::to_pubkey(&pc_mint))?;
+        Self::attach(
+            sandbox,
             serum,
-            base_mint.actor().pubkey(),
-            quote_mint.actor().pubkey(),
-            base_vault.account().pubkey(),
-            quote_vault.account().pubkey(),
-            authority,
-            authority,
-            authority,
-            bids.pubkey(),
-            asks.pubkey(),
-            request_queue.pubkey(),
-            event_queue.pubkey(),
-            base_lot_size,
-            quote_lot_size,
-            vault_nonce,
-            dust_threshold,
-        )?);
+            market,
+            MaybeOwned::Owned(base_mint),
+            MaybeOwned::Owned(quote_mint),
+        )
+    }
 
-        sandbox.send_signed_transaction_with_payers(
-            &instructions,
-            Some(actor.pubkey()),
-            vec![
-                actor.keypair(),
-                market.keypair(),
-                request_queue.keypair(),
-                event_queue.keypair(),
-                bids.keypair(),
-                asks.keypair(),
-            ],
-        )?;
+    /// Attaches to a market that already exists on chain like
+    /// new_from_existing, with mints whose authorities can sign, so that
+    /// participants can be funded by minting. The given mints must match the
+    /// market's base and quote mints.
+    pub fn new_from_existing_with_mints(
+        sandbox: &'a Sandbox,
+        serum: &'a Pubkey,
+        market: &Pubkey,
+        base_mint: &'a Mint,
+        quote_mint: &'a Mint,
+    ) -> Result<Self> {
+        Self::attach(
+            sandbox,
+            serum,
+            market,
+            MaybeOwned::Borrowed(base_mint),
+            MaybeOwned::Borrowed(quote_mint),
+        )
+    }
+
+    fn attach(
+        sandbox: &'a Sandbox,
+        serum: &'a Pubkey,
+        market: &Pubkey,
+        base_mint: MaybeOwned<'a, Mint<'a>>,
+        quote_mint: MaybeOwned<'a, Mint<'a>>,
+    ) -> Result<Self> {
+        sandbox.register_decoder(serum, decoder::decode_serum_dex);
+        let data = sandbox.get_account_data(market)?;
+        let state = Self::load_market_state(&data)?;
+
+        let coin_mint = state.coin_mint;
+        let pc_mint = state.pc_mint;
+        if Self::to_pubkey(&coin_mint) != *base_mint.actor().pubkey() {
+            return Err(Error::from(serum_dex::error::DexError::from(
+                serum_dex::error::DexErrorCode::WrongCoinMint,
+            )));
+        }
+        if Self::to_pubkey(&pc_mint) != *quote_mint.actor().pubkey() {
+            return Err(Error::from(serum_dex::error::DexError::from(
+                serum_dex::error::DexErrorCode::WrongPcMint,
+            )));
+        }
 
+        // Permissioned markets store their authorities after the V1 state.
+        let account_flags = state.account_flags;
+        let authority = if account_flags & (serum_state::AccountFlag::Permissioned as u64) != 0 {
+            let offset = serum_state::ACCOUNT_HEAD_PADDING.len()
+                + std::mem::size_of::<serum_state::MarketState>();
+            data.get(offset..offset + 32).map(Pubkey::new)
+        } else {
+            None
+        };
+
+        let vault_signer_nonce = state.vault_signer_nonce;
         let vault_signer_key =
-            serum_dex::state::gen_vault_signer_key(vault_nonce, market.pubkey(), serum)?;
+            serum_dex::state::gen_vault_signer_key(vault_signer_nonce, market, serum)?;
+
+        let coin_vault = state.coin_vault;
+        let pc_vault = state.pc_vault;
+        let req_q = state.req_q;
+        let event_q = state.event_q;
+        let bids = state.bids;
+        let asks = state.asks;
 
         Ok(Market {
             sandbox,
             serum,
-            market,
+            market: Actor::from_pubkey(sandbox, market),
+            version: DexVersion::V3,
+            proxy: None,
             authority,
-            request_queue,
-            event_queue,
-            bids,
-            asks,
-            base_vault,
-            quote_vault,
+            request_queue: Actor::from_pubkey(sandbox, &Self::to_pubkey(&req_q)),
+            event_queue: Actor::from_pubkey(sandbox, &Self::to_pubkey(&event_q)),
+            bids: Actor::from_pubkey(sandbox, &Self::to_pubkey(&bids)),
+            asks: Actor::from_pubkey(sandbox, &Self::to_pubkey(&asks)),
+            base_vault: TokenAccount::from_pubkey(sandbox, &Self::to_pubkey(&coin_vault)),
+            quote_vault: TokenAccount::from_pubkey(sandbox, &Self::to_pubkey(&pc_vault)),
             vault_signer_key,
+            quote_lot_size: state.pc_lot_size,
             base_mint,
             quote_mint,
             open_orders_accounts: Vec::new(),
@@ -161,7 +605,18 @@ impl<'a> Market<'a> {
     /// will fail if the transaction does not go through.
     /// It is important to note that matching occurs at this state
     /// inside of Serum itself in V3, however, in earlier versions,
-    /// this does not occur until requests are popped off of the request queue.
+    /// this does not occur until requests are popped off of the request queue
+    /// by match_orders. V1 orders ignore the self trade behavior, and V1 and
+    /// V2 orders ignore the match limit and the quote limit.
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            market = %self.market.pubkey(),
+            side = ?side,
+            limit_price = %limit_price,
+            max_base_qty = %max_base_qty,
+        )
+    )]
     pub fn new_order(
         &self,
         payer: &Actor<'a>,
@@ -176,6 +631,62 @@ impl<'a> Market<'a> {
         max_native_quote_including_fees: NonZeroU64,
         srm_account_referral: Option<&Pubkey>,
     ) -> Result<()> {
+        let instruction = self.new_order_ix(
+            payer,
+            participant,
+            side,
+            limit_price,
+            order_type,
+            max_base_qty,
+            client_order_id,
+            self_trade_behavior,
+            limit,
+            max_native_quote_including_fees,
+            srm_account_referral,
+        )?;
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(participant.owner().try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Builds the instruction new_order sends, for the market's version and
+    /// through its proxy, without sending it. The participant's owner must
+    /// sign it.
+    pub fn new_order_ix(
+        &self,
+        payer: &Actor,
+        participant: &Participant,
+        side: Side,
+        limit_price: NonZeroU64,
+        order_type: OrderType,
+        max_base_qty: NonZeroU64,
+        client_order_id: u64,
+        self_trade_behavior: SelfTradeBehavior,
+        limit: u16,
+        max_native_quote_including_fees: NonZeroU64,
+        srm_account_referral: Option<&Pubkey>,
+    ) -> Result<Instruction> {
+        self.ensure_participant(participant)?;
+        if !self.version.matches_immediately() {
+            let order = queued_order(
+                side,
+                limit_price,
+                max_base_qty,
+                order_type,
+                client_order_id,
+                match self.version {
+                    DexVersion::V2 => Some(self_trade_behavior),
+                    _ => None,
+                },
+            );
+            let instruction =
+                self.queued_order_instruction(&order, participant, payer, srm_account_referral);
+            return Ok(self.proxied(instruction));
+        }
+
         let new_order_instruction = serum_dex::instruction::new_order(
             self.market.pubkey(),
             participant.open_orders().pubkey(),
@@ -184,7 +695,7 @@ impl<'a> Market<'a> {
             self.bids.pubkey(),
             self.asks.pubkey(),
             payer.pubkey(),
-            participant.account().pubkey(),
+            participant.owner().pubkey(),
             self.base_vault.account().pubkey(),
             self.quote_vault.account().pubkey(),
             &spl_token::ID,
@@ -200,23 +711,186 @@ impl<'a> Market<'a> {
             limit,
             max_native_quote_including_fees,
         )?;
+        Ok(self.proxied(new_order_instruction))
+    }
+
+    /// Places a limit order for size base lots at price quote lots per base
+    /// lot. See place_order.
+    pub fn place_limit(
+        &self,
+        participant: &Participant<'a>,
+        side: Side,
+        price: NonZeroU64,
+        size: NonZeroU64,
+    ) -> Result<()> {
+        self.place_order(participant, side, price, size, OrderType::Limit, 0)
+    }
+
+    /// Places an order for size base lots at price quote lots per base lot,
+    /// paid from the participant's quote wallet for bids and its base wallet
+    /// for asks. The quote budget covers the whole order and the base taker
+    /// fee, so only price and size limit it. Self trades decrement the
+    /// incoming order. Fails with InvalidInput if the budget overflows.
+    pub fn place_order(
+        &self,
+        participant: &Participant<'a>,
+        side: Side,
+        price: NonZeroU64,
+        size: NonZeroU64,
+        order_type: OrderType,
+        client_order_id: u64,
+    ) -> Result<()> {
+        let instruction =
+            self.place_order_ix(participant, side, price, size, order_type, client_order_id)?;
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(participant.owner().try_keypair()?)
+            .send()?;
+        Ok(())
+    }
 
-        self.sandbox.send_signed_transaction_with_payers(
-            &[new_order_instruction],
-            Some(participant.account.pubkey()),
-            vec![participant.account.keypair()],
+    /// Builds the instruction place_order sends, without sending it. The
+    /// participant's owner must sign it.
+    pub fn place_order_ix(
+        &self,
+        participant: &Participant,
+        side: Side,
+        price: NonZeroU64,
+        size: NonZeroU64,
+        order_type: OrderType,
+        client_order_id: u64,
+    ) -> Result<Instruction> {
+        let wallet = match side {
+            Side::Bid => participant.quote(),
+            Side::Ask => participant.base(),
+        };
+        self.new_order_ix(
+            wallet,
+            participant,
+            side,
+            price,
+            order_type,
+            size,
+            client_order_id,
+            SelfTradeBehavior::DecrementTake,
+            PLACE_ORDER_MATCH_LIMIT,
+            self.order_budget(price, size)?,
+            None,
         )
     }
 
+    /// Returns the native quote, including the base taker fee, that an order
+    /// for size base lots at price may spend.
+    fn order_budget(&self, price: NonZeroU64, size: NonZeroU64) -> Result<NonZeroU64> {
+        price
+            .get()
+            .checked_mul(size.get())
+            .and_then(|quote_lots| quote_lots.checked_mul(self.quote_lot_size))
+            .and_then(|native_quote| {
+                native_quote.checked_add(FeeTier::Base.taker_fee(native_quote))
+            })
+            .and_then(NonZeroU64::new)
+            .ok_or_else(|| {
+                Error::from(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "an order of {} lots at {} on market {} exceeds the largest quote amount",
+                        size,
+                        price,
+                        self.sandbox.label(self.market.pubkey())
+                    ),
+                ))
+            })
+    }
+
+    /// Builds a V1 or V2 new order instruction, which queues the order in the
+    /// request queue.
+    fn queued_order_instruction(
+        &self,
+        order: &MarketInstruction,
+        participant: &Participant,
+        payer: &Actor,
+        srm_account_referral: Option<&Pubkey>,
+    ) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(*self.market.pubkey(), false),
+            AccountMeta::new(*participant.open_orders().pubkey(), false),
+            AccountMeta::new(*self.request_queue.pubkey(), false),
+            AccountMeta::new(*payer.pubkey(), false),
+            AccountMeta::new_readonly(*participant.owner().pubkey(), true),
+            AccountMeta::new(*self.base_vault.account().pubkey(), false),
+            AccountMeta::new(*self.quote_vault.account().pubkey(), false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+        ];
+        if let Some(referral) = srm_account_referral {
+            accounts.push(AccountMeta::new(*referral, false));
+        }
+        Instruction {
+            program_id: *self.serum,
+            accounts,
+            data: order.pack(),
+        }
+    }
+
+    /// Matches up to limit requests waiting in the request queue of a V1 or
+    /// V2 market, which places and cancels their orders. V3 markets match
+    /// orders as they are placed and reject this instruction.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn match_orders(&self, payer: &Actor, limit: u16) -> Result<()> {
+        self.sandbox
+            .transaction()
+            .instruction(self.match_orders_ix(limit))
+            .payer(payer.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Builds the instruction match_orders sends, without sending it.
+    pub fn match_orders_ix(&self, limit: u16) -> Instruction {
+        // The fee receivable accounts are not used by any released Serum
+        // program, so the vaults stand in for them.
+        Instruction {
+            program_id: *self.serum,
+            accounts: vec![
+                AccountMeta::new(*self.market.pubkey(), false),
+                AccountMeta::new(*self.request_queue.pubkey(), false),
+                AccountMeta::new(*self.event_queue.pubkey(), false),
+                AccountMeta::new(*self.bids.pubkey(), false),
+                AccountMeta::new(*self.asks.pubkey(), false),
+                AccountMeta::new(*self.base_vault.account().pubkey(), false),
+                AccountMeta::new(*self.quote_vault.account().pubkey(), false),
+            ],
+            data: MarketInstruction::MatchOrders(limit).pack(),
+        }
+    }
+
     /// Spin up consume_events_loop on another thread and kill it after
     /// crank_for_ms milliseconds.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
     pub fn consume_events(
         &self,
         payer: &Actor,
         open_orders: Vec<&Pubkey>,
         num_events: u16,
     ) -> Result<()> {
-        let consume_events = serum_dex::instruction::consume_events(
+        let consume_events = self.consume_events_ix(open_orders, num_events)?;
+        self.sandbox
+            .transaction()
+            .instruction(consume_events)
+            .payer(payer.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Builds the instruction consume_events sends, without sending it.
+    pub fn consume_events_ix(
+        &self,
+        open_orders: Vec<&Pubkey>,
+        num_events: u16,
+    ) -> Result<Instruction> {
+        Ok(serum_dex::instruction::consume_events(
             self.serum(),
             open_orders,
             self.market().pubkey(),
@@ -224,40 +898,143 @@ impl<'a> Market<'a> {
             self.base_vault().account().pubkey(),
             self.quote_vault().account().pubkey(),
             num_events,
-        )
-        .unwrap();
+        )?)
+    }
 
-        self.sandbox.send_signed_transaction_with_payers(
-            &[consume_events],
-            Some(payer.pubkey()),
-            vec![payer.keypair()],
-        )
+    /// Returns every open orders account initialized for this market. The
+    /// accounts are discovered by scanning the Serum program's accounts for
+    /// open orders whose market field matches this market.
+    pub fn find_open_orders_accounts(&self) -> Result<Vec<Pubkey>> {
+        let filter = AccountFilter::new()
+            .data_size(Self::open_orders_size() as u64)
+            .memcmp_pubkey(OPEN_ORDERS_MARKET_OFFSET, self.market.pubkey())
+            .keys_only();
+        Ok(self
+            .sandbox
+            .find_program_accounts(self.serum, &filter)?
+            .into_iter()
+            .map(|(pubkey, _)| pubkey)
+            .collect())
+    }
+
+    /// Consumes up to num_events events on behalf of every open orders
+    /// account in this market. The discovered accounts are sorted, as Serum
+    /// expects, and passed MAX_OPEN_ORDERS_PER_CRANK at a time to one
+    /// consume_events transaction each, which keeps every transaction under
+    /// the size limit. Serum stops at the first event whose owner is not in
+    /// the instruction, so a single pass may leave events for accounts in an
+    /// earlier chunk; drain_events repeats passes until the queue is empty.
+    /// V1 and V2 markets first match up to num_events queued requests.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn crank_all(&self, payer: &Actor, num_events: u16) -> Result<()> {
+        if !self.version.matches_immediately() {
+            self.match_orders(payer, num_events)?;
+        }
+        let mut open_orders = self.find_open_orders_accounts()?;
+        open_orders.sort_unstable();
+        for chunk in open_orders.chunks(crank::MAX_OPEN_ORDERS_PER_CRANK) {
+            self.consume_events(payer, chunk.iter().collect(), num_events)?;
+        }
+        Ok(())
     }
 
     /// Cranker settles funds for a particular participant by invoking crank::start
     pub fn settle_funds(&self, payer: &Actor, participant: &Participant) -> Result<()> {
+        self.settle(payer, participant, None)
+    }
+
+    /// Settles funds for a participant and pays the referral rebates accrued
+    /// by its open orders into referrer_quote, a quote token account of the
+    /// referrer. Without a referrer the rebates are kept by the market.
+    pub fn settle_funds_with_referrer(
+        &self,
+        payer: &Actor,
+        participant: &Participant,
+        referrer_quote: &Pubkey,
+    ) -> Result<()> {
+        self.settle(payer, participant, Some(referrer_quote))
+    }
+
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    fn settle(
+        &self,
+        payer: &Actor,
+        participant: &Participant,
+        referrer_quote: Option<&Pubkey>,
+    ) -> Result<()> {
+        let settle_funds = self.settle_funds_ix(participant, referrer_quote)?;
+        self.sandbox
+            .transaction()
+            .instruction(settle_funds)
+            .payer(payer.try_keypair()?)
+            .signer(participant.owner().try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Builds the instruction settle_funds sends, through the market's proxy,
+    /// without sending it. With a referrer quote account the referral rebates
+    /// are paid into it, as in settle_funds_with_referrer. The participant's
+    /// owner must sign it.
+    pub fn settle_funds_ix(
+        &self,
+        participant: &Participant,
+        referrer_quote: Option<&Pubkey>,
+    ) -> Result<Instruction> {
+        self.ensure_participant(participant)?;
         let settle_funds = serum_dex::instruction::settle_funds(
             self.serum(),
             self.market().pubkey(),
             &spl_token::ID,
             participant.open_orders.pubkey(),
-            participant.account().pubkey(),
+            participant.owner().pubkey(),
             self.base_vault().account().pubkey(),
             participant.base().pubkey(),
             self.quote_vault().account().pubkey(),
             participant.quote().pubkey(),
-            None,
+            referrer_quote,
             self.vault_signer_key(),
-        )
-        .unwrap();
+        )?;
+        Ok(self.proxied(settle_funds))
+    }
 
-        self.sandbox.send_signed_transaction_with_payers(
-            &[settle_funds],
-            Some(payer.pubkey()),
-            vec![payer.keypair(), participant.account().keypair()],
-        )
+    /// Moves the quote fees accrued by the market, pc_fees_accrued in the
+    /// market state, into destination, a quote token account.
+    ///
+    /// Released Serum programs only accept the hardcoded fee sweeper as
+    /// authority, so sweeping requires a Serum build that accepts the given
+    /// authority, such as one compiled with the test feature.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn sweep_fees(&self, authority: &Actor, destination: &TokenAccount) -> Result<()> {
+        let sweep_fees = serum_dex::instruction::sweep_fees(
+            self.serum(),
+            self.market().pubkey(),
+            self.quote_vault().account().pubkey(),
+            authority.pubkey(),
+            destination.account().pubkey(),
+            self.vault_signer_key(),
+            &spl_token::ID,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(sweep_fees)
+            .payer(authority.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Returns the quote fees accrued by the market and not yet swept, and
+    /// the referral rebates credited to open orders accounts so far.
+    pub fn fees_accrued(&self) -> Result<(u64, u64)> {
+        let view = self.view()?;
+        Ok((view.pc_fees_accrued, view.referrer_rebates_accrued))
     }
 
+    #[tracing::instrument(
+        skip_all,
+        fields(market = %self.market.pubkey(), side = ?side, order_id = order_id)
+    )]
     pub fn cancel_order(
         &self,
         payer: &Actor,
@@ -265,24 +1042,892 @@ impl<'a> Market<'a> {
         side: Side,
         order_id: u128,
     ) -> Result<()> {
-        let cancel_order = serum_dex::instruction::cancel_order(
-            self.serum(),
-            self.market().pubkey(),
+        let cancel_order = self.cancel_order_ix(participant, side, order_id)?;
+        self.sandbox
+            .transaction()
+            .instruction(cancel_order)
+            .payer(payer.try_keypair()?)
+            .signer(participant.owner().try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Builds the instruction cancel_order sends, for the market's version
+    /// and through its proxy, without sending it. On V1 and V2 markets the
+    /// participant's open orders account is read to find the order's slot.
+    /// The participant's owner must sign it.
+    pub fn cancel_order_ix(
+        &self,
+        participant: &Participant,
+        side: Side,
+        order_id: u128,
+    ) -> Result<Instruction> {
+        self.ensure_participant(participant)?;
+        if !self.version.matches_immediately() {
+            let instruction = self.cancel_queued_order(participant, side, order_id)?;
+            return Ok(self.proxied(instruction));
+        }
+
+        let cancel_order = serum_dex::instruction::cancel_order(
+            self.serum(),
+            self.market().pubkey(),
             self.bids().pubkey(),
             self.asks().pubkey(),
             participant.open_orders().pubkey(),
-            participant.account().pubkey(),
+            participant.owner().pubkey(),
             self.event_queue().pubkey(),
             side,
             order_id,
-        )
-        .unwrap();
+        )?;
+        Ok(self.proxied(cancel_order))
+    }
 
-        self.sandbox.send_signed_transaction_with_payers(
-            &[cancel_order],
-            Some(payer.pubkey()),
-            vec![payer.keypair(), participant.account().keypair()],
-        )
+    /// Builds the cancellation of an order on a V1 or V2 market, which is
+    /// queued. The order leaves the book once the request is matched.
+    fn cancel_queued_order(
+        &self,
+        participant: &Participant,
+        side: Side,
+        order_id: u128,
+    ) -> Result<Instruction> {
+        let slot = participant
+            .open_orders_view()?
+            .orders
+            .into_iter()
+            .find(|order| order.order_id == order_id)
+            .map(|order| order.slot)
+            .ok_or_else(|| {
+                Error::from(serum_dex::error::DexError::from(
+                    serum_dex::error::DexErrorCode::OrderNotFound,
+                ))
+            })?;
+        let open_orders = participant.open_orders().pubkey();
+        Ok(Instruction {
+            program_id: *self.serum,
+            accounts: vec![
+                AccountMeta::new_readonly(*self.market.pubkey(), false),
+                AccountMeta::new(*open_orders, false),
+                AccountMeta::new(*self.request_queue.pubkey(), false),
+                AccountMeta::new_readonly(*participant.owner().pubkey(), true),
+            ],
+            data: MarketInstruction::CancelOrder(CancelOrderInstruction {
+                side,
+                order_id,
+                owner: bytemuck::cast(open_orders.to_bytes()),
+                owner_slot: slot,
+            })
+            .pack(),
+        })
+    }
+
+    /// Closes a participant's open orders account and sends its rent to
+    /// destination. The account must have no resting orders or unsettled
+    /// funds, so cancel, crank, and settle first. Returns the lamports
+    /// reclaimed.
+    pub fn close_open_orders(
+        &self,
+        participant: &Participant,
+        destination: &Pubkey,
+    ) -> Result<u64> {
+        self.ensure_participant(participant)?;
+        let open_orders = participant.open_orders().pubkey();
+        let lamports = self.sandbox.get_account(open_orders)?.lamports;
+        let instruction = serum_dex::instruction::close_open_orders(
+            self.serum,
+            open_orders,
+            participant.owner().pubkey(),
+            destination,
+            self.market.pubkey(),
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(self.proxied(instruction))
+            .payer(participant.owner().try_keypair()?)
+            .send()?;
+        Ok(lamports)
+    }
+
+    /// Cancels every order the participant has resting on the book, packing
+    /// the cancels into as few transactions as fit. Returns the number of
+    /// orders cancelled. On V1 and V2 markets the cancels are queued until
+    /// the request queue is matched.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn cancel_all_orders(&self, payer: &Actor, participant: &Participant) -> Result<usize> {
+        let orders = participant.open_orders_view()?.orders;
+        if orders.is_empty() {
+            return Ok(0);
+        }
+        let instructions = orders
+            .iter()
+            .map(|order| {
+                let side = if order.is_bid { Side::Bid } else { Side::Ask };
+                self.cancel_order_ix(participant, side, order.order_id)
+            })
+            .collect::<Result<Vec<Instruction>>>()?;
+        self.sandbox
+            .transaction()
+            .instructions(&instructions)
+            .payer(payer.try_keypair()?)
+            .signer(participant.owner().try_keypair()?)
+            .send_split()?;
+        Ok(orders.len())
+    }
+
+    /// Fills the book with resting orders of one base lot each, for matching
+    /// and crank benchmarks that need a deep book. Each side gets levels
+    /// price levels of orders_per_level orders: bids rest at prices 1
+    /// through levels and asks from levels + spread upwards, in quote lots
+    /// per base lot, so the best bid and ask are spread apart. Returns the
+    /// number of orders placed.
+    ///
+    /// Orders are dealt to the participants in turn, and each participant is
+    /// topped up with the tokens its orders lock. The orders are post only
+    /// and sent in parallel batches packing as many as fit in a transaction.
+    /// A participant holds at most 128 orders, and the book must have room
+    /// for every order. Only V3 markets are supported, since older versions
+    /// queue new orders in a request queue too small for a deep book.
+    #[tracing::instrument(
+        skip_all,
+        fields(market = %self.market.pubkey(), levels = levels, orders_per_level = orders_per_level)
+    )]
+    pub fn seed_book(
+        &self,
+        payer: &Actor,
+        participants: &[&Participant],
+        levels: u64,
+        orders_per_level: usize,
+        spread: u64,
+    ) -> Result<usize> {
+        if !self.version.matches_immediately() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "seed_book requires a V3 market",
+            )));
+        }
+        if spread == 0 || participants.is_empty() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seed_book needs a positive spread and at least one participant",
+            )));
+        }
+        let mut orders = Vec::new();
+        for level in 0..levels {
+            for _ in 0..orders_per_level {
+                orders.push((Side::Bid, levels - level));
+                orders.push((Side::Ask, levels + spread + level));
+            }
+        }
+        if orders.len() > participants.len() * MAX_ORDERS_PER_OPEN_ORDERS {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} orders do not fit in the open orders accounts of {} participants",
+                    orders.len(),
+                    participants.len()
+                ),
+            )));
+        }
+        for participant in participants {
+            self.ensure_participant(participant)?;
+        }
+
+        let view = self.view()?;
+        let mut funding = vec![(0, 0); participants.len()];
+        for (i, (side, price)) in orders.iter().enumerate() {
+            match side {
+                Side::Bid => funding[i % participants.len()].1 += price * view.pc_lot_size,
+                Side::Ask => funding[i % participants.len()].0 += view.coin_lot_size,
+            }
+        }
+        for (participant, (base, quote)) in participants.iter().zip(funding) {
+            participant.top_up(base, quote)?;
+        }
+
+        let groups = orders
+            .iter()
+            .enumerate()
+            .map(|(i, (side, price))| {
+                let participant = participants[i % participants.len()];
+                let instruction = self.place_order_ix(
+                    participant,
+                    *side,
+                    NonZeroU64::new(*price).expect("book prices are positive"),
+                    NonZeroU64::new(1).expect("one is positive"),
+                    OrderType::PostOnly,
+                    // Distinct client ids keep otherwise identical orders
+                    // from producing identical transactions.
+                    i as u64,
+                )?;
+                Ok((vec![instruction], vec![participant.owner().try_keypair()?]))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.sandbox.send_batch(payer.try_keypair()?, &groups)?;
+        Ok(orders.len())
+    }
+
+    /// Reads the event queue's header without the events, fetching a few
+    /// bytes of the account rather than the whole queue, so that crank loops
+    /// and wait conditions can poll it cheaply.
+    pub fn event_queue_header(&self) -> Result<EventQueueHeader> {
+        let length = std::mem::size_of::<serum_state::EventQueueHeader>();
+        let data = self.sandbox.get_account_slice(
+            self.event_queue.pubkey(),
+            serum_state::ACCOUNT_HEAD_PADDING.len(),
+            length,
+        )?;
+        if data.len() < length {
+            return Err(Error::from(serum_dex::error::DexError::from(
+                serum_dex::error::DexErrorCode::WrongAccountDataPaddingLength,
+            )));
+        }
+        Ok(EventQueueHeader::load(&data))
+    }
+
+    /// Returns the number of events waiting in the event queue. See
+    /// event_queue_header.
+    pub fn event_queue_len(&self) -> Result<u64> {
+        Ok(self.event_queue_header()?.count)
+    }
+
+    /// Cranks the market until its event queue is empty, matching queued
+    /// requests first on V1 and V2 markets. Fails if a crank makes no
+    /// progress, as when an event belongs to an open orders account that
+    /// cannot be found.
+    pub fn drain_events(&self, payer: &Actor, num_events: u16) -> Result<()> {
+        // Matching pushes events while the crank consumes them, so progress
+        // is measured by the events consumed rather than by the queue's
+        // length. Every event ever pushed is either consumed or waiting.
+        let mut consumed_before = None;
+        loop {
+            let header = self.event_queue_header()?;
+            if header.count == 0 {
+                return Ok(());
+            }
+            let consumed = header.seq_num.saturating_sub(header.count);
+            if consumed_before.map_or(false, |before| consumed <= before) {
+                return Err(Error::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("crank left {} events in the event queue", header.count),
+                )));
+            }
+            consumed_before = Some(consumed);
+            self.crank_all(payer, num_events)?;
+        }
+    }
+
+    /// Winds the participants down so that a long-running fixture can be
+    /// discarded and its lamports accounted for: cancels their resting
+    /// orders, cranks the resulting events, settles their funds out of the
+    /// vaults, and closes their open orders accounts, sending the rent to
+    /// destination. Wallets are left open with the settled tokens.
+    ///
+    /// The Serum version this crate builds against has no instruction to
+    /// close the market's own accounts, so their rent is reported as locked
+    /// rather than reclaimed. Fees accrued by the market also stay in the
+    /// quote vault; see sweep_fees.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn teardown(
+        &self,
+        payer: &Actor,
+        participants: &[&Participant],
+        destination: &Pubkey,
+    ) -> Result<TeardownReport> {
+        let mut orders_cancelled = 0;
+        for participant in participants {
+            orders_cancelled += self
+                .cancel_all_orders(payer, participant)
+                .with_context(|| {
+                    format!(
+                        "cancelling orders of {}",
+                        self.sandbox.label(participant.account().pubkey())
+                    )
+                })?;
+        }
+        self.drain_events(payer, 32)?;
+
+        let mut lamports_reclaimed = 0;
+        for participant in participants {
+            self.settle_funds(payer, participant).with_context(|| {
+                format!(
+                    "settling {}",
+                    self.sandbox.label(participant.account().pubkey())
+                )
+            })?;
+            lamports_reclaimed += self
+                .close_open_orders(participant, destination)
+                .with_context(|| {
+                    format!(
+                        "closing {}",
+                        self.sandbox.label(participant.account().pubkey())
+                    )
+                })?;
+        }
+
+        let mut lamports_locked = 0;
+        for pubkey in [
+            self.market.pubkey(),
+            self.request_queue.pubkey(),
+            self.event_queue.pubkey(),
+            self.bids.pubkey(),
+            self.asks.pubkey(),
+            self.base_vault.account().pubkey(),
+            self.quote_vault.account().pubkey(),
+        ] {
+            lamports_locked += self.sandbox.get_account(pubkey)?.lamports;
+        }
+        Ok(TeardownReport {
+            orders_cancelled,
+            lamports_reclaimed,
+            lamports_locked,
+            base_vault: self.base_vault.get_account_info()?.amount,
+            quote_vault: self.quote_vault.get_account_info()?.amount,
+        })
+    }
+
+    /// Approves a participant to trade on a permissioned market by
+    /// initializing its open orders account with the market authority's
+    /// signature. Participants that are not approved cannot place orders.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn approve_open_orders(&self, authority: &Actor, participant: &Participant) -> Result<()> {
+        self.ensure_participant(participant)?;
+        let init_open_orders = serum_dex::instruction::init_open_orders(
+            self.serum,
+            participant.open_orders().pubkey(),
+            participant.owner().pubkey(),
+            self.market.pubkey(),
+            Some(authority.pubkey()),
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(self.proxied(init_open_orders))
+            .payer(authority.try_keypair()?)
+            .signer(participant.owner().try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Cancels up to limit of a participant's resting orders on a permissioned
+    /// market. The prune authority signs and pays for the transaction, so the
+    /// participant does not need to cooperate.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn prune(&self, authority: &Actor, participant: &Participant, limit: u16) -> Result<()> {
+        self.ensure_participant(participant)?;
+        let prune = serum_dex::instruction::prune(
+            self.serum,
+            self.market.pubkey(),
+            self.bids.pubkey(),
+            self.asks.pubkey(),
+            authority.pubkey(),
+            participant.open_orders().pubkey(),
+            participant.owner().pubkey(),
+            self.event_queue.pubkey(),
+            limit,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(self.proxied(prune))
+            .payer(authority.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Consumes events on a permissioned market. Markets created with an
+    /// authority require the consume events authority to sign every crank.
+    #[tracing::instrument(skip_all, fields(market = %self.market.pubkey()))]
+    pub fn consume_events_permissioned(
+        &self,
+        authority: &Actor,
+        open_orders: Vec<&Pubkey>,
+        num_events: u16,
+    ) -> Result<()> {
+        let consume_events = serum_dex::instruction::consume_events_permissioned(
+            self.serum,
+            open_orders,
+            self.market.pubkey(),
+            self.event_queue.pubkey(),
+            authority.pubkey(),
+            num_events,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(self.proxied(consume_events))
+            .payer(authority.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Fetches the market account as a typed view, for field-level
+    /// comparison with views::diff.
+    pub fn view(&self) -> Result<MarketView> {
+        MarketView::load(&self.sandbox.get_account_data(self.market.pubkey())?)
+    }
+
+    /// Fetches every node ever allocated on one side of the order book.
+    /// Nodes past the slab's bump index have never held an order, so they
+    /// are neither fetched nor returned.
+    pub fn book_nodes(&self, side: Side) -> Result<Vec<SlabNodeView>> {
+        SlabNodeView::load_all(&self.slab_data(side)?)
+    }
+
+    /// Fetches one side of the order book in two slices: the slab header,
+    /// then the nodes up to its bump index. The tail padding is appended so
+    /// that the result parses like the whole account.
+    fn slab_data(&self, side: Side) -> Result<Vec<u8>> {
+        let account = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let mut data =
+            self.sandbox
+                .get_account_slice(account.pubkey(), 0, views::SLAB_NODES_OFFSET)?;
+        if data.len() < views::SLAB_NODES_OFFSET {
+            return Err(views::wrong_padding());
+        }
+        let bump_index = u64::from_le_bytes(
+            data[slab::BUMP_INDEX_OFFSET..slab::BUMP_INDEX_OFFSET + 8]
+                .try_into()
+                .expect("8 bytes"),
+        );
+        let nodes_len = usize::try_from(bump_index)
+            .ok()
+            .and_then(|nodes| nodes.checked_mul(std::mem::size_of::<AnyNode>()))
+            .ok_or_else(views::wrong_padding)?;
+        data.extend(self.sandbox.get_account_slice(
+            account.pubkey(),
+            views::SLAB_NODES_OFFSET,
+            nodes_len,
+        )?);
+        data.extend_from_slice(&serum_state::ACCOUNT_TAIL_PADDING);
+        Ok(data)
+    }
+
+    /// Returns the number of orders resting on one side of the book, read
+    /// from the slab header without fetching its nodes.
+    pub fn order_count(&self, side: Side) -> Result<u64> {
+        let account = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let data = self
+            .sandbox
+            .get_account_slice(account.pubkey(), slab::LEAF_COUNT_OFFSET, 8)?;
+        let bytes: [u8; 8] = data.as_slice().try_into().map_err(|_| {
+            Error::from(serum_dex::error::DexError::from(
+                serum_dex::error::DexErrorCode::WrongAccountDataPaddingLength,
+            ))
+        })?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Returns the fills in the event queue that belong to the participant's
+    /// open orders account, oldest first. Fills that have already been
+    /// consumed are no longer in the queue; use load_fills_with_history to
+    /// include them.
+    pub fn load_fills_for(&self, participant: &Participant) -> Result<Vec<ParticipantFill>> {
+        self.fills_for(participant, Vec::new())
+    }
+
+    /// Returns the participant's fills recorded by a MarketRecorder together
+    /// with those still in the event queue, oldest first and without
+    /// duplicates.
+    pub fn load_fills_with_history(
+        &self,
+        participant: &Participant,
+        recorder: &MarketRecorder,
+    ) -> Result<Vec<ParticipantFill>> {
+        self.fills_for(participant, recorder.events())
+    }
+
+    fn fills_for(
+        &self,
+        participant: &Participant,
+        recorded: Vec<MarketEvent>,
+    ) -> Result<Vec<ParticipantFill>> {
+        let owner = participant.open_orders().pubkey().to_string();
+        let mut events: BTreeMap<u64, MarketEvent> = recorded
+            .into_iter()
+            .map(|event| (event.seq_num, event))
+            .collect();
+
+        let data = self.sandbox.get_account_data(self.event_queue.pubkey())?;
+        let (seq_num, queue) = load_event_queue(&data)?;
+        let first_seq_num = seq_num - queue.len() as u64;
+        let names = HashMap::new();
+        for (i, event) in queue.iter().enumerate() {
+            let seq_num = first_seq_num + i as u64;
+            if !events.contains_key(&seq_num) {
+                let event = MarketEvent::from_view(event.as_view()?, 0, 0, seq_num, &names);
+                events.insert(seq_num, event);
+            }
+        }
+
+        let view = self.view()?;
+        Ok(events
+            .values()
+            .filter(|event| event.kind == "fill" && event.owner == owner)
+            .map(|event| ParticipantFill::from_event(event, view.coin_lot_size, view.pc_lot_size))
+            .collect())
+    }
+
+    /// Converts an expected balance change to native units of the market's
+    /// base or quote token, using the market's lot sizes and the mint's
+    /// decimals. Fails if the amount overflows.
+    ///
+    /// Taker and maker amounts apply the base fee tier, which Serum charges
+    /// orders placed without an SRM or MSRM fee discount account, such as
+    /// those placed with place_order. They are rejected on V1 and V2
+    /// markets, which charge the market's fee_rate_bps instead; use
+    /// Amount::Native for other tiers and versions.
+    pub fn to_native(&self, token: BaseOrQuote, amount: Amount) -> Result<i128> {
+        let view = self.view()?;
+        let (lot_size, mint) = match token {
+            BaseOrQuote::Base => (view.coin_lot_size, self.base_mint()),
+            BaseOrQuote::Quote => (view.pc_lot_size, self.quote_mint()),
+        };
+        let overflow = || {
+            Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} overflows native units", amount),
+            ))
+        };
+        let (side, maker, price, quantity) = match amount {
+            Amount::Native(native) => return Ok(native),
+            Amount::Lots(lots) => return lots.checked_mul(lot_size as i128).ok_or_else(overflow),
+            Amount::Ui(ui) => {
+                let scale = 10f64.powi(mint.decimals()? as i32);
+                return Ok((ui * scale).round() as i128);
+            }
+            Amount::TakerQuote {
+                side,
+                price,
+                quantity,
+            } => (side, false, price, quantity),
+            Amount::MakerQuote {
+                side,
+                price,
+                quantity,
+            } => (side, true, price, quantity),
+        };
+        if token != BaseOrQuote::Quote {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "taker and maker amounts are quote amounts",
+            )));
+        }
+        if !self.version.matches_immediately() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{:?} markets charge fee_rate_bps rather than fee tiers",
+                    self.version
+                ),
+            )));
+        }
+
+        let gross = price
+            .checked_mul(quantity)
+            .and_then(|lots| lots.checked_mul(view.pc_lot_size))
+            .ok_or_else(overflow)?;
+        let fee = FeeTier::Base.taker_fee(gross) as i128;
+        let rebate = FeeTier::Base.maker_rebate(gross) as i128;
+        let gross = gross as i128;
+        Ok(match (side, maker) {
+            (Side::Bid, false) => -(gross + fee),
+            (Side::Ask, false) => gross - fee,
+            (Side::Bid, true) => -(gross - rebate),
+            (Side::Ask, true) => gross + rebate,
+        })
+    }
+
+    /// Asserts that the participant has settled all of its free funds and
+    /// that its wallets changed by the given amounts since it was funded,
+    /// counting top ups as funding. Funds locked in resting orders count as
+    /// spent. Panics with every mismatch otherwise.
+    ///
+    /// ```ignore
+    /// market.assert_settled(
+    ///     &taker,
+    ///     Amount::Lots(15),
+    ///     Amount::TakerQuote { side: Side::Bid, price: 20, quantity: 15 },
+    /// );
+    /// ```
+    pub fn assert_settled(
+        &self,
+        participant: &Participant,
+        base_delta: Amount,
+        quote_delta: Amount,
+    ) {
+        let snapshot = participant
+            .snapshot()
+            .expect("could not load participant balances");
+        let expected_base = self
+            .to_native(BaseOrQuote::Base, base_delta)
+            .expect("could not convert base amount");
+        let expected_quote = self
+            .to_native(BaseOrQuote::Quote, quote_delta)
+            .expect("could not convert quote amount");
+        let (funded_base, funded_quote) = *participant
+            .funded
+            .lock()
+            .expect("participant lock poisoned");
+
+        let mut mismatches = Vec::new();
+        if snapshot.base_unsettled != 0 || snapshot.quote_unsettled != 0 {
+            mismatches.push(format!(
+                "unsettled funds remain: {} base, {} quote",
+                snapshot.base_unsettled, snapshot.quote_unsettled
+            ));
+        }
+        let base_change = snapshot.base as i128 - funded_base as i128;
+        if base_change != expected_base {
+            mismatches.push(format!(
+                "base: expected {:+}, got {:+} ({:?})",
+                expected_base, base_change, base_delta
+            ));
+        }
+        let quote_change = snapshot.quote as i128 - funded_quote as i128;
+        if quote_change != expected_quote {
+            mismatches.push(format!(
+                "quote: expected {:+}, got {:+} ({:?})",
+                expected_quote, quote_change, quote_delta
+            ));
+        }
+        assert!(
+            mismatches.is_empty(),
+            "participant is not settled as expected:\n{}",
+            mismatches.join("\n")
+        );
+    }
+
+    /// Returns a description of every violated order book invariant:
+    ///
+    /// - Both slabs are well formed crit-bit trees whose leaf counts match
+    ///   their headers, with orders in ascending key order.
+    /// - Every resting order has a quantity and is listed, on the right side,
+    ///   in its owner's open orders account.
+    /// - The best bid is below the best ask.
+    /// - The base vault holds exactly what open orders accounts are owed, and
+    ///   the quote vault at least what they are owed, counting maker fills
+    ///   still waiting in the event queue. The quote vault also holds fees.
+    pub fn book_violations(&self) -> Result<Vec<String>> {
+        let mut violations = Vec::new();
+
+        let mut open_orders = HashMap::new();
+        for address in self.find_open_orders_accounts()? {
+            let view = OpenOrdersView::load(&self.sandbox.get_account_data(&address)?)?;
+            open_orders.insert(address, view);
+        }
+
+        let mut best = [None, None];
+        for (i, side) in [Side::Bid, Side::Ask].into_iter().enumerate() {
+            let orders: Vec<_> = match slab::iter_orders(&self.slab_data(side)?) {
+                Ok(orders) => orders.collect(),
+                Err(err) => {
+                    violations.push(format!("{:?} slab: {}", side, err));
+                    continue;
+                }
+            };
+            if orders
+                .windows(2)
+                .any(|pair| pair[0].order_id >= pair[1].order_id)
+            {
+                violations.push(format!("{:?} slab orders are not in key order", side));
+            }
+            best[i] = match side {
+                Side::Bid => orders.last(),
+                Side::Ask => orders.first(),
+            }
+            .map(|order| order.price);
+
+            for order in orders.iter() {
+                if order.quantity == 0 {
+                    violations.push(format!(
+                        "{:?} order {} has no quantity",
+                        side, order.order_id
+                    ));
+                }
+                let listed = open_orders.get(&order.owner).map(|view| {
+                    view.orders.iter().any(|slot| {
+                        slot.order_id == order.order_id && slot.is_bid == matches!(side, Side::Bid)
+                    })
+                });
+                match listed {
+                    Some(true) => {}
+                    Some(false) => violations.push(format!(
+                        "{:?} order {} is not listed in open orders {}",
+                        side,
+                        order.order_id,
+                        self.sandbox.label(&order.owner)
+                    )),
+                    None => violations.push(format!(
+                        "{:?} order {} is owned by unknown open orders {}",
+                        side,
+                        order.order_id,
+                        self.sandbox.label(&order.owner)
+                    )),
+                }
+            }
+        }
+        if let [Some(bid), Some(ask)] = best {
+            if bid >= ask {
+                violations.push(format!(
+                    "book is crossed: best bid {} >= best ask {}",
+                    bid, ask
+                ));
+            }
+        }
+
+        // Takers are credited when orders match, makers when their fill
+        // events are consumed. Until then a maker's total still counts what
+        // it sold and not what it bought.
+        let mut owed_base: i128 = open_orders
+            .values()
+            .map(|view| view.native_coin_total as i128)
+            .sum();
+        let mut owed_quote: i128 = open_orders
+            .values()
+            .map(|view| view.native_pc_total as i128)
+            .sum();
+        let data = self.sandbox.get_account_data(self.event_queue.pubkey())?;
+        for event in load_event_queue(&data)?.1.iter() {
+            if let serum_state::EventView::Fill {
+                side,
+                maker: true,
+                native_qty_paid,
+                native_qty_received,
+                ..
+            } = event.as_view()?
+            {
+                let (paid, received) = match side {
+                    Side::Bid => (&mut owed_quote, &mut owed_base),
+                    Side::Ask => (&mut owed_base, &mut owed_quote),
+                };
+                *paid -= native_qty_paid as i128;
+                *received += native_qty_received as i128;
+            }
+        }
+        let vault_base = self.base_vault.get_account_info()?.amount as i128;
+        let vault_quote = self.quote_vault.get_account_info()?.amount as i128;
+        if vault_base != owed_base {
+            violations.push(format!(
+                "base vault holds {} but open orders are owed {}",
+                vault_base, owed_base
+            ));
+        }
+        if vault_quote < owed_quote {
+            violations.push(format!(
+                "quote vault holds {} but open orders are owed {}",
+                vault_quote, owed_quote
+            ));
+        }
+        Ok(violations)
+    }
+
+    /// Panics with every violated order book invariant, if any. See
+    /// book_violations. Cheap enough to call after every step of a scenario
+    /// test:
+    ///
+    /// ```ignore
+    /// market.place_limit(...)?;
+    /// market.crank_all(&payer, 10)?;
+    /// market.assert_book_consistent();
+    /// ```
+    pub fn assert_book_consistent(&self) {
+        let violations = self
+            .book_violations()
+            .expect("could not load order book state");
+        assert!(
+            violations.is_empty(),
+            "order book is inconsistent:\n{}",
+            violations.join("\n")
+        );
+    }
+
+    /// Fetches both sides of the order book, for use with
+    /// matching::expected_fills.
+    pub fn book_snapshot(&self) -> Result<BookSnapshot> {
+        let view = self.view()?;
+        Ok(BookSnapshot::from_nodes(
+            view.coin_lot_size,
+            view.pc_lot_size,
+            &self.book_nodes(Side::Bid)?,
+            &self.book_nodes(Side::Ask)?,
+        ))
+    }
+
+    /// Calls callback on a background thread for every fill pushed to the
+    /// event queue from now on. Fills stop being delivered when the returned
+    /// subscription is stopped or dropped.
+    pub fn on_fill<F>(&self, callback: F) -> Result<EventSubscription>
+    where
+        F: FnMut(&MarketEvent) + Send + 'static,
+    {
+        EventSubscription::start(self.sandbox, self, "fill", callback)
+    }
+
+    /// Calls callback on a background thread for every out event, pushed
+    /// when an order is cancelled or fully filled, from now on. Events stop
+    /// being delivered when the returned subscription is stopped or dropped.
+    pub fn on_out<F>(&self, callback: F) -> Result<EventSubscription>
+    where
+        F: FnMut(&MarketEvent) + Send + 'static,
+    {
+        EventSubscription::start(self.sandbox, self, "out", callback)
+    }
+
+    /// Returns the highest bid price level, if any bids rest on the book.
+    pub fn best_bid(&self) -> Result<Option<PriceLevel>> {
+        Ok(self.side_snapshot(Side::Bid)?.best_bid())
+    }
+
+    /// Returns the lowest ask price level, if any asks rest on the book.
+    pub fn best_ask(&self) -> Result<Option<PriceLevel>> {
+        Ok(self.side_snapshot(Side::Ask)?.best_ask())
+    }
+
+    /// Returns up to n_levels aggregated price levels of each side of the
+    /// book, best first.
+    pub fn depth(&self, n_levels: usize) -> Result<Depth> {
+        Ok(self.book_snapshot()?.depth(n_levels))
+    }
+
+    /// Fetches one side of the order book, leaving the other empty.
+    fn side_snapshot(&self, side: Side) -> Result<BookSnapshot> {
+        let view = self.view()?;
+        let nodes = self.book_nodes(side)?;
+        let (bids, asks) = match side {
+            Side::Bid => (&nodes[..], &[][..]),
+            Side::Ask => (&[][..], &nodes[..]),
+        };
+        Ok(BookSnapshot::from_nodes(
+            view.coin_lot_size,
+            view.pc_lot_size,
+            bids,
+            asks,
+        ))
+    }
+
+    /// Registers readable names for the market's accounts, such as
+    /// "SOL/USDC.bids" and "SOL/USDC.base_vault".
+    fn register_labels(&self, label: &str) {
+        let names = [
+            (self.market.pubkey(), "market"),
+            (self.request_queue.pubkey(), "request_queue"),
+            (self.event_queue.pubkey(), "event_queue"),
+            (self.bids.pubkey(), "bids"),
+            (self.asks.pubkey(), "asks"),
+            (self.base_vault.account().pubkey(), "base_vault"),
+            (self.quote_vault.account().pubkey(), "quote_vault"),
+            (&self.vault_signer_key, "vault_signer"),
+        ];
+        for (pubkey, name) in names.iter() {
+            self.sandbox
+                .register_name(pubkey, &format!("{}.{}", label, name));
+        }
     }
 
     /// Returns reference to the Serum program id
@@ -295,9 +1940,104 @@ impl<'a> Market<'a> {
         &self.market
     }
 
+    /// Returns the version of the Serum program the market is traded on.
+    pub fn version(&self) -> DexVersion {
+        self.version
+    }
+
+    /// Sets the version of the Serum program the market is traded on, for
+    /// markets attached with new_from_existing.
+    pub fn with_version(mut self, version: DexVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Routes the instructions participants send through a proxy program
+    /// from now on, as well as the permissioned consume_events_permissioned
+    /// and prune. Unpermissioned cranking with consume_events and crank_all
+    /// and sweeping fees go to the DEX directly.
+    pub fn with_proxy(mut self, proxy: MarketProxy) -> Self {
+        self.sandbox
+            .register_decoder(proxy.program(), decoder::decode_serum_dex);
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Returns the proxy participants' instructions are routed through, if
+    /// any.
+    pub fn proxy(&self) -> Option<&MarketProxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Rewrites a DEX instruction to go through the market's proxy, if it has
+    /// one.
+    fn proxied(&self, instruction: Instruction) -> Instruction {
+        match &self.proxy {
+            Some(proxy) => proxy.wrap(instruction),
+            None => instruction,
+        }
+    }
+
+    /// Fails with a readable error if the participant was created for
+    /// another market, such as an earlier generation of a recreated market,
+    /// which Serum would otherwise reject with an opaque error.
+    fn ensure_participant(&self, participant: &Participant) -> Result<()> {
+        let theirs = participant.market.market.pubkey();
+        if theirs == self.market.pubkey() {
+            return Ok(());
+        }
+        Err(Error::from(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "participant {} trades on market {}, not market {}; use Participant::rejoin",
+                self.sandbox.label(participant.account.pubkey()),
+                self.sandbox.label(theirs),
+                self.sandbox.label(self.market.pubkey())
+            ),
+        )))
+    }
+
+    /// Checks that a participant's open orders account can be used with the
+    /// market: it must exist, belong to the market's Serum program, and, once
+    /// initialized, be initialized for this market.
+    pub fn check_open_orders(&self, participant: &Participant) -> Result<()> {
+        self.ensure_participant(participant)?;
+        let open_orders = participant.open_orders.pubkey();
+        let mismatch = |message: String| {
+            Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "open orders {} {}",
+                    self.sandbox.label(open_orders),
+                    message
+                ),
+            )))
+        };
+        let account = match self.sandbox.get_account_if_exists(open_orders)? {
+            Some(account) => account,
+            None => return mismatch(String::from("does not exist")),
+        };
+        if account.owner != *self.serum {
+            return mismatch(format!(
+                "is owned by {}, not Serum program {}",
+                self.sandbox.label(&account.owner),
+                self.sandbox.label(self.serum)
+            ));
+        }
+        let view = OpenOrdersView::load(&account.data)?;
+        if view.account_flags != 0 && view.market != *self.market.pubkey() {
+            return mismatch(format!(
+                "is initialized for market {}, not market {}",
+                self.sandbox.label(&view.market),
+                self.sandbox.label(self.market.pubkey())
+            ));
+        }
+        Ok(())
+    }
+
     /// Returns reference to market authority account
-    pub fn authority(&self) -> &Option<&Pubkey> {
-        &self.authority
+    pub fn authority(&self) -> Option<&Pubkey> {
+        self.authority.as_ref()
     }
 
     /// Returns reference to request queue account
@@ -376,6 +2116,15 @@ impl<'a> Market<'a> {
         size
     }
 
+    /// Fetch the size/space of an open orders account
+    fn open_orders_size() -> usize {
+        let mut size: usize = 0;
+        size += serum_state::ACCOUNT_HEAD_PADDING.len();
+        size += serum_state::ACCOUNT_TAIL_PADDING.len();
+        size += std::mem::size_of::<serum_state::OpenOrders>();
+        size
+    }
+
     /// Fetch the size/space of the market account depending on authority
     fn market_size(has_authority: bool) -> usize {
         let mut size: usize = 0;
@@ -389,114 +2138,841 @@ impl<'a> Market<'a> {
         size
     }
 
-    /// Generates the vault authority address. Note that you cannot use
-    /// find_program_address because Serum uses a u64 nonce convention.
-    fn create_vault_address(serum: &Pubkey, market: &Pubkey) -> (Pubkey, u64) {
-        let mut nonce: u64 = 0;
-        loop {
-            let seeds = [market.as_ref(), bytemuck::bytes_of(&nonce)];
-            match Pubkey::create_program_address(&seeds, serum) {
-                Ok(key) => return (key, nonce),
-                _ => nonce += 1,
-            }
+    /// Parses the market state from raw market account data.
+    fn load_market_state(data: &[u8]) -> Result<serum_state::MarketState> {
+        let start = serum_state::ACCOUNT_HEAD_PADDING.len();
+        let end = start + std::mem::size_of::<serum_state::MarketState>();
+        let bytes = data.get(start..end).ok_or_else(|| {
+            Error::from(serum_dex::error::DexError::from(
+                serum_dex::error::DexErrorCode::WrongAccountDataPaddingLength,
+            ))
+        })?;
+        let state: serum_state::MarketState = *bytemuck::from_bytes(bytes);
+
+        let account_flags = state.account_flags;
+        let expected =
+            serum_state::AccountFlag::Initialized as u64 | serum_state::AccountFlag::Market as u64;
+        if account_flags & expected != expected {
+            return Err(Error::from(serum_dex::error::DexError::from(
+                serum_dex::error::DexErrorCode::InvalidMarketFlags,
+            )));
         }
+        Ok(state)
+    }
+
+    /// Converts a Serum packed public key into a Pubkey.
+    fn to_pubkey(key: &[u64; 4]) -> Pubkey {
+        Pubkey::new(bytemuck::bytes_of(key))
     }
 }
 
-/// Represents a Serum market participant.
-pub struct Participant<'a> {
-    market: &'a Market<'a>,
-    base: TokenAccount<'a>,
-    quote: TokenAccount<'a>,
-    open_orders: Actor<'a>,
-    account: Actor<'a>,
+/// Returns the V1 or V2 new order instruction for an order, V2 if it has a
+/// self trade behavior.
+fn queued_order(
+    side: Side,
+    limit_price: NonZeroU64,
+    max_qty: NonZeroU64,
+    order_type: OrderType,
+    client_id: u64,
+    self_trade_behavior: Option<SelfTradeBehavior>,
+) -> MarketInstruction {
+    match self_trade_behavior {
+        Some(self_trade_behavior) => MarketInstruction::NewOrderV2(NewOrderInstructionV2 {
+            side,
+            limit_price,
+            max_qty,
+            order_type,
+            client_id,
+            self_trade_behavior,
+        }),
+        None => MarketInstruction::NewOrder(NewOrderInstructionV1 {
+            side,
+            limit_price,
+            max_qty,
+            order_type,
+            client_id,
+        }),
+    }
 }
 
-impl<'a> Participant<'a> {
-    /// Constructs a Serum market participant and seeds the participant account
-    /// with lamports to drive transactions, as well as some amount of base and
-    /// quote tokens.
-    pub fn new(
-        sandbox: &'a Sandbox,
-        payer: &'a Actor,
-        market: &'a Market<'a>,
-        starting_lamports: u64,
-        starting_base: u64,
-        starting_quote: u64,
-    ) -> Result<Participant<'a>> {
-        // Create a participant actor with initial balance
-        let participant_actor = Actor::new(sandbox)?;
-        participant_actor.airdrop(starting_lamports)?;
+/// The position of a Serum event queue's contents within its ring buffer.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EventQueueHeader {
+    /// Index of the oldest event in the ring buffer.
+    pub head: u64,
+    /// Number of events waiting to be consumed.
+    pub count: u64,
+    /// Number of events ever pushed.
+    pub seq_num: u64,
+}
 
-        // Setup base and quote accounts
-        let participant_base = TokenAccount::new(
-            sandbox,
-            payer,
-            market.base_mint,
-            Some(participant_actor.pubkey()),
-        )?;
-        let participant_quote = TokenAccount::new(
-            sandbox,
+impl EventQueueHeader {
+    /// Parses the header, which follows the account head padding.
+    fn load(header: &[u8]) -> Self {
+        // The header is four u64s: account flags, head, count, and sequence
+        // number. Its fields are private, so they are read directly.
+        let read_u64 = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&header[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        EventQueueHeader {
+            head: read_u64(8),
+            count: read_u64(16),
+            seq_num: read_u64(24),
+        }
+    }
+}
+
+/// Parses raw request queue account data of a V1 or V2 market and returns the
+/// number of requests waiting to be matched. The request queue's header has
+/// the same layout as the event queue's.
+pub(crate) fn request_queue_len(data: &[u8]) -> Result<u64> {
+    let start = serum_state::ACCOUNT_HEAD_PADDING.len();
+    let end = start + std::mem::size_of::<serum_state::RequestQueueHeader>();
+    match data.get(start..end) {
+        Some(header) => Ok(EventQueueHeader::load(header).count),
+        None => Err(Error::from(serum_dex::error::DexError::from(
+            serum_dex::error::DexErrorCode::WrongAccountDataPaddingLength,
+        ))),
+    }
+}
+
+/// Parses raw event queue account data. Returns the queue's sequence number,
+/// which counts every event ever pushed, and the events currently in the
+/// queue from oldest to newest.
+pub(crate) fn load_event_queue(data: &[u8]) -> Result<(u64, Vec<serum_state::Event>)> {
+    let start = serum_state::ACCOUNT_HEAD_PADDING.len();
+    let events_start = start + std::mem::size_of::<serum_state::EventQueueHeader>();
+    let events_end = data
+        .len()
+        .saturating_sub(serum_state::ACCOUNT_TAIL_PADDING.len());
+    if events_end < events_start {
+        return Err(Error::from(serum_dex::error::DexError::from(
+            serum_dex::error::DexErrorCode::WrongAccountDataPaddingLength,
+        )));
+    }
+
+    let EventQueueHeader {
+        head,
+        count,
+        seq_num,
+    } = EventQueueHeader::load(&data[start..events_start]);
+
+    let event_size = std::mem::size_of::<serum_state::Event>();
+    let capacity = ((events_end - events_start) / event_size) as u64;
+    if capacity == 0 {
+        return Ok((seq_num, Vec::new()));
+    }
+    let events = (0..count.min(capacity))
+        .map(|i| {
+            let offset = events_start + ((head + i) % capacity) as usize * event_size;
+            *bytemuck::from_bytes::<serum_state::Event>(&data[offset..offset + event_size])
+        })
+        .collect();
+    Ok((seq_num, events))
+}
+
+/// How the open orders accounts of new participants are initialized.
+#[derive(Clone, Copy, Default)]
+pub struct OpenOrdersInit<'a> {
+    /// Open orders authority of a permissioned market, which signs the
+    /// initialization. Without one, the accounts of permissioned markets are
+    /// left for Market::approve_open_orders.
+    pub authority: Option<&'a Actor<'a>>,
+
+    /// Owner of the open orders accounts in place of the participant
+    /// accounts. The participants approve the delegate to spend their base
+    /// and quote.
+    pub delegate: Option<&'a Actor<'a>>,
+}
+
+impl<'a> OpenOrdersInit<'a> {
+    /// Returns true if open orders accounts are initialized as they are
+    /// created.
+    fn initializes(&self, market: &Market) -> bool {
+        market.authority.is_none() || self.authority.is_some()
+    }
+}
+
+/// Where a participant built by ParticipantBuilder keeps one of its tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WalletSource {
+    /// A new token account.
+    New,
+    /// The participant account's associated token account.
+    Associated,
+    /// An existing token account owned by the participant account.
+    Existing(Pubkey),
+}
+
+/// Builds a single Participant with options beyond Participant::new.
+///
+/// By default the participant gets a new account without lamports, new base
+/// and quote token accounts, and an open orders account of the size Serum
+/// expects. The participant pays for its own orders, so a new account needs
+/// lamports to place resting orders:
+///
+/// ```ignore
+/// let maker = Participant::builder(&payer, &market)
+///     .lamports(LAMPORTS_PER_SOL)
+///     .base(1000)
+///     .quote(1000)
+///     .associated_token_accounts()
+///     .payer_funded()
+///     .resting_order(Side::Ask, NonZeroU64::new(21).unwrap(), NonZeroU64::new(10).unwrap())
+///     .resting_order(Side::Bid, NonZeroU64::new(19).unwrap(), NonZeroU64::new(10).unwrap())
+///     .build()?;
+/// ```
+pub struct ParticipantBuilder<'a> {
+    payer: &'a Actor<'a>,
+    market: &'a Market<'a>,
+    account: Option<Actor<'a>>,
+    lamports: u64,
+    payer_funded: bool,
+    starting_base: u64,
+    starting_quote: u64,
+    base_wallet: WalletSource,
+    quote_wallet: WalletSource,
+    open_orders_size: Option<usize>,
+    init: OpenOrdersInit<'a>,
+    resting_orders: Vec<(Side, NonZeroU64, NonZeroU64)>,
+}
+
+impl<'a> ParticipantBuilder<'a> {
+    /// Returns a builder for a participant of the market whose accounts are
+    /// paid for by the payer.
+    pub fn new(payer: &'a Actor<'a>, market: &'a Market<'a>) -> Self {
+        ParticipantBuilder {
             payer,
-            market.quote_mint,
-            Some(participant_actor.pubkey()),
-        )?;
+            market,
+            account: None,
+            lamports: 0,
+            payer_funded: false,
+            starting_base: 0,
+            starting_quote: 0,
+            base_wallet: WalletSource::New,
+            quote_wallet: WalletSource::New,
+            open_orders_size: None,
+            init: OpenOrdersInit::default(),
+            resting_orders: Vec::new(),
+        }
+    }
 
-        // Mint amounts to base & quote token accounts
-        if starting_base > 0 {
-            market
-                .base_mint
-                .mint_to(payer, &participant_base, starting_base)?;
+    /// Uses the given actor as the participant account instead of a new one.
+    /// Existing token accounts must be owned by it.
+    pub fn account(mut self, account: Actor<'a>) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    /// Sets the lamports given to the participant account for transaction
+    /// fees.
+    pub fn lamports(mut self, lamports: u64) -> Self {
+        self.lamports = lamports;
+        self
+    }
+
+    /// Transfers the lamports from the payer instead of airdropping them, for
+    /// sandboxes with a rate limited or small faucet.
+    pub fn payer_funded(mut self) -> Self {
+        self.payer_funded = true;
+        self
+    }
+
+    /// Sets the base minted into the participant's base wallet.
+    pub fn base(mut self, amount: u64) -> Self {
+        self.starting_base = amount;
+        self
+    }
+
+    /// Sets the quote minted into the participant's quote wallet.
+    pub fn quote(mut self, amount: u64) -> Self {
+        self.starting_quote = amount;
+        self
+    }
+
+    /// Keeps base and quote in the participant account's associated token
+    /// accounts, creating them if needed, as wallets do.
+    pub fn associated_token_accounts(mut self) -> Self {
+        self.base_wallet = WalletSource::Associated;
+        self.quote_wallet = WalletSource::Associated;
+        self
+    }
+
+    /// Keeps base in an existing token account owned by the participant
+    /// account.
+    pub fn base_account(mut self, pubkey: &Pubkey) -> Self {
+        self.base_wallet = WalletSource::Existing(*pubkey);
+        self
+    }
+
+    /// Keeps quote in an existing token account owned by the participant
+    /// account.
+    pub fn quote_account(mut self, pubkey: &Pubkey) -> Self {
+        self.quote_wallet = WalletSource::Existing(*pubkey);
+        self
+    }
+
+    /// Creates the open orders account with the given size in bytes instead
+    /// of the size Serum expects, such as for testing how a program handles
+    /// malformed accounts. Serum rejects open orders of any other size.
+    pub fn open_orders_size(mut self, size: usize) -> Self {
+        self.open_orders_size = Some(size);
+        self
+    }
+
+    /// Initializes the open orders account as described by init.
+    pub fn open_orders_init(mut self, init: OpenOrdersInit<'a>) -> Self {
+        self.init = init;
+        self
+    }
+
+    /// Places a post-only limit order once the participant is created, with
+    /// price in quote lots per base lot and size in base lots. Orders are
+    /// placed in the order added, with client order ids counting up from 1.
+    /// The participant pays for the orders, so a new account must be given
+    /// lamports.
+    pub fn resting_order(mut self, side: Side, price: NonZeroU64, size: NonZeroU64) -> Self {
+        self.resting_orders.push((side, price, size));
+        self
+    }
+
+    /// Creates the participant's accounts and places its resting orders.
+    #[tracing::instrument(skip_all, fields(market = %self.market.market.pubkey()))]
+    pub fn build(self) -> Result<Participant<'a>> {
+        let market = self.market;
+        let sandbox = market.sandbox;
+        let payer = self.payer;
+        if !self.resting_orders.is_empty() && self.account.is_none() && self.lamports == 0 {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a new participant needs lamports to place resting orders",
+            )));
         }
-        if starting_quote > 0 {
-            market
-                .quote_mint
-                .mint_to(payer, &participant_quote, starting_quote)?;
+        let account = match self.account {
+            Some(account) => account,
+            None => Actor::new(sandbox)?,
+        };
+
+        if self.lamports > 0 {
+            if self.payer_funded {
+                sandbox
+                    .transaction()
+                    .instruction(solana_sdk::system_instruction::transfer(
+                        payer.pubkey(),
+                        account.pubkey(),
+                        self.lamports,
+                    ))
+                    .payer(payer.try_keypair()?)
+                    .send()
+                    .context("funding participant")?;
+            } else {
+                account.airdrop(self.lamports)?;
+            }
         }
 
-        // Create open orders account
-        let participant_open_orders = Actor::new(sandbox)?;
-        let open_orders_size = std::mem::size_of::<serum_dex::state::OpenOrders>()
-            + serum_state::ACCOUNT_HEAD_PADDING.len()
-            + serum_state::ACCOUNT_TAIL_PADDING.len();
+        let mut token_instructions = Vec::new();
+        let mut new_wallets = Vec::new();
+        let mut wallets = Vec::with_capacity(2);
+        for (source, mint, amount) in [
+            (self.base_wallet, market.base_mint(), self.starting_base),
+            (self.quote_wallet, market.quote_mint(), self.starting_quote),
+        ] {
+            let mint_pubkey = mint.actor().pubkey();
+            let wallet = match source {
+                WalletSource::New => {
+                    let (wallet, instructions) =
+                        TokenAccount::prepare(sandbox, payer, mint_pubkey, Some(account.pubkey()))?;
+                    token_instructions.extend(instructions);
+                    new_wallets.push(*wallet.account().pubkey());
+                    wallet
+                }
+                WalletSource::Associated => {
+                    let (wallet, instruction) = TokenAccount::prepare_associated(
+                        sandbox,
+                        payer,
+                        mint_pubkey,
+                        account.pubkey(),
+                    )?;
+                    token_instructions.extend(instruction);
+                    wallet
+                }
+                WalletSource::Existing(pubkey) => TokenAccount::from_pubkey(sandbox, &pubkey),
+            };
+            if amount > 0 {
+                token_instructions.push(spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    mint_pubkey,
+                    wallet.account().pubkey(),
+                    mint.authority().pubkey(),
+                    &[],
+                    amount,
+                )?);
+            }
+            if let Some(delegate) = self.init.delegate {
+                token_instructions.push(spl_token::instruction::approve(
+                    &spl_token::id(),
+                    wallet.account().pubkey(),
+                    delegate.pubkey(),
+                    account.pubkey(),
+                    &[],
+                    u64::MAX,
+                )?);
+            }
+            wallets.push(wallet);
+        }
+        let quote = wallets.pop().expect("quote wallet");
+        let base = wallets.pop().expect("base wallet");
 
-        // Set serum to the owner of the open orders account
-        let create_open_orders = solana_sdk::system_instruction::create_account(
-            payer.pubkey(),
-            participant_open_orders.pubkey(),
-            sandbox
-                .client()
-                .get_minimum_balance_for_rent_exemption(open_orders_size)?,
-            open_orders_size as u64,
-            market.serum,
-        );
+        if !token_instructions.is_empty() {
+            let mut transaction = sandbox
+                .transaction()
+                .instructions(&token_instructions)
+                .payer(payer.try_keypair()?);
+            for wallet in [&base, &quote] {
+                if new_wallets.contains(wallet.account().pubkey()) {
+                    transaction = transaction.signer(wallet.account().try_keypair()?);
+                }
+            }
+            if self.starting_base > 0 {
+                transaction = transaction.signer(market.base_mint.authority().try_keypair()?);
+            }
+            if self.starting_quote > 0 {
+                transaction = transaction.signer(market.quote_mint.authority().try_keypair()?);
+            }
+            if self.init.delegate.is_some() {
+                transaction = transaction.signer(account.try_keypair()?);
+            }
+            transaction
+                .send_split()
+                .context("creating and funding participant token accounts")?;
+        }
 
-        // Set participant_open_order's userspace owner to participant
-        let init_open_orders = serum_dex::instruction::init_open_orders(
-            market.serum,
-            participant_open_orders.pubkey(),
-            participant_actor.pubkey(),
-            market.market.pubkey(),
-            None,
+        let open_orders = Actor::new(sandbox)?;
+        let open_orders_size = self
+            .open_orders_size
+            .unwrap_or_else(Market::open_orders_size);
+        let mut open_orders_instructions =
+            vec![payer.create_account(open_orders.pubkey(), open_orders_size, market.serum)?];
+        let initializes = self.init.initializes(market);
+        let owner = self.init.delegate.unwrap_or(&account);
+        if initializes {
+            open_orders_instructions.push(market.proxied(
+                serum_dex::instruction::init_open_orders(
+                    market.serum,
+                    open_orders.pubkey(),
+                    owner.pubkey(),
+                    market.market.pubkey(),
+                    self.init.authority.map(|authority| authority.pubkey()),
+                )?,
+            ));
+        }
+        let mut transaction = sandbox
+            .transaction()
+            .instructions(&open_orders_instructions)
+            .payer(payer.try_keypair()?)
+            .signer(open_orders.try_keypair()?);
+        if initializes {
+            transaction = transaction.signer(owner.try_keypair()?);
+            if let Some(authority) = self.init.authority {
+                transaction = transaction.signer(authority.try_keypair()?);
+            }
+        }
+        transaction
+            .send()
+            .context("creating participant open orders account")?;
+
+        let participant = Participant {
+            market,
+            base,
+            quote,
+            open_orders,
+            account,
+            delegate: self.init.delegate,
+            funded: Mutex::new((self.starting_base, self.starting_quote)),
+        };
+
+        for (i, (side, price, size)) in self.resting_orders.into_iter().enumerate() {
+            market
+                .place_order(
+                    &participant,
+                    side,
+                    price,
+                    size,
+                    OrderType::PostOnly,
+                    i as u64 + 1,
+                )
+                .with_context(|| format!("placing resting order {} of participant", i))?;
+        }
+        Ok(participant)
+    }
+}
+
+/// Represents a Serum market participant.
+pub struct Participant<'a> {
+    market: &'a Market<'a>,
+    base: TokenAccount<'a>,
+    quote: TokenAccount<'a>,
+    open_orders: Actor<'a>,
+    account: Actor<'a>,
+    /// Owner of the open orders account in place of the participant account,
+    /// which trades the participant's tokens by approval.
+    delegate: Option<&'a Actor<'a>>,
+    /// Base and quote minted into the participant's wallets, at creation and
+    /// by top ups.
+    funded: Mutex<(u64, u64)>,
+}
+
+impl<'a> Participant<'a> {
+    /// Constructs a Serum market participant and seeds the participant account
+    /// with lamports to drive transactions, as well as some amount of base and
+    /// quote tokens.
+    ///
+    /// On permissioned markets the open orders account is created but left
+    /// uninitialized, since initialization must be signed by the market
+    /// authority. Such participants cannot trade until the authority approves
+    /// them with Market::approve_open_orders.
+    pub fn new(
+        sandbox: &'a Sandbox,
+        payer: &'a Actor,
+        market: &'a Market<'a>,
+        starting_lamports: u64,
+        starting_base: u64,
+        starting_quote: u64,
+    ) -> Result<Participant<'a>> {
+        let mut participants = Self::new_batch(
+            sandbox,
+            payer,
+            market,
+            1,
+            starting_lamports,
+            starting_base,
+            starting_quote,
         )?;
+        Ok(participants.remove(0))
+    }
 
-        sandbox.send_signed_transaction_with_payers(
-            &[create_open_orders, init_open_orders],
-            Some(payer.pubkey()),
-            vec![
-                payer.keypair(),
-                participant_open_orders.keypair(),
-                participant_actor.keypair(),
-            ],
+    /// Creates a participant like new and registers readable names for its
+    /// accounts in the Sandbox, such as "maker", "maker.base", and
+    /// "maker.open_orders".
+    pub fn new_named(
+        sandbox: &'a Sandbox,
+        payer: &'a Actor,
+        market: &'a Market<'a>,
+        starting_lamports: u64,
+        starting_base: u64,
+        starting_quote: u64,
+        label: &str,
+    ) -> Result<Participant<'a>> {
+        let participant = Self::new(
+            sandbox,
+            payer,
+            market,
+            starting_lamports,
+            starting_base,
+            starting_quote,
         )?;
+        participant.register_labels(label);
+        Ok(participant)
+    }
 
-        Ok(Participant {
+    /// Attaches to a participant whose accounts already exist on chain, such
+    /// as one listed in the market.json written by create_serum_market. The
+    /// open orders account is attached watch-only, and must already be
+    /// initialized for the market. Balances held when attaching count as
+    /// funding for Market::assert_settled.
+    pub fn from_existing(
+        market: &'a Market<'a>,
+        account: Actor<'a>,
+        base: &Pubkey,
+        quote: &Pubkey,
+        open_orders: &Pubkey,
+    ) -> Result<Participant<'a>> {
+        let sandbox = market.sandbox;
+        let participant = Participant {
             market,
-            base: participant_base,
-            quote: participant_quote,
-            open_orders: participant_open_orders,
-            account: participant_actor,
-        })
+            base: TokenAccount::from_pubkey(sandbox, base),
+            quote: TokenAccount::from_pubkey(sandbox, quote),
+            open_orders: Actor::from_pubkey(sandbox, open_orders),
+            account,
+            delegate: None,
+            funded: Mutex::new((0, 0)),
+        };
+        market.check_open_orders(&participant)?;
+        let funded = (
+            participant.base.get_account_info()?.amount,
+            participant.quote.get_account_info()?.amount,
+        );
+        *participant
+            .funded
+            .lock()
+            .expect("participant lock poisoned") = funded;
+        Ok(participant)
+    }
+
+    /// Returns a builder for a participant with options beyond new, such as
+    /// associated or existing token accounts, payer funded lamports, and
+    /// resting orders.
+    pub fn builder(payer: &'a Actor<'a>, market: &'a Market<'a>) -> ParticipantBuilder<'a> {
+        ParticipantBuilder::new(payer, market)
+    }
+
+    /// Constructs n participants with the same starting balances, as in
+    /// Participant::new. Airdrops are confirmed together, and token and open
+    /// orders accounts are packed into as few concurrently submitted
+    /// transactions as possible.
+    pub fn new_batch(
+        sandbox: &'a Sandbox,
+        payer: &'a Actor,
+        market: &'a Market<'a>,
+        n: usize,
+        starting_lamports: u64,
+        starting_base: u64,
+        starting_quote: u64,
+    ) -> Result<Vec<Participant<'a>>> {
+        Self::new_batch_with_init(
+            sandbox,
+            payer,
+            market,
+            n,
+            OpenOrdersInit::default(),
+            starting_lamports,
+            starting_base,
+            starting_quote,
+        )
+    }
+
+    /// Constructs n participants as in Participant::new_batch, initializing
+    /// their open orders accounts as init describes. With an authority the
+    /// accounts of a permissioned market are initialized, and approved, as
+    /// they are created. With a delegate the delegate owns the accounts and
+    /// places orders, cancels, and settles for every participant, so the
+    /// delegate must hold lamports for transaction fees:
+    ///
+    /// ```ignore
+    /// let participants = Participant::new_batch_with_init(
+    ///     &sandbox,
+    ///     &payer,
+    ///     &market,
+    ///     2,
+    ///     OpenOrdersInit {
+    ///         authority: Some(&market_authority),
+    ///         delegate: Some(&delegate),
+    ///     },
+    ///     LAMPORTS_PER_SOL,
+    ///     1000,
+    ///     1000,
+    /// )?;
+    /// ```
+    pub fn new_batch_with_init(
+        sandbox: &'a Sandbox,
+        payer: &'a Actor,
+        market: &'a Market<'a>,
+        n: usize,
+        init: OpenOrdersInit<'a>,
+        starting_lamports: u64,
+        starting_base: u64,
+        starting_quote: u64,
+    ) -> Result<Vec<Participant<'a>>> {
+        Self::new_batch_with_open_orders(
+            sandbox,
+            payer,
+            market,
+            (0..n).map(|_| None).collect(),
+            init,
+            starting_lamports,
+            starting_base,
+            starting_quote,
+        )
+    }
+
+    /// Constructs one participant per entry of open_orders. Entries that hold
+    /// an already created, uninitialized open orders account use it; the
+    /// others get a new one.
+    fn new_batch_with_open_orders(
+        sandbox: &'a Sandbox,
+        payer: &'a Actor,
+        market: &'a Market<'a>,
+        open_orders: Vec<Option<Actor<'a>>>,
+        init: OpenOrdersInit<'a>,
+        starting_lamports: u64,
+        starting_base: u64,
+        starting_quote: u64,
+    ) -> Result<Vec<Participant<'a>>> {
+        let n = open_orders.len();
+        let open_orders_size = Market::open_orders_size();
+        let open_orders_lamports = sandbox
+            .client()
+            .get_minimum_balance_for_rent_exemption(open_orders_size)
+            .context("fetching open orders rent exemption")?;
+
+        let mut prepared = Vec::with_capacity(n);
+        for pooled in open_orders.into_iter() {
+            // Create a participant actor; it is funded below
+            let participant_actor = Actor::new(sandbox)?;
+
+            // Setup base and quote accounts, minting starting amounts
+            let (participant_base, mut token_instructions) = TokenAccount::prepare(
+                sandbox,
+                payer,
+                market.base_mint.actor().pubkey(),
+                Some(participant_actor.pubkey()),
+            )?;
+            let (participant_quote, quote_instructions) = TokenAccount::prepare(
+                sandbox,
+                payer,
+                market.quote_mint.actor().pubkey(),
+                Some(participant_actor.pubkey()),
+            )?;
+            token_instructions.extend(quote_instructions);
+            if starting_base > 0 {
+                token_instructions.push(spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    market.base_mint.actor().pubkey(),
+                    participant_base.account().pubkey(),
+                    market.base_mint.authority().pubkey(),
+                    &[],
+                    starting_base,
+                )?);
+            }
+            if starting_quote > 0 {
+                token_instructions.push(spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    market.quote_mint.actor().pubkey(),
+                    participant_quote.account().pubkey(),
+                    market.quote_mint.authority().pubkey(),
+                    &[],
+                    starting_quote,
+                )?);
+            }
+            if let Some(delegate) = init.delegate {
+                for wallet in [&participant_base, &participant_quote] {
+                    token_instructions.push(spl_token::instruction::approve(
+                        &spl_token::id(),
+                        wallet.account().pubkey(),
+                        delegate.pubkey(),
+                        participant_actor.pubkey(),
+                        &[],
+                        u64::MAX,
+                    )?);
+                }
+            }
+
+            // Create open orders account, with serum as the owner, unless one
+            // was created ahead of time
+            let created = pooled.is_none();
+            let participant_open_orders = match pooled {
+                Some(open_orders) => open_orders,
+                None => Actor::new(sandbox)?,
+            };
+            let mut open_orders_instructions = Vec::new();
+            if created {
+                open_orders_instructions.push(solana_sdk::system_instruction::create_account(
+                    payer.pubkey(),
+                    participant_open_orders.pubkey(),
+                    open_orders_lamports,
+                    open_orders_size as u64,
+                    market.serum,
+                ));
+            }
+            if init.initializes(market) {
+                // Set participant_open_order's userspace owner to participant,
+                // or to its delegate
+                let owner = init.delegate.unwrap_or(&participant_actor);
+                open_orders_instructions.push(market.proxied(
+                    serum_dex::instruction::init_open_orders(
+                        market.serum,
+                        participant_open_orders.pubkey(),
+                        owner.pubkey(),
+                        market.market.pubkey(),
+                        init.authority.map(|authority| authority.pubkey()),
+                    )?,
+                ));
+            }
+
+            let participant = Participant {
+                market,
+                base: participant_base,
+                quote: participant_quote,
+                open_orders: participant_open_orders,
+                account: participant_actor,
+                delegate: init.delegate,
+                funded: Mutex::new((starting_base, starting_quote)),
+            };
+            prepared.push((
+                participant,
+                token_instructions,
+                open_orders_instructions,
+                created,
+            ));
+        }
+
+        let airdrops: Vec<(&Actor, u64)> = prepared
+            .iter()
+            .map(|(participant, _, _, _)| (&participant.account, starting_lamports))
+            .collect();
+        sandbox
+            .airdrop_many(&airdrops)
+            .with_context(|| format!("airdropping lamports to {} participants", n))?;
+
+        // Token and open orders accounts are sent as separate batches, so a
+        // failure says which of them was rejected.
+        let mut token_groups = Vec::with_capacity(n);
+        let mut open_orders_groups = Vec::with_capacity(n);
+        for (participant, token_instructions, open_orders_instructions, created) in prepared.iter()
+        {
+            let mut token_signers = vec![
+                participant.base.account().try_keypair()?,
+                participant.quote.account().try_keypair()?,
+            ];
+            if starting_base > 0 {
+                token_signers.push(market.base_mint.authority().try_keypair()?);
+            }
+            if starting_quote > 0 {
+                token_signers.push(market.quote_mint.authority().try_keypair()?);
+            }
+            if init.delegate.is_some() {
+                token_signers.push(participant.account.try_keypair()?);
+            }
+            token_groups.push((token_instructions.clone(), token_signers));
+
+            let mut open_orders_signers = Vec::new();
+            if *created {
+                open_orders_signers.push(participant.open_orders.try_keypair()?);
+            }
+            if init.initializes(market) {
+                open_orders_signers.push(participant.owner().try_keypair()?);
+                if let Some(authority) = init.authority {
+                    open_orders_signers.push(authority.try_keypair()?);
+                }
+            }
+            if !open_orders_instructions.is_empty() {
+                open_orders_groups.push((open_orders_instructions.clone(), open_orders_signers));
+            }
+        }
+        let market_label = sandbox.label(market.market.pubkey());
+        sandbox
+            .send_batch(payer.try_keypair()?, &token_groups)
+            .with_context(|| {
+                format!(
+                    "creating and funding token accounts for {} participants on market {}",
+                    n, market_label
+                )
+            })?;
+        sandbox
+            .send_batch(payer.try_keypair()?, &open_orders_groups)
+            .with_context(|| {
+                format!(
+                    "creating open orders accounts for {} participants on market {}",
+                    n, market_label
+                )
+            })?;
+
+        Ok(prepared
+            .into_iter()
+            .map(|(participant, _, _, _)| participant)
+            .collect())
     }
 
     /// Returns reference to base account.
@@ -514,8 +2990,628 @@ impl<'a> Participant<'a> {
         &self.open_orders
     }
 
+    /// Returns the delegate that owns the open orders account, if the
+    /// participant trades through one.
+    pub fn delegate(&self) -> Option<&Actor> {
+        self.delegate
+    }
+
+    /// Returns the owner of the open orders account, which signs orders,
+    /// cancels, and settlements: the delegate if there is one, and the
+    /// participant account otherwise.
+    pub fn owner(&self) -> &Actor {
+        self.delegate.unwrap_or(&self.account)
+    }
+
+    /// Registers readable names for the participant's accounts, such as
+    /// "maker", "maker.base", and "maker.open_orders".
+    fn register_labels(&self, label: &str) {
+        let sandbox = self.account.sandbox();
+        sandbox.register_name(self.account.pubkey(), label);
+        sandbox.register_name(self.base().pubkey(), &format!("{}.base", label));
+        sandbox.register_name(self.quote().pubkey(), &format!("{}.quote", label));
+        sandbox.register_name(self.open_orders.pubkey(), &format!("{}.open_orders", label));
+    }
+
     /// Returns reference to underlying account.
     pub fn account(&self) -> &Actor {
         &self.account
     }
+
+    /// Returns the market the participant trades on.
+    pub fn market(&self) -> &'a Market<'a> {
+        self.market
+    }
+
+    /// Captures the participant's SOL, wallet token, and open orders balances.
+    /// Open orders balances are zero if the account is not yet initialized.
+    pub fn snapshot(&self) -> Result<PortfolioSnapshot> {
+        let sandbox = self.account.sandbox();
+        let lamports = sandbox
+            .get_account_if_exists(self.account.pubkey())?
+            .map_or(0, |account| account.lamports);
+        let base = self.base.get_account_info()?.amount;
+        let quote = self.quote.get_account_info()?.amount;
+
+        let data = sandbox
+            .get_account_if_exists(self.open_orders.pubkey())?
+            .map(|account| account.data)
+            .unwrap_or_default();
+        let start = serum_state::ACCOUNT_HEAD_PADDING.len();
+        let end = start + std::mem::size_of::<serum_state::OpenOrders>();
+        let (base_total, base_free, quote_total, quote_free) = match data.get(start..end) {
+            Some(bytes) => {
+                let open_orders: &serum_state::OpenOrders = bytemuck::from_bytes(bytes);
+                let account_flags = open_orders.account_flags;
+                if account_flags & serum_state::AccountFlag::Initialized as u64 != 0 {
+                    (
+                        open_orders.native_coin_total,
+                        open_orders.native_coin_free,
+                        open_orders.native_pc_total,
+                        open_orders.native_pc_free,
+                    )
+                } else {
+                    (0, 0, 0, 0)
+                }
+            }
+            None => (0, 0, 0, 0),
+        };
+
+        Ok(PortfolioSnapshot {
+            lamports,
+            base,
+            quote,
+            base_locked: base_total - base_free,
+            quote_locked: quote_total - quote_free,
+            base_unsettled: base_free,
+            quote_unsettled: quote_free,
+        })
+    }
+
+    /// Fetches the participant's open orders account as a typed view.
+    pub fn open_orders_view(&self) -> Result<OpenOrdersView> {
+        let sandbox = self.account.sandbox();
+        OpenOrdersView::load(&sandbox.get_account_data(self.open_orders.pubkey())?)
+    }
+
+    /// Mints additional base and quote tokens into the participant's
+    /// wallet, signed by the market mints' authorities and paid for by the
+    /// participant.
+    pub fn top_up(&self, base: u64, quote: u64) -> Result<()> {
+        let market = self.market;
+        let mut transaction = market
+            .sandbox
+            .transaction()
+            .payer(self.account.try_keypair()?);
+        if base > 0 {
+            transaction = transaction
+                .instruction(spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    market.base_mint.actor().pubkey(),
+                    self.base().pubkey(),
+                    market.base_mint.authority().pubkey(),
+                    &[],
+                    base,
+                )?)
+                .signer(market.base_mint.authority().try_keypair()?);
+        }
+        if quote > 0 {
+            transaction = transaction
+                .instruction(spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    market.quote_mint.actor().pubkey(),
+                    self.quote().pubkey(),
+                    market.quote_mint.authority().pubkey(),
+                    &[],
+                    quote,
+                )?)
+                .signer(market.quote_mint.authority().try_keypair()?);
+        }
+        if transaction.get_instructions().is_empty() {
+            return Ok(());
+        }
+        transaction.send()?;
+        let mut funded = self.funded.lock().expect("participant lock poisoned");
+        funded.0 += base;
+        funded.1 += quote;
+        Ok(())
+    }
+
+    /// Returns the changes in the participant's balances since the given
+    /// snapshot.
+    pub fn diff_since(&self, before: &PortfolioSnapshot) -> Result<PortfolioDiff> {
+        Ok(before.diff(&self.snapshot()?))
+    }
+
+    /// Moves the participant to another market with the same mints, such as
+    /// the next generation of a market a test tears down and recreates. The
+    /// participant keeps its account, wallets, and open orders address.
+    ///
+    /// An open orders account initialized for the previous market is closed
+    /// first, so it must have no resting orders or unsettled funds, and its
+    /// rent goes to the payer. The account is then created again for the new
+    /// market if needed, paid for by the payer, and initialized for it unless
+    /// the market is permissioned, in which case the participant must be
+    /// approved with Market::approve_open_orders. An account that no longer
+    /// exists, such as after a ledger snapshot was restored, is simply recreated.
+    ///
+    /// The participant only moves once every step succeeded. If a step
+    /// fails, it keeps its keypairs, so rejoin can be retried:
+    ///
+    /// ```ignore
+    /// let market = Market::new(&sandbox, &payer, serum.pubkey(), &base, &quote, None, &params)?;
+    /// maker.rejoin(&payer, &market)?;
+    /// ```
+    #[tracing::instrument(skip_all, fields(market = %market.market.pubkey()))]
+    pub fn rejoin(&mut self, payer: &Actor, market: &'a Market<'a>) -> Result<()> {
+        let sandbox = market.sandbox;
+        let previous = self.market;
+        if previous.base_mint.actor().pubkey() != market.base_mint.actor().pubkey()
+            || previous.quote_mint.actor().pubkey() != market.quote_mint.actor().pubkey()
+        {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "market {} trades other mints than market {}",
+                    sandbox.label(market.market.pubkey()),
+                    sandbox.label(previous.market.pubkey())
+                ),
+            )));
+        }
+
+        let open_orders = self.open_orders.pubkey();
+        let mut create = true;
+        let mut initialize = market.authority.is_none();
+        if let Some(account) = sandbox.get_account_if_exists(open_orders)? {
+            let view = OpenOrdersView::load(&account.data).ok();
+            match view.filter(|view| view.account_flags != 0) {
+                Some(view) if view.market == *market.market.pubkey() => {
+                    create = false;
+                    initialize = false;
+                }
+                Some(_) => {
+                    previous
+                        .close_open_orders(self, payer.pubkey())
+                        .context("closing open orders on previous market")?;
+                }
+                None if account.owner == *market.serum => create = false,
+                None => {
+                    return Err(Error::from(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "open orders {} is owned by {}, not Serum program {}",
+                            sandbox.label(open_orders),
+                            sandbox.label(&account.owner),
+                            sandbox.label(market.serum)
+                        ),
+                    )));
+                }
+            }
+        }
+
+        let mut instructions = Vec::new();
+        if create {
+            instructions.push(payer.create_account(
+                open_orders,
+                Market::open_orders_size(),
+                market.serum,
+            )?);
+        }
+        if initialize {
+            instructions.push(market.proxied(serum_dex::instruction::init_open_orders(
+                market.serum,
+                open_orders,
+                self.owner().pubkey(),
+                market.market.pubkey(),
+                None,
+            )?));
+        }
+        if !instructions.is_empty() {
+            let mut transaction = sandbox
+                .transaction()
+                .instructions(&instructions)
+                .payer(payer.try_keypair()?);
+            if create {
+                transaction = transaction.signer(self.open_orders.try_keypair()?);
+            }
+            if initialize {
+                transaction = transaction.signer(self.owner().try_keypair()?);
+            }
+            transaction.send().with_context(|| {
+                format!("recreating open orders {}", sandbox.label(open_orders))
+            })?;
+        }
+
+        self.market = market;
+        Ok(())
+    }
+}
+
+/// Open orders accounts created ahead of time for a market.
+///
+/// Creating the open orders account is the slowest part of constructing a
+/// Participant. A pool creates many accounts at once in batched transactions,
+/// for example during test setup, and hands them out as participants are
+/// constructed. Accounts are created uninitialized and are initialized for
+/// their participant when handed out. Participants are constructed with new
+/// accounts once the pool is empty.
+pub struct OpenOrdersPool<'a> {
+    market: &'a Market<'a>,
+    available: Mutex<Vec<Actor<'a>>>,
+}
+
+impl<'a> OpenOrdersPool<'a> {
+    /// Creates a pool of size open orders accounts for the market, funded by
+    /// the payer.
+    pub fn new(payer: &Actor, market: &'a Market<'a>, size: usize) -> Result<Self> {
+        let pool = OpenOrdersPool {
+            market,
+            available: Mutex::new(Vec::new()),
+        };
+        pool.refill(payer, size)?;
+        Ok(pool)
+    }
+
+    /// Creates count more open orders accounts and adds them to the pool.
+    pub fn refill(&self, payer: &Actor, count: usize) -> Result<()> {
+        let sandbox = self.market.sandbox;
+        let open_orders_size = Market::open_orders_size();
+        let open_orders_lamports = sandbox
+            .client()
+            .get_minimum_balance_for_rent_exemption(open_orders_size)?;
+
+        let accounts = (0..count)
+            .map(|_| Actor::new(sandbox))
+            .collect::<Result<Vec<Actor>>>()?;
+        let groups: Vec<(Vec<Instruction>, Vec<&Keypair>)> = accounts
+            .iter()
+            .map(|account| {
+                (
+                    vec![solana_sdk::system_instruction::create_account(
+                        payer.pubkey(),
+                        account.pubkey(),
+                        open_orders_lamports,
+                        open_orders_size as u64,
+                        self.market.serum,
+                    )],
+                    vec![account.keypair()],
+                )
+            })
+            .collect();
+        sandbox.send_batch(payer.try_keypair()?, &groups)?;
+
+        self.available
+            .lock()
+            .expect("open orders pool lock poisoned")
+            .extend(accounts);
+        Ok(())
+    }
+
+    /// Removes an account from the pool, if any is left.
+    pub fn take(&self) -> Option<Actor<'a>> {
+        self.available
+            .lock()
+            .expect("open orders pool lock poisoned")
+            .pop()
+    }
+
+    /// Returns the number of accounts left in the pool.
+    pub fn len(&self) -> usize {
+        self.available
+            .lock()
+            .expect("open orders pool lock poisoned")
+            .len()
+    }
+
+    /// Returns true if the pool has no accounts left.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Constructs a participant as in Participant::new, using an account
+    /// from the pool.
+    pub fn participant(
+        &self,
+        payer: &'a Actor,
+        starting_lamports: u64,
+        starting_base: u64,
+        starting_quote: u64,
+    ) -> Result<Participant<'a>> {
+        let mut participants =
+            self.participants(payer, 1, starting_lamports, starting_base, starting_quote)?;
+        Ok(participants.remove(0))
+    }
+
+    /// Constructs n participants as in Participant::new_batch, using accounts
+    /// from the pool for as many of them as possible. If construction fails,
+    /// the taken accounts that are still uninitialized return to the pool.
+    pub fn participants(
+        &self,
+        payer: &'a Actor,
+        n: usize,
+        starting_lamports: u64,
+        starting_base: u64,
+        starting_quote: u64,
+    ) -> Result<Vec<Participant<'a>>> {
+        let open_orders: Vec<Option<Actor<'a>>> = (0..n).map(|_| self.take()).collect();
+        let taken: Vec<Keypair> = open_orders
+            .iter()
+            .flatten()
+            .map(|account| {
+                Keypair::from_bytes(&account.keypair().to_bytes())
+                    .expect("keypair bytes round trip")
+            })
+            .collect();
+        let participants = Participant::new_batch_with_open_orders(
+            self.market.sandbox,
+            payer,
+            self.market,
+            open_orders,
+            OpenOrdersInit::default(),
+            starting_lamports,
+            starting_base,
+            starting_quote,
+        );
+        if participants.is_err() {
+            self.give_back(taken);
+        }
+        participants
+    }
+
+    /// Returns taken accounts to the pool, except those that were initialized
+    /// for a participant before construction failed.
+    fn give_back(&self, taken: Vec<Keypair>) {
+        let sandbox = self.market.sandbox;
+        let mut available = self
+            .available
+            .lock()
+            .expect("open orders pool lock poisoned");
+        for keypair in taken {
+            let pubkey = keypair.pubkey();
+            match sandbox.get_account_if_exists(&pubkey) {
+                Ok(Some(account)) if account.data.iter().all(|byte| *byte == 0) => {
+                    if let Ok(actor) = Actor::from_keypair(sandbox, keypair) {
+                        available.push(actor);
+                    }
+                }
+                _ => tracing::warn!(
+                    open_orders = %sandbox.label(&pubkey),
+                    "dropping pooled open orders account that was initialized"
+                ),
+            }
+        }
+    }
+}
+
+/// What Market::teardown cleaned up and what it left behind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TeardownReport {
+    /// Orders cancelled across every participant.
+    pub orders_cancelled: usize,
+    /// Rent reclaimed from the participants' open orders accounts.
+    pub lamports_reclaimed: u64,
+    /// Rent held by the market's own accounts, which cannot be closed.
+    pub lamports_locked: u64,
+    /// Base left in the base vault once every participant settled.
+    pub base_vault: u64,
+    /// Quote left in the quote vault once every participant settled, such
+    /// as accrued fees.
+    pub quote_vault: u64,
+}
+
+/// A fill of one of a participant's orders, in native units unless noted.
+///
+/// A taker's order produces a single fill covering everything it matched, so
+/// the price of a taker fill is the average execution price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParticipantFill {
+    /// Position of the fill event in the event queue's history.
+    pub seq_num: u64,
+    /// Side of the participant's order.
+    pub side: Side,
+    /// Whether the participant's order was resting on the book.
+    pub maker: bool,
+    pub order_id: u128,
+    pub client_order_id: u64,
+    /// Execution price in quote lots per base lot.
+    pub price: u64,
+    /// Base exchanged.
+    pub base_quantity: u64,
+    /// Quote exchanged before fees and rebates.
+    pub quote_quantity: u64,
+    /// Fee paid by a taker, or rebate received by a maker, in quote.
+    pub fee_or_rebate: u64,
+    pub native_qty_paid: u64,
+    pub native_qty_received: u64,
+}
+
+impl ParticipantFill {
+    fn from_event(event: &MarketEvent, coin_lot_size: u64, pc_lot_size: u64) -> Self {
+        let side = if event.side == "bid" {
+            Side::Bid
+        } else {
+            Side::Ask
+        };
+        let paid = event.native_qty_paid;
+        let received = event.native_qty_received;
+        let fee = event.native_fee_or_rebate;
+        // Takers pay fees on top of the quote they exchange and makers
+        // receive rebates on top of it.
+        let (base_quantity, quote_quantity) = match (side, event.maker) {
+            (Side::Bid, false) => (received, paid.saturating_sub(fee)),
+            (Side::Bid, true) => (received, paid + fee),
+            (Side::Ask, false) => (paid, received + fee),
+            (Side::Ask, true) => (paid, received.saturating_sub(fee)),
+        };
+        let base_lots = base_quantity / coin_lot_size.max(1);
+        let quote_lots = quote_quantity / pc_lot_size.max(1);
+        ParticipantFill {
+            seq_num: event.seq_num,
+            side,
+            maker: event.maker,
+            order_id: event.order_id,
+            client_order_id: event.client_order_id,
+            price: quote_lots.checked_div(base_lots).unwrap_or(0),
+            base_quantity,
+            quote_quantity,
+            fee_or_rebate: fee,
+            native_qty_paid: paid,
+            native_qty_received: received,
+        }
+    }
+
+    /// Returns the participant's change in base from the fill.
+    pub fn base_change(&self) -> i128 {
+        match self.side {
+            Side::Bid => self.native_qty_received as i128,
+            Side::Ask => -(self.native_qty_paid as i128),
+        }
+    }
+
+    /// Returns the participant's change in quote from the fill, including
+    /// fees and rebates.
+    pub fn quote_change(&self) -> i128 {
+        match self.side {
+            Side::Bid => -(self.native_qty_paid as i128),
+            Side::Ask => self.native_qty_received as i128,
+        }
+    }
+}
+
+/// An expected change in a participant's balance of one token, converted to
+/// native units with Market::to_native.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Amount {
+    /// Native units, the smallest unit of the token.
+    Native(i128),
+    /// Lots of the market's base or quote token.
+    Lots(i128),
+    /// Whole tokens, scaled by the mint's decimals.
+    Ui(f64),
+    /// Quote paid by a taker bid or received by a taker ask for quantity base
+    /// lots at price quote lots per base lot, after the base tier taker fee.
+    TakerQuote {
+        side: Side,
+        price: u64,
+        quantity: u64,
+    },
+    /// Quote paid by a resting bid or received by a resting ask for quantity
+    /// base lots at price quote lots per base lot, after the base tier maker
+    /// rebate.
+    MakerQuote {
+        side: Side,
+        price: u64,
+        quantity: u64,
+    },
+}
+
+/// A balance tracked by a PortfolioSnapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Balance {
+    /// SOL held by the participant account, in lamports.
+    Lamports,
+    /// Base tokens held in the participant's wallet.
+    Base,
+    /// Quote tokens held in the participant's wallet.
+    Quote,
+    /// Base tokens locked in open orders.
+    BaseLocked,
+    /// Quote tokens locked in open orders.
+    QuoteLocked,
+    /// Base tokens in the open orders account that are free to settle.
+    BaseUnsettled,
+    /// Quote tokens in the open orders account that are free to settle.
+    QuoteUnsettled,
+}
+
+/// Balances of a Participant at a point in time, in native units.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortfolioSnapshot {
+    pub lamports: u64,
+    pub base: u64,
+    pub quote: u64,
+    pub base_locked: u64,
+    pub quote_locked: u64,
+    pub base_unsettled: u64,
+    pub quote_unsettled: u64,
+}
+
+impl PortfolioSnapshot {
+    /// Returns the given balance.
+    pub fn get(&self, balance: Balance) -> u64 {
+        match balance {
+            Balance::Lamports => self.lamports,
+            Balance::Base => self.base,
+            Balance::Quote => self.quote,
+            Balance::BaseLocked => self.base_locked,
+            Balance::QuoteLocked => self.quote_locked,
+            Balance::BaseUnsettled => self.base_unsettled,
+            Balance::QuoteUnsettled => self.quote_unsettled,
+        }
+    }
+
+    /// Returns the changes from this snapshot to a later one.
+    pub fn diff(&self, after: &PortfolioSnapshot) -> PortfolioDiff {
+        PortfolioDiff {
+            before: *self,
+            after: *after,
+        }
+    }
+}
+
+/// Changes in a Participant's balances between two snapshots.
+///
+/// ```ignore
+/// let before = taker.snapshot()?;
+/// // ... trade, crank, and settle ...
+/// taker
+///     .diff_since(&before)?
+///     .assert_changes(&[(Balance::Base, 15), (Balance::Quote, -300)]);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortfolioDiff {
+    before: PortfolioSnapshot,
+    after: PortfolioSnapshot,
+}
+
+impl PortfolioDiff {
+    /// Returns the signed change of the given balance.
+    pub fn change(&self, balance: Balance) -> i128 {
+        self.after.get(balance) as i128 - self.before.get(balance) as i128
+    }
+
+    /// Asserts that each listed balance changed by exactly the given amount.
+    /// Balances that are not listed are not checked. Panics with every
+    /// mismatching balance if any differ.
+    pub fn assert_changes(&self, expected: &[(Balance, i128)]) {
+        let mismatches: Vec<String> = expected
+            .iter()
+            .filter(|(balance, change)| self.change(*balance) != *change)
+            .map(|(balance, change)| {
+                format!(
+                    "{:?}: expected {:+}, got {:+} ({} -> {})",
+                    balance,
+                    change,
+                    self.change(*balance),
+                    self.before.get(*balance),
+                    self.after.get(*balance)
+                )
+            })
+            .collect();
+        assert!(
+            mismatches.is_empty(),
+            "unexpected balance changes:\n{}",
+            mismatches.join("\n")
+        );
+    }
+
+    /// Returns the snapshot the changes are measured from.
+    pub fn before(&self) -> &PortfolioSnapshot {
+        &self.before
+    }
+
+    /// Returns the snapshot the changes are measured to.
+    pub fn after(&self) -> &PortfolioSnapshot {
+        &self.after
+    }
 }
@@ -0,0 +1,116 @@
+//! Reads the orders resting in a Serum bids or asks account.
+//!
+//! The account holds a crit-bit tree of orders keyed by order id, whose upper
+//! 64 bits are the price. These functions work on raw account data, so they
+//! apply equally to sandbox accounts and to data captured from another
+//! cluster:
+//!
+//! ```ignore
+//! let data = client.get_account_data(&asks)?;
+//! let best_ask = slab::iter_orders(&data)?.next();
+//! let best_bid = slab::iter_orders(&client.get_account_data(&bids)?)?.next_back();
+//! ```
+
+use crate::errors::{Error, Result};
+use crate::matching::BookOrder;
+use crate::views::{self, SlabNodeView};
+use serum_dex::{critbit::AnyNode, state as serum_state};
+use std::io;
+
+/// Byte offset of the slab's bump index, the number of nodes ever allocated:
+/// the account head padding and the order book account flags.
+pub(crate) const BUMP_INDEX_OFFSET: usize = 5 + 8;
+
+/// Byte offset of the slab's root node index: the bump index, the free list
+/// length, and the free list head.
+const ROOT_NODE_OFFSET: usize = BUMP_INDEX_OFFSET + 8 + 8 + 4;
+
+/// Byte offset of the slab's leaf count.
+pub(crate) const LEAF_COUNT_OFFSET: usize = ROOT_NODE_OFFSET + 4;
+
+/// Returns the orders of a bids or asks account in ascending order id, and so
+/// ascending price. Orders at the same price are in time priority for asks
+/// and reverse time priority for bids, so the best bid is the last order.
+///
+/// The tree is walked from its root rather than scanning the node array, so
+/// freed nodes are never returned. Fails without panicking if the data is
+/// too short or the tree is malformed.
+pub fn iter_orders(
+    data: &[u8],
+) -> Result<impl DoubleEndedIterator<Item = BookOrder> + ExactSizeIterator> {
+    let end = data
+        .len()
+        .saturating_sub(serum_state::ACCOUNT_TAIL_PADDING.len());
+    if end < views::SLAB_NODES_OFFSET {
+        return Err(views::wrong_padding());
+    }
+    let root = u32::from_le_bytes(
+        data[ROOT_NODE_OFFSET..ROOT_NODE_OFFSET + 4]
+            .try_into()
+            .expect("4 bytes"),
+    );
+    let leaf_count = u64::from_le_bytes(
+        data[LEAF_COUNT_OFFSET..LEAF_COUNT_OFFSET + 8]
+            .try_into()
+            .expect("8 bytes"),
+    );
+
+    let nodes: Vec<&[u8]> = data[views::SLAB_NODES_OFFSET..end]
+        .chunks_exact(std::mem::size_of::<AnyNode>())
+        .collect();
+    let mut orders = Vec::new();
+    if leaf_count == 0 {
+        return Ok(orders.into_iter());
+    }
+
+    // Children are pushed high key first so that low keys are visited first.
+    // A well formed tree visits each node once, which bounds the walk.
+    let mut stack = vec![root];
+    let mut visited = 0;
+    while let Some(index) = stack.pop() {
+        visited += 1;
+        if visited > nodes.len() {
+            return Err(malformed("tree has a cycle".to_string()));
+        }
+        let bytes = nodes
+            .get(index as usize)
+            .ok_or_else(|| malformed(format!("node {} is out of range", index)))?;
+        match SlabNodeView::from_bytes(bytes) {
+            SlabNodeView::Inner { children, .. } => {
+                stack.push(children[1]);
+                stack.push(children[0]);
+            }
+            SlabNodeView::Leaf {
+                fee_tier,
+                key,
+                owner,
+                quantity,
+                client_order_id,
+                ..
+            } => orders.push(BookOrder {
+                order_id: key,
+                price: (key >> 64) as u64,
+                quantity,
+                owner,
+                client_order_id,
+                fee_tier,
+            }),
+            node => return Err(malformed(format!("node {} is {:?}", index, node))),
+        }
+    }
+    if orders.len() as u64 != leaf_count {
+        return Err(malformed(format!(
+            "found {} leaves, header lists {}",
+            orders.len(),
+            leaf_count
+        )));
+    }
+    Ok(orders.into_iter())
+}
+
+fn malformed(message: String) -> Error {
+    Error::from(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed slab: {}", message),
+    ))
+}
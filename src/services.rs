@@ -0,0 +1,304 @@
+use crate::actor::Actor;
+use crate::crank::{self, Crank, CrankMetrics};
+use crate::errors::{Error, Result};
+use crate::serum::Market;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    any::Any,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread, time,
+};
+
+#[cfg(feature = "fault-injection")]
+use std::sync::atomic::AtomicUsize;
+
+/// Settings of a CrankService.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrankConfig {
+    /// Time to wait before polling again once the event queue is empty.
+    pub interval: time::Duration,
+
+    /// Events consumed by each consume_events transaction.
+    pub num_events: u16,
+
+    /// Times the crank is restarted after panicking before the service
+    /// gives up.
+    pub max_restarts: usize,
+
+    /// Time to wait before restarting a crank that panicked.
+    pub restart_delay: time::Duration,
+
+    /// Time the crank may go without polling the event queue before it is
+    /// reported unhealthy.
+    pub stall_timeout: time::Duration,
+}
+
+impl Default for CrankConfig {
+    fn default() -> Self {
+        CrankConfig {
+            interval: time::Duration::from_millis(100),
+            num_events: 10,
+            max_restarts: 3,
+            restart_delay: time::Duration::from_millis(100),
+            stall_timeout: time::Duration::from_secs(10),
+        }
+    }
+}
+
+/// The state of a CrankService, as reported by CrankService::health.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceHealth {
+    /// Whether a crank thread is running. False once the service is stopped
+    /// or has given up after too many panics.
+    pub running: bool,
+
+    /// Times the crank was restarted after panicking.
+    pub restarts: usize,
+
+    /// Message of the most recent panic, if any.
+    pub last_panic: Option<String>,
+
+    /// Time since the crank last polled the event queue, or since the
+    /// service started if it has not polled yet.
+    pub since_last_poll: time::Duration,
+}
+
+/// Supervision state shared with the supervisor thread.
+#[derive(Debug, Default)]
+struct Supervision {
+    running: bool,
+    gave_up: bool,
+    restarts: usize,
+    last_panic: Option<String>,
+}
+
+/// Cranks a Serum market on a supervised background thread.
+///
+/// The crank works like CrankHandle, but a panic in the crank thread is
+/// caught, logged, and followed by a restart with fresh RPC connections, up
+/// to CrankConfig::max_restarts times, rather than silently ending the
+/// crank. Metrics survive restarts. Lifecycle events and failed cranks are
+/// logged with tracing, tagged with the market address.
+///
+/// ```ignore
+/// let crank = CrankService::spawn(&market, &cranker, CrankConfig::default())?;
+/// // ... place orders ...
+/// crank.check_health()?;
+/// let metrics = crank.stop()?;
+/// ```
+pub struct CrankService {
+    market: Pubkey,
+    config: CrankConfig,
+    metrics: Arc<Mutex<CrankMetrics>>,
+    supervision: Arc<Mutex<Supervision>>,
+    start: time::Instant,
+    stop: Arc<AtomicBool>,
+    supervisor: Option<thread::JoinHandle<()>>,
+    #[cfg(feature = "fault-injection")]
+    injected_panics: Arc<AtomicUsize>,
+}
+
+impl CrankService {
+    /// Starts cranking a market. The cranker pays for and signs every
+    /// transaction.
+    pub fn spawn(market: &Market, cranker: &Actor, config: CrankConfig) -> Result<Self> {
+        let crank = Crank::new(market, cranker, config.num_events)?;
+        let metrics = Arc::new(Mutex::new(CrankMetrics::default()));
+        let supervision = Arc::new(Mutex::new(Supervision {
+            running: true,
+            ..Supervision::default()
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let start = time::Instant::now();
+        #[cfg(feature = "fault-injection")]
+        let injected_panics = crank.injected_panics();
+
+        let supervisor = {
+            let (metrics, supervision, stop) = (metrics.clone(), supervision.clone(), stop.clone());
+            let config = config.clone();
+            thread::Builder::new()
+                .name(format!("crank-supervisor-{}", market.market().pubkey()))
+                .spawn(move || supervise(crank, config, metrics, supervision, start, stop))?
+        };
+        tracing::info!(market = %market.market().pubkey(), "crank service started");
+
+        Ok(CrankService {
+            market: *market.market().pubkey(),
+            config,
+            metrics,
+            supervision,
+            start,
+            stop,
+            supervisor: Some(supervisor),
+            #[cfg(feature = "fault-injection")]
+            injected_panics,
+        })
+    }
+
+    /// Makes the crank panic on its next count polls, including polls by
+    /// the cranks restarted in its place, to exercise supervision.
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_panics(&self, count: usize) {
+        self.injected_panics.store(count, Ordering::SeqCst);
+    }
+
+    /// Returns the metrics collected so far, across restarts.
+    pub fn metrics(&self) -> CrankMetrics {
+        let mut metrics = crank::lock(&self.metrics).clone();
+        metrics.elapsed = self.start.elapsed();
+        metrics
+    }
+
+    /// Reports whether the crank is running, how often it restarted, and
+    /// how recently it polled.
+    pub fn health(&self) -> ServiceHealth {
+        let metrics = self.metrics();
+        let supervision = crank::lock(&self.supervision);
+        ServiceHealth {
+            running: supervision.running,
+            restarts: supervision.restarts,
+            last_panic: supervision.last_panic.clone(),
+            since_last_poll: metrics.elapsed - metrics.last_poll.unwrap_or_default(),
+        }
+    }
+
+    /// Fails if the crank is no longer running or has not polled the event
+    /// queue within the stall timeout.
+    pub fn check_health(&self) -> Result<()> {
+        let health = self.health();
+        if !health.running {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "crank of market {} is not running after {} restarts, last panic: {}",
+                    self.market,
+                    health.restarts,
+                    health.last_panic.as_deref().unwrap_or("none")
+                ),
+            )));
+        }
+        if health.since_last_poll > self.config.stall_timeout {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "crank of market {} has not polled for {:?}",
+                    self.market, health.since_last_poll
+                ),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stops cranking, waiting for a crank in progress to finish, and
+    /// returns the final metrics. Fails if the service gave up after the
+    /// crank panicked too often.
+    pub fn stop(mut self) -> Result<CrankMetrics> {
+        self.join()?;
+        let supervision = crank::lock(&self.supervision);
+        if supervision.gave_up {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "crank of market {} panicked {} times, last panic: {}",
+                    self.market,
+                    supervision.restarts + 1,
+                    supervision.last_panic.as_deref().unwrap_or("none")
+                ),
+            )));
+        }
+        Ok(self.metrics())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.supervisor.take() {
+            Some(supervisor) => supervisor.join().map_err(|_| {
+                Error::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    "crank supervisor panicked",
+                ))
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CrankService {
+    /// Stops the crank and its supervisor.
+    fn drop(&mut self) {
+        self.join().unwrap_or(());
+    }
+}
+
+/// Runs the crank on a worker thread until stopped, restarting it whenever
+/// it panics until the restart budget is spent.
+fn supervise(
+    crank: Crank,
+    config: CrankConfig,
+    metrics: Arc<Mutex<CrankMetrics>>,
+    supervision: Arc<Mutex<Supervision>>,
+    start: time::Instant,
+    stop: Arc<AtomicBool>,
+) {
+    let market = *crank.market();
+    loop {
+        let worker = crank.reconnect();
+        let (worker_metrics, worker_stop) = (metrics.clone(), stop.clone());
+        let interval = config.interval;
+        let result = thread::Builder::new()
+            .name(format!("crank-{}", market))
+            .spawn(move || worker.run(&worker_metrics, start, &worker_stop, interval))
+            .map_err(|err| Box::new(err.to_string()) as Box<dyn Any + Send>)
+            .and_then(|handle| handle.join());
+
+        let mut state = crank::lock(&supervision);
+        let panic = match result {
+            Ok(()) => {
+                tracing::info!(market = %market, "crank service stopped");
+                state.running = false;
+                return;
+            }
+            Err(panic) => panic_message(panic.as_ref()),
+        };
+        tracing::error!(
+            market = %market,
+            panic = %panic,
+            restarts = state.restarts,
+            "crank panicked"
+        );
+        crank::lock(&metrics)
+            .errors
+            .push(format!("panic: {}", panic));
+        state.last_panic = Some(panic);
+        if stop.load(Ordering::Relaxed) {
+            state.running = false;
+            return;
+        }
+        if state.restarts == config.max_restarts {
+            tracing::error!(market = %market, restarts = state.restarts, "crank service gave up");
+            state.running = false;
+            state.gave_up = true;
+            return;
+        }
+        state.restarts += 1;
+        drop(state);
+
+        thread::sleep(config.restart_delay);
+        tracing::warn!(market = %market, "restarting crank");
+    }
+}
+
+/// Returns the message a thread panicked with.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
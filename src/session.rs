@@ -0,0 +1,174 @@
+use crate::errors::{Error, Result};
+use crate::sandbox::Sandbox;
+use std::io;
+
+/// Name of the phase before continuous trading, used by
+/// SessionClock::trading_day.
+pub const PRE_OPEN: &str = "pre-open";
+
+/// Name of the continuous trading phase, used by SessionClock::trading_day.
+pub const CONTINUOUS: &str = "continuous";
+
+/// Name of the phase after continuous trading, used by
+/// SessionClock::trading_day.
+pub const CLOSE: &str = "close";
+
+/// A phase of a session, as entered by SessionClock::advance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionPhase {
+    /// Name of the phase.
+    pub name: String,
+
+    /// Position of the phase in the session, starting at zero.
+    pub index: usize,
+
+    /// Slot at which the phase begins.
+    pub start_slot: u64,
+
+    /// Number of slots the phase lasts.
+    pub slots: u64,
+}
+
+impl SessionPhase {
+    /// Returns the first slot after the phase.
+    pub fn end_slot(&self) -> u64 {
+        self.start_slot + self.slots
+    }
+}
+
+type PhaseCallback<'a> = Box<dyn FnMut(&Sandbox, &SessionPhase) -> Result<()> + 'a>;
+
+/// Drives a Sandbox through the labeled phases of a session, such as a
+/// trading day's pre-open, continuous trading, and close, for testing
+/// programs whose behavior depends on the slot or epoch.
+///
+/// Each phase lasts a number of slots, counted from the slot at which the
+/// session starts. Advancing to a phase warps the validator to the phase's
+/// first slot, unless the validator has already reached it, and then runs
+/// the callbacks registered for the phase in registration order:
+///
+/// ```ignore
+/// let mut clock = SessionClock::trading_day(&sandbox, 100, 1_000, 100);
+/// clock.on_phase(session::CONTINUOUS, |_, _| open_market(&market, &admin));
+/// clock.on_phase(session::CLOSE, |_, _| close_market(&market, &admin));
+/// clock.advance()?; // pre-open
+/// // ... submit auction orders ...
+/// clock.advance()?; // continuous
+/// // ... trade ...
+/// clock.run()?; // close
+/// ```
+///
+/// Warping restarts the validator, see Sandbox::warp_to_slot, so phases
+/// shorter than the time a restart takes are reached without warping.
+/// With SandboxBuilder::slots_per_epoch, phases can be sized in epochs.
+pub struct SessionClock<'a> {
+    sandbox: &'a Sandbox,
+    phases: Vec<(String, u64)>,
+    callbacks: Vec<(Option<String>, PhaseCallback<'a>)>,
+    current: Option<SessionPhase>,
+}
+
+impl<'a> SessionClock<'a> {
+    /// Returns a clock with no phases.
+    pub fn new(sandbox: &'a Sandbox) -> Self {
+        SessionClock {
+            sandbox,
+            phases: Vec::new(),
+            callbacks: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Returns a clock with the phases PRE_OPEN, CONTINUOUS, and CLOSE, of
+    /// the given lengths in slots.
+    pub fn trading_day(sandbox: &'a Sandbox, pre_open: u64, continuous: u64, close: u64) -> Self {
+        let mut clock = SessionClock::new(sandbox);
+        clock
+            .phase(PRE_OPEN, pre_open)
+            .phase(CONTINUOUS, continuous)
+            .phase(CLOSE, close);
+        clock
+    }
+
+    /// Appends a phase lasting the given number of slots.
+    pub fn phase(&mut self, name: &str, slots: u64) -> &mut Self {
+        self.phases.push((String::from(name), slots));
+        self
+    }
+
+    /// Registers a callback run whenever the named phase begins.
+    pub fn on_phase<F>(&mut self, name: &str, callback: F) -> &mut Self
+    where
+        F: FnMut(&Sandbox, &SessionPhase) -> Result<()> + 'a,
+    {
+        self.callbacks
+            .push((Some(String::from(name)), Box::new(callback)));
+        self
+    }
+
+    /// Registers a callback run whenever any phase begins.
+    pub fn on_every_phase<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&Sandbox, &SessionPhase) -> Result<()> + 'a,
+    {
+        self.callbacks.push((None, Box::new(callback)));
+        self
+    }
+
+    /// Returns the phase the session is in, or None before the first
+    /// advance.
+    pub fn current_phase(&self) -> Option<&SessionPhase> {
+        self.current.as_ref()
+    }
+
+    /// Returns true once the last phase has been entered.
+    pub fn is_finished(&self) -> bool {
+        self.next_index() == self.phases.len()
+    }
+
+    /// Enters the next phase: warps to its first slot if needed and runs its
+    /// callbacks. The first advance starts the session at the current slot.
+    /// Fails if every phase has been entered or a callback fails, in which
+    /// case the remaining callbacks are not run.
+    pub fn advance(&mut self) -> Result<SessionPhase> {
+        let index = self.next_index();
+        let (name, slots) = self.phases.get(index).cloned().ok_or_else(|| {
+            Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the session has no phases left",
+            ))
+        })?;
+        let slot = self.sandbox.run_rpc(|| self.sandbox.client().get_slot())?;
+        let start_slot = self.current.as_ref().map_or(slot, |phase| phase.end_slot());
+        if start_slot > slot {
+            self.sandbox.warp_to_slot(start_slot)?;
+        }
+
+        let phase = SessionPhase {
+            name,
+            index,
+            start_slot,
+            slots,
+        };
+        tracing::info!(phase = %phase.name, slot = start_slot, "entering session phase");
+        self.current = Some(phase.clone());
+        for (filter, callback) in self.callbacks.iter_mut() {
+            if filter.as_ref().map_or(true, |name| *name == phase.name) {
+                callback(self.sandbox, &phase)?;
+            }
+        }
+        Ok(phase)
+    }
+
+    /// Enters every remaining phase in turn.
+    pub fn run(&mut self) -> Result<()> {
+        while !self.is_finished() {
+            self.advance()?;
+        }
+        Ok(())
+    }
+
+    fn next_index(&self) -> usize {
+        self.current.as_ref().map_or(0, |phase| phase.index + 1)
+    }
+}
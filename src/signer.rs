@@ -0,0 +1,123 @@
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signature,
+    signer::{Signer, SignerError},
+};
+use std::sync::{mpsc, Mutex};
+
+type SignFn = dyn Fn(&[u8]) -> std::result::Result<Signature, SignerError> + Send + Sync;
+
+/// Signs on behalf of a key that is held outside of this process, such as in
+/// a hardware wallet or a remote signing service.
+///
+/// Each signature is requested from a callback, or through a channel that a
+/// wallet emulator answers, so applications can exercise the same
+/// partial-sign, serialize, and countersign flows against the Sandbox that
+/// they run against a real wallet:
+///
+/// ```ignore
+/// let (signer, requests) = RemoteSigner::channel(wallet.pubkey());
+/// thread::spawn(move || {
+///     for request in requests.iter() {
+///         let signature = wallet.sign_message(request.message());
+///         request.approve(signature);
+///     }
+/// });
+/// let actor = Actor::remote(&sandbox, signer);
+/// ```
+pub struct RemoteSigner {
+    pubkey: Pubkey,
+    sign: Box<SignFn>,
+}
+
+impl RemoteSigner {
+    /// Creates a signer for the given public key that signs each message by
+    /// calling sign.
+    pub fn new<F>(pubkey: Pubkey, sign: F) -> Self
+    where
+        F: Fn(&[u8]) -> std::result::Result<Signature, SignerError> + Send + Sync + 'static,
+    {
+        RemoteSigner {
+            pubkey,
+            sign: Box::new(sign),
+        }
+    }
+
+    /// Creates a signer for the given public key that sends each message to
+    /// the returned receiver and blocks until the request is answered.
+    /// Signing fails if the request is dropped unanswered or the receiver is
+    /// gone.
+    pub fn channel(pubkey: Pubkey) -> (Self, mpsc::Receiver<SigningRequest>) {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let signer = RemoteSigner::new(pubkey, move |message| {
+            let (reply, response) = mpsc::channel();
+            sender
+                .lock()
+                .expect("remote signer lock poisoned")
+                .send(SigningRequest {
+                    pubkey,
+                    message: message.to_vec(),
+                    reply,
+                })
+                .map_err(|_| SignerError::Connection("remote signer is gone".to_string()))?;
+            response
+                .recv()
+                .map_err(|_| SignerError::Connection("signing request was dropped".to_string()))?
+        });
+        (signer, receiver)
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn try_pubkey(&self) -> std::result::Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> std::result::Result<Signature, SignerError> {
+        let signature = (self.sign)(message)?;
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(SignerError::Custom(format!(
+                "remote signature does not verify for {}",
+                self.pubkey
+            )));
+        }
+        Ok(signature)
+    }
+
+    fn is_interactive(&self) -> bool {
+        true
+    }
+}
+
+/// A message waiting to be signed by a RemoteSigner created with
+/// RemoteSigner::channel.
+pub struct SigningRequest {
+    pubkey: Pubkey,
+    message: Vec<u8>,
+    reply: mpsc::Sender<std::result::Result<Signature, SignerError>>,
+}
+
+impl SigningRequest {
+    /// Returns the public key whose signature is requested.
+    pub fn pubkey(&self) -> &Pubkey {
+        &self.pubkey
+    }
+
+    /// Returns the serialized message to sign.
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// Answers the request with a signature.
+    pub fn approve(self, signature: Signature) {
+        self.reply.send(Ok(signature)).unwrap_or(());
+    }
+
+    /// Answers the request the way a wallet does when its user declines.
+    pub fn reject(self, reason: &str) {
+        self.reply
+            .send(Err(SignerError::UserCancel(reason.to_string())))
+            .unwrap_or(());
+    }
+}
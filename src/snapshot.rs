@@ -0,0 +1,132 @@
+use crate::errors::Result;
+use crate::sandbox::Sandbox;
+use solana_sdk::pubkey::Pubkey;
+use std::{env, fs, io, path::Path};
+
+/// Environment variable that, when set to a non-empty value other than "0",
+/// makes snapshot assertions rewrite their golden files instead of comparing
+/// against them.
+pub const UPDATE_ENV_VAR: &str = "SOLARIUM_UPDATE_SNAPSHOTS";
+
+/// Bytes shown per row of a hex diff.
+const ROW_WIDTH: usize = 16;
+
+/// Differing rows shown before a hex diff is truncated.
+const MAX_DIFF_ROWS: usize = 32;
+
+/// Asserts that an account's data matches a golden file, panicking with a
+/// hex diff otherwise. Set SOLARIUM_UPDATE_SNAPSHOTS=1 to write the current
+/// data to the golden file instead.
+///
+/// ```ignore
+/// assert_account_snapshot!(sandbox, market.pubkey(), "tests/golden/market_state.bin");
+/// ```
+#[macro_export]
+macro_rules! assert_account_snapshot {
+    ($sandbox:expr, $pubkey:expr, $path:expr) => {
+        match $crate::snapshot::account_snapshot_diff(&$sandbox, &$pubkey, $path) {
+            Ok(None) => {}
+            Ok(Some(diff)) => panic!("account {} does not match {}:\n{}", $pubkey, $path, diff),
+            Err(err) => panic!("could not snapshot account {}: {:?}", $pubkey, err),
+        }
+    };
+}
+
+/// Returns true if snapshot assertions should update golden files.
+pub fn update_mode() -> bool {
+    env::var(UPDATE_ENV_VAR)
+        .map(|value| !value.is_empty() && value != "0")
+        .unwrap_or(false)
+}
+
+/// Compares an account's data to a golden file and returns a readable diff
+/// if they differ. In update mode the golden file is written instead and no
+/// diff is returned.
+pub fn account_snapshot_diff<P: AsRef<Path>>(
+    sandbox: &Sandbox,
+    pubkey: &Pubkey,
+    path: P,
+) -> Result<Option<String>> {
+    let data = sandbox.get_account_data(pubkey)?;
+    snapshot_diff(&data, path)
+}
+
+/// Compares data to a golden file and returns a readable diff if they
+/// differ. In update mode the golden file is written instead and no diff is
+/// returned.
+pub fn snapshot_diff<P: AsRef<Path>>(data: &[u8], path: P) -> Result<Option<String>> {
+    let path = path.as_ref();
+    if update_mode() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, data)?;
+        return Ok(None);
+    }
+
+    let expected = match fs::read(path) {
+        Ok(expected) => expected,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(format!(
+                "golden file {} does not exist, run with {}=1 to create it",
+                path.display(),
+                UPDATE_ENV_VAR
+            )));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if expected == data {
+        return Ok(None);
+    }
+    Ok(Some(hex_diff(&expected, data)))
+}
+
+/// Renders the rows in which two byte strings differ, expected first.
+pub fn hex_diff(expected: &[u8], actual: &[u8]) -> String {
+    let mut lines = Vec::new();
+    if expected.len() != actual.len() {
+        lines.push(format!(
+            "length differs: expected {} bytes, got {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+
+    let rows = (expected.len().max(actual.len()) + ROW_WIDTH - 1) / ROW_WIDTH;
+    let mut differing = 0;
+    for row in 0..rows {
+        let start = row * ROW_WIDTH;
+        let expected_row = row_slice(expected, start);
+        let actual_row = row_slice(actual, start);
+        if expected_row == actual_row {
+            continue;
+        }
+        differing += 1;
+        if differing > MAX_DIFF_ROWS {
+            continue;
+        }
+        lines.push(format!("{:08x} - {}", start, hex_row(expected_row)));
+        lines.push(format!("{:08x} + {}", start, hex_row(actual_row)));
+    }
+    if differing > MAX_DIFF_ROWS {
+        lines.push(format!(
+            "... {} more differing rows",
+            differing - MAX_DIFF_ROWS
+        ));
+    }
+    lines.join("\n")
+}
+
+fn row_slice(data: &[u8], start: usize) -> &[u8] {
+    if start >= data.len() {
+        return &[];
+    }
+    &data[start..(start + ROW_WIDTH).min(data.len())]
+}
+
+fn hex_row(row: &[u8]) -> String {
+    row.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
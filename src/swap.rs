@@ -0,0 +1,353 @@
+use crate::actor::Actor;
+use crate::errors::{Error, Result};
+use crate::pda;
+use crate::sandbox::Sandbox;
+use crate::token::{Mint, TokenAccount};
+use solana_program::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use spl_token::{self, instruction as spl_instruction, state as spl_state};
+use spl_token_swap::{
+    curve::{
+        base::{CurveType, SwapCurve},
+        constant_product::ConstantProductCurve,
+        fees::Fees,
+    },
+    instruction::{self as swap_instruction, Swap},
+    state::SwapVersion,
+};
+use std::io;
+
+pub use spl_token_swap::instruction::{DepositAllTokenTypes, WithdrawAllTokenTypes};
+
+/// Decimals of the pool token minted to liquidity providers.
+const POOL_TOKEN_DECIMALS: u8 = 2;
+
+/// Local file name used when downloading the swap program.
+const SWAP_PROGRAM_FILE: &str = "spl_token_swap.so";
+
+/// Deploys a constant product swap program downloaded from the given url. The
+/// returned Actor is the program id to pass to Pool::new.
+pub fn deploy_program<'a>(actor: &Actor<'a>, remote_location: &str) -> Result<Actor<'a>> {
+    actor.deploy_remote(remote_location, SWAP_PROGRAM_FILE)
+}
+
+/// Parameters used to create a Pool.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolParams {
+    /// Amount of token A initially deposited in the pool.
+    pub initial_a: u64,
+
+    /// Amount of token B initially deposited in the pool.
+    pub initial_b: u64,
+
+    /// Trading and withdrawal fees charged by the pool.
+    pub fees: Fees,
+}
+
+impl PoolParams {
+    /// Returns parameters with the given initial reserves and fees resembling
+    /// a typical constant product pool: a 0.25% trade fee, of which 0.05%
+    /// goes to the pool owner.
+    pub fn new(initial_a: u64, initial_b: u64) -> Self {
+        PoolParams {
+            initial_a,
+            initial_b,
+            fees: Fees {
+                trade_fee_numerator: 25,
+                trade_fee_denominator: 10000,
+                owner_trade_fee_numerator: 5,
+                owner_trade_fee_denominator: 10000,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+                host_fee_numerator: 0,
+                host_fee_denominator: 0,
+            },
+        }
+    }
+}
+
+/// Represents a constant product AMM pool run by an spl-token-swap style
+/// program.
+///
+/// The swap program is deployed like any other program, for example with
+/// Actor::deploy_remote. A Pool holds the two token reserves, the pool token
+/// mint, and the fee account, all created when the pool is initialized.
+pub struct Pool<'a> {
+    sandbox: &'a Sandbox,
+    program: &'a Pubkey,
+    swap: Actor<'a>,
+    authority: Pubkey,
+    mint_a: &'a Mint<'a>,
+    mint_b: &'a Mint<'a>,
+    reserve_a: TokenAccount<'a>,
+    reserve_b: TokenAccount<'a>,
+    pool_mint: Actor<'a>,
+    fee_account: TokenAccount<'a>,
+    initial_pool_tokens: TokenAccount<'a>,
+}
+
+impl<'a> Pool<'a> {
+    /// Creates a pool over two mints and seeds it with the initial reserves
+    /// given in params. The reserves are minted by the given actor, which
+    /// must be the mint authority of both mints. The actor also pays for
+    /// creation, owns the pool's fee account, and receives the initial pool
+    /// tokens.
+    pub fn new(
+        sandbox: &'a Sandbox,
+        actor: &'a Actor,
+        program: &'a Pubkey,
+        mint_a: &'a Mint,
+        mint_b: &'a Mint,
+        params: &PoolParams,
+    ) -> Result<Self> {
+        let swap = Actor::new(sandbox)?;
+        let (authority, nonce) = pda::find_pda(&[swap.pubkey().as_ref()], program);
+
+        // Reserves are owned by the pool authority and must be funded before
+        // the pool is initialized.
+        let reserve_a = TokenAccount::new(sandbox, actor, mint_a, Some(&authority))?;
+        let reserve_b = TokenAccount::new(sandbox, actor, mint_b, Some(&authority))?;
+        mint_a.mint_to(actor, &reserve_a, params.initial_a)?;
+        mint_b.mint_to(actor, &reserve_b, params.initial_b)?;
+
+        // The pool mint is controlled by the pool authority and must not have
+        // a freeze authority.
+        let pool_mint = Actor::new(sandbox)?;
+        let create_pool_mint =
+            actor.create_account(pool_mint.pubkey(), spl_state::Mint::LEN, &spl_token::id())?;
+        let initialize_pool_mint = spl_instruction::initialize_mint(
+            &spl_token::id(),
+            pool_mint.pubkey(),
+            &authority,
+            None,
+            POOL_TOKEN_DECIMALS,
+        )?;
+        sandbox
+            .transaction()
+            .instruction(create_pool_mint)
+            .instruction(initialize_pool_mint)
+            .payer(actor.try_keypair()?)
+            .signer(pool_mint.try_keypair()?)
+            .send()?;
+
+        let fee_account = TokenAccount::new_for_mint(sandbox, actor, pool_mint.pubkey(), None)?;
+        let initial_pool_tokens =
+            TokenAccount::new_for_mint(sandbox, actor, pool_mint.pubkey(), None)?;
+
+        let create_swap = actor.create_account(swap.pubkey(), SwapVersion::LATEST_LEN, program)?;
+        let initialize = swap_instruction::initialize(
+            program,
+            &spl_token::id(),
+            swap.pubkey(),
+            &authority,
+            reserve_a.account().pubkey(),
+            reserve_b.account().pubkey(),
+            pool_mint.pubkey(),
+            fee_account.account().pubkey(),
+            initial_pool_tokens.account().pubkey(),
+            nonce,
+            params.fees.clone(),
+            SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(ConstantProductCurve {}),
+            },
+        )?;
+        sandbox
+            .transaction()
+            .instruction(create_swap)
+            .instruction(initialize)
+            .payer(actor.try_keypair()?)
+            .signer(swap.try_keypair()?)
+            .send()?;
+
+        Ok(Pool {
+            sandbox,
+            program,
+            swap,
+            authority,
+            mint_a,
+            mint_b,
+            reserve_a,
+            reserve_b,
+            pool_mint,
+            fee_account,
+            initial_pool_tokens,
+        })
+    }
+
+    /// Creates a pool token account owned by the given owner, or by the actor
+    /// if no owner is specified.
+    pub fn new_pool_token_account(
+        &self,
+        actor: &Actor,
+        owner: Option<&Pubkey>,
+    ) -> Result<TokenAccount<'a>> {
+        TokenAccount::new_for_mint(self.sandbox, actor, self.pool_mint.pubkey(), owner)
+    }
+
+    /// Deposits both tokens in exchange for the requested amount of pool
+    /// tokens. The provider owns the source accounts and signs the transfers.
+    /// Fails if more than the requested maximums would be deposited.
+    pub fn add_liquidity(
+        &self,
+        provider: &Actor,
+        source_a: &TokenAccount,
+        source_b: &TokenAccount,
+        destination: &TokenAccount,
+        deposit: DepositAllTokenTypes,
+    ) -> Result<()> {
+        let instruction = swap_instruction::deposit_all_token_types(
+            self.program,
+            &spl_token::id(),
+            self.swap.pubkey(),
+            &self.authority,
+            provider.pubkey(),
+            source_a.account().pubkey(),
+            source_b.account().pubkey(),
+            self.reserve_a.account().pubkey(),
+            self.reserve_b.account().pubkey(),
+            self.pool_mint.pubkey(),
+            destination.account().pubkey(),
+            deposit,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(provider.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Burns the requested amount of pool tokens in exchange for both tokens.
+    /// Fails if less than the requested minimums would be withdrawn.
+    pub fn remove_liquidity(
+        &self,
+        provider: &Actor,
+        source: &TokenAccount,
+        destination_a: &TokenAccount,
+        destination_b: &TokenAccount,
+        withdraw: WithdrawAllTokenTypes,
+    ) -> Result<()> {
+        let instruction = swap_instruction::withdraw_all_token_types(
+            self.program,
+            &spl_token::id(),
+            self.swap.pubkey(),
+            &self.authority,
+            provider.pubkey(),
+            self.pool_mint.pubkey(),
+            self.fee_account.account().pubkey(),
+            source.account().pubkey(),
+            self.reserve_a.account().pubkey(),
+            self.reserve_b.account().pubkey(),
+            destination_a.account().pubkey(),
+            destination_b.account().pubkey(),
+            withdraw,
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(provider.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Swaps amount_in of the source account's token for the other token in
+    /// the pool. The swap direction is derived from the source account's
+    /// mint. Fails if less than minimum_amount_out would be received.
+    pub fn swap(
+        &self,
+        trader: &Actor,
+        source: &TokenAccount,
+        destination: &TokenAccount,
+        amount_in: u64,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        let source_mint = source.get_account_info()?.mint;
+        let (swap_source, swap_destination) = if source_mint == *self.mint_a.actor().pubkey() {
+            (&self.reserve_a, &self.reserve_b)
+        } else if source_mint == *self.mint_b.actor().pubkey() {
+            (&self.reserve_b, &self.reserve_a)
+        } else {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "source account mint is not traded by this pool",
+            )));
+        };
+
+        let swap = swap_instruction::swap(
+            self.program,
+            &spl_token::id(),
+            self.swap.pubkey(),
+            &self.authority,
+            trader.pubkey(),
+            source.account().pubkey(),
+            swap_source.account().pubkey(),
+            swap_destination.account().pubkey(),
+            destination.account().pubkey(),
+            self.pool_mint.pubkey(),
+            self.fee_account.account().pubkey(),
+            None,
+            Swap {
+                amount_in,
+                minimum_amount_out,
+            },
+        )?;
+
+        self.sandbox
+            .transaction()
+            .instruction(swap)
+            .payer(trader.try_keypair()?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Returns the current token A and token B reserves.
+    pub fn reserves(&self) -> Result<(u64, u64)> {
+        Ok((
+            self.reserve_a.get_account_info()?.amount,
+            self.reserve_b.get_account_info()?.amount,
+        ))
+    }
+
+    /// Returns reference to the swap program id
+    pub fn program(&self) -> &Pubkey {
+        self.program
+    }
+
+    /// Returns reference to the underlying swap state account
+    pub fn swap_account(&self) -> &Actor {
+        &self.swap
+    }
+
+    /// Returns reference to the pool authority
+    pub fn authority(&self) -> &Pubkey {
+        &self.authority
+    }
+
+    /// Returns reference to the pool token mint account
+    pub fn pool_mint(&self) -> &Actor {
+        &self.pool_mint
+    }
+
+    /// Returns reference to the token A reserve
+    pub fn reserve_a(&self) -> &TokenAccount {
+        &self.reserve_a
+    }
+
+    /// Returns reference to the token B reserve
+    pub fn reserve_b(&self) -> &TokenAccount {
+        &self.reserve_b
+    }
+
+    /// Returns reference to the pool's fee account
+    pub fn fee_account(&self) -> &TokenAccount {
+        &self.fee_account
+    }
+
+    /// Returns reference to the account holding the initial pool tokens
+    pub fn initial_pool_tokens(&self) -> &TokenAccount {
+        &self.initial_pool_tokens
+    }
+}
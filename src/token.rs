@@ -1,22 +1,63 @@
 use crate::actor::Actor;
-use crate::errors::Result;
+use crate::errors::{Context, Result};
 use crate::sandbox::Sandbox;
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::program_pack::Pack;
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_instruction,
+};
 use spl_token::{self, instruction as spl_instruction, state as spl_state};
+use std::{ops::Deref, str::FromStr};
+
+/// Address of the associated token account program.
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+
+/// Returns the address of the associated token account program.
+pub fn associated_token_program_id() -> Pubkey {
+    Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).expect("valid program id")
+}
+
+/// Returns the address of the wallet's associated token account for the
+/// mint.
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), spl_token::id().as_ref(), mint.as_ref()],
+        &associated_token_program_id(),
+    )
+    .0
+}
 
 #[derive(BorshSerialize, BorshDeserialize, Eq, PartialEq, PartialOrd)]
 pub enum BaseOrQuote {
     Base,
     Quote,
 }
+/// A value either borrowed from the caller or owned, such as a watch-only
+/// actor attached from chain.
+pub(crate) enum MaybeOwned<'a, T> {
+    Borrowed(&'a T),
+    Owned(T),
+}
+
+impl<'a, T> Deref for MaybeOwned<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            MaybeOwned::Borrowed(value) => value,
+            MaybeOwned::Owned(value) => value,
+        }
+    }
+}
+
 /// Represents an spl_token program Mint.
 pub struct Mint<'a> {
     sandbox: &'a Sandbox,
     mint: Actor<'a>,
-    authority: &'a Actor<'a>,
-    freeze_authority: &'a Actor<'a>,
+    authority: MaybeOwned<'a, Actor<'a>>,
+    freeze_authority: MaybeOwned<'a, Actor<'a>>,
 }
 
 impl<'a> Mint<'a> {
@@ -24,6 +65,7 @@ impl<'a> Mint<'a> {
     ///
     /// The input actor creates the mint and is the default authority and freeze
     /// authority.
+    #[tracing::instrument(skip_all, fields(decimals = decimals))]
     pub fn new(
         sandbox: &'a Sandbox,
         actor: &'a Actor,
@@ -53,17 +95,57 @@ impl<'a> Mint<'a> {
             decimals,
         )?;
 
-        sandbox.send_signed_transaction_with_payers(
-            &[create_account, initialize_mint],
-            Some(actor.pubkey()),
-            vec![actor.keypair(), mint.keypair()],
-        )?;
+        sandbox
+            .transaction()
+            .instruction(create_account)
+            .instruction(initialize_mint)
+            .payer(actor.try_keypair()?)
+            .signer(mint.try_keypair()?)
+            .send()
+            .with_context(|| format!("creating mint {}", sandbox.label(mint.pubkey())))?;
 
         Ok(Mint {
             sandbox,
             mint,
-            authority,
-            freeze_authority,
+            authority: MaybeOwned::Borrowed(authority),
+            freeze_authority: MaybeOwned::Borrowed(freeze_authority),
+        })
+    }
+
+    /// Attaches to a mint that already exists on chain. The given actor is
+    /// used as both the mint authority and freeze authority when signing, so
+    /// minting only succeeds if it holds the on-chain mint authority.
+    pub fn from_existing(
+        sandbox: &'a Sandbox,
+        pubkey: &Pubkey,
+        authority: &'a Actor,
+    ) -> Result<Mint<'a>> {
+        let data = sandbox.get_account_data(pubkey)?;
+        spl_state::Mint::unpack_from_slice(&data)?;
+
+        Ok(Mint {
+            sandbox,
+            mint: Actor::from_pubkey(sandbox, pubkey),
+            authority: MaybeOwned::Borrowed(authority),
+            freeze_authority: MaybeOwned::Borrowed(authority),
+        })
+    }
+
+    /// Attaches to a mint that already exists on chain without the keypairs
+    /// of its authorities, which are read from the mint and attached as
+    /// watch-only actors. Minting fails rather than signing. A mint without
+    /// a freeze authority reports its mint authority in its place.
+    pub fn watch_only(sandbox: &'a Sandbox, pubkey: &Pubkey) -> Result<Mint<'a>> {
+        let data = sandbox.get_account_data(pubkey)?;
+        let state = spl_state::Mint::unpack(&data)?;
+        let authority = state.mint_authority.unwrap_or_default();
+        let freeze_authority = state.freeze_authority.unwrap_or(authority);
+
+        Ok(Mint {
+            sandbox,
+            mint: Actor::from_pubkey(sandbox, pubkey),
+            authority: MaybeOwned::Owned(Actor::from_pubkey(sandbox, &authority)),
+            freeze_authority: MaybeOwned::Owned(Actor::from_pubkey(sandbox, &freeze_authority)),
         })
     }
 
@@ -72,19 +154,26 @@ impl<'a> Mint<'a> {
         &self.mint
     }
 
+    /// Fetches the number of decimals of the mint's token.
+    pub fn decimals(&self) -> Result<u8> {
+        let data = self.sandbox.get_account_data(self.mint.pubkey())?;
+        Ok(spl_state::Mint::unpack(&data)?.decimals)
+    }
+
     /// Returns the Mint authority.
     pub fn authority(&self) -> &Actor {
-        self.authority
+        &self.authority
     }
 
     /// Returns the Mint freeze authority.
     pub fn freeze_authority(&self) -> &Actor {
-        self.freeze_authority
+        &self.freeze_authority
     }
 
     /// The given Actor mints an amount into the provided token account. Note
     /// that this instruction is always signed by the mint authority, even if
     /// the input actor doesn't have minting authority.
+    #[tracing::instrument(skip_all, fields(mint = %self.mint.pubkey(), amount = amount))]
     pub fn mint_to(&self, actor: &Actor, destination: &TokenAccount, amount: u64) -> Result<()> {
         let instruction = spl_instruction::mint_to(
             &spl_token::id(),
@@ -95,11 +184,21 @@ impl<'a> Mint<'a> {
             amount,
         )?;
 
-        self.sandbox.send_signed_transaction_with_payers(
-            &[instruction],
-            Some(actor.pubkey()),
-            vec![actor.keypair(), self.authority.keypair()],
-        )
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(actor.try_keypair()?)
+            .signer(self.authority.try_keypair()?)
+            .send()
+            .with_context(|| {
+                format!(
+                    "minting {} of {} to {}",
+                    amount,
+                    self.sandbox.label(self.mint.pubkey()),
+                    self.sandbox.label(destination.account().pubkey())
+                )
+            })?;
+        Ok(())
     }
 }
 
@@ -120,6 +219,46 @@ impl<'a> TokenAccount<'a> {
         mint: &'a Mint,
         owner: Option<&'b Pubkey>,
     ) -> Result<TokenAccount<'a>> {
+        Self::new_for_mint(sandbox, actor, mint.actor().pubkey(), owner)
+    }
+
+    /// Creates and initializes an spl_token account for a mint given by
+    /// public key, such as a mint created by another program.
+    #[tracing::instrument(skip_all, fields(mint = %mint))]
+    pub fn new_for_mint<'b>(
+        sandbox: &'a Sandbox,
+        actor: &Actor,
+        mint: &Pubkey,
+        owner: Option<&'b Pubkey>,
+    ) -> Result<TokenAccount<'a>> {
+        let (token_account, instructions) = Self::prepare(sandbox, actor, mint, owner)?;
+
+        sandbox
+            .transaction()
+            .instructions(&instructions)
+            .payer(actor.try_keypair()?)
+            .signer(token_account.account.try_keypair()?)
+            .send()
+            .with_context(|| {
+                format!(
+                    "creating token account {} for mint {}",
+                    sandbox.label(token_account.account.pubkey()),
+                    sandbox.label(mint)
+                )
+            })?;
+
+        Ok(token_account)
+    }
+
+    /// Returns a token account that does not exist yet, along with the
+    /// instructions creating and initializing it. The instructions must be
+    /// signed by the actor and by the returned account.
+    pub(crate) fn prepare<'b>(
+        sandbox: &'a Sandbox,
+        actor: &Actor,
+        mint: &Pubkey,
+        owner: Option<&'b Pubkey>,
+    ) -> Result<(TokenAccount<'a>, Vec<Instruction>)> {
         let account = Actor::new(sandbox)?;
 
         let owner = match owner {
@@ -129,20 +268,136 @@ impl<'a> TokenAccount<'a> {
 
         let create_account =
             actor.create_account(account.pubkey(), spl_state::Account::LEN, &spl_token::id())?;
+        let initialize_account =
+            spl_instruction::initialize_account(&spl_token::id(), account.pubkey(), mint, owner)?;
+
+        Ok((
+            TokenAccount { sandbox, account },
+            vec![create_account, initialize_account],
+        ))
+    }
+
+    /// Creates the wallet's associated token account for a mint, funded by
+    /// the payer. An account that already exists is attached as is.
+    pub fn new_associated(
+        sandbox: &'a Sandbox,
+        payer: &Actor,
+        mint: &Pubkey,
+        wallet: &Pubkey,
+    ) -> Result<TokenAccount<'a>> {
+        let (token_account, instruction) = Self::prepare_associated(sandbox, payer, mint, wallet)?;
+        if let Some(instruction) = instruction {
+            sandbox
+                .transaction()
+                .instruction(instruction)
+                .payer(payer.try_keypair()?)
+                .send()
+                .with_context(|| {
+                    format!(
+                        "creating associated token account of {} for mint {}",
+                        sandbox.label(wallet),
+                        sandbox.label(mint)
+                    )
+                })?;
+        }
+        Ok(token_account)
+    }
+
+    /// Returns the wallet's associated token account for a mint, along with
+    /// the instruction creating it unless it already exists. The instruction
+    /// only needs the payer's signature.
+    pub(crate) fn prepare_associated(
+        sandbox: &'a Sandbox,
+        payer: &Actor,
+        mint: &Pubkey,
+        wallet: &Pubkey,
+    ) -> Result<(TokenAccount<'a>, Option<Instruction>)> {
+        let address = associated_token_address(wallet, mint);
+        let token_account = TokenAccount::from_pubkey(sandbox, &address);
+        if sandbox.get_account_if_exists(&address)?.is_some() {
+            return Ok((token_account, None));
+        }
+        let instruction = Instruction {
+            program_id: associated_token_program_id(),
+            accounts: vec![
+                AccountMeta::new(*payer.pubkey(), true),
+                AccountMeta::new(address, false),
+                AccountMeta::new_readonly(*wallet, false),
+                AccountMeta::new_readonly(*mint, false),
+                AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(solana_sdk::sysvar::rent::id(), false),
+            ],
+            data: Vec::new(),
+        };
+        Ok((token_account, Some(instruction)))
+    }
+
+    /// Creates a temporary wrapped SOL account owned by the owner and holding
+    /// the given lamports as tokens, for programs that trade SOL as an SPL
+    /// token. The owner pays for the account. Close it with close to unwrap
+    /// the balance and reclaim the rent.
+    pub fn new_wrapped_sol(
+        sandbox: &'a Sandbox,
+        owner: &Actor,
+        lamports: u64,
+    ) -> Result<TokenAccount<'a>> {
+        let account = Actor::new(sandbox)?;
+        let rent = sandbox
+            .client()
+            .get_minimum_balance_for_rent_exemption(spl_state::Account::LEN)?;
+        let create_account = system_instruction::create_account(
+            owner.pubkey(),
+            account.pubkey(),
+            rent + lamports,
+            spl_state::Account::LEN as u64,
+            &spl_token::id(),
+        );
         let initialize_account = spl_instruction::initialize_account(
             &spl_token::id(),
             account.pubkey(),
-            mint.actor().pubkey(),
-            owner,
+            &spl_token::native_mint::id(),
+            owner.pubkey(),
         )?;
 
-        sandbox.send_signed_transaction_with_payers(
-            &[create_account, initialize_account],
-            Some(actor.pubkey()),
-            vec![actor.keypair(), account.keypair()],
+        sandbox
+            .transaction()
+            .instruction(create_account)
+            .instruction(initialize_account)
+            .payer(owner.try_keypair()?)
+            .signer(account.try_keypair()?)
+            .send()?;
+        Ok(TokenAccount { sandbox, account })
+    }
+
+    /// Closes the token account, signed by its owner, and sends its rent to
+    /// destination. The account must hold no tokens, unless it is a wrapped
+    /// SOL account, whose balance is sent along with the rent. Returns the
+    /// lamports reclaimed.
+    pub fn close(&self, owner: &Actor, destination: &Pubkey) -> Result<u64> {
+        let lamports = self.sandbox.get_account(self.account.pubkey())?.lamports;
+        let instruction = spl_instruction::close_account(
+            &spl_token::id(),
+            self.account.pubkey(),
+            destination,
+            owner.pubkey(),
+            &[],
         )?;
 
-        Ok(TokenAccount { sandbox, account })
+        self.sandbox
+            .transaction()
+            .instruction(instruction)
+            .payer(owner.try_keypair()?)
+            .send()?;
+        Ok(lamports)
+    }
+
+    /// Attaches to a token account that already exists on chain.
+    pub fn from_pubkey(sandbox: &'a Sandbox, pubkey: &Pubkey) -> TokenAccount<'a> {
+        TokenAccount {
+            sandbox,
+            account: Actor::from_pubkey(sandbox, pubkey),
+        }
     }
 
     /// Returns the underlying account.
@@ -152,10 +407,7 @@ impl<'a> TokenAccount<'a> {
 
     /// Returns the account information
     pub fn get_account_info(&self) -> Result<spl_token::state::Account> {
-        let data = self
-            .sandbox
-            .client()
-            .get_account_data(self.account.pubkey())?;
+        let data = self.sandbox.get_account_data(self.account.pubkey())?;
         Ok(spl_token::state::Account::unpack_from_slice(&data)?)
     }
 }
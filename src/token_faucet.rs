@@ -0,0 +1,204 @@
+use crate::actor::Actor;
+use crate::errors::{Context, Error, Result};
+use crate::pda;
+use crate::sandbox::Sandbox;
+use crate::token::Mint;
+use solana_program::program_pack::Pack;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+use spl_token::{instruction::AuthorityType, state as spl_state};
+use std::{io, path::Path};
+
+/// Size of a faucet account: an initialized flag, an optional admin, the
+/// mint, and the most a single request can mint.
+const FAUCET_ACCOUNT_SIZE: usize = 1 + (4 + 32) + 32 + 8;
+
+/// Instruction tags of the token faucet program.
+const INIT_FAUCET: u8 = 0;
+const MINT_TOKENS: u8 = 1;
+
+/// A faucet of the SPL token faucet program, which mints a token to anyone
+/// who asks, up to a fixed amount per request.
+///
+/// Creating a faucet hands the mint authority to the program, so that
+/// clients connected to the Sandbox, such as JS tests or the spl-token CLI,
+/// can mint test tokens themselves by sending the program's MintTokens
+/// instruction. Minting through Mint::mint_to stops working once the
+/// authority is handed over, so use a mint dedicated to the faucet:
+///
+/// ```ignore
+/// let program = TokenFaucet::deploy_local(&payer, Path::new("spl_token_faucet.so"))?;
+/// let usdc = Mint::new(&sandbox, &payer, 6, None, None)?;
+/// let faucet = TokenFaucet::new(&sandbox, &payer, program.pubkey(), &usdc, 1_000_000_000)?;
+/// // Hand faucet.faucet() and faucet.program() to the external client.
+/// ```
+pub struct TokenFaucet<'a> {
+    sandbox: &'a Sandbox,
+    program: Pubkey,
+    faucet: Actor<'a>,
+    mint: Pubkey,
+    max_amount: u64,
+}
+
+impl<'a> TokenFaucet<'a> {
+    /// Deploys the token faucet program from a local file. Returns the
+    /// program's Actor, as Actor::deploy_local does.
+    pub fn deploy_local(payer: &Actor<'a>, program_location: &Path) -> Result<Actor<'a>> {
+        payer.deploy_local(program_location)
+    }
+
+    /// Downloads and deploys the token faucet program.
+    pub fn deploy_remote(
+        payer: &Actor<'a>,
+        remote_location: &str,
+        file_name: &str,
+    ) -> Result<Actor<'a>> {
+        payer.deploy_remote(remote_location, file_name)
+    }
+
+    /// Creates a faucet for the mint that mints up to max_amount per request,
+    /// handing the mint authority to the faucet program. The payer funds the
+    /// faucet account, and the mint's authority signs the handover.
+    #[tracing::instrument(skip_all, fields(mint = %mint.actor().pubkey()))]
+    pub fn new(
+        sandbox: &'a Sandbox,
+        payer: &Actor,
+        program: &Pubkey,
+        mint: &Mint,
+        max_amount: u64,
+    ) -> Result<Self> {
+        let faucet = Actor::new(sandbox)?;
+        let (mint_authority, _) = pda::token_faucet_pda(program);
+
+        let create_account = payer.create_account(faucet.pubkey(), FAUCET_ACCOUNT_SIZE, program)?;
+        let set_authority = spl_token::instruction::set_authority(
+            &spl_token::id(),
+            mint.actor().pubkey(),
+            Some(&mint_authority),
+            AuthorityType::MintTokens,
+            mint.authority().pubkey(),
+            &[],
+        )?;
+        let init_faucet = Instruction {
+            program_id: *program,
+            accounts: vec![
+                AccountMeta::new(*mint.actor().pubkey(), false),
+                AccountMeta::new(*faucet.pubkey(), false),
+                AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            ],
+            data: instruction_data(INIT_FAUCET, max_amount),
+        };
+
+        sandbox
+            .transaction()
+            .instruction(create_account)
+            .instruction(set_authority)
+            .instruction(init_faucet)
+            .payer(payer.try_keypair()?)
+            .signer(faucet.try_keypair()?)
+            .signer(mint.authority().try_keypair()?)
+            .send()
+            .with_context(|| {
+                format!(
+                    "creating token faucet for {}",
+                    sandbox.label(mint.actor().pubkey())
+                )
+            })?;
+
+        Ok(TokenFaucet {
+            sandbox,
+            program: *program,
+            faucet,
+            mint: *mint.actor().pubkey(),
+            max_amount,
+        })
+    }
+
+    /// Returns the MintTokens instruction minting amount into destination, a
+    /// token account of the faucet's mint. External clients build the same
+    /// instruction.
+    pub fn mint_instruction(&self, destination: &Pubkey, amount: u64) -> Instruction {
+        Instruction {
+            program_id: self.program,
+            accounts: vec![
+                AccountMeta::new_readonly(self.mint_authority(), false),
+                AccountMeta::new(self.mint, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(*self.faucet.pubkey(), false),
+            ],
+            data: instruction_data(MINT_TOKENS, amount),
+        }
+    }
+
+    /// Mints amount into destination through the faucet, as an external
+    /// client would, without the mint authority. The payer only pays the
+    /// transaction fee.
+    #[tracing::instrument(skip_all, fields(faucet = %self.faucet.pubkey(), amount = amount))]
+    pub fn mint_to(&self, payer: &Actor, destination: &Pubkey, amount: u64) -> Result<()> {
+        if amount > self.max_amount {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "faucet mints at most {} per request, {} requested",
+                    self.max_amount, amount
+                ),
+            )));
+        }
+        self.sandbox
+            .transaction()
+            .instruction(self.mint_instruction(destination, amount))
+            .payer(payer.try_keypair()?)
+            .send()
+            .with_context(|| {
+                format!(
+                    "minting {} of {} to {} through faucet",
+                    amount,
+                    self.sandbox.label(&self.mint),
+                    self.sandbox.label(destination)
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Returns the faucet program's address.
+    pub fn program(&self) -> &Pubkey {
+        &self.program
+    }
+
+    /// Returns the faucet account's address.
+    pub fn faucet(&self) -> &Pubkey {
+        self.faucet.pubkey()
+    }
+
+    /// Returns the address of the faucet's mint.
+    pub fn mint(&self) -> &Pubkey {
+        &self.mint
+    }
+
+    /// Returns the most a single request can mint.
+    pub fn max_amount(&self) -> u64 {
+        self.max_amount
+    }
+
+    /// Returns the program derived mint authority the faucet mints with.
+    pub fn mint_authority(&self) -> Pubkey {
+        pda::token_faucet_pda(&self.program).0
+    }
+
+    /// Fetches the faucet mint's supply.
+    pub fn supply(&self) -> Result<u64> {
+        let data = self.sandbox.get_account_data(&self.mint)?;
+        Ok(spl_state::Mint::unpack(&data)?.supply)
+    }
+}
+
+/// Encodes a token faucet instruction: its tag followed by an amount.
+fn instruction_data(tag: u8, amount: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(9);
+    data.push(tag);
+    data.extend_from_slice(&amount.to_le_bytes());
+    data
+}
@@ -0,0 +1,160 @@
+use crate::decoder::DecodedInstruction;
+use crate::sandbox::Sandbox;
+use serde::{Deserialize, Serialize};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_request::{RpcError, RpcResponseErrorData},
+};
+use solana_sdk::{signature::Signature, transaction::Transaction};
+
+/// An instruction as recorded in a trace.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TraceInstruction {
+    /// Name or address of the invoked program.
+    pub program: String,
+
+    /// Names or addresses of the instruction accounts, in order.
+    pub accounts: Vec<String>,
+
+    /// Base64 encoded instruction data.
+    pub data: String,
+
+    /// The instruction as described by the decoder registered for its
+    /// program, if any.
+    pub decoded: Option<DecodedInstruction>,
+}
+
+/// A transaction send attempt as recorded in a trace.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Base58 signature of the transaction.
+    pub signature: String,
+
+    /// Names or addresses of the signers, fee payer first.
+    pub signers: Vec<String>,
+
+    /// Instructions in execution order.
+    pub instructions: Vec<TraceInstruction>,
+
+    /// Whether the transaction was confirmed without error.
+    pub success: bool,
+
+    /// The error returned by the send, if any.
+    pub error: Option<String>,
+
+    /// Compute units consumed by top-level instructions, if reported in the
+    /// program logs.
+    pub compute_units: Option<u64>,
+
+    /// Program log messages.
+    pub logs: Vec<String>,
+}
+
+impl TraceEntry {
+    /// Builds an entry for a send attempt. Logs of confirmed transactions are
+    /// fetched from the validator; logs of transactions rejected during
+    /// preflight are taken from the simulation result carried by the error.
+    /// Addresses are rendered with the names registered in the Sandbox.
+    pub(crate) fn new(
+        sandbox: &Sandbox,
+        transaction: &Transaction,
+        result: &std::result::Result<Signature, ClientError>,
+    ) -> Self {
+        let message = &transaction.message;
+        let keys = &message.account_keys;
+        let signers = keys[..message.header.num_required_signatures as usize]
+            .iter()
+            .map(|pubkey| sandbox.label(pubkey))
+            .collect();
+        let instructions = message
+            .instructions
+            .iter()
+            .map(|instruction| {
+                let program_id = &keys[instruction.program_id_index as usize];
+                TraceInstruction {
+                    program: sandbox.label(program_id),
+                    accounts: instruction
+                        .accounts
+                        .iter()
+                        .map(|index| sandbox.label(&keys[*index as usize]))
+                        .collect(),
+                    data: base64::encode(&instruction.data),
+                    decoded: sandbox.decode_instruction(program_id, &instruction.data),
+                }
+            })
+            .collect();
+
+        let (error, logs) = match result {
+            Ok(signature) => (None, sandbox.program_logs(signature).unwrap_or_default()),
+            Err(err) => (
+                Some(sandbox.render(&err.to_string())),
+                preflight_logs(err)
+                    .iter()
+                    .map(|line| sandbox.render(line))
+                    .collect(),
+            ),
+        };
+
+        TraceEntry {
+            signature: transaction.signatures[0].to_string(),
+            signers,
+            instructions,
+            success: result.is_ok(),
+            error,
+            compute_units: compute_units(&logs),
+            logs,
+        }
+    }
+}
+
+/// Returns the simulation logs attached to a preflight failure.
+fn preflight_logs(err: &ClientError) -> Vec<String> {
+    match err.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(simulation),
+            ..
+        }) => simulation.logs.clone().unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+/// Sums the compute units reported for top-level instructions. Inner
+/// instructions are already included in their caller's consumption.
+fn compute_units(logs: &[String]) -> Option<u64> {
+    instruction_compute_units(logs)
+        .into_iter()
+        .flatten()
+        .fold(None, |total, units| Some(total.unwrap_or(0) + units))
+}
+
+/// Returns the compute units reported for each top-level instruction, in
+/// order, including the instructions it invokes. Instructions that do not
+/// report units, such as those of native programs, are None.
+pub(crate) fn instruction_compute_units(logs: &[String]) -> Vec<Option<u64>> {
+    let mut depth: i64 = 0;
+    let mut units = Vec::new();
+    for line in logs.iter() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["Program", _, "invoke", level] => {
+                depth = level
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .parse()
+                    .unwrap_or(depth + 1);
+                if depth == 1 {
+                    units.push(None);
+                }
+            }
+            ["Program", _, "success"] | ["Program", _, "failed:", ..] => {
+                depth -= 1;
+            }
+            ["Program", _, "consumed", consumed, "of", ..] if depth == 1 => {
+                if let (Some(last), Ok(consumed)) = (units.last_mut(), consumed.parse::<u64>()) {
+                    *last = Some(last.unwrap_or(0) + consumed);
+                }
+            }
+            _ => {}
+        }
+    }
+    units
+}
@@ -0,0 +1,175 @@
+use crate::actor::Actor;
+use crate::errors::{Context, Error, Result};
+use crate::sandbox::Sandbox;
+use crate::serum::{Market, Participant};
+use crate::token::{self, Mint, TokenAccount};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use std::io;
+
+/// One identity trading on many Serum markets, such as for modeling
+/// triangular arbitrage with a single wallet.
+///
+/// The trader keeps its tokens in its account's associated token accounts,
+/// one per mint, so markets sharing a mint share a wallet: quote bought on
+/// one market can be spent on the next without transfers. Each market the
+/// trader joins gets its own open orders account, held by a Participant
+/// whose account is the trader's:
+///
+/// ```ignore
+/// let mut trader = Trader::new(&sandbox, LAMPORTS_PER_SOL)?;
+/// trader.fund(&payer, &usdc, 1_000_000)?;
+/// trader.join(&payer, &sol_usdc)?;
+/// trader.join(&payer, &btc_usdc)?;
+/// trader.join(&payer, &sol_btc)?;
+/// let leg = trader.participant(&sol_usdc).unwrap();
+/// ```
+///
+/// Participants of a trader are not funded individually, so compare
+/// Trader::balance before and after trading rather than using
+/// Market::assert_settled.
+pub struct Trader<'a> {
+    account: Actor<'a>,
+    participants: Vec<Participant<'a>>,
+}
+
+impl<'a> Trader<'a> {
+    /// Creates a trader with a new account airdropped the given lamports for
+    /// transaction fees.
+    pub fn new(sandbox: &'a Sandbox, lamports: u64) -> Result<Self> {
+        let account = Actor::new(sandbox)?;
+        if lamports > 0 {
+            account.airdrop(lamports)?;
+        }
+        Ok(Trader::from_actor(account))
+    }
+
+    /// Creates a trader for an existing actor, which must hold a keypair.
+    pub fn from_actor(account: Actor<'a>) -> Self {
+        Trader {
+            account,
+            participants: Vec::new(),
+        }
+    }
+
+    /// Returns the trader's account, which owns its wallets and open orders.
+    pub fn account(&self) -> &Actor<'a> {
+        &self.account
+    }
+
+    /// Creates the trader's open orders account for a market, and its wallets
+    /// for the market's mints if they do not exist yet, paid for by the
+    /// payer. Fails if the trader already trades on the market.
+    pub fn join(
+        &mut self,
+        payer: &'a Actor<'a>,
+        market: &'a Market<'a>,
+    ) -> Result<&Participant<'a>> {
+        let sandbox = self.account.sandbox();
+        if self.participant(market).is_some() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "trader {} already trades on market {}",
+                    sandbox.label(self.account.pubkey()),
+                    sandbox.label(market.market().pubkey())
+                ),
+            )));
+        }
+        let participant = Participant::builder(payer, market)
+            .account(self.identity()?)
+            .associated_token_accounts()
+            .build()
+            .with_context(|| {
+                format!(
+                    "joining market {} as trader {}",
+                    sandbox.label(market.market().pubkey()),
+                    sandbox.label(self.account.pubkey())
+                )
+            })?;
+        self.participants.push(participant);
+        Ok(self
+            .participants
+            .last()
+            .expect("participant was just added"))
+    }
+
+    /// Mints an amount into the trader's wallet for the mint, creating the
+    /// wallet if needed, paid for by the payer.
+    pub fn fund(&self, payer: &Actor, mint: &Mint, amount: u64) -> Result<()> {
+        let wallet = TokenAccount::new_associated(
+            self.account.sandbox(),
+            payer,
+            mint.actor().pubkey(),
+            self.account.pubkey(),
+        )?;
+        if amount > 0 {
+            mint.mint_to(payer, &wallet, amount)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the address of the trader's wallet for a mint, which may not
+    /// exist yet.
+    pub fn wallet(&self, mint: &Pubkey) -> Pubkey {
+        token::associated_token_address(self.account.pubkey(), mint)
+    }
+
+    /// Returns the amount of a mint held in the trader's wallet, or zero if
+    /// the wallet does not exist. Funds in open orders are not counted.
+    pub fn balance(&self, mint: &Pubkey) -> Result<u64> {
+        let sandbox = self.account.sandbox();
+        let wallet = self.wallet(mint);
+        if sandbox.get_account_if_exists(&wallet)?.is_none() {
+            return Ok(0);
+        }
+        Ok(TokenAccount::from_pubkey(sandbox, &wallet)
+            .get_account_info()?
+            .amount)
+    }
+
+    /// Returns the trader's participant on a market, if it has joined it.
+    pub fn participant(&self, market: &Market) -> Option<&Participant<'a>> {
+        self.participants
+            .iter()
+            .find(|participant| participant.market().market().pubkey() == market.market().pubkey())
+    }
+
+    /// Returns the trader's participants, in the order the markets were
+    /// joined.
+    pub fn participants(&self) -> &[Participant<'a>] {
+        &self.participants
+    }
+
+    /// Settles the trader's free funds on every market it has joined, paid
+    /// for by the payer. Markets where the open orders account is not yet
+    /// initialized are skipped.
+    pub fn settle_all(&self, payer: &Actor) -> Result<()> {
+        for participant in self.participants.iter() {
+            if participant.open_orders_view()?.account_flags == 0 {
+                continue;
+            }
+            let market = participant.market();
+            market.settle_funds(payer, participant).with_context(|| {
+                format!(
+                    "settling trader on market {}",
+                    self.account.sandbox().label(market.market().pubkey())
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns another Actor with the trader's keypair, since each
+    /// Participant holds its own account.
+    fn identity(&self) -> Result<Actor<'a>> {
+        if !self.account.can_sign() || self.account.is_remote() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "trader needs an actor with a keypair",
+            )));
+        }
+        let keypair = Keypair::from_bytes(&self.account.keypair().to_bytes())
+            .map_err(|err| Error::from(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        Actor::from_keypair(self.account.sandbox(), keypair)
+    }
+}
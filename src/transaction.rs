@@ -0,0 +1,432 @@
+use crate::errors::{Error, Result};
+use crate::retry::{self, RetryPolicy};
+use crate::sandbox::Sandbox;
+use solana_client::{client_error::ClientError, rpc_response::RpcSimulateTransactionResult};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    message::Message,
+    nonce,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use std::{io, thread};
+use tracing::field::display;
+
+/// Accumulates instructions and signers for a single transaction.
+///
+/// A TransactionBuilder is obtained from Sandbox::transaction. Instructions
+/// and signers are added fluently, and the transaction is finished by either
+/// sending it, simulating it, or partially signing it so that another actor
+/// can complete it.
+///
+/// ```ignore
+/// sandbox
+///     .transaction()
+///     .instruction(create_account)
+///     .instruction(initialize_mint)
+///     .payer(actor.keypair())
+///     .signer(mint.keypair())
+///     .send()?;
+/// ```
+pub struct TransactionBuilder<'a> {
+    sandbox: &'a Sandbox,
+    instructions: Vec<Instruction>,
+    payer: Option<Pubkey>,
+    signers: Vec<&'a dyn Signer>,
+    nonce: Option<(Pubkey, Pubkey)>,
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl<'a> TransactionBuilder<'a> {
+    /// Creates an empty builder for the given Sandbox.
+    pub fn new(sandbox: &'a Sandbox) -> Self {
+        TransactionBuilder {
+            sandbox,
+            instructions: Vec::new(),
+            payer: None,
+            signers: Vec::new(),
+            nonce: None,
+            retry_policy: None,
+        }
+    }
+
+    /// Appends an instruction.
+    pub fn instruction(mut self, instruction: Instruction) -> Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    /// Appends several instructions, preserving their order.
+    pub fn instructions(mut self, instructions: &[Instruction]) -> Self {
+        self.instructions.extend_from_slice(instructions);
+        self
+    }
+
+    /// Sets the fee payer. The payer is also added as a signer. Any Signer
+    /// is accepted, such as a keypair or an Actor's RemoteSigner.
+    pub fn payer(mut self, payer: &'a dyn Signer) -> Self {
+        self.payer = Some(payer.pubkey());
+        self.signer(payer)
+    }
+
+    /// Adds a signer. Signers that were already added are ignored.
+    pub fn signer(mut self, signer: &'a dyn Signer) -> Self {
+        if !self
+            .signers
+            .iter()
+            .any(|existing| existing.pubkey() == signer.pubkey())
+        {
+            self.signers.push(signer);
+        }
+        self
+    }
+
+    /// Adds several signers.
+    pub fn signers<S: Signer>(mut self, signers: &[&'a S]) -> Self {
+        for signer in signers.iter() {
+            self = self.signer(*signer);
+        }
+        self
+    }
+
+    /// Uses the blockhash stored in the given durable nonce account instead
+    /// of a recent blockhash. An instruction advancing the nonce is prepended
+    /// to the transaction, and the nonce authority is added as a signer.
+    pub fn nonce(mut self, nonce_account: &Pubkey, authority: &'a dyn Signer) -> Self {
+        self.nonce = Some((*nonce_account, authority.pubkey()));
+        self.signer(authority)
+    }
+
+    /// Overrides the Sandbox's retry policy for this transaction.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Returns the instructions accumulated so far.
+    pub fn get_instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Builds and fully signs the transaction. Fails if a required signer is
+    /// missing.
+    pub fn sign(&self) -> Result<Transaction> {
+        let (message, blockhash) = self.message()?;
+        let mut transaction = Transaction::new_unsigned(message);
+        transaction
+            .try_sign(&self.signers, blockhash)
+            .map_err(ClientError::from)?;
+        Ok(transaction)
+    }
+
+    /// Builds the transaction and signs it with the signers available to this
+    /// builder. The returned transaction can be completed and sent with
+    /// Sandbox::send_partially_signed_transaction.
+    pub fn partial_sign(&self) -> Result<Transaction> {
+        let (message, blockhash) = self.message()?;
+        let mut transaction = Transaction::new_unsigned(message);
+        transaction
+            .try_partial_sign(&self.signers, blockhash)
+            .map_err(ClientError::from)?;
+        Ok(transaction)
+    }
+
+    /// Signs and sends the transaction. Blocks until it is confirmed.
+    /// Transient failures are retried according to the retry policy. Since
+    /// a send that failed to confirm may still land, the cluster is asked
+    /// about the signature before every retry: a transaction it processed is
+    /// waited on rather than sent again, and the transaction is only signed
+    /// again once its blockhash has expired unseen.
+    #[tracing::instrument(
+        name = "transaction",
+        skip_all,
+        fields(instructions = self.instructions.len(), signature)
+    )]
+    pub fn send(self) -> Result<Signature> {
+        let policy = match &self.retry_policy {
+            Some(policy) => policy.clone(),
+            None => self.sandbox.retry_policy().clone(),
+        };
+
+        let mut transaction = self.sign()?;
+        let mut retry = 0;
+        loop {
+            let result = self.sandbox.throttle().and_then(|_| {
+                self.sandbox.send_with_faults(|| {
+                    self.sandbox
+                        .client()
+                        .send_and_confirm_transaction(&transaction)
+                })
+            });
+            self.sandbox
+                .account_cache()
+                .invalidate_writable(&transaction.message);
+            self.sandbox.trace_transaction(&transaction, &result);
+            if result.is_err() {
+                // A dead validator would otherwise be retried until the
+                // policy gives up, hiding the cause.
+                self.sandbox.check_alive()?;
+            }
+            let result = match result {
+                Err(err)
+                    if err.get_transaction_error() == Some(TransactionError::AlreadyProcessed)
+                        || (retry < policy.max_retries && retry::is_transient(&err)) =>
+                {
+                    match self.landed(&transaction)? {
+                        Some(signature) => Ok(signature),
+                        None => Err(err),
+                    }
+                }
+                result => result,
+            };
+            match result {
+                Ok(signature) => {
+                    tracing::Span::current().record("signature", &display(&signature));
+                    tracing::debug!("transaction confirmed");
+                    return Ok(signature);
+                }
+                Err(err) if retry < policy.max_retries && retry::is_transient(&err) => {
+                    tracing::warn!(retry, error = ?err, "retrying transaction");
+                    thread::sleep(policy.backoff(retry));
+                    if policy.resign_on_expiry && self.expired(&transaction)? {
+                        transaction = self.sign()?;
+                    }
+                    retry += 1;
+                }
+                Err(err) => {
+                    tracing::debug!(error = ?err, "transaction failed");
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+
+    /// Waits for an earlier send of the transaction to be confirmed if the
+    /// cluster has processed it. Returns None if the cluster has not seen the
+    /// transaction, and the transaction's error if it failed.
+    fn landed(&self, transaction: &Transaction) -> Result<Option<Signature>> {
+        let client = self.sandbox.client();
+        let signature = transaction.signatures[0];
+        let status = self.sandbox.run_rpc(|| {
+            client.get_signature_status_with_commitment(&signature, CommitmentConfig::processed())
+        })?;
+        match status {
+            None => Ok(None),
+            Some(Ok(())) => {
+                self.sandbox.run_rpc(|| {
+                    client.poll_for_signature_with_commitment(&signature, client.commitment())
+                })?;
+                Ok(Some(signature))
+            }
+            Some(Err(err)) => Err(ClientError::from(err).into()),
+        }
+    }
+
+    /// Returns true once the transaction's blockhash has expired, after which
+    /// a transaction the cluster has not seen can no longer land.
+    fn expired(&self, transaction: &Transaction) -> Result<bool> {
+        let client = self.sandbox.client();
+        let blockhash = transaction.message.recent_blockhash;
+        let valid = self
+            .sandbox
+            .run_rpc(|| client.is_blockhash_valid(&blockhash, client.commitment()))?;
+        Ok(!valid)
+    }
+
+    /// Splits the instructions into as few transactions as the packet size
+    /// allows and sends them one after another, waiting for each to be
+    /// confirmed before sending the next, so later instructions can depend on
+    /// earlier ones. Each transaction is signed only by the signers its
+    /// instructions require. Returns a signature per transaction sent.
+    ///
+    /// A failed transaction stops the sequence, leaving the transactions
+    /// before it applied.
+    #[tracing::instrument(
+        name = "split_transaction",
+        skip_all,
+        fields(instructions = self.instructions.len())
+    )]
+    pub fn send_split(self) -> Result<Vec<Signature>> {
+        let payer = self.payer.unwrap_or_default();
+        let prefix = match self.nonce {
+            Some((nonce_account, authority)) => vec![system_instruction::advance_nonce_account(
+                &nonce_account,
+                &authority,
+            )],
+            None => Vec::new(),
+        };
+        let chunks = split_instructions(&payer, &prefix, &self.instructions)?;
+        tracing::debug!(transactions = chunks.len(), "sending split transaction");
+
+        let mut signatures = Vec::with_capacity(chunks.len());
+        for instructions in chunks.into_iter() {
+            let mut message_instructions = prefix.clone();
+            message_instructions.extend_from_slice(&instructions);
+            let message = Message::new(&message_instructions, self.payer.as_ref());
+            let required = &message.account_keys[..message.header.num_required_signatures as usize];
+            let signers = self
+                .signers
+                .iter()
+                .filter(|signer| required.contains(&signer.pubkey()))
+                .copied()
+                .collect();
+            let builder = TransactionBuilder {
+                sandbox: self.sandbox,
+                instructions,
+                payer: self.payer,
+                signers,
+                nonce: self.nonce,
+                retry_policy: self.retry_policy.clone(),
+            };
+            signatures.push(builder.send()?);
+        }
+        Ok(signatures)
+    }
+
+    /// Sends the instructions as exactly one transaction, so they all apply
+    /// or none do. Signers that no instruction requires are dropped, since
+    /// instructions gathered from several helpers often bring along signers
+    /// of their own. Fails without sending if there are no instructions, if
+    /// they do not fit in one transaction, or if a required signer is
+    /// missing.
+    pub fn send_atomic(mut self) -> Result<Signature> {
+        if self.instructions.is_empty() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an atomic transaction needs at least one instruction",
+            )));
+        }
+        let (message, _) = self.message()?;
+        let size = bincode::serialized_size(&Transaction::new_unsigned(message.clone()))
+            .unwrap_or(u64::MAX) as usize;
+        if size > PACKET_DATA_SIZE {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} instructions take {} bytes, more than the {} bytes of one transaction",
+                    self.instructions.len(),
+                    size,
+                    PACKET_DATA_SIZE
+                ),
+            )));
+        }
+
+        let required = &message.account_keys[..message.header.num_required_signatures as usize];
+        let missing: Vec<String> = required
+            .iter()
+            .filter(|pubkey| {
+                !self
+                    .signers
+                    .iter()
+                    .any(|signer| signer.pubkey() == **pubkey)
+            })
+            .map(|pubkey| self.sandbox.label(pubkey))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("missing signers: {}", missing.join(", ")),
+            )));
+        }
+        self.signers
+            .retain(|signer| required.contains(&signer.pubkey()));
+        self.send()
+    }
+
+    /// Signs and simulates the transaction without submitting it.
+    pub fn simulate(self) -> Result<RpcSimulateTransactionResult> {
+        let transaction = self.sign()?;
+        Ok(self
+            .sandbox
+            .client()
+            .simulate_transaction(&transaction)?
+            .value)
+    }
+
+    /// Compiles the message and returns it with the blockhash to sign with.
+    fn message(&self) -> Result<(Message, Hash)> {
+        match self.nonce {
+            Some((nonce_account, authority)) => {
+                let mut instructions = vec![system_instruction::advance_nonce_account(
+                    &nonce_account,
+                    &authority,
+                )];
+                instructions.extend_from_slice(&self.instructions);
+                let blockhash = self.sandbox.nonce_blockhash(&nonce_account)?;
+                Ok((Message::new(&instructions, self.payer.as_ref()), blockhash))
+            }
+            None => {
+                let blockhash = self.sandbox.client().get_latest_blockhash()?;
+                Ok((
+                    Message::new(&self.instructions, self.payer.as_ref()),
+                    blockhash,
+                ))
+            }
+        }
+    }
+}
+
+/// Returns the serialized size in bytes of a transaction paid for by payer
+/// and holding the given instructions, including a signature for each
+/// required signer. Transactions larger than PACKET_DATA_SIZE are rejected.
+pub fn estimate_size(payer: &Pubkey, instructions: &[Instruction]) -> usize {
+    let message = Message::new(instructions, Some(payer));
+    bincode::serialized_size(&Transaction::new_unsigned(message)).unwrap_or(u64::MAX) as usize
+}
+
+/// Returns true if a transaction paid for by payer and holding the given
+/// instructions fits in a packet.
+pub fn fits(payer: &Pubkey, instructions: &[Instruction]) -> bool {
+    estimate_size(payer, instructions) <= PACKET_DATA_SIZE
+}
+
+/// Splits instructions into consecutive chunks that each fit in a packet
+/// when prefixed by the given instructions, such as a nonce advance. Chunks
+/// are in the original order and hold as many instructions as fit. Fails if
+/// a single instruction does not fit on its own.
+pub fn split_instructions(
+    payer: &Pubkey,
+    prefix: &[Instruction],
+    instructions: &[Instruction],
+) -> Result<Vec<Vec<Instruction>>> {
+    let mut chunks = Vec::new();
+    let mut candidate = prefix.to_vec();
+    for (i, instruction) in instructions.iter().enumerate() {
+        let mut alone = prefix.to_vec();
+        alone.push(instruction.clone());
+        if !fits(payer, &alone) {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("instruction {} does not fit in a single transaction", i),
+            )));
+        }
+        candidate.push(instruction.clone());
+        if !fits(payer, &candidate) {
+            candidate.pop();
+            chunks.push(candidate.split_off(prefix.len()));
+            candidate.push(instruction.clone());
+        }
+    }
+    if candidate.len() > prefix.len() {
+        chunks.push(candidate.split_off(prefix.len()));
+    }
+    Ok(chunks)
+}
+
+/// Returns the blockhash stored in durable nonce account data.
+pub(crate) fn parse_nonce_blockhash(data: &[u8]) -> Result<Hash> {
+    let versions: nonce::state::Versions = bincode::deserialize(data)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    match versions.convert_to_current() {
+        nonce::state::State::Initialized(data) => Ok(data.blockhash),
+        nonce::state::State::Uninitialized => Err(Error::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "nonce account is not initialized",
+        ))),
+    }
+}
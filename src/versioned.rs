@@ -0,0 +1,303 @@
+use crate::actor::Actor;
+use crate::errors::{Error, Result};
+use crate::sandbox::Sandbox;
+use solana_address_lookup_table_program::instruction as lookup_instruction;
+use solana_sdk::{
+    hash::Hash,
+    instruction::{CompiledInstruction, Instruction},
+    message::{
+        v0::{self, MessageAddressTableLookup},
+        Message, MessageHeader, VersionedMessage,
+    },
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, VersionedTransaction},
+};
+use std::{collections::HashMap, io, thread, time};
+
+/// Maximum number of addresses appended by a single extend instruction. This
+/// keeps each extension transaction under the packet size limit.
+const MAX_ADDRESSES_PER_EXTEND: usize = 20;
+
+/// Represents an address lookup table created in a Sandbox.
+///
+/// Lookup tables let v0 transactions reference accounts by a one byte index
+/// instead of a full public key, so instructions whose account lists exceed
+/// legacy transaction limits can still be sent.
+pub struct AddressLookupTable<'a> {
+    sandbox: &'a Sandbox,
+    authority: &'a Actor<'a>,
+    address: Pubkey,
+    addresses: Vec<Pubkey>,
+}
+
+impl<'a> AddressLookupTable<'a> {
+    /// Creates an empty lookup table. The authority pays for the table and is
+    /// the only actor allowed to extend it.
+    pub fn new(sandbox: &'a Sandbox, authority: &'a Actor<'a>) -> Result<Self> {
+        let recent_slot = sandbox.client().get_slot()?;
+        let (create_table, address) = lookup_instruction::create_lookup_table(
+            *authority.pubkey(),
+            *authority.pubkey(),
+            recent_slot,
+        );
+
+        sandbox
+            .transaction()
+            .instruction(create_table)
+            .payer(authority.try_keypair()?)
+            .send()?;
+
+        Ok(AddressLookupTable {
+            sandbox,
+            authority,
+            address,
+            addresses: Vec::new(),
+        })
+    }
+
+    /// Appends addresses to the table. Newly added addresses only become
+    /// usable in the slot after they were added, so this blocks until the
+    /// validator has advanced past that slot.
+    pub fn extend(&mut self, addresses: &[Pubkey]) -> Result<()> {
+        for chunk in addresses.chunks(MAX_ADDRESSES_PER_EXTEND) {
+            let extend_table = lookup_instruction::extend_lookup_table(
+                self.address,
+                *self.authority.pubkey(),
+                Some(*self.authority.pubkey()),
+                chunk.to_vec(),
+            );
+            self.sandbox
+                .transaction()
+                .instruction(extend_table)
+                .payer(self.authority.try_keypair()?)
+                .send()?;
+            self.addresses.extend_from_slice(chunk);
+        }
+
+        let extended_slot = self.sandbox.client().get_slot()?;
+        while self.sandbox.client().get_slot()? <= extended_slot {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    /// Returns the address of the lookup table account.
+    pub fn address(&self) -> &Pubkey {
+        &self.address
+    }
+
+    /// Returns the addresses stored in the table, in index order.
+    pub fn addresses(&self) -> &[Pubkey] {
+        &self.addresses
+    }
+}
+
+/// Compiles instructions into a v0 message. Accounts that are neither signers
+/// nor invoked programs are loaded from the first lookup table containing
+/// them; all other accounts are stored statically in the message.
+pub fn compile_v0_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[&AddressLookupTable],
+    recent_blockhash: Hash,
+) -> Result<v0::Message> {
+    // Merge account metas in order of first appearance, with the payer first.
+    let mut keys: Vec<(Pubkey, bool, bool, bool)> = vec![(*payer, true, true, false)];
+    let mut merge =
+        |pubkey: &Pubkey, is_signer: bool, is_writable: bool, is_invoked: bool| match keys
+            .iter_mut()
+            .find(|(key, _, _, _)| key == pubkey)
+        {
+            Some(entry) => {
+                entry.1 |= is_signer;
+                entry.2 |= is_writable;
+                entry.3 |= is_invoked;
+            }
+            None => keys.push((*pubkey, is_signer, is_writable, is_invoked)),
+        };
+    for instruction in instructions.iter() {
+        merge(&instruction.program_id, false, false, true);
+        for meta in instruction.accounts.iter() {
+            merge(&meta.pubkey, meta.is_signer, meta.is_writable, false);
+        }
+    }
+
+    let mut writable_signers = Vec::new();
+    let mut readonly_signers = Vec::new();
+    let mut writable_static = Vec::new();
+    let mut readonly_static = Vec::new();
+    let mut lookups: Vec<MessageAddressTableLookup> = lookup_tables
+        .iter()
+        .map(|table| MessageAddressTableLookup {
+            account_key: *table.address(),
+            writable_indexes: Vec::new(),
+            readonly_indexes: Vec::new(),
+        })
+        .collect();
+    let mut writable_loaded: Vec<Vec<Pubkey>> = vec![Vec::new(); lookup_tables.len()];
+    let mut readonly_loaded: Vec<Vec<Pubkey>> = vec![Vec::new(); lookup_tables.len()];
+
+    for (pubkey, is_signer, is_writable, is_invoked) in keys.iter() {
+        if *is_signer {
+            if *is_writable {
+                writable_signers.push(*pubkey);
+            } else {
+                readonly_signers.push(*pubkey);
+            }
+            continue;
+        }
+
+        let found = if *is_invoked {
+            None
+        } else {
+            lookup_tables
+                .iter()
+                .enumerate()
+                .find_map(|(table, lookup)| {
+                    lookup
+                        .addresses()
+                        .iter()
+                        .position(|address| address == pubkey)
+                        .map(|index| (table, index as u8))
+                })
+        };
+
+        match (found, *is_writable) {
+            (Some((table, index)), true) => {
+                lookups[table].writable_indexes.push(index);
+                writable_loaded[table].push(*pubkey);
+            }
+            (Some((table, index)), false) => {
+                lookups[table].readonly_indexes.push(index);
+                readonly_loaded[table].push(*pubkey);
+            }
+            (None, true) => writable_static.push(*pubkey),
+            (None, false) => readonly_static.push(*pubkey),
+        }
+    }
+
+    let header = MessageHeader {
+        num_required_signatures: (writable_signers.len() + readonly_signers.len()) as u8,
+        num_readonly_signed_accounts: readonly_signers.len() as u8,
+        num_readonly_unsigned_accounts: readonly_static.len() as u8,
+    };
+
+    let mut account_keys = writable_signers;
+    account_keys.extend(readonly_signers);
+    account_keys.extend(writable_static);
+    account_keys.extend(readonly_static);
+
+    // Loaded accounts are indexed after the static keys: all writable loaded
+    // accounts in table order, followed by all readonly loaded accounts.
+    let mut all_keys = account_keys.clone();
+    all_keys.extend(writable_loaded.into_iter().flatten());
+    all_keys.extend(readonly_loaded.into_iter().flatten());
+    if all_keys.len() > 256 {
+        return Err(Error::from(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "transaction references more than 256 accounts",
+        )));
+    }
+    let indexes: HashMap<Pubkey, u8> = all_keys
+        .iter()
+        .enumerate()
+        .map(|(index, pubkey)| (*pubkey, index as u8))
+        .collect();
+
+    let compiled_instructions = instructions
+        .iter()
+        .map(|instruction| CompiledInstruction {
+            program_id_index: indexes[&instruction.program_id],
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|meta| indexes[&meta.pubkey])
+                .collect(),
+            data: instruction.data.clone(),
+        })
+        .collect();
+
+    lookups.retain(|lookup| {
+        !lookup.writable_indexes.is_empty() || !lookup.readonly_indexes.is_empty()
+    });
+
+    Ok(v0::Message {
+        header,
+        account_keys,
+        recent_blockhash,
+        instructions: compiled_instructions,
+        address_table_lookups: lookups,
+    })
+}
+
+/// Returns a v0 transaction as a legacy transaction whose account keys are
+/// followed by the accounts loaded from the lookup tables, in the order its
+/// instructions index them, so that traces can name every account.
+pub(crate) fn resolve_transaction(
+    transaction: &VersionedTransaction,
+    lookup_tables: &[&AddressLookupTable],
+) -> Transaction {
+    let message = match &transaction.message {
+        VersionedMessage::Legacy(message) => message.clone(),
+        VersionedMessage::V0(message) => {
+            let mut writable = Vec::new();
+            let mut readonly = Vec::new();
+            for lookup in message.address_table_lookups.iter() {
+                let addresses = lookup_tables
+                    .iter()
+                    .find(|table| table.address() == &lookup.account_key)
+                    .map_or(&[][..], |table| table.addresses());
+                let address =
+                    |index: &u8| addresses.get(*index as usize).copied().unwrap_or_default();
+                writable.extend(lookup.writable_indexes.iter().map(address));
+                readonly.extend(lookup.readonly_indexes.iter().map(address));
+            }
+            let mut account_keys = message.account_keys.clone();
+            account_keys.extend(writable);
+            account_keys.extend(readonly);
+            Message {
+                header: message.header,
+                account_keys,
+                recent_blockhash: message.recent_blockhash,
+                instructions: message.instructions.clone(),
+            }
+        }
+    };
+    Transaction {
+        signatures: transaction.signatures.clone(),
+        message,
+    }
+}
+
+/// Signs a v0 message. Signers may be given in any order, but every signer
+/// account required by the message must be present.
+pub fn sign_v0_message(message: v0::Message, signers: &[&Keypair]) -> Result<VersionedTransaction> {
+    let num_signers = message.header.num_required_signatures as usize;
+    let ordered_signers = message.account_keys[..num_signers]
+        .iter()
+        .map(|pubkey| {
+            signers
+                .iter()
+                .find(|signer| signer.pubkey() == *pubkey)
+                .ok_or_else(|| {
+                    Error::from(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("missing signer {}", pubkey),
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let message = VersionedMessage::V0(message);
+    let message_data = bincode::serialize(&message)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let signatures = ordered_signers
+        .iter()
+        .map(|signer| signer.sign_message(&message_data))
+        .collect();
+    Ok(VersionedTransaction {
+        signatures,
+        message,
+    })
+}
@@ -0,0 +1,481 @@
+use crate::errors::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serum_dex::{critbit::AnyNode, state as serum_state};
+use solana_sdk::pubkey::Pubkey;
+use std::fmt;
+
+/// Byte offset of the first slab node within a bids or asks account: the
+/// account head padding, the order book account flags, and the slab header.
+pub(crate) const SLAB_NODES_OFFSET: usize = 5 + 8 + 32;
+
+/// Serializes addresses as base58 strings, as JSON tooling expects, for use
+/// with serde's with attribute.
+pub(crate) mod pubkey_string {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use solana_sdk::pubkey::Pubkey;
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(pubkey)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+        Pubkey::from_str(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// Serializes 128-bit values such as order ids as decimal strings, since
+/// JSON numbers lose precision beyond 53 bits in most tools.
+pub(crate) mod u128_string {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Types whose state can be listed field by field and compared with diff.
+pub trait Fields {
+    /// Returns the name and rendered value of every field, in layout order.
+    fn fields(&self) -> Vec<(String, String)>;
+}
+
+/// A field whose value differs between two states.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// The field-level differences between two states. Displays one change per
+/// line, such as "native_pc_free: 300 -> 0".
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub changes: Vec<FieldChange>,
+}
+
+impl StateDiff {
+    /// Returns true if no field changed.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Returns the change of the named field, if it changed.
+    pub fn get(&self, field: &str) -> Option<&FieldChange> {
+        self.changes.iter().find(|change| change.field == field)
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in self.changes.iter() {
+            writeln!(f, "{}: {} -> {}", change.field, change.before, change.after)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports every field that differs between two states.
+pub fn diff<T: Fields>(before: &T, after: &T) -> StateDiff {
+    diff_fields(before.fields(), after.fields())
+}
+
+/// Reports every field that differs between two lists of slab nodes. Fields
+/// are prefixed with the node index, such as "nodes[3].quantity".
+pub fn diff_nodes(before: &[SlabNodeView], after: &[SlabNodeView]) -> StateDiff {
+    let indexed = |nodes: &[SlabNodeView]| {
+        nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, node)| {
+                node.fields()
+                    .into_iter()
+                    .map(move |(field, value)| (format!("nodes[{}].{}", index, field), value))
+            })
+            .collect()
+    };
+    diff_fields(indexed(before), indexed(after))
+}
+
+/// Pairs fields by name. Fields present on only one side are reported with
+/// a value of "-" on the other.
+fn diff_fields(before: Vec<(String, String)>, after: Vec<(String, String)>) -> StateDiff {
+    let mut changes = Vec::new();
+    for (field, old) in before.iter() {
+        let new = after
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| String::from("-"));
+        if *old != new {
+            changes.push(FieldChange {
+                field: field.clone(),
+                before: old.clone(),
+                after: new,
+            });
+        }
+    }
+    for (field, new) in after.iter() {
+        if !before.iter().any(|(name, _)| name == field) {
+            changes.push(FieldChange {
+                field: field.clone(),
+                before: String::from("-"),
+                after: new.clone(),
+            });
+        }
+    }
+    StateDiff { changes }
+}
+
+/// Converts a Serum packed public key into a Pubkey.
+fn to_pubkey(key: &[u64; 4]) -> Pubkey {
+    Pubkey::new(bytemuck::bytes_of(key))
+}
+
+pub(crate) fn wrong_padding() -> Error {
+    Error::from(serum_dex::error::DexError::from(
+        serum_dex::error::DexErrorCode::WrongAccountDataPaddingLength,
+    ))
+}
+
+/// Typed copy of a Serum market account. Serializes addresses as base58
+/// strings.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketView {
+    pub account_flags: u64,
+    #[serde(with = "pubkey_string")]
+    pub own_address: Pubkey,
+    pub vault_signer_nonce: u64,
+    #[serde(with = "pubkey_string")]
+    pub coin_mint: Pubkey,
+    #[serde(with = "pubkey_string")]
+    pub pc_mint: Pubkey,
+    #[serde(with = "pubkey_string")]
+    pub coin_vault: Pubkey,
+    pub coin_deposits_total: u64,
+    pub coin_fees_accrued: u64,
+    #[serde(with = "pubkey_string")]
+    pub pc_vault: Pubkey,
+    pub pc_deposits_total: u64,
+    pub pc_fees_accrued: u64,
+    pub pc_dust_threshold: u64,
+    #[serde(with = "pubkey_string")]
+    pub req_q: Pubkey,
+    #[serde(with = "pubkey_string")]
+    pub event_q: Pubkey,
+    #[serde(with = "pubkey_string")]
+    pub bids: Pubkey,
+    #[serde(with = "pubkey_string")]
+    pub asks: Pubkey,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub fee_rate_bps: u64,
+    pub referrer_rebates_accrued: u64,
+}
+
+impl MarketView {
+    /// Parses a market from raw account data.
+    pub fn load(data: &[u8]) -> Result<Self> {
+        let start = serum_state::ACCOUNT_HEAD_PADDING.len();
+        let end = start + std::mem::size_of::<serum_state::MarketState>();
+        let bytes = data.get(start..end).ok_or_else(wrong_padding)?;
+        Ok(Self::from(
+            bytemuck::from_bytes::<serum_state::MarketState>(bytes),
+        ))
+    }
+}
+
+impl From<&serum_state::MarketState> for MarketView {
+    fn from(state: &serum_state::MarketState) -> Self {
+        // The state is packed, so every field is copied out before use.
+        let (own_address, coin_mint, pc_mint) = (state.own_address, state.coin_mint, state.pc_mint);
+        let (coin_vault, pc_vault) = (state.coin_vault, state.pc_vault);
+        let (req_q, event_q, bids, asks) = (state.req_q, state.event_q, state.bids, state.asks);
+        MarketView {
+            account_flags: state.account_flags,
+            own_address: to_pubkey(&own_address),
+            vault_signer_nonce: state.vault_signer_nonce,
+            coin_mint: to_pubkey(&coin_mint),
+            pc_mint: to_pubkey(&pc_mint),
+            coin_vault: to_pubkey(&coin_vault),
+            coin_deposits_total: state.coin_deposits_total,
+            coin_fees_accrued: state.coin_fees_accrued,
+            pc_vault: to_pubkey(&pc_vault),
+            pc_deposits_total: state.pc_deposits_total,
+            pc_fees_accrued: state.pc_fees_accrued,
+            pc_dust_threshold: state.pc_dust_threshold,
+            req_q: to_pubkey(&req_q),
+            event_q: to_pubkey(&event_q),
+            bids: to_pubkey(&bids),
+            asks: to_pubkey(&asks),
+            coin_lot_size: state.coin_lot_size,
+            pc_lot_size: state.pc_lot_size,
+            fee_rate_bps: state.fee_rate_bps,
+            referrer_rebates_accrued: state.referrer_rebates_accrued,
+        }
+    }
+}
+
+impl Fields for MarketView {
+    fn fields(&self) -> Vec<(String, String)> {
+        vec![
+            field("account_flags", self.account_flags),
+            field("own_address", self.own_address),
+            field("vault_signer_nonce", self.vault_signer_nonce),
+            field("coin_mint", self.coin_mint),
+            field("pc_mint", self.pc_mint),
+            field("coin_vault", self.coin_vault),
+            field("coin_deposits_total", self.coin_deposits_total),
+            field("coin_fees_accrued", self.coin_fees_accrued),
+            field("pc_vault", self.pc_vault),
+            field("pc_deposits_total", self.pc_deposits_total),
+            field("pc_fees_accrued", self.pc_fees_accrued),
+            field("pc_dust_threshold", self.pc_dust_threshold),
+            field("req_q", self.req_q),
+            field("event_q", self.event_q),
+            field("bids", self.bids),
+            field("asks", self.asks),
+            field("coin_lot_size", self.coin_lot_size),
+            field("pc_lot_size", self.pc_lot_size),
+            field("fee_rate_bps", self.fee_rate_bps),
+            field("referrer_rebates_accrued", self.referrer_rebates_accrued),
+        ]
+    }
+}
+
+/// Typed copy of a Serum open orders account. Only occupied order slots are
+/// listed. Serializes addresses as base58 strings and bit sets as decimal
+/// strings.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OpenOrdersView {
+    pub account_flags: u64,
+    #[serde(with = "pubkey_string")]
+    pub market: Pubkey,
+    #[serde(with = "pubkey_string")]
+    pub owner: Pubkey,
+    pub native_coin_free: u64,
+    pub native_coin_total: u64,
+    pub native_pc_free: u64,
+    pub native_pc_total: u64,
+    #[serde(with = "u128_string")]
+    pub free_slot_bits: u128,
+    #[serde(with = "u128_string")]
+    pub is_bid_bits: u128,
+    pub orders: Vec<OpenOrderSlot>,
+    pub referrer_rebates_accrued: u64,
+}
+
+/// An occupied slot of an open orders account.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenOrderSlot {
+    pub slot: u8,
+    #[serde(with = "u128_string")]
+    pub order_id: u128,
+    pub client_order_id: u64,
+    pub is_bid: bool,
+}
+
+impl OpenOrdersView {
+    /// Parses an open orders account from raw account data.
+    pub fn load(data: &[u8]) -> Result<Self> {
+        let start = serum_state::ACCOUNT_HEAD_PADDING.len();
+        let end = start + std::mem::size_of::<serum_state::OpenOrders>();
+        let bytes = data.get(start..end).ok_or_else(wrong_padding)?;
+        Ok(Self::from(bytemuck::from_bytes::<serum_state::OpenOrders>(
+            bytes,
+        )))
+    }
+}
+
+impl From<&serum_state::OpenOrders> for OpenOrdersView {
+    fn from(state: &serum_state::OpenOrders) -> Self {
+        // The state is packed, so every field is copied out before use.
+        let (market, owner) = (state.market, state.owner);
+        let (orders, client_order_ids) = (state.orders, state.client_order_ids);
+        let free_slot_bits = state.free_slot_bits;
+        let is_bid_bits = state.is_bid_bits;
+        OpenOrdersView {
+            account_flags: state.account_flags,
+            market: to_pubkey(&market),
+            owner: to_pubkey(&owner),
+            native_coin_free: state.native_coin_free,
+            native_coin_total: state.native_coin_total,
+            native_pc_free: state.native_pc_free,
+            native_pc_total: state.native_pc_total,
+            free_slot_bits,
+            is_bid_bits,
+            orders: (0..orders.len())
+                .filter(|slot| free_slot_bits & (1u128 << slot) == 0)
+                .map(|slot| OpenOrderSlot {
+                    slot: slot as u8,
+                    order_id: orders[slot],
+                    client_order_id: client_order_ids[slot],
+                    is_bid: is_bid_bits & (1u128 << slot) != 0,
+                })
+                .collect(),
+            referrer_rebates_accrued: state.referrer_rebates_accrued,
+        }
+    }
+}
+
+impl Fields for OpenOrdersView {
+    fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = vec![
+            field("account_flags", self.account_flags),
+            field("market", self.market),
+            field("owner", self.owner),
+            field("native_coin_free", self.native_coin_free),
+            field("native_coin_total", self.native_coin_total),
+            field("native_pc_free", self.native_pc_free),
+            field("native_pc_total", self.native_pc_total),
+            field("free_slot_bits", format!("{:#x}", self.free_slot_bits)),
+            field("is_bid_bits", format!("{:#x}", self.is_bid_bits)),
+        ];
+        for order in self.orders.iter() {
+            fields.push(field(
+                &format!("orders[{}]", order.slot),
+                format!(
+                    "{} {} (client {})",
+                    if order.is_bid { "bid" } else { "ask" },
+                    order.order_id,
+                    order.client_order_id
+                ),
+            ));
+        }
+        fields.push(field(
+            "referrer_rebates_accrued",
+            self.referrer_rebates_accrued,
+        ));
+        fields
+    }
+}
+
+/// Typed copy of a node of a Serum order book slab. Serializes with a kind
+/// field naming the variant, as listed by Fields.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SlabNodeView {
+    Uninitialized,
+    Inner {
+        prefix_len: u32,
+        #[serde(with = "u128_string")]
+        key: u128,
+        children: [u32; 2],
+    },
+    Leaf {
+        owner_slot: u8,
+        fee_tier: u8,
+        #[serde(with = "u128_string")]
+        key: u128,
+        #[serde(with = "pubkey_string")]
+        owner: Pubkey,
+        quantity: u64,
+        client_order_id: u64,
+    },
+    Free {
+        next: u32,
+    },
+    LastFree,
+}
+
+impl SlabNodeView {
+    /// Parses a single slab node.
+    pub fn from_node(node: &AnyNode) -> Self {
+        Self::from_bytes(bytemuck::bytes_of(node))
+    }
+
+    /// Parses a single slab node from its raw bytes, which need not be
+    /// aligned. Panics if fewer bytes than a node are given.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+        };
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+        };
+        let u128_at = |offset: usize| {
+            u128::from_le_bytes(bytes[offset..offset + 16].try_into().expect("16 bytes"))
+        };
+        match u32_at(0) {
+            1 => SlabNodeView::Inner {
+                prefix_len: u32_at(4),
+                key: u128_at(8),
+                children: [u32_at(24), u32_at(28)],
+            },
+            2 => SlabNodeView::Leaf {
+                owner_slot: bytes[4],
+                fee_tier: bytes[5],
+                key: u128_at(8),
+                owner: Pubkey::new(&bytes[24..56]),
+                quantity: u64_at(56),
+                client_order_id: u64_at(64),
+            },
+            3 => SlabNodeView::Free { next: u32_at(4) },
+            4 => SlabNodeView::LastFree,
+            _ => SlabNodeView::Uninitialized,
+        }
+    }
+
+    /// Parses every node of a bids or asks account, in slab order.
+    pub fn load_all(data: &[u8]) -> Result<Vec<Self>> {
+        let end = data
+            .len()
+            .saturating_sub(serum_state::ACCOUNT_TAIL_PADDING.len());
+        if end < SLAB_NODES_OFFSET {
+            return Err(wrong_padding());
+        }
+        let node_size = std::mem::size_of::<AnyNode>();
+        Ok(data[SLAB_NODES_OFFSET..end]
+            .chunks_exact(node_size)
+            .map(Self::from_bytes)
+            .collect())
+    }
+}
+
+impl Fields for SlabNodeView {
+    fn fields(&self) -> Vec<(String, String)> {
+        match self {
+            SlabNodeView::Uninitialized => vec![field("kind", "uninitialized")],
+            SlabNodeView::Inner {
+                prefix_len,
+                key,
+                children,
+            } => vec![
+                field("kind", "inner"),
+                field("prefix_len", prefix_len),
+                field("key", key),
+                field("children", format!("{:?}", children)),
+            ],
+            SlabNodeView::Leaf {
+                owner_slot,
+                fee_tier,
+                key,
+                owner,
+                quantity,
+                client_order_id,
+            } => vec![
+                field("kind", "leaf"),
+                field("owner_slot", owner_slot),
+                field("fee_tier", fee_tier),
+                field("key", key),
+                field("price", (key >> 64) as u64),
+                field("owner", owner),
+                field("quantity", quantity),
+                field("client_order_id", client_order_id),
+            ],
+            SlabNodeView::Free { next } => vec![field("kind", "free"), field("next", next)],
+            SlabNodeView::LastFree => vec![field("kind", "last_free")],
+        }
+    }
+}
+
+fn field<T: fmt::Display>(name: &str, value: T) -> (String, String) {
+    (String::from(name), value.to_string())
+}
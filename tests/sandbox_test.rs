@@ -6,19 +6,38 @@ mod tests {
 
     use solarium::{
         actor::Actor,
+        cluster::{Cluster, ClusterBuilder},
+        errors::{Context, Error, MarketParamsError},
+        filter::AccountFilter,
+        fixtures::MarketManifest,
+        funding::{FundingPlan, FundingSource},
+        harness::{random_operations, MarketHarness},
+        inventory::Inventory,
+        matching::{expected_fills, maker_changes, taker_changes, IncomingOrder},
+        multisig::Multisig,
+        performance::PerformanceMeter,
+        profile::{ComputeProfile, ComputeProfiler},
         pyth::PriceAccount,
-        sandbox::Sandbox,
-        serum::Participant,
-        token::{BaseOrQuote, Mint},
+        ratelimit::RateLimit,
+        sandbox::{Sandbox, SandboxBuilder},
+        scenario::{Scenario, Step},
+        serum::{slab, Amount, Balance, DexVersion, MarketParams, OpenOrdersInit, Participant},
+        services::{CrankConfig, CrankService},
+        session::{self, SessionClock},
+        token::{BaseOrQuote, Mint, TokenAccount},
+        trader::Trader,
     };
 
     use serum_dex::{
+        fees::FeeTier,
         instruction::SelfTradeBehavior,
         matching::{OrderType, Side},
     };
 
     use pyth_client::{load_price, PriceConf};
 
+    use spl_token::instruction::AuthorityType;
+
     use std::thread::sleep;
 
     use std::time::Duration;
@@ -70,6 +89,9 @@ mod tests {
             .get_account_data(priceAccount.account().pubkey())
             .unwrap();
 
+        assert_eq!(price_data.len(), std::mem::size_of::<pyth_client::Price>());
+        assert_eq!(price_data.len(), ::pyth_sim::state::PRICE_ACCOUNT_SIZE);
+
         let val = load_price(price_data.as_slice().try_into().unwrap()).unwrap();
 
         assert_eq!(val.get_current_price().unwrap().price, 0);
@@ -87,140 +109,4207 @@ mod tests {
 
         assert_eq!(val.get_current_price().unwrap().price, 15);
         assert_eq!(val.get_current_price().unwrap().expo, 2);
+
+        let view = priceAccount.view().unwrap();
+        assert_eq!((view.price, view.expo), (15, 2));
+        let json = serde_json::to_string(&view).unwrap();
+        assert_eq!(
+            serde_json::from_str::<solarium::pyth::PriceView>(&json).unwrap(),
+            view
+        );
     }
 
     #[test]
-    fn integration() {
+    fn pyth_admin_instructions() {
         let sandbox = Sandbox::new().unwrap();
-        println!("sandbox url: {}", sandbox.url());
-        let market_creator = Actor::new(&sandbox).unwrap();
-        market_creator.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
-        let base_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
-        let quote_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
-        let serum_program = market_creator
-            .deploy_remote(
-                "https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so",
-                "serum_dex.so",
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let pyth_sim = local_pyth_sim(&payer);
+        let price = solarium::pyth::PriceAccount::new(&sandbox, pyth_sim.pubkey(), &payer).unwrap();
+
+        price
+            .set_price_status(
+                pyth_sim.pubkey(),
+                &payer,
+                ::pyth_sim::state::PriceStatus::Halted,
             )
             .unwrap();
+        price.set_exponent(pyth_sim.pubkey(), &payer, -4).unwrap();
+        let view = price.view().unwrap();
+        assert_eq!(view.status, solarium::oracle::OracleStatus::Halted);
+        assert_eq!(view.expo, -4);
 
-        let market = solarium::serum::Market::new(
-            &sandbox,
-            &market_creator,
-            serum_program.pubkey(),
-            &base_mint,
-            &quote_mint,
-            None,
-            1,
-            1,
-            100,
-            128,
-            128,
-            256,
+        // The program refuses a change its payer did not sign.
+        let mut unsigned = ::pyth_sim::instruction::set_exponent(
+            pyth_sim.pubkey(),
+            payer.pubkey(),
+            price.account().pubkey(),
+            2,
         )
         .unwrap();
-        println!("Made market.");
+        unsigned.accounts[0].is_signer = false;
+        let other = Actor::new(&sandbox).unwrap();
+        other.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let result = sandbox
+            .transaction()
+            .instruction(unsigned)
+            .payer(other.keypair())
+            .send();
+        assert!(result.unwrap_err().is_program_error());
+        assert_eq!(price.view().unwrap().expo, -4);
+    }
 
-        let maker = Participant::new(
-            &sandbox,
-            &market_creator,
-            &market,
-            10 * LAMPORTS_PER_SOL,
-            1000,
-            2000,
-        )
-        .unwrap();
-        let taker = Participant::new(
+    #[test]
+    fn pyth_make_stale() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let pyth_sim = local_pyth_sim(&payer);
+        let price = solarium::pyth::PriceAccount::new(&sandbox, pyth_sim.pubkey(), &payer).unwrap();
+        price
+            .publish_price(pyth_sim.pubkey(), &payer, 15, 2)
+            .unwrap();
+        let published = price.view().unwrap().publish_slot;
+        assert!(published > 0);
+
+        price.make_stale(pyth_sim.pubkey(), &payer, 5).unwrap();
+        let view = price.view().unwrap();
+        assert_eq!(view.valid_slot, view.publish_slot);
+        assert!(view.publish_slot + 5 <= sandbox.client().get_slot().unwrap());
+        assert_eq!(view.price, 15);
+    }
+
+    #[test]
+    fn pegged_quoter() {
+        use solarium::{oracle::PeggedQuoter, rng::SandboxRng};
+
+        let mut quoter = PeggedQuoter::new(100, 2, 3);
+        assert_eq!(quoter.price(), 100);
+        assert_eq!(quoter.quotes(), (98, 102));
+        assert_eq!(quoter.step(0), 97);
+        assert_eq!(quoter.step(3), 97);
+        assert_eq!(quoter.step(6), 100);
+        // Steps beyond 2 * max_step move the price by at most max_step.
+        assert_eq!(quoter.step(100), 103);
+
+        // The price stays high enough for a positive bid.
+        let mut low = PeggedQuoter::new(1, 2, 3);
+        assert_eq!(low.floor(), 3);
+        assert_eq!(low.price(), 3);
+        assert_eq!(low.quotes(), (1, 5));
+        assert_eq!(low.step(0), 3);
+
+        let walk = |seed| {
+            let mut rng = SandboxRng::from_seed(seed);
+            let mut quoter = PeggedQuoter::new(10, 2, 3);
+            (0..200)
+                .map(|_| {
+                    let before = quoter.price();
+                    let after = quoter.random_step(&mut rng);
+                    assert!(after >= quoter.floor());
+                    assert!(after + 3 >= before && after <= before + 3);
+                    after
+                })
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(walk(7), walk(7));
+    }
+
+    #[test]
+    fn pyth_oracle() {
+        use solarium::oracle::{Oracle, OracleStatus};
+
+        // Scenario code is written against the trait, not the simulator.
+        fn halt_after<O: Oracle>(oracle: &O, path: &[(i64, u64)]) {
+            for (price, conf) in path.iter() {
+                oracle.publish(*price, *conf, -2).unwrap();
+            }
+            oracle.set_status(OracleStatus::Halted).unwrap();
+        }
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let pyth_sim = local_pyth_sim(&payer);
+        let price = solarium::pyth::PriceAccount::new(&sandbox, pyth_sim.pubkey(), &payer).unwrap();
+        assert_eq!(Oracle::pubkey(&price), price.account().pubkey());
+
+        halt_after(&price, &[(1500, 10), (1525, 20)]);
+        let view = price.view().unwrap();
+        assert_eq!((view.price, view.conf, view.expo), (1525, 20, -2));
+        assert_eq!(view.status, OracleStatus::Halted);
+
+        price.set_status(OracleStatus::Trading).unwrap();
+        assert_eq!(price.view().unwrap().status, OracleStatus::Trading);
+    }
+
+    #[test]
+    #[ignore = "requires SOLARIUM_TOKEN_FAUCET_SO, a built spl_token_faucet.so"]
+    fn token_faucet() {
+        use solarium::token_faucet::TokenFaucet;
+
+        let program_location = std::env::var("SOLARIUM_TOKEN_FAUCET_SO").unwrap();
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let program =
+            TokenFaucet::deploy_local(&payer, std::path::Path::new(&program_location)).unwrap();
+        let mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let faucet = TokenFaucet::new(&sandbox, &payer, program.pubkey(), &mint, 1000).unwrap();
+
+        let client = Actor::new(&sandbox).unwrap();
+        client.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let tokens = TokenAccount::new(&sandbox, &payer, &mint, Some(client.pubkey())).unwrap();
+        faucet
+            .mint_to(&client, tokens.account().pubkey(), 600)
+            .unwrap();
+        faucet
+            .mint_to(&client, tokens.account().pubkey(), 400)
+            .unwrap();
+        assert_eq!(tokens.get_account_info().unwrap().amount, 1000);
+        assert_eq!(faucet.supply().unwrap(), 1000);
+
+        // The program enforces the limit recorded in the faucet account.
+        assert!(faucet
+            .mint_to(&client, tokens.account().pubkey(), 1001)
+            .is_err());
+        let result = sandbox
+            .transaction()
+            .instruction(faucet.mint_instruction(tokens.account().pubkey(), 1001))
+            .payer(client.keypair())
+            .send();
+        assert!(result.unwrap_err().is_program_error());
+        assert_eq!(faucet.supply().unwrap(), 1000);
+    }
+
+    #[test]
+    #[ignore = "requires SOLARIUM_TOKEN_SWAP_SO, a built spl_token_swap.so"]
+    fn swap_pool() {
+        use solarium::swap::{Pool, PoolParams};
+
+        let program_location = std::env::var("SOLARIUM_TOKEN_SWAP_SO").unwrap();
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let program = payer
+            .deploy_local(std::path::Path::new(&program_location))
+            .unwrap();
+        let mint_a = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let mint_b = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+
+        // Without fees every swap keeps the constant product exactly.
+        let mut params = PoolParams::new(1000, 1000);
+        params.fees = spl_token_swap::curve::fees::Fees::default();
+        let pool = Pool::new(
             &sandbox,
-            &market_creator,
-            &market,
-            10 * LAMPORTS_PER_SOL,
-            1000,
-            2000,
+            &payer,
+            program.pubkey(),
+            &mint_a,
+            &mint_b,
+            &params,
         )
         .unwrap();
+        assert_eq!(pool.reserves().unwrap(), (1000, 1000));
 
-        // Place ask order
-        let _taker_order = market
-            .new_order(
-                &taker.quote(),
-                &taker,
-                Side::Bid,
-                NonZeroU64::new(20).unwrap(),
-                OrderType::Limit,
-                NonZeroU64::new(500).unwrap(),
-                1,
-                SelfTradeBehavior::DecrementTake,
-                1,
-                NonZeroU64::new(300).unwrap(),
-                None,
-            )
+        let trader = Actor::new(&sandbox).unwrap();
+        trader.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let trader_a = TokenAccount::new(&sandbox, &payer, &mint_a, Some(trader.pubkey())).unwrap();
+        let trader_b = TokenAccount::new(&sandbox, &payer, &mint_b, Some(trader.pubkey())).unwrap();
+        mint_a.mint_to(&payer, &trader_a, 1000).unwrap();
+
+        let quote = |reserve_in: u64, reserve_out: u64, amount_in: u64| {
+            reserve_out * amount_in / (reserve_in + amount_in)
+        };
+
+        let a_to_b = quote(1000, 1000, 1000);
+        pool.swap(&trader, &trader_a, &trader_b, 1000, a_to_b)
             .unwrap();
-        println!("Placed bid order.");
+        assert_eq!(trader_b.get_account_info().unwrap().amount, a_to_b);
+        assert_eq!(pool.reserves().unwrap(), (2000, 1000 - a_to_b));
 
-        let _maker_order = market
-            .new_order(
-                &maker.base(),
-                &maker,
-                Side::Ask,
-                NonZeroU64::new(20).unwrap(),
-                OrderType::Limit,
-                NonZeroU64::new(400).unwrap(),
-                1,
-                SelfTradeBehavior::DecrementTake,
-                1,
-                NonZeroU64::new(500).unwrap(),
-                None,
-            )
+        let (reserve_a, reserve_b) = pool.reserves().unwrap();
+        let b_to_a = quote(reserve_b, reserve_a, a_to_b);
+        pool.swap(&trader, &trader_b, &trader_a, a_to_b, b_to_a)
             .unwrap();
+        assert_eq!(trader_a.get_account_info().unwrap().amount, b_to_a);
+        assert_eq!(pool.reserves().unwrap(), (reserve_a - b_to_a, 1000));
+        assert_eq!(reserve_a * reserve_b, 1000 * 1000);
 
-        println!("Placed ask order.");
+        // The minimum output is enforced by the program.
+        assert!(pool.swap(&trader, &trader_a, &trader_b, 10, 1000).is_err());
+    }
 
-        let maker_oo_info = sandbox
-            .client()
-            .get_account(maker.open_orders().pubkey())
+    #[test]
+    fn funding() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        let bob = Actor::new(&sandbox).unwrap();
+        sandbox
+            .airdrop_many(&[(&payer, 10 * LAMPORTS_PER_SOL), (&alice, LAMPORTS_PER_SOL)])
             .unwrap();
-        let maker_oo_data: OpenOrders =
-            OpenOrders::try_from_slice(&maker_oo_info.data.borrow()).unwrap();
-        let maker_order_id: u128 = maker_oo_data.orders[0];
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
 
-        market.cancel_order(&market_creator, &maker, Side::Ask, maker_order_id);
+        let mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let alice_tokens =
+            TokenAccount::new(&sandbox, &payer, &mint, Some(alice.pubkey())).unwrap();
+        let bob_tokens = TokenAccount::new(&sandbox, &payer, &mint, Some(bob.pubkey())).unwrap();
 
-        market.consume_events(
-            &market_creator,
-            vec![maker.open_orders().pubkey(), taker.open_orders().pubkey()],
-            10,
+        let mut plan = FundingPlan::new(&sandbox);
+        plan.lamports(&bob, 2 * LAMPORTS_PER_SOL)
+            .tokens(&mint, &alice_tokens, 100)
+            .tokens(&mint, &bob_tokens, 200);
+        plan.execute(&payer).unwrap();
+
+        assert_eq!(
+            sandbox.client().get_balance(bob.pubkey()).unwrap(),
+            2 * LAMPORTS_PER_SOL
         );
+        assert_eq!(alice_tokens.get_account_info().unwrap().amount, 100);
+        assert_eq!(bob_tokens.get_account_info().unwrap().amount, 200);
+    }
 
-        market.settle_funds(&market_creator, &taker);
-        market.settle_funds(&market_creator, &maker);
+    #[test]
+    fn faucet() {
+        use solarium::funding::Faucet;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let bank = Actor::new(&sandbox).unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        sandbox
+            .airdrop_many(&[
+                (&payer, 10 * LAMPORTS_PER_SOL),
+                (&bank, 10 * LAMPORTS_PER_SOL),
+            ])
+            .unwrap();
+        let btc = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let usdc = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let alice_btc = TokenAccount::new(&sandbox, &payer, &btc, Some(alice.pubkey())).unwrap();
+        let alice_usdc = TokenAccount::new(&sandbox, &payer, &usdc, Some(alice.pubkey())).unwrap();
 
-        let end_maker_b = get_pubkey_balance(maker.base().pubkey(), &sandbox);
-        let end_taker_b = get_pubkey_balance(taker.base().pubkey(), &sandbox);
+        // The bank pays while each mint's authority signs.
+        let faucet = Faucet::new(&sandbox, &bank)
+            .with_token("BTC", &btc)
+            .with_token("USDC", &usdc);
+        assert_eq!(faucet.symbols(), ["BTC", "USDC"]);
+        assert_eq!(faucet.actor().pubkey(), bank.pubkey());
+        faucet.give("BTC", &alice_btc, 3).unwrap();
+        faucet.give("USDC", &alice_usdc, 1000).unwrap();
+        faucet.give("USDC", &alice_usdc, 500).unwrap();
+        assert_eq!(alice_btc.get_account_info().unwrap().amount, 3);
+        assert_eq!(alice_usdc.get_account_info().unwrap().amount, 1500);
+        assert!(faucet.give("BTC", &alice_usdc, 1).is_err());
 
-        let end_maker_q = get_pubkey_balance(maker.quote().pubkey(), &sandbox);
-        let end_taker_q = get_pubkey_balance(taker.quote().pubkey(), &sandbox);
+        faucet
+            .give_lamports(alice.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
 
-        assert_eq!(end_maker_b, "985");
-        assert_eq!(end_taker_b, "1015");
-        assert_eq!(end_maker_q, "2299");
-        assert_eq!(end_taker_q, "1700");
+        let err = faucet.give("ETH", &alice_usdc, 1).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("no token registered for symbol ETH"),
+            "{}",
+            err
+        );
+        assert!(faucet.mint("ETH").is_err());
     }
 
-    fn do_vecs_match<T: PartialEq>(a: &Vec<T>, b: &Vec<T>) -> bool {
-        let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
-        matching == a.len() && matching == b.len()
+    #[test]
+    fn participant_top_up() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, _) = trading_pair(&sandbox, &payer, &market);
+
+        maker.top_up(5, 0).unwrap();
+        maker.top_up(0, 7).unwrap();
+        maker.top_up(0, 0).unwrap();
+        let balance = |wallet: &solana_sdk::pubkey::Pubkey| {
+            TokenAccount::from_pubkey(&sandbox, wallet)
+                .get_account_info()
+                .unwrap()
+                .amount
+        };
+        assert_eq!(balance(maker.base().pubkey()), 1005);
+        assert_eq!(balance(maker.quote().pubkey()), 2007);
+    }
+
+    #[test]
+    fn confirm_all_airdrops() {
+        let sandbox = Sandbox::new().unwrap();
+        let actors: Vec<Actor> = (0..5).map(|_| Actor::new(&sandbox).unwrap()).collect();
+        let pending: Vec<_> = actors
+            .iter()
+            .map(|actor| actor.request_airdrop(LAMPORTS_PER_SOL).unwrap())
+            .collect();
+        assert_eq!(pending[0].pubkey(), actors[0].pubkey());
+        sandbox.confirm_all(&pending).unwrap();
+        for actor in actors.iter() {
+            assert_eq!(
+                sandbox.client().get_balance(actor.pubkey()).unwrap(),
+                LAMPORTS_PER_SOL
+            );
+        }
+    }
+
+    #[test]
+    fn fund_with_faucet() {
+        let sandbox = Sandbox::new().unwrap();
+        let faucet_port = sandbox.faucet_port().unwrap();
+        assert_ne!(faucet_port, sandbox.port());
+        assert_eq!(
+            sandbox.faucet_url(),
+            Some(format!("127.0.0.1:{}", faucet_port))
+        );
+
+        let alice = Actor::new(&sandbox).unwrap();
+        sandbox
+            .fund_with_faucet(alice.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    #[ignore = "requires docker"]
+    fn docker() {
+        let sandbox = SandboxBuilder::fast()
+            .docker(solarium::sandbox::DEFAULT_DOCKER_IMAGE)
+            .build()
+            .unwrap();
+        let container = sandbox.docker_container().unwrap().to_string();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        payer
+            .deploy_remote(
+                "https://github.com/foonetic/solarium-deps/raw/main/pyth_sim.so",
+                "pyth_sim.so",
+            )
+            .unwrap();
+
+        sandbox.restart().unwrap();
+        assert!(sandbox.client().get_balance(payer.pubkey()).unwrap() > 0);
+
+        drop(sandbox);
+        let output = std::process::Command::new("docker")
+            .args(["ps", "--quiet", "--filter", &format!("name={}", container)])
+            .output()
+            .unwrap();
+        assert!(output.stdout.is_empty());
     }
 
-    fn get_pubkey_balance(pubkey: &solana_program::pubkey::Pubkey, sandbox: &Sandbox) -> String {
+    #[test]
+    fn atomic() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        let bob = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+
+        let transfer = |from: &Actor, to: &Actor, lamports| {
+            solana_sdk::system_instruction::transfer(from.pubkey(), to.pubkey(), lamports)
+        };
+        let result = sandbox.atomic(|transaction| {
+            Ok(transaction
+                .instruction(transfer(&payer, &alice, LAMPORTS_PER_SOL))
+                .instruction(transfer(&alice, &bob, 2 * LAMPORTS_PER_SOL))
+                .payer(payer.keypair())
+                .signer(alice.keypair()))
+        });
+        assert!(result.is_err());
+        assert_eq!(sandbox.client().get_balance(alice.pubkey()).unwrap(), 0);
+
+        let missing = sandbox.atomic(|transaction| {
+            Ok(transaction
+                .instruction(transfer(&alice, &bob, 1))
+                .payer(payer.keypair()))
+        });
+        assert!(missing.is_err());
+
         sandbox
+            .atomic(|transaction| {
+                Ok(transaction
+                    .instruction(transfer(&payer, &alice, 2 * LAMPORTS_PER_SOL))
+                    .instruction(transfer(&alice, &bob, LAMPORTS_PER_SOL))
+                    .payer(payer.keypair())
+                    .signer(alice.keypair())
+                    .signer(payer.keypair())
+                    .signer(bob.keypair()))
+            })
+            .unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+        assert_eq!(
+            sandbox.client().get_balance(bob.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    fn create_pda_account() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let program = solana_sdk::pubkey::Pubkey::new_unique();
+        let seeds: [&[u8]; 2] = [b"vault", payer.pubkey().as_ref()];
+
+        let vault = payer.create_pda_account(&seeds, 100, &program).unwrap();
+        let (expected, bump) = solana_sdk::pubkey::Pubkey::find_program_address(&seeds, &program);
+        assert_eq!(vault.pubkey(), &expected);
+        assert_eq!(vault.bump(), bump);
+        assert_eq!(vault.seeds(), seeds.to_vec());
+        assert!(vault.exists().unwrap());
+        assert!(!vault.meta(false).is_writable);
+        assert!(!vault.meta(true).is_signer);
+
+        let rent = sandbox
             .client()
-            .get_token_account_balance(pubkey)
+            .get_minimum_balance_for_rent_exemption(100)
+            .unwrap();
+        payer.create_pda_account(&seeds, 100, &program).unwrap();
+        assert_eq!(sandbox.client().get_balance(vault.pubkey()).unwrap(), rent);
+    }
+
+    #[test]
+    #[ignore = "requires network"]
+    fn connect_public_cluster() {
+        let sandbox = Sandbox::connect("https://api.devnet.solana.com").unwrap();
+        assert_eq!(sandbox.port(), 443);
+        assert_eq!(sandbox.ws_url(), "wss://api.devnet.solana.com");
+        assert_eq!(sandbox.faucet_url(), None);
+    }
+
+    #[test]
+    fn fork_manifest() {
+        use solarium::fork::ForkManifest;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "programs": [
+                    {
+                        "address": "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin",
+                        "label": "serum_dex"
+                    }
+                ],
+                "accounts": [
+                    { "address": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let manifest = ForkManifest::load(&path).unwrap();
+        let serum: solana_sdk::pubkey::Pubkey = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"
+            .parse()
+            .unwrap();
+        let usdc: solana_sdk::pubkey::Pubkey = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            manifest.entries().unwrap(),
+            [(serum, Some(String::from("serum_dex"))), (usdc, None)]
+        );
+        assert_eq!(
+            manifest,
+            ForkManifest::new()
+                .program(&serum, Some("serum_dex"))
+                .account(&usdc, None)
+        );
+
+        let mut invalid = manifest.clone();
+        invalid.accounts[0].address = String::from("not-base58");
+        let err = invalid.entries().unwrap_err();
+        assert!(
+            err.to_string().contains("invalid address not-base58"),
+            "{}",
+            err
+        );
+        assert!(SandboxBuilder::fast()
+            .fork_from("http://localhost:1", 1, &invalid)
+            .is_err());
+
+        std::fs::write(&path, "{ \"programs\": 1 }").unwrap();
+        assert!(ForkManifest::load(&path).is_err());
+        assert!(ForkManifest::load(dir.path().join("missing.json")).is_err());
+        assert!(ForkManifest::new().entries().unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires network"]
+    fn fork_public_cluster() {
+        use solarium::fork::ForkManifest;
+
+        let memo: solana_sdk::pubkey::Pubkey = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"
+            .parse()
+            .unwrap();
+        let manifest = ForkManifest::new().program(&memo, Some("memo"));
+        let sandbox =
+            Sandbox::fork_from("https://api.devnet.solana.com", 1_000, &manifest).unwrap();
+        assert!(sandbox.client().get_slot().unwrap() >= 1_000);
+        assert_eq!(sandbox.label(&memo), "memo");
+        assert!(sandbox.client().get_account(&memo).unwrap().executable);
+    }
+
+    #[test]
+    fn connect() {
+        let local = SandboxBuilder::fast()
+            .faucet_per_request_sol_cap(1.0)
+            .build()
+            .unwrap();
+        let funder = Actor::new(&local).unwrap();
+        funder.airdrop(LAMPORTS_PER_SOL).unwrap();
+        funder.airdrop(LAMPORTS_PER_SOL - 1).unwrap();
+
+        let mut sandbox = Sandbox::connect(&local.url()).unwrap();
+        assert!(!sandbox.is_local());
+        assert_eq!(sandbox.url(), local.url());
+        assert_eq!(sandbox.ws_url(), local.ws_url());
+        assert_eq!(sandbox.faucet_port(), None);
+        assert_eq!(sandbox.faucet_url(), None);
+        assert!(sandbox.health().unwrap().running);
+        assert!(sandbox.restart().is_err());
+        let keypair =
+            solana_sdk::signature::Keypair::from_bytes(&funder.keypair().to_bytes()).unwrap();
+        sandbox.set_funding_source(FundingSource::FaucetOrFunder(keypair));
+
+        let alice = Actor::new(&sandbox).unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        assert_eq!(
+            local.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+
+        // The faucet refuses airdrops over its cap, which the funder pays.
+        let bob = Actor::new(&sandbox).unwrap();
+        bob.airdrop(3 * LAMPORTS_PER_SOL / 2).unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(bob.pubkey()).unwrap(),
+            3 * LAMPORTS_PER_SOL / 2
+        );
+        assert!(local.client().get_balance(funder.pubkey()).unwrap() < LAMPORTS_PER_SOL);
+    }
+
+    #[test]
+    fn find_program_accounts() {
+        use solana_program::program_pack::Pack;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        let mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let other_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let payer_tokens = TokenAccount::new(&sandbox, &payer, &mint, None).unwrap();
+        let alice_tokens =
+            TokenAccount::new(&sandbox, &payer, &mint, Some(alice.pubkey())).unwrap();
+        TokenAccount::new(&sandbox, &payer, &other_mint, None).unwrap();
+
+        let holders = AccountFilter::new()
+            .data_size(spl_token::state::Account::LEN as u64)
+            .memcmp_pubkey(0, mint.actor().pubkey());
+        let accounts = sandbox
+            .find_program_accounts(&spl_token::id(), &holders)
+            .unwrap();
+        let mut expected = vec![
+            *payer_tokens.account().pubkey(),
+            *alice_tokens.account().pubkey(),
+        ];
+        expected.sort();
+        assert_eq!(
+            accounts
+                .iter()
+                .map(|(pubkey, _)| *pubkey)
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(accounts[0].1.data.len(), spl_token::state::Account::LEN);
+
+        let alice_only = holders
+            .memcmp(32, alice.pubkey().as_ref())
+            .data_slice(64, 8);
+        let accounts = sandbox
+            .find_program_accounts(&spl_token::id(), &alice_only)
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].0, *alice_tokens.account().pubkey());
+        assert_eq!(accounts[0].1.data, 0u64.to_le_bytes().to_vec());
+    }
+
+    #[test]
+    fn compute_profile() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let mint = Mint::new(&sandbox, &alice, 0, None, None).unwrap();
+        let tokens = TokenAccount::new(&sandbox, &alice, &mint, None).unwrap();
+
+        let mut profiler = ComputeProfiler::start(&sandbox);
+        mint.mint_to(&alice, &tokens, 10).unwrap();
+        mint.mint_to(&alice, &tokens, 20).unwrap();
+        let mint_to = spl_token::instruction::mint_to(
+            &spl_token::id(),
+            mint.actor().pubkey(),
+            tokens.account().pubkey(),
+            alice.pubkey(),
+            &[],
+            5,
+        )
+        .unwrap();
+        let simulated = profiler
+            .simulate(
+                sandbox
+                    .transaction()
+                    .instruction(mint_to)
+                    .payer(alice.keypair()),
+            )
+            .unwrap();
+        assert!(simulated.err.is_none());
+        let profile = profiler.finish().unwrap();
+
+        let stats = profile.get("spl_token", "MintTo").unwrap();
+        assert_eq!(stats.count, 3);
+        assert!(0 < stats.min && stats.min <= stats.max);
+        assert_eq!(profile.total(), stats.total);
+        assert!(profile.regressions(&profile, 0.0).is_empty());
+
+        let path = sandbox.tmpdir().join("profile.json");
+        profile.save(&path).unwrap();
+        let mut baseline = ComputeProfile::load(&path).unwrap();
+        assert_eq!(baseline, profile);
+        baseline
+            .programs
+            .get_mut("spl_token")
+            .unwrap()
+            .get_mut("MintTo")
             .unwrap()
-            .amount
+            .total /= 2;
+        assert_eq!(profile.regressions(&baseline, 0.1).len(), 1);
+    }
+
+    #[test]
+    fn cluster() {
+        assert!(ClusterBuilder::new().nodes(0).build().is_err());
+
+        let cluster = Cluster::new(2).unwrap();
+        assert_eq!(cluster.nodes().len(), 2);
+        assert!(cluster.nodes().iter().all(|node| node.is_running()));
+        assert_eq!(
+            cluster.bootstrap().client().get_identity().unwrap(),
+            cluster.bootstrap().identity()
+        );
+        let follower = cluster.node(1);
+        assert_eq!(
+            follower.client().get_genesis_hash().unwrap(),
+            cluster.bootstrap().client().get_genesis_hash().unwrap()
+        );
+
+        follower.stop().unwrap();
+        assert!(!follower.is_running());
+        assert!(follower.client().get_slot().is_err());
+        assert!(cluster.bootstrap().is_running());
+
+        // Nodes are stopped before their ledgers are deleted.
+        let dir = cluster.tmpdir().to_path_buf();
+        drop(cluster);
+        assert!(!dir.exists());
     }
 
-    fn get_balance(participant: &Participant, sandbox: &Sandbox) -> String {
-        get_pubkey_balance(participant.base().pubkey(), sandbox)
+    #[test]
+    fn restart() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+
+        sandbox.restart().unwrap();
+        let health = sandbox.health().unwrap();
+        assert!(health.running);
+        assert!(health.slot.is_some());
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+
+        // The validator shuts down cleanly, so the ledger written since the
+        // last start can be restarted from again.
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        sandbox.restart().unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            2 * LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    fn performance_meter() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+
+        let meter = PerformanceMeter::start(&sandbox).unwrap();
+        for _ in 0..5 {
+            alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        }
+        let report = meter.finish().unwrap();
+        assert!(report.transactions >= 5);
+        assert!(report.slots > 0);
+        report.assert_throughput(0.1);
+        report.assert_slot_rate(0.1);
+
+        let samples = sandbox.performance_samples(Some(1)).unwrap();
+        assert!(samples.len() <= 1);
+    }
+
+    #[test]
+    fn save_and_restore_ledger() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+
+        let snapshot = sandbox.save_ledger().unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            2 * LAMPORTS_PER_SOL
+        );
+
+        sandbox.restore_ledger(&snapshot).unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    fn session_clock() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+
+        let entered = std::cell::RefCell::new(Vec::new());
+        let closes = std::cell::Cell::new(0);
+        let mut clock = SessionClock::trading_day(&sandbox, 1_000, 1_000, 1_000);
+        clock
+            .on_every_phase(|sandbox, phase| {
+                assert!(sandbox.client().get_slot().unwrap() >= phase.start_slot);
+                entered.borrow_mut().push(phase.name.clone());
+                Ok(())
+            })
+            .on_phase(session::CLOSE, |_, _| {
+                closes.set(closes.get() + 1);
+                Ok(())
+            });
+        let pre_open = clock.advance().unwrap();
+        assert_eq!(clock.current_phase(), Some(&pre_open));
+        clock.run().unwrap();
+        assert!(clock.is_finished());
+        assert!(clock.advance().is_err());
+        assert_eq!(
+            clock.current_phase().unwrap().start_slot,
+            pre_open.start_slot + 2_000
+        );
+        drop(clock);
+
+        assert_eq!(
+            *entered.borrow(),
+            vec![session::PRE_OPEN, session::CONTINUOUS, session::CLOSE]
+        );
+        assert_eq!(closes.get(), 1);
+        assert_eq!(
+            sandbox.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    fn dump_and_import_accounts() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let dir = sandbox.tmpdir().join("dump");
+        sandbox.dump_accounts(&dir, &[*alice.pubkey()]).unwrap();
+
+        let imported = Sandbox::import_accounts(&dir).unwrap();
+        assert_eq!(
+            imported.client().get_balance(alice.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    fn derive_child() {
+        let sandbox = Sandbox::new().unwrap();
+        let root = Actor::from_path(&sandbox, "scenario").unwrap();
+        let maker = root.derive_child("maker-1").unwrap();
+        assert_eq!(
+            maker.pubkey(),
+            root.derive_child("maker-1").unwrap().pubkey()
+        );
+        assert_ne!(
+            maker.pubkey(),
+            root.derive_child("maker-2").unwrap().pubkey()
+        );
+        assert_eq!(
+            maker.derive_child("open-orders").unwrap().pubkey(),
+            root.derive_child("maker-1/open-orders").unwrap().pubkey()
+        );
+        assert_eq!(
+            maker.pubkey(),
+            Actor::from_path(&sandbox, "scenario/maker-1")
+                .unwrap()
+                .pubkey()
+        );
+    }
+
+    #[test]
+    fn genesis_accounts() {
+        let funded = solana_sdk::pubkey::Pubkey::new_unique();
+        let sandbox = SandboxBuilder::fast()
+            .seed(7)
+            .genesis_account(&funded, LAMPORTS_PER_SOL)
+            .genesis_actor("trader-0", 2 * LAMPORTS_PER_SOL)
+            .genesis_actor("trader-1", 3 * LAMPORTS_PER_SOL)
+            .build()
+            .unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(&funded).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+        let trader = Actor::from_path(&sandbox, "trader-1").unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(trader.pubkey()).unwrap(),
+            3 * LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    fn lazy_keyfiles() {
+        let sandbox = SandboxBuilder::fast().lazy_keyfiles().build().unwrap();
+        let count_files = || std::fs::read_dir(sandbox.tmpdir()).unwrap().count();
+        let before = count_files();
+        let alice = Actor::new(&sandbox).unwrap();
+        assert_eq!(count_files(), before);
+
+        let keyfile = alice.keyfile().unwrap();
+        assert_eq!(count_files(), before + 1);
+        assert_eq!(alice.keyfile().unwrap(), keyfile);
+        let keypair = solana_sdk::signature::read_keypair_file(&keyfile).unwrap();
+        assert_eq!(
+            &solana_sdk::signer::Signer::pubkey(&keypair),
+            alice.pubkey()
+        );
+
+        assert!(Actor::from_pubkey(&sandbox, alice.pubkey())
+            .keyfile()
+            .is_err());
+    }
+
+    #[test]
+    fn keep_artifacts() {
+        let workdir = tempfile::tempdir().unwrap();
+        let sandbox = SandboxBuilder::fast()
+            .workdir(workdir.path())
+            .keep_artifacts(true)
+            .build()
+            .unwrap();
+        let tmpdir = sandbox.tmpdir().to_path_buf();
+        assert!(tmpdir.starts_with(workdir.path()));
+        drop(sandbox);
+        assert!(tmpdir.join("validator.log").exists());
+    }
+
+    #[test]
+    fn close_accounts() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        let bob = Actor::new(&sandbox).unwrap();
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        bob.airdrop(LAMPORTS_PER_SOL).unwrap();
+
+        let wrapped =
+            TokenAccount::new_wrapped_sol(&sandbox, &alice, LAMPORTS_PER_SOL / 2).unwrap();
+        let wrapped_pubkey = *wrapped.account().pubkey();
+        let before = sandbox.client().get_balance(bob.pubkey()).unwrap();
+        let reclaimed = wrapped.close(&alice, bob.pubkey()).unwrap();
+        assert!(reclaimed > LAMPORTS_PER_SOL / 2);
+        assert_eq!(
+            sandbox.client().get_balance(bob.pubkey()).unwrap(),
+            before + reclaimed
+        );
+
+        alice.close_account(bob.pubkey()).unwrap();
+        assert!(sandbox
+            .leaked_accounts(&[*alice.pubkey(), wrapped_pubkey])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            sandbox.leaked_accounts(&[*bob.pubkey()]).unwrap(),
+            vec![*bob.pubkey()]
+        );
+    }
+
+    #[test]
+    fn rate_limit() {
+        let sandbox = Sandbox::new().unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        let accounts = [*alice.pubkey()];
+
+        sandbox.set_rate_limit(Some(RateLimit::new(10.0, 1)));
+        let start = std::time::Instant::now();
+        for _ in 0..3 {
+            sandbox.leaked_accounts(&accounts).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(sandbox.rate_limit_stats().delayed, 2);
+
+        sandbox.set_rate_limit(Some(RateLimit::new(0.001, 1).reject()));
+        sandbox.leaked_accounts(&accounts).unwrap();
+        assert!(sandbox.leaked_accounts(&accounts).is_err());
+        assert!(sandbox.rate_limit_stats().rejected > 0);
+    }
+
+    #[test]
+    fn market_params_validation() {
+        assert_eq!(MarketParams::default_small().validate(), Ok(()));
+
+        let mut params = MarketParams::default_small();
+        params.event_queue_size = 64;
+        assert_eq!(
+            params.validate(),
+            Err(MarketParamsError::EventQueueTooSmall {
+                given: 64,
+                minimum: 128
+            })
+        );
+
+        params = MarketParams::default_small();
+        params.quote_lot_size = 0;
+        assert!(matches!(
+            params.validate(),
+            Err(MarketParamsError::ZeroLotSize { .. })
+        ));
+
+        params = MarketParams::default_small();
+        params.quote_lot_size = 100;
+        params.dust_threshold = 100;
+        assert_eq!(
+            params.validate(),
+            Err(MarketParamsError::DustThresholdTooLarge {
+                dust_threshold: 100,
+                quote_lot_size: 100
+            })
+        );
+        params.dust_threshold = 99;
+        assert_eq!(params.validate(), Ok(()));
+        let realistic = MarketParams::realistic(9, 6, 0.01, 0.1).unwrap();
+        assert_eq!(realistic.validate(), Ok(()));
+    }
+
+    #[test]
+    fn dex_version() {
+        assert_eq!(MarketParams::default_small().version, DexVersion::V3);
+        assert!(DexVersion::V3.matches_immediately());
+        assert!(!DexVersion::V2.matches_immediately());
+        assert!(!DexVersion::V1.supports_authority());
+        assert!(DexVersion::V3.market_bytes(true) > DexVersion::V3.market_bytes(false));
+        assert_eq!(
+            DexVersion::V2.market_bytes(true),
+            DexVersion::V2.market_bytes(false)
+        );
+        assert_eq!(DexVersion::V2.request_queue_entries(128), 128);
+        assert_eq!(DexVersion::V3.request_queue_entries(128), 1);
+        let v2 = MarketParams {
+            version: DexVersion::V2,
+            ..MarketParams::default_small()
+        };
+        assert!(v2.request_queue_bytes() > MarketParams::default_small().request_queue_bytes());
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let base_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let quote_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let serum = solana_sdk::pubkey::Pubkey::new_unique();
+        let authority = solana_sdk::pubkey::Pubkey::new_unique();
+        let result = solarium::serum::Market::new(
+            &sandbox,
+            &payer,
+            &serum,
+            &base_mint,
+            &quote_mint,
+            Some(&authority),
+            &MarketParams {
+                version: DexVersion::V2,
+                ..MarketParams::default_small()
+            },
+        );
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err(),
+            Error::InputOutputError(err) if err.kind() == std::io::ErrorKind::InvalidInput
+        ));
+    }
+
+    #[test]
+    fn settle_funds_with_referrer() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        fee_trade(&market, &payer, &maker, &taker);
+
+        // The taker's fee funds the rebate, which stays with the taker's open
+        // orders until a settle names a referrer.
+        let rebate = taker.open_orders_view().unwrap().referrer_rebates_accrued;
+        assert!(rebate > 0);
+        let referrer = Actor::new(&sandbox).unwrap();
+        let referrer_quote = TokenAccount::new(
+            &sandbox,
+            &payer,
+            &fixture.quote_mint,
+            Some(referrer.pubkey()),
+        )
+        .unwrap();
+        market
+            .settle_funds_with_referrer(&payer, &taker, referrer_quote.account().pubkey())
+            .unwrap();
+        assert_eq!(referrer_quote.get_account_info().unwrap().amount, rebate);
+        assert_eq!(
+            taker.open_orders_view().unwrap().referrer_rebates_accrued,
+            0
+        );
+    }
+
+    #[test]
+    #[ignore = "requires SOLARIUM_SERUM_TEST_SO, a serum_dex.so built with the test feature"]
+    fn sweep_fees() {
+        let program_location = std::env::var("SOLARIUM_SERUM_TEST_SO").unwrap();
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(100 * LAMPORTS_PER_SOL).unwrap();
+        let fixture = SerumFixture {
+            sandbox: &sandbox,
+            payer: &payer,
+            serum: *payer
+                .deploy_local(std::path::Path::new(&program_location))
+                .unwrap()
+                .pubkey(),
+            base_mint: Mint::new(&sandbox, &payer, 0, None, None).unwrap(),
+            quote_mint: Mint::new(&sandbox, &payer, 0, None, None).unwrap(),
+        };
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        fee_trade(&market, &payer, &maker, &taker);
+        market.settle_funds(&payer, &maker).unwrap();
+        market.settle_funds(&payer, &taker).unwrap();
+
+        let (fees, _) = market.fees_accrued().unwrap();
+        assert!(fees > 0);
+        let vault_before = market.quote_vault().get_account_info().unwrap().amount;
+        let receiver = TokenAccount::new(&sandbox, &payer, &fixture.quote_mint, None).unwrap();
+        market.sweep_fees(&payer, &receiver).unwrap();
+        assert_eq!(receiver.get_account_info().unwrap().amount, fees);
+        assert_eq!(market.fees_accrued().unwrap().0, 0);
+        assert_eq!(
+            market.quote_vault().get_account_info().unwrap().amount,
+            vault_before - fees
+        );
+    }
+
+    #[test]
+    #[ignore = "requires SOLARIUM_SERUM_V2_SO, a built V2 serum_dex.so"]
+    fn dex_v2_market() {
+        let program_location = std::env::var("SOLARIUM_SERUM_V2_SO").unwrap();
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(100 * LAMPORTS_PER_SOL).unwrap();
+        let serum = *payer
+            .deploy_local(std::path::Path::new(&program_location))
+            .unwrap()
+            .pubkey();
+        let base_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let quote_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let params = MarketParams {
+            version: DexVersion::V2,
+            ..MarketParams::default_small()
+        };
+        let market = solarium::serum::Market::new(
+            &sandbox,
+            &payer,
+            &serum,
+            &base_mint,
+            &quote_mint,
+            None,
+            &params,
+        )
+        .unwrap();
+        let request_queue = sandbox
+            .get_account_data(market.request_queue().pubkey())
+            .unwrap();
+        assert_eq!(request_queue.len(), params.request_queue_bytes());
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        let price = NonZeroU64::new(20).unwrap();
+        let size = NonZeroU64::new(15).unwrap();
+
+        // Orders wait in the request queue until they are matched.
+        market.place_limit(&maker, Side::Ask, price, size).unwrap();
+        assert_eq!(market.order_count(Side::Ask).unwrap(), 0);
+        market.match_orders(&payer, 10).unwrap();
+        assert_eq!(market.best_ask().unwrap().unwrap().price, 20);
+
+        market.place_limit(&taker, Side::Bid, price, size).unwrap();
+        market.crank_all(&payer, 10).unwrap();
+        market.drain_events(&payer, 10).unwrap();
+        assert_eq!(market.order_count(Side::Ask).unwrap(), 0);
+        market.settle_funds(&payer, &taker).unwrap();
+        market.settle_funds(&payer, &maker).unwrap();
+        for (participant, amount) in [(&taker, 1015), (&maker, 985)] {
+            assert_eq!(
+                TokenAccount::from_pubkey(&sandbox, participant.base().pubkey())
+                    .get_account_info()
+                    .unwrap()
+                    .amount,
+                amount
+            );
+        }
+
+        // Cancels are queued as well.
+        market
+            .place_limit(&maker, Side::Ask, NonZeroU64::new(30).unwrap(), size)
+            .unwrap();
+        market.match_orders(&payer, 10).unwrap();
+        let order_id = maker.open_orders_view().unwrap().orders[0].order_id;
+        market
+            .cancel_order(&payer, &maker, Side::Ask, order_id)
+            .unwrap();
+        assert_eq!(market.order_count(Side::Ask).unwrap(), 1);
+        market.match_orders(&payer, 10).unwrap();
+        assert_eq!(market.order_count(Side::Ask).unwrap(), 0);
+
+        // A crank matches queued requests by itself.
+        let crank =
+            solarium::crank::CrankHandle::start(&market, &payer, Duration::from_millis(50), 10)
+                .unwrap();
+        market.place_limit(&maker, Side::Ask, price, size).unwrap();
+        for _ in 0..100 {
+            if market.order_count(Side::Ask).unwrap() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        assert_eq!(market.order_count(Side::Ask).unwrap(), 1);
+        assert!(crank.stop().unwrap().errors.is_empty());
+    }
+
+    #[test]
+    fn program_logs() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let tokens = TokenAccount::new(&sandbox, &payer, &mint, None).unwrap();
+        sandbox.register_name(&spl_token::id(), "spl_token");
+
+        let signature = sandbox
+            .transaction()
+            .instruction(
+                spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    mint.actor().pubkey(),
+                    tokens.account().pubkey(),
+                    payer.pubkey(),
+                    &[],
+                    10,
+                )
+                .unwrap(),
+            )
+            .payer(payer.keypair())
+            .send()
+            .unwrap();
+        let logs = sandbox.program_logs(&signature).unwrap();
+        assert_eq!(logs.first().unwrap(), "Program spl_token invoke [1]");
+        assert!(
+            logs.iter()
+                .any(|line| line == "Program log: Instruction: MintTo"),
+            "{:?}",
+            logs
+        );
+        assert_eq!(logs.last().unwrap(), "Program spl_token success");
+
+        // The validator keeps writing, so the tail is read before the file.
+        let tail = sandbox.tail_logs(3).unwrap();
+        let path = sandbox.validator_log_path();
+        assert!(path.starts_with(sandbox.tmpdir()));
+        let written = std::fs::read_to_string(path).unwrap();
+        assert!(!tail.is_empty() && tail.len() <= 3);
+        assert!(written.contains(&tail.join("\n")));
+    }
+
+    #[test]
+    fn wait_for_account_change() {
+        use solana_program::program_pack::Pack;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let tokens = TokenAccount::new(&sandbox, &payer, &mint, None).unwrap();
+        let amount = |data: &[u8]| spl_token::state::Account::unpack(data).unwrap().amount;
+
+        mint.mint_to(&payer, &tokens, 10).unwrap();
+        let seen = sandbox
+            .wait_for_account_change(
+                tokens.account().pubkey(),
+                |data| Some(amount(data)).filter(|amount| *amount == 10),
+                Duration::from_secs(10),
+            )
+            .unwrap();
+        assert_eq!(seen, 10);
+
+        let err = sandbox
+            .wait_for_account_change(
+                tokens.account().pubkey(),
+                |data| Some(amount(data)).filter(|amount| *amount == 11),
+                Duration::from_millis(200),
+            )
+            .unwrap_err();
+        match err {
+            Error::InputOutputError(err) => assert_eq!(err.kind(), std::io::ErrorKind::TimedOut),
+            err => panic!("expected a timeout, got {:?}", err),
+        }
+
+        // Accounts that do not exist are never passed to the predicate.
+        let missing = solana_sdk::pubkey::Pubkey::new_unique();
+        assert!(sandbox
+            .wait_for_account_change(&missing, |_| Some(()), Duration::from_millis(200))
+            .is_err());
+    }
+
+    #[test]
+    fn trace_mode() {
+        use solarium::trace::TraceEntry;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new_named(&sandbox, "payer").unwrap();
+        let recipient = Actor::new_named(&sandbox, "recipient").unwrap();
+        payer.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let transfer = |lamports| {
+            sandbox
+                .transaction()
+                .instruction(solana_sdk::system_instruction::transfer(
+                    payer.pubkey(),
+                    recipient.pubkey(),
+                    lamports,
+                ))
+                .payer(payer.keypair())
+                .send()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        sandbox.enable_trace(&path).unwrap();
+        assert!(sandbox.is_tracing());
+        let signature = transfer(LAMPORTS_PER_SOL / 2).unwrap();
+        assert!(transfer(2 * LAMPORTS_PER_SOL).is_err());
+        sandbox.disable_trace();
+        assert!(!sandbox.is_tracing());
+        transfer(LAMPORTS_PER_SOL / 4).unwrap();
+
+        let entries: Vec<TraceEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        let (sent, failed) = (&entries[0], &entries[1]);
+        assert_eq!(sent.signature, signature.to_string());
+        assert_eq!(sent.signers, ["payer"]);
+        assert!(sent.success);
+        assert_eq!(sent.error, None);
+        assert_eq!(sent.instructions.len(), 1);
+        let instruction = &sent.instructions[0];
+        assert_eq!(
+            instruction.program,
+            sandbox.label(&solana_sdk::system_program::id())
+        );
+        assert_eq!(instruction.accounts, ["payer", "recipient"]);
+        assert!(!sent.logs.is_empty());
+
+        assert!(!failed.success);
+        assert!(failed.error.is_some());
+        assert_eq!(failed.instructions.len(), 1);
+    }
+
+    #[test]
+    fn deactivate_feature() {
+        let feature = solana_sdk::feature_set::versioned_tx_message_enabled::id();
+        {
+            let sandbox = Sandbox::new().unwrap();
+            assert!(sandbox.is_feature_active(&feature).unwrap());
+            assert!(sandbox.feature_activation_slot(&feature).unwrap().is_some());
+        }
+
+        let sandbox = SandboxBuilder::new()
+            .deactivate_feature(&feature)
+            .build()
+            .unwrap();
+        assert!(!sandbox.is_feature_active(&feature).unwrap());
+        assert_eq!(sandbox.feature_activation_slot(&feature).unwrap(), None);
+        let unknown = solana_sdk::pubkey::Pubkey::new_unique();
+        assert!(!sandbox.is_feature_active(&unknown).unwrap());
+    }
+
+    #[test]
+    fn error_context() {
+        let result: Result<(), std::io::Error> = Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "account not found",
+        ));
+        let err = result
+            .context("creating open orders account")
+            .context("creating participant")
+            .unwrap_err();
+        assert_eq!(
+            err.contexts(),
+            vec!["creating participant", "creating open orders account"]
+        );
+        assert!(matches!(err.root(), Error::InputOutputError(_)));
+        assert_eq!(
+            err.to_string(),
+            "creating participant: creating open orders account: i/o error: account not found"
+        );
+
+        let boxed: Box<dyn std::error::Error> = Box::new(err);
+        let mut depth = 0;
+        let mut source = boxed.source();
+        while let Some(err) = source {
+            depth += 1;
+            source = err.source();
+        }
+        assert_eq!(depth, 3);
+    }
+
+    #[test]
+    fn anchor_instructions() {
+        use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey};
+        use solarium::anchor::{account_discriminator, instruction_discriminator, Idl};
+
+        for (name, expected) in [
+            ("initialize", [175, 175, 109, 31, 13, 152, 155, 237]),
+            ("setData", [223, 114, 91, 136, 197, 78, 153, 153]),
+            ("set_data", [223, 114, 91, 136, 197, 78, 153, 153]),
+            ("createPool", [233, 146, 209, 142, 207, 104, 64, 188]),
+        ] {
+            assert_eq!(instruction_discriminator(name), expected, "{}", name);
+        }
+        for (name, expected) in [
+            ("MyAccount", [246, 28, 6, 87, 251, 45, 50, 42]),
+            ("State", [216, 146, 107, 94, 104, 75, 182, 177]),
+        ] {
+            assert_eq!(account_discriminator(name), expected, "{}", name);
+        }
+
+        let idl: Idl = serde_json::from_value(serde_json::json!({
+            "name": "example",
+            "instructions": [{
+                "name": "setData",
+                "accounts": [
+                    { "name": "data", "isMut": true, "isSigner": false },
+                    { "name": "group", "accounts": [
+                        { "name": "authority", "isMut": false, "isSigner": true },
+                    ]},
+                ],
+                "args": [
+                    { "name": "value", "type": "u64" },
+                    { "name": "label", "type": "string" },
+                    { "name": "limit", "type": { "option": "u8" } },
+                    { "name": "pair", "type": { "array": ["u16", 2] } },
+                    { "name": "points", "type": { "vec": { "defined": "Point" } } },
+                    { "name": "kind", "type": { "defined": "Kind" } },
+                ],
+            }],
+            "types": [
+                { "name": "Point", "type": { "kind": "struct", "fields": [
+                    { "name": "x", "type": "i32" },
+                    { "name": "big", "type": "u128" },
+                ]}},
+                { "name": "Kind", "type": { "kind": "enum", "variants": [
+                    { "name": "Unit" },
+                    { "name": "Named", "fields": [{ "name": "flag", "type": "bool" }] },
+                    { "name": "Tuple", "fields": ["u8", "i16"] },
+                ]}},
+            ],
+        }))
+        .unwrap();
+
+        let program = Pubkey::new_unique();
+        let (data, authority) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let accounts = [("authority", authority), ("data", data)];
+        let args = serde_json::json!({
+            "value": 42,
+            "label": "hi",
+            "limit": null,
+            "pair": [1, 513],
+            "points": [{ "x": -1, "big": "340282366920938463463374607431768211455" }],
+            "kind": { "Tuple": [7, -2] },
+        });
+        let instruction = idl
+            .instruction(&program, "setData", &accounts, &args)
+            .unwrap();
+        assert_eq!(instruction.program_id, program);
+        assert_eq!(
+            instruction.accounts,
+            vec![
+                AccountMeta::new(data, false),
+                AccountMeta::new_readonly(authority, true),
+            ]
+        );
+        let mut expected = instruction_discriminator("setData").to_vec();
+        expected.extend_from_slice(&42u64.to_le_bytes());
+        expected.extend_from_slice(&[2, 0, 0, 0, b'h', b'i']);
+        expected.push(0);
+        expected.extend_from_slice(&[1, 0, 1, 2]);
+        expected.extend_from_slice(&[1, 0, 0, 0]);
+        expected.extend_from_slice(&(-1i32).to_le_bytes());
+        expected.extend_from_slice(&u128::MAX.to_le_bytes());
+        expected.extend_from_slice(&[2, 7]);
+        expected.extend_from_slice(&(-2i16).to_le_bytes());
+        assert_eq!(instruction.data, expected);
+
+        // Unit and named variants, and arguments given in order.
+        let args = serde_json::json!([1, "", 9, [0, 0], [], { "Named": { "flag": true } }]);
+        let data = idl
+            .instruction(&program, "setData", &accounts, &args)
+            .unwrap()
+            .data;
+        assert_eq!(&data[data.len() - 9..], &[0, 0, 0, 0, 0, 0, 0, 1, 1]);
+        let args = serde_json::json!([1, "", 9, [0, 0], [], "Unit"]);
+        let data = idl
+            .instruction(&program, "setData", &accounts, &args)
+            .unwrap()
+            .data;
+        assert_eq!(&data[data.len() - 11..data.len() - 9], &[1, 9]);
+        assert_eq!(data.last(), Some(&0));
+
+        for args in [
+            serde_json::json!([1, "", 256, [0, 0], [], "Unit"]),
+            serde_json::json!([1, "", 9, [0], [], "Unit"]),
+            serde_json::json!([1, "", 9, [0, 0], [], "Missing"]),
+            serde_json::json!([1, ""]),
+        ] {
+            assert!(idl
+                .instruction(&program, "setData", &accounts, &args)
+                .is_err());
+        }
+        assert!(idl
+            .instruction(
+                &program,
+                "setData",
+                &accounts[..1],
+                &serde_json::json!(null)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn product_attributes() {
+        use pyth_sim::instruction::{PROD_ATTR_SIZE, PROD_HDR_SIZE};
+        use solarium::pyth::{unpack_attrs, ProductAccount};
+
+        let builder = ProductAccount::builder()
+            .attr("symbol", "BTC/USD")
+            .attr("asset_type", "Crypto")
+            .attr("empty", "");
+        let packed = builder.pack().unwrap();
+        let mut expected = vec![6];
+        expected.extend_from_slice(b"symbol");
+        expected.push(7);
+        expected.extend_from_slice(b"BTC/USD");
+        expected.push(10);
+        expected.extend_from_slice(b"asset_type");
+        expected.push(6);
+        expected.extend_from_slice(b"Crypto");
+        expected.extend_from_slice(&[5, b'e', b'm', b'p', b't', b'y', 0]);
+        assert_eq!(packed, expected);
+
+        // The attributes follow the header, whose size field counts both.
+        let product_data = |attrs: &[u8]| {
+            let mut data = vec![0; PROD_HDR_SIZE];
+            data[12..16].copy_from_slice(&((PROD_HDR_SIZE + attrs.len()) as u32).to_le_bytes());
+            data.extend_from_slice(attrs);
+            data.resize(512, 0);
+            data
+        };
+        assert_eq!(
+            unpack_attrs(&product_data(&packed)).unwrap(),
+            vec![
+                (String::from("symbol"), String::from("BTC/USD")),
+                (String::from("asset_type"), String::from("Crypto")),
+                (String::from("empty"), String::new()),
+            ]
+        );
+        assert!(unpack_attrs(&product_data(&[])).unwrap().is_empty());
+        assert!(unpack_attrs(&product_data(&packed[..packed.len() - 1])).is_err());
+        assert!(unpack_attrs(&product_data(&packed[..7])).is_err());
+        assert!(unpack_attrs(&[0; 16]).is_err());
+
+        let long = "x".repeat(256);
+        assert!(ProductAccount::builder().attr(&long, "").pack().is_err());
+        let value = "x".repeat(200);
+        let full = (0..3).fold(ProductAccount::builder(), |builder, _| {
+            builder.attr("k", &value)
+        });
+        assert!(3 * (value.len() + 3) > PROD_ATTR_SIZE);
+        assert!(full.pack().is_err());
+    }
+
+    #[test]
+    fn account_cache() {
+        let sandbox = Sandbox::new().unwrap();
+        let cache = sandbox.account_cache();
+        cache.enable();
+        let alice = Actor::new(&sandbox).unwrap();
+        let bob = Actor::new(&sandbox).unwrap();
+
+        // Missing accounts are cached as absent rather than failing.
+        assert_eq!(sandbox.get_account_if_exists(alice.pubkey()).unwrap(), None);
+        let hits = cache.hits();
+        assert_eq!(sandbox.get_account_if_exists(alice.pubkey()).unwrap(), None);
+        assert!(sandbox.get_account(alice.pubkey()).is_err());
+        assert_eq!(cache.hits(), hits + 2);
+
+        // Airdrops and transactions invalidate the accounts they write.
+        alice.airdrop(LAMPORTS_PER_SOL).unwrap();
+        assert_eq!(
+            sandbox.get_account(alice.pubkey()).unwrap().lamports,
+            LAMPORTS_PER_SOL
+        );
+        let sent = alice.close_account(bob.pubkey()).unwrap();
+        assert_eq!(sandbox.get_account_if_exists(alice.pubkey()).unwrap(), None);
+        assert_eq!(sandbox.get_account(bob.pubkey()).unwrap().lamports, sent);
+
+        let misses = cache.misses();
+        assert_eq!(
+            sandbox.get_account_data(bob.pubkey()).unwrap(),
+            Vec::<u8>::new()
+        );
+        assert_eq!(cache.misses(), misses);
+        cache.disable();
+        assert_eq!(cache.get(bob.pubkey()), None);
+    }
+
+    #[test]
+    fn account_recorder() {
+        use solarium::recorder::{AccountRecorder, AccountUpdate};
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let recipient = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.jsonl");
+        let recorder =
+            AccountRecorder::start(&sandbox, &[*recipient.pubkey()], Some(&path)).unwrap();
+        let wait_for_updates = |count: usize| {
+            for _ in 0..100 {
+                if recorder.updates_for(recipient.pubkey()).len() >= count {
+                    break;
+                }
+                sleep(Duration::from_millis(100));
+            }
+            assert_eq!(recorder.updates_for(recipient.pubkey()).len(), count);
+        };
+        let transfer = || {
+            sandbox
+                .transaction()
+                .instruction(solana_sdk::system_instruction::transfer(
+                    payer.pubkey(),
+                    recipient.pubkey(),
+                    LAMPORTS_PER_SOL,
+                ))
+                .payer(payer.keypair())
+                .send()
+                .unwrap();
+        };
+
+        // The missing account is recorded as its initial state, then each
+        // transfer is recorded once.
+        wait_for_updates(1);
+        transfer();
+        wait_for_updates(2);
+        transfer();
+        wait_for_updates(3);
+
+        let updates = recorder.stop().unwrap();
+        let lamports: Vec<u64> = updates.iter().map(|update| update.lamports).collect();
+        assert_eq!(lamports, [0, LAMPORTS_PER_SOL, 2 * LAMPORTS_PER_SOL]);
+        assert!(updates[0].data.is_empty());
+        assert!(updates.windows(2).all(|pair| pair[0].slot < pair[1].slot));
+        assert!(updates
+            .iter()
+            .all(|update| update.pubkey == recipient.pubkey().to_string()));
+
+        let written: Vec<AccountUpdate> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(written, updates);
+    }
+
+    #[test]
+    fn account_snapshot() {
+        use solarium::snapshot::{hex_diff, snapshot_diff, UPDATE_ENV_VAR};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("golden").join("state.bin");
+        let data: Vec<u8> = (0..40).collect();
+        let missing = snapshot_diff(&data, &path).unwrap().unwrap();
+        assert!(missing.contains("does not exist"), "{}", missing);
+
+        std::env::set_var(UPDATE_ENV_VAR, "1");
+        let updated = snapshot_diff(&data, &path);
+        std::env::remove_var(UPDATE_ENV_VAR);
+        assert_eq!(updated.unwrap(), None);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert_eq!(snapshot_diff(&data, &path).unwrap(), None);
+
+        let mut changed = data.clone();
+        changed[17] = 0xff;
+        changed.push(40);
+        assert_eq!(
+            snapshot_diff(&changed, &path).unwrap().unwrap(),
+            [
+                "length differs: expected 40 bytes, got 41",
+                "00000010 - 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f",
+                "00000010 + 10 ff 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f",
+                "00000020 - 20 21 22 23 24 25 26 27",
+                "00000020 + 20 21 22 23 24 25 26 27 28",
+            ]
+            .join("\n")
+        );
+
+        let diff = hex_diff(&[0; 16 * 40], &[1; 16 * 40]);
+        assert_eq!(diff.lines().count(), 2 * 32 + 1);
+        assert!(diff.ends_with("... 8 more differing rows"));
+    }
+
+    #[test]
+    fn retry_policy() {
+        use solana_client::{
+            client_error::{ClientError, ClientErrorKind},
+            rpc_request::{RpcError, RpcResponseErrorData},
+        };
+        use solana_sdk::transaction::TransactionError;
+        use solarium::retry::RetryPolicy;
+
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let attempts = |err: fn() -> ClientError| {
+            let mut attempts = 0;
+            let result: solarium::errors::Result<()> = policy.run(|| {
+                attempts += 1;
+                Err(err())
+            });
+            assert!(result.is_err());
+            attempts
+        };
+
+        let transient: [fn() -> ClientError; 4] = [
+            || {
+                ClientErrorKind::Io(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "timed out",
+                ))
+                .into()
+            },
+            || TransactionError::BlockhashNotFound.into(),
+            || {
+                RpcError::RpcResponseError {
+                    code: -32005,
+                    message: String::from("node is behind"),
+                    data: RpcResponseErrorData::NodeUnhealthy {
+                        num_slots_behind: Some(10),
+                    },
+                }
+                .into()
+            },
+            || {
+                RpcError::ForUser(String::from(
+                    "unable to confirm transaction. This can happen in situations such as \
+                     transaction expiration and insufficient fee-payer funds",
+                ))
+                .into()
+            },
+        ];
+        for err in transient {
+            assert_eq!(attempts(err), 3, "{}", err());
+        }
+
+        let permanent: [fn() -> ClientError; 4] = [
+            || TransactionError::InsufficientFundsForFee.into(),
+            || RpcError::ForUser(String::from("blockhash not found in message")).into(),
+            || ClientErrorKind::Custom(String::from("unable to confirm transaction")).into(),
+            || {
+                RpcError::RpcResponseError {
+                    code: -32002,
+                    message: String::from("Transaction simulation failed"),
+                    data: RpcResponseErrorData::Empty,
+                }
+                .into()
+            },
+        ];
+        for err in permanent {
+            assert_eq!(attempts(err), 1, "{}", err());
+        }
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn solarium_server() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        // Sends a raw request and returns the status line and JSON body.
+        let send = |port: u16, request: &str| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let status = head.lines().next().unwrap().to_string();
+            (
+                status,
+                serde_json::from_str::<serde_json::Value>(body).unwrap(),
+            )
+        };
+        let post = |port: u16, path: &str, body: &str| {
+            send(
+                port,
+                &format!(
+                    "POST {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                    path,
+                    body.len(),
+                    body
+                ),
+            )
+        };
+
+        let port = portpicker::pick_unused_port().unwrap();
+        let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_solarium-server"))
+            .args(["--port", &port.to_string()])
+            .spawn()
+            .unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(120);
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(std::time::Instant::now() < deadline, "server did not start");
+            sleep(Duration::from_millis(100));
+        }
+
+        // A client that connects and sends nothing only holds the server
+        // until the request times out.
+        let idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (status, _) = send(port, "GET /status HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        drop(idle);
+
+        let (status, _) = send(
+            port,
+            "POST /mints HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\n",
+        );
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+
+        for request in [
+            ("/mints", r#"{"name": "base"}"#),
+            ("/mints", r#"{"name": "quote"}"#),
+            (
+                "/markets",
+                r#"{"name": "m", "base_mint": "base", "quote_mint": "quote"}"#,
+            ),
+            (
+                "/participants",
+                r#"{"name": "alice", "market": "m", "base": 100, "quote": 100}"#,
+            ),
+        ] {
+            assert_eq!(post(port, request.0, request.1).0, "HTTP/1.1 200 OK");
+        }
+        let (status, response) = post(
+            port,
+            "/orders",
+            &format!(
+                r#"{{"participant": "alice", "side": "buy", "price": {}, "size": 2}}"#,
+                u64::MAX
+            ),
+        );
+        assert_eq!(status, "HTTP/1.1 400 Bad Request", "{}", response);
+        assert_eq!(
+            post(
+                port,
+                "/orders",
+                r#"{"participant": "alice", "side": "sell", "price": 10, "size": 5}"#,
+            )
+            .0,
+            "HTTP/1.1 200 OK"
+        );
+
+        // SIGTERM lets the server stop its validator before exiting.
+        std::process::Command::new("kill")
+            .args(["-TERM", &server.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(server.wait().unwrap().success());
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn fault_injection() {
+        use solarium::fault::FaultPolicy;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let recipient = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let transfer = || {
+            sandbox
+                .transaction()
+                .instruction(solana_sdk::system_instruction::transfer(
+                    payer.pubkey(),
+                    recipient.pubkey(),
+                    LAMPORTS_PER_SOL,
+                ))
+                .payer(payer.keypair())
+                .send()
+        };
+
+        // Timed out sends never reach the validator.
+        sandbox.inject_faults(FaultPolicy::new().timeout(1.0), Some(7));
+        assert!(transfer().is_err());
+        assert!(sandbox.clear_faults().timeouts > 0);
+        assert_eq!(sandbox.client().get_balance(recipient.pubkey()).unwrap(), 0);
+
+        // A dropped confirmation is retried by asking the cluster about the
+        // signature, which finds the landed transfer instead of sending it
+        // again.
+        sandbox.inject_faults(FaultPolicy::new().drop_confirmation(1.0), Some(7));
+        transfer().unwrap();
+        assert_eq!(sandbox.clear_faults().dropped_confirmations, 1);
+        assert_eq!(
+            sandbox.client().get_balance(recipient.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+    }
+
+    #[test]
+    fn versioned_transaction() {
+        use solarium::versioned::{self, AddressLookupTable};
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let recipients: Vec<_> = (0..30)
+            .map(|_| solana_sdk::pubkey::Pubkey::new_unique())
+            .collect();
+
+        let mut table = AddressLookupTable::new(&sandbox, &payer).unwrap();
+        table.extend(&recipients).unwrap();
+        assert_eq!(table.addresses(), &recipients[..]);
+        assert_eq!(
+            sandbox.get_account(table.address()).unwrap().owner,
+            solana_address_lookup_table_program::id()
+        );
+
+        // Thirty transfers only fit in one transaction when the recipients
+        // are loaded from the table.
+        let transfers: Vec<_> = recipients
+            .iter()
+            .map(|recipient| {
+                solana_sdk::system_instruction::transfer(payer.pubkey(), recipient, 10_000_000)
+            })
+            .collect();
+        let message = versioned::compile_v0_message(
+            payer.pubkey(),
+            &transfers,
+            &[&table],
+            solana_sdk::hash::Hash::default(),
+        )
+        .unwrap();
+        assert_eq!(message.address_table_lookups[0].writable_indexes.len(), 30);
+        sandbox
+            .send_versioned_transaction(
+                &transfers,
+                payer.pubkey(),
+                vec![payer.keypair()],
+                &[&table],
+            )
+            .unwrap();
+        for recipient in recipients.iter() {
+            assert_eq!(sandbox.client().get_balance(recipient).unwrap(), 10_000_000);
+        }
+    }
+
+    #[test]
+    fn multisig() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let alice = Actor::new(&sandbox).unwrap();
+        let bob = Actor::new(&sandbox).unwrap();
+        let carol = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+
+        let multisig = Multisig::new(&sandbox, &payer, &[&alice, &bob, &carol], 2).unwrap();
+        let mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        multisig
+            .take_authority(&payer, mint.actor().pubkey(), AuthorityType::MintTokens)
+            .unwrap();
+
+        let tokens = TokenAccount::new(&sandbox, &payer, &mint, None).unwrap();
+        assert!(multisig
+            .mint_to(&payer, &mint, &tokens, 100, &[&alice])
+            .is_err());
+        assert!(multisig
+            .mint_to(&payer, &mint, &tokens, 100, &[&alice, &payer])
+            .is_err());
+        multisig
+            .mint_to(&payer, &mint, &tokens, 100, &[&alice, &carol])
+            .unwrap();
+        assert_eq!(tokens.get_account_info().unwrap().amount, 100);
+
+        multisig
+            .set_authority(
+                &payer,
+                mint.actor().pubkey(),
+                AuthorityType::MintTokens,
+                Some(payer.pubkey()),
+                &[&bob, &carol],
+            )
+            .unwrap();
+        mint.mint_to(&payer, &tokens, 50).unwrap();
+        assert_eq!(tokens.get_account_info().unwrap().amount, 150);
+    }
+
+    #[test]
+    fn send_split() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let recipients: Vec<_> = (0..40)
+            .map(|_| solana_sdk::pubkey::Pubkey::new_unique())
+            .collect();
+        let transfers: Vec<_> = recipients
+            .iter()
+            .map(|recipient| {
+                solana_sdk::system_instruction::transfer(
+                    payer.pubkey(),
+                    recipient,
+                    LAMPORTS_PER_SOL / 100,
+                )
+            })
+            .collect();
+        assert!(!solarium::transaction::fits(payer.pubkey(), &transfers));
+
+        let signatures = sandbox
+            .transaction()
+            .instructions(&transfers)
+            .payer(payer.keypair())
+            .send_split()
+            .unwrap();
+        assert!(signatures.len() > 1);
+        for recipient in recipients.iter() {
+            assert_eq!(
+                sandbox.client().get_balance(recipient).unwrap(),
+                LAMPORTS_PER_SOL / 100
+            );
+        }
+    }
+
+    #[test]
+    fn durable_nonce() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let recipient = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let nonce = payer.create_nonce_account(None).unwrap();
+        let blockhash = sandbox.nonce_blockhash(nonce.pubkey()).unwrap();
+
+        // Sign offline against the stored blockhash, then wait for it to fall
+        // out of the recent blockhash window before sending.
+        let transfer = solana_sdk::system_instruction::transfer(
+            payer.pubkey(),
+            recipient.pubkey(),
+            LAMPORTS_PER_SOL,
+        );
+        let transaction = sandbox
+            .transaction()
+            .instruction(transfer)
+            .payer(payer.keypair())
+            .nonce(nonce.pubkey(), payer.keypair())
+            .sign()
+            .unwrap();
+        assert_eq!(transaction.message.recent_blockhash, blockhash);
+        let start_slot = sandbox.client().get_slot().unwrap();
+        while sandbox.client().get_slot().unwrap() < start_slot + 160 {
+            sleep(Duration::from_millis(100));
+        }
+
+        sandbox.send_transaction(&transaction).unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(recipient.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+        assert_ne!(sandbox.nonce_blockhash(nonce.pubkey()).unwrap(), blockhash);
+    }
+
+    #[test]
+    fn lamport_audit() {
+        use solarium::audit::LamportAudit;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let recipient = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+
+        let transfer = |to: &solana_sdk::pubkey::Pubkey, lamports: u64| {
+            sandbox
+                .transaction()
+                .instruction(solana_sdk::system_instruction::transfer(
+                    payer.pubkey(),
+                    to,
+                    lamports,
+                ))
+                .payer(payer.keypair())
+                .send()
+                .unwrap();
+        };
+
+        let audit = LamportAudit::start(&sandbox).unwrap();
+        payer.airdrop(LAMPORTS_PER_SOL).unwrap();
+        let mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        transfer(recipient.pubkey(), LAMPORTS_PER_SOL);
+        let report = audit.finish().unwrap();
+        assert!(report.is_balanced(), "{}", report);
+        assert_eq!(report.airdropped(), LAMPORTS_PER_SOL);
+        assert!(report.fees() > 0);
+        let mint_rent = report
+            .accounts
+            .iter()
+            .find(|account| &account.pubkey == mint.actor().pubkey())
+            .unwrap()
+            .rent;
+        assert!(mint_rent > 0);
+        assert_eq!(report.rent(), mint_rent);
+        let received = report
+            .accounts
+            .iter()
+            .find(|account| &account.pubkey == recipient.pubkey())
+            .unwrap();
+        assert_eq!(received.transfers, LAMPORTS_PER_SOL as i128);
+
+        // Lamports sent outside the audited accounts are unaccounted.
+        let audit = LamportAudit::start(&sandbox).unwrap();
+        transfer(
+            &solana_sdk::pubkey::Pubkey::new_unique(),
+            LAMPORTS_PER_SOL / 10,
+        );
+        let report = audit.finish().unwrap();
+        assert_eq!(report.unaccounted(), (LAMPORTS_PER_SOL / 10) as i128);
+    }
+
+    #[test]
+    fn decoder() {
+        use solarium::decoder::DecodedInstruction;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let recipient = Actor::new(&sandbox).unwrap();
+        payer.airdrop(LAMPORTS_PER_SOL).unwrap();
+
+        let transfer = solana_sdk::system_instruction::transfer(
+            payer.pubkey(),
+            recipient.pubkey(),
+            LAMPORTS_PER_SOL / 2,
+        );
+        let signature = sandbox
+            .transaction()
+            .instruction(transfer)
+            .payer(payer.keypair())
+            .send()
+            .unwrap();
+        let decoded = sandbox.decode_transaction(&signature).unwrap();
+        assert_eq!(decoded.len(), 1);
+        let decoded = decoded[0].as_ref().unwrap();
+        assert_eq!(decoded.program, "system");
+        assert_eq!(decoded.name, "Transfer");
+
+        let mint_to = spl_token::instruction::mint_to(
+            &spl_token::id(),
+            recipient.pubkey(),
+            recipient.pubkey(),
+            payer.pubkey(),
+            &[],
+            5,
+        )
+        .unwrap();
+        assert_eq!(
+            sandbox
+                .decode_instruction(&spl_token::id(), &mint_to.data)
+                .unwrap()
+                .name,
+            "MintTo"
+        );
+
+        let program = solana_sdk::pubkey::Pubkey::new_unique();
+        assert!(sandbox.decode_instruction(&program, &[7]).is_none());
+        sandbox.register_decoder(&program, |data: &[u8]| {
+            Some(DecodedInstruction {
+                program: "custom".to_string(),
+                name: format!("Op{}", data.first()?),
+                details: String::new(),
+            })
+        });
+        assert_eq!(
+            sandbox.decode_instruction(&program, &[7]).unwrap().name,
+            "Op7"
+        );
+        assert!(sandbox.decode_instruction(&program, &[]).is_none());
+    }
+
+    #[test]
+    fn remote_signer() {
+        use solana_sdk::signature::{Keypair, Signer};
+        use solarium::signer::RemoteSigner;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let recipient = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+
+        // The wallet approves the first request and declines the rest.
+        let wallet = Keypair::new();
+        let (signer, requests) = RemoteSigner::channel(wallet.pubkey());
+        std::thread::spawn(move || {
+            for (i, request) in requests.iter().enumerate() {
+                if i == 0 {
+                    let signature = wallet.sign_message(request.message());
+                    request.approve(signature);
+                } else {
+                    request.reject("declined");
+                }
+            }
+        });
+        let owner = Actor::remote(&sandbox, signer);
+        assert!(owner.can_sign() && owner.is_remote());
+        assert!(owner.keyfile().is_err());
+        owner.airdrop(2 * LAMPORTS_PER_SOL).unwrap();
+
+        // The payer signs first, the transaction travels serialized, and the
+        // wallet countersigns.
+        let transfer = solana_sdk::system_instruction::transfer(
+            owner.pubkey(),
+            recipient.pubkey(),
+            LAMPORTS_PER_SOL,
+        );
+        let partial = sandbox
+            .transaction()
+            .instruction(transfer.clone())
+            .payer(payer.keypair())
+            .partial_sign()
+            .unwrap();
+        let serialized = bincode::serialize(&partial).unwrap();
+        let mut transaction: solana_sdk::transaction::Transaction =
+            bincode::deserialize(&serialized).unwrap();
+        owner.countersign(&mut transaction).unwrap();
+        sandbox.send_transaction(&transaction).unwrap();
+        assert_eq!(
+            sandbox.client().get_balance(recipient.pubkey()).unwrap(),
+            LAMPORTS_PER_SOL
+        );
+
+        // A declined request fails the transaction instead of sending it.
+        assert!(sandbox
+            .transaction()
+            .instruction(transfer)
+            .payer(payer.keypair())
+            .signer(owner.signer())
+            .send()
+            .is_err());
+    }
+
+    #[test]
+    fn integration() {
+        let sandbox = Sandbox::new().unwrap();
+        println!("sandbox url: {}", sandbox.url());
+        let market_creator = Actor::new(&sandbox).unwrap();
+        market_creator.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+        let base_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+        let quote_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+        let serum_program = market_creator
+            .deploy_remote(
+                "https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so",
+                "serum_dex.so",
+            )
+            .unwrap();
+
+        let market = solarium::serum::Market::new(
+            &sandbox,
+            &market_creator,
+            serum_program.pubkey(),
+            &base_mint,
+            &quote_mint,
+            None,
+            &MarketParams::default_small(),
+        )
+        .unwrap();
+        println!("Made market.");
+
+        let maker = Participant::new(
+            &sandbox,
+            &market_creator,
+            &market,
+            10 * LAMPORTS_PER_SOL,
+            1000,
+            2000,
+        )
+        .unwrap();
+        let taker = Participant::new(
+            &sandbox,
+            &market_creator,
+            &market,
+            10 * LAMPORTS_PER_SOL,
+            1000,
+            2000,
+        )
+        .unwrap();
+
+        // Place ask order
+        let _taker_order = market
+            .new_order(
+                &taker.quote(),
+                &taker,
+                Side::Bid,
+                NonZeroU64::new(20).unwrap(),
+                OrderType::Limit,
+                NonZeroU64::new(500).unwrap(),
+                1,
+                SelfTradeBehavior::DecrementTake,
+                1,
+                NonZeroU64::new(300).unwrap(),
+                None,
+            )
+            .unwrap();
+        println!("Placed bid order.");
+
+        let _maker_order = market
+            .new_order(
+                &maker.base(),
+                &maker,
+                Side::Ask,
+                NonZeroU64::new(20).unwrap(),
+                OrderType::Limit,
+                NonZeroU64::new(400).unwrap(),
+                1,
+                SelfTradeBehavior::DecrementTake,
+                1,
+                NonZeroU64::new(500).unwrap(),
+                None,
+            )
+            .unwrap();
+
+        println!("Placed ask order.");
+
+        let maker_oo_info = sandbox
+            .client()
+            .get_account(maker.open_orders().pubkey())
+            .unwrap();
+        let maker_oo_data: OpenOrders =
+            OpenOrders::try_from_slice(&maker_oo_info.data.borrow()).unwrap();
+        let maker_order_id: u128 = maker_oo_data.orders[0];
+
+        market.cancel_order(&market_creator, &maker, Side::Ask, maker_order_id);
+
+        market.consume_events(
+            &market_creator,
+            vec![maker.open_orders().pubkey(), taker.open_orders().pubkey()],
+            10,
+        );
+
+        market.settle_funds(&market_creator, &taker);
+        market.settle_funds(&market_creator, &maker);
+
+        // The maker sold 15 lots into the taker's bid and cancelled the rest
+        // of its ask.
+        market.assert_settled(
+            &maker,
+            Amount::Lots(-15),
+            Amount::TakerQuote {
+                side: Side::Ask,
+                price: 20,
+                quantity: 15,
+            },
+        );
+        market.assert_settled(
+            &taker,
+            Amount::Lots(15),
+            Amount::MakerQuote {
+                side: Side::Bid,
+                price: 20,
+                quantity: 15,
+            },
+        );
+    }
+
+    #[test]
+    fn named_accounts() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new_named(&sandbox, "payer").unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = solarium::serum::Market::new_named(
+            &sandbox,
+            &payer,
+            &fixture.serum,
+            &fixture.base_mint,
+            &fixture.quote_mint,
+            None,
+            &MarketParams::default_small(),
+            "SOL/USDC",
+        )
+        .unwrap();
+        let maker =
+            Participant::new_named(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 100, 0, "maker")
+                .unwrap();
+
+        assert_eq!(sandbox.label(payer.pubkey()), "payer");
+        assert_eq!(sandbox.label(market.bids().pubkey()), "SOL/USDC.bids");
+        assert_eq!(
+            sandbox.label(market.base_vault().account().pubkey()),
+            "SOL/USDC.base_vault"
+        );
+        assert_eq!(sandbox.label(maker.account().pubkey()), "maker");
+        assert_eq!(
+            sandbox.name_of(maker.open_orders().pubkey()).as_deref(),
+            Some("maker.open_orders")
+        );
+        let unnamed = solana_sdk::pubkey::Pubkey::new_unique();
+        assert_eq!(sandbox.label(&unnamed), unnamed.to_string());
+        assert_eq!(
+            sandbox.render(&format!(
+                "{} sent to {} and {}",
+                maker.base().pubkey(),
+                market.base_vault().account().pubkey(),
+                unnamed
+            )),
+            format!("maker.base sent to SOL/USDC.base_vault and {}", unnamed)
+        );
+    }
+
+    #[test]
+    fn portfolio_diff() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        let maker_before = maker.snapshot().unwrap();
+        let taker_before = taker.snapshot().unwrap();
+
+        rest_bid(&market, &taker);
+        cross_book(&market, &maker);
+        market.crank_all(&payer, 10).unwrap();
+        market.settle_funds(&payer, &taker).unwrap();
+        market.settle_funds(&payer, &maker).unwrap();
+
+        maker.diff_since(&maker_before).unwrap().assert_changes(&[
+            (Balance::Base, -15),
+            (Balance::Quote, 299),
+            (Balance::BaseUnsettled, 0),
+        ]);
+        taker.diff_since(&taker_before).unwrap().assert_changes(&[
+            (Balance::Base, 15),
+            (Balance::Quote, -300),
+            (Balance::QuoteUnsettled, 0),
+        ]);
+    }
+
+    #[test]
+    fn place_limit() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        let price = NonZeroU64::new(20).unwrap();
+        let size = NonZeroU64::new(15).unwrap();
+
+        market.place_limit(&taker, Side::Bid, price, size).unwrap();
+        market.place_limit(&maker, Side::Ask, price, size).unwrap();
+        market.crank_all(&payer, 10).unwrap();
+        market.settle_funds(&payer, &taker).unwrap();
+        market.settle_funds(&payer, &maker).unwrap();
+        market.assert_settled(
+            &maker,
+            Amount::Lots(-15),
+            Amount::TakerQuote {
+                side: Side::Ask,
+                price: 20,
+                quantity: 15,
+            },
+        );
+        market.assert_settled(
+            &taker,
+            Amount::Lots(15),
+            Amount::MakerQuote {
+                side: Side::Bid,
+                price: 20,
+                quantity: 15,
+            },
+        );
+
+        let result =
+            market.place_limit(&taker, Side::Bid, NonZeroU64::new(u64::MAX).unwrap(), size);
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::InputOutputError(err) if err.kind() == std::io::ErrorKind::InvalidInput
+        ));
+        let result = market.to_native(
+            BaseOrQuote::Quote,
+            Amount::TakerQuote {
+                side: Side::Bid,
+                price: u64::MAX,
+                quantity: 2,
+            },
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::InputOutputError(err) if err.kind() == std::io::ErrorKind::InvalidInput
+        ));
+    }
+
+    #[test]
+    fn expected_fills_several_makers() {
+        let makers: Vec<solana_sdk::pubkey::Pubkey> = (0..3)
+            .map(|_| solana_sdk::pubkey::Pubkey::new_unique())
+            .collect();
+        let resting = |index: usize, price: u64, quantity: u64| solarium::matching::BookOrder {
+            order_id: ((price as u128) << 64) | index as u128,
+            price,
+            quantity,
+            owner: makers[index],
+            client_order_id: index as u64,
+            fee_tier: 0,
+        };
+        let book = solarium::matching::BookSnapshot {
+            base_lot_size: 1,
+            quote_lot_size: 10,
+            bids: vec![resting(0, 10, 1), resting(1, 10, 1), resting(2, 9, 5)],
+            asks: vec![resting(0, 10, 1), resting(1, 10, 1), resting(2, 11, 5)],
+        };
+
+        // A fee per fill would round up three times, but Serum charges it
+        // once on the 310 total.
+        let bid = IncomingOrder {
+            side: Side::Bid,
+            limit_price: 11,
+            max_base_qty: 3,
+            max_native_quote_including_fees: 10_000,
+            order_type: OrderType::Limit,
+            fee_tier: FeeTier::Base,
+        };
+        let fills = expected_fills(&book, &bid);
+        assert_eq!(
+            fills.iter().map(|fill| fill.maker).collect::<Vec<_>>(),
+            makers
+        );
+        assert_eq!(
+            fills
+                .iter()
+                .map(|fill| fill.native_quote)
+                .collect::<Vec<_>>(),
+            vec![100, 100, 110]
+        );
+        let fee = FeeTier::Base.taker_fee(310) as i128;
+        assert!(fee < 3 * FeeTier::Base.taker_fee(100) as i128);
+        assert_eq!(taker_changes(Side::Bid, &fills), (3, -(310 + fee)));
+        for (fill, maker) in fills.iter().zip(makers.iter()) {
+            assert_eq!(
+                maker_changes(Side::Bid, maker, &fills),
+                (
+                    -(fill.native_base as i128),
+                    (fill.native_quote + FeeTier::Base.maker_rebate(fill.native_quote)) as i128
+                )
+            );
+        }
+
+        let ask = IncomingOrder {
+            side: Side::Ask,
+            limit_price: 9,
+            max_base_qty: 4,
+            ..bid
+        };
+        let fills = expected_fills(&book, &ask);
+        assert_eq!(fills.len(), 3);
+        let fee = FeeTier::Base.taker_fee(380) as i128;
+        assert_eq!(taker_changes(Side::Ask, &fills), (-4, 380 - fee));
+    }
+
+    #[test]
+    fn expected_fills_match_book() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        let maker_before = maker.snapshot().unwrap();
+        let taker_before = taker.snapshot().unwrap();
+        rest_bid(&market, &taker);
+
+        let fills = expected_fills(
+            &market.book_snapshot().unwrap(),
+            &IncomingOrder {
+                side: Side::Ask,
+                limit_price: 20,
+                max_base_qty: 15,
+                max_native_quote_including_fees: 500,
+                order_type: OrderType::Limit,
+                fee_tier: FeeTier::Base,
+            },
+        );
+        assert_eq!(fills.len(), 1);
+        assert_eq!(taker_changes(Side::Ask, &fills), (-15, 299));
+        assert_eq!(
+            maker_changes(Side::Ask, taker.open_orders().pubkey(), &fills),
+            (15, -300)
+        );
+
+        cross_book(&market, &maker);
+        market.crank_all(&payer, 10).unwrap();
+        market.settle_funds(&payer, &taker).unwrap();
+        market.settle_funds(&payer, &maker).unwrap();
+        let (base, quote) = taker_changes(Side::Ask, &fills);
+        maker
+            .diff_since(&maker_before)
+            .unwrap()
+            .assert_changes(&[(Balance::Base, base), (Balance::Quote, quote)]);
+        let (base, quote) = maker_changes(Side::Ask, taker.open_orders().pubkey(), &fills);
+        taker
+            .diff_since(&taker_before)
+            .unwrap()
+            .assert_changes(&[(Balance::Base, base), (Balance::Quote, quote)]);
+    }
+
+    #[test]
+    fn load_fills_for() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        rest_bid(&market, &taker);
+        cross_book(&market, &maker);
+
+        // Both sides of the trade are in the event queue until it is cranked.
+        let maker_fills = market.load_fills_for(&maker).unwrap();
+        assert_eq!(maker_fills.len(), 1);
+        assert_eq!(maker_fills[0].side, Side::Ask);
+        assert_eq!(maker_fills[0].price, 20);
+        assert_eq!(maker_fills[0].base_quantity, 15);
+        assert_eq!(maker_fills[0].base_change(), -15);
+        let taker_fills = market.load_fills_for(&taker).unwrap();
+        assert_eq!(taker_fills.len(), 1);
+        assert!(taker_fills[0].maker);
+        assert_eq!(taker_fills[0].quote_quantity, 300);
+
+        market.crank_all(&payer, 10).unwrap();
+        assert!(market.load_fills_for(&maker).unwrap().is_empty());
+    }
+
+    #[test]
+    fn settlement_assertions() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        rest_bid(&market, &taker);
+        cross_book(&market, &maker);
+        market.crank_all(&payer, 10).unwrap();
+        market.settle_funds(&payer, &taker).unwrap();
+        market.settle_funds(&payer, &maker).unwrap();
+
+        market.assert_settled(
+            &maker,
+            Amount::Lots(-15),
+            Amount::TakerQuote {
+                side: Side::Ask,
+                price: 20,
+                quantity: 15,
+            },
+        );
+        market.assert_settled(
+            &taker,
+            Amount::Lots(15),
+            Amount::MakerQuote {
+                side: Side::Bid,
+                price: 20,
+                quantity: 15,
+            },
+        );
+    }
+
+    #[test]
+    fn inventory() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        let mut inventory = Inventory::new(&market, &taker).unwrap();
+        rest_bid(&market, &taker);
+        cross_book(&market, &maker);
+
+        assert_eq!(inventory.update().unwrap(), 1);
+        assert_eq!((inventory.position(), inventory.cash()), (15, -300));
+        assert_eq!(inventory.realized_pnl(), 0);
+        assert_eq!(inventory.unrealized_pnl(20).unwrap(), 0);
+        assert!(inventory.unrealized_pnl(21).unwrap() > 0);
+
+        market.crank_all(&payer, 10).unwrap();
+        market.settle_funds(&payer, &taker).unwrap();
+        assert_eq!(
+            inventory.balance_changes().unwrap(),
+            (inventory.position(), inventory.cash())
+        );
+    }
+
+    #[test]
+    fn book_depth() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (_, taker) = trading_pair(&sandbox, &payer, &market);
+        rest_bid(&market, &taker);
+
+        let best_bid = market.best_bid().unwrap().unwrap();
+        assert_eq!(best_bid.price, 20);
+        assert_eq!(best_bid.orders, 1);
+        assert_eq!(market.best_ask().unwrap(), None);
+        let depth = market.depth(5).unwrap();
+        assert_eq!(depth.bids, vec![best_bid]);
+        assert_eq!(depth.spread(), None);
+    }
+
+    #[test]
+    fn slab_node_views() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (_, taker) = trading_pair(&sandbox, &payer, &market);
+        rest_bid(&market, &taker);
+
+        // Nodes start at an odd offset into a real account, so this checks
+        // that parsing does not rely on alignment.
+        let data = sandbox.get_account_data(market.bids().pubkey()).unwrap();
+        let nodes = solarium::views::SlabNodeView::load_all(&data).unwrap();
+        let leaves: Vec<_> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                solarium::views::SlabNodeView::Leaf {
+                    key,
+                    owner,
+                    client_order_id,
+                    ..
+                } => Some(((key >> 64) as u64, *owner, *client_order_id)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(leaves, vec![(20, *taker.open_orders().pubkey(), 1)]);
+    }
+
+    #[test]
+    fn slab_iter_orders() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (_, taker) = trading_pair(&sandbox, &payer, &market);
+        rest_bid(&market, &taker);
+
+        let book = market.book_snapshot().unwrap();
+        let bids = sandbox.get_account_data(market.bids().pubkey()).unwrap();
+        let orders: Vec<_> = slab::iter_orders(&bids).unwrap().rev().collect();
+        assert_eq!(orders, book.bids);
+        assert_eq!(orders.len(), 1);
+    }
+
+    #[test]
+    fn fill_subscription() {
+        solarium::init_test_logging();
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        rest_bid(&market, &taker);
+
+        let (fills, filled) = std::sync::mpsc::channel();
+        let subscription = market
+            .on_fill(move |event| fills.send(event.clone()).unwrap_or(()))
+            .unwrap();
+        cross_book(&market, &maker);
+        let fill = filled.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!((fill.order_id >> 64) as u64, 20);
+        assert_eq!(subscription.stop().unwrap(), 2);
+    }
+
+    #[test]
+    fn market_recorder() {
+        use solarium::recorder::{ExportFormat, MarketEvent, MarketRecorder};
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let new_participant = |name| {
+            Participant::new_named(
+                &sandbox,
+                &payer,
+                &market,
+                LAMPORTS_PER_SOL,
+                1000,
+                2000,
+                name,
+            )
+            .unwrap()
+        };
+        let buyer = new_participant("buyer");
+        let seller = new_participant("seller");
+
+        let dir = tempfile::tempdir().unwrap();
+        let csv_path = dir.path().join("events.csv");
+        let jsonl_path = dir.path().join("events.jsonl");
+        let csv =
+            MarketRecorder::start(&sandbox, &market, Some((&csv_path, ExportFormat::Csv))).unwrap();
+        let jsonl = MarketRecorder::start(
+            &sandbox,
+            &market,
+            Some((&jsonl_path, ExportFormat::JsonLines)),
+        )
+        .unwrap();
+        rest_bid(&market, &buyer);
+        cross_book(&market, &seller);
+        for _ in 0..100 {
+            if csv.fills().len() >= 2 && jsonl.fills().len() >= 2 {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        // The events stay in the uncranked queue, so later polls see them
+        // again and must skip them by sequence number.
+        sleep(Duration::from_millis(500));
+        let csv_events = csv.stop().unwrap();
+        let jsonl_events = jsonl.stop().unwrap();
+
+        let queued = market.event_queue_len().unwrap();
+        for events in [&csv_events, &jsonl_events] {
+            let seq_nums: Vec<u64> = events.iter().map(|event| event.seq_num).collect();
+            assert_eq!(seq_nums, (0..queued as u64).collect::<Vec<_>>());
+        }
+        let fills: Vec<&MarketEvent> = csv_events
+            .iter()
+            .filter(|event| event.kind == "fill")
+            .collect();
+        assert_eq!(fills.len(), 2);
+        let maker = fills.iter().find(|fill| fill.maker).unwrap();
+        assert_eq!(maker.owner, buyer.open_orders().pubkey().to_string());
+        assert_eq!(maker.owner_label, "buyer.open_orders");
+        assert_eq!(maker.side, "bid");
+        let taker = fills.iter().find(|fill| !fill.maker).unwrap();
+        assert_eq!(taker.owner_label, "seller.open_orders");
+        assert_eq!(taker.side, "ask");
+
+        let written = std::fs::read_to_string(&csv_path).unwrap();
+        let mut lines = written.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("kind,slot,timestamp_ms,seq_num,side,owner,owner_label,"));
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), csv_events.len());
+        for (row, event) in rows.iter().zip(csv_events.iter()) {
+            assert_eq!(row.len(), header.split(',').count());
+            assert_eq!(row[0], event.kind);
+            assert_eq!(row[3], event.seq_num.to_string());
+            assert_eq!(row[6], event.owner_label);
+        }
+
+        let written: Vec<MarketEvent> = std::fs::read_to_string(&jsonl_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(written, jsonl_events);
+    }
+
+    #[test]
+    fn permissioned_market() {
+        let sandbox = Sandbox::new().unwrap();
+        let market_creator = Actor::new(&sandbox).unwrap();
+        let authority = Actor::new(&sandbox).unwrap();
+        sandbox
+            .airdrop_many(&[
+                (&market_creator, 10 * LAMPORTS_PER_SOL),
+                (&authority, LAMPORTS_PER_SOL),
+            ])
+            .unwrap();
+        let base_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+        let quote_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+        let serum_program = market_creator
+            .deploy_remote(
+                "https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so",
+                "serum_dex.so",
+            )
+            .unwrap();
+
+        let market = solarium::serum::Market::new(
+            &sandbox,
+            &market_creator,
+            serum_program.pubkey(),
+            &base_mint,
+            &quote_mint,
+            Some(authority.pubkey()),
+            &MarketParams::default_small(),
+        )
+        .unwrap();
+
+        let approved = Participant::new(
+            &sandbox,
+            &market_creator,
+            &market,
+            10 * LAMPORTS_PER_SOL,
+            1000,
+            2000,
+        )
+        .unwrap();
+        let unapproved = Participant::new(
+            &sandbox,
+            &market_creator,
+            &market,
+            10 * LAMPORTS_PER_SOL,
+            1000,
+            2000,
+        )
+        .unwrap();
+        market.approve_open_orders(&authority, &approved).unwrap();
+
+        let place_ask = |participant: &Participant| {
+            market.new_order(
+                participant.base(),
+                participant,
+                Side::Ask,
+                NonZeroU64::new(20).unwrap(),
+                OrderType::Limit,
+                NonZeroU64::new(10).unwrap(),
+                1,
+                SelfTradeBehavior::DecrementTake,
+                1,
+                NonZeroU64::new(500).unwrap(),
+                None,
+            )
+        };
+        assert!(place_ask(&approved).is_ok());
+        assert!(place_ask(&unapproved).is_err());
+
+        // The prune authority can pull the approved participant's orders.
+        market.prune(&authority, &approved, 10).unwrap();
+    }
+
+    #[test]
+    fn open_orders_delegate() {
+        let sandbox = Sandbox::new().unwrap();
+        let market_creator = Actor::new(&sandbox).unwrap();
+        let authority = Actor::new(&sandbox).unwrap();
+        let delegate = Actor::new(&sandbox).unwrap();
+        sandbox
+            .airdrop_many(&[
+                (&market_creator, 10 * LAMPORTS_PER_SOL),
+                (&authority, LAMPORTS_PER_SOL),
+                (&delegate, LAMPORTS_PER_SOL),
+            ])
+            .unwrap();
+        let base_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+        let quote_mint = Mint::new(&sandbox, &market_creator, 0, None, None).unwrap();
+        let serum_program = market_creator
+            .deploy_remote(
+                "https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so",
+                "serum_dex.so",
+            )
+            .unwrap();
+        let market = solarium::serum::Market::new(
+            &sandbox,
+            &market_creator,
+            serum_program.pubkey(),
+            &base_mint,
+            &quote_mint,
+            Some(authority.pubkey()),
+            &MarketParams::default_small(),
+        )
+        .unwrap();
+
+        let participants = Participant::new_batch_with_init(
+            &sandbox,
+            &market_creator,
+            &market,
+            2,
+            OpenOrdersInit {
+                authority: Some(&authority),
+                delegate: Some(&delegate),
+            },
+            LAMPORTS_PER_SOL,
+            1000,
+            2000,
+        )
+        .unwrap();
+        let (maker, taker) = (&participants[0], &participants[1]);
+        assert_eq!(maker.owner().pubkey(), delegate.pubkey());
+        assert_eq!(maker.open_orders_view().unwrap().owner, *delegate.pubkey());
+
+        for (participant, side, wallet) in [
+            (maker, Side::Ask, maker.base()),
+            (taker, Side::Bid, taker.quote()),
+        ] {
+            market
+                .new_order(
+                    wallet,
+                    participant,
+                    side,
+                    NonZeroU64::new(20).unwrap(),
+                    OrderType::Limit,
+                    NonZeroU64::new(10).unwrap(),
+                    1,
+                    SelfTradeBehavior::DecrementTake,
+                    1,
+                    NonZeroU64::new(500).unwrap(),
+                    None,
+                )
+                .unwrap();
+        }
+        market.crank_all(&market_creator, 10).unwrap();
+        market.settle_funds(&market_creator, taker).unwrap();
+        assert_eq!(
+            TokenAccount::from_pubkey(&sandbox, taker.base().pubkey())
+                .get_account_info()
+                .unwrap()
+                .amount,
+            1010
+        );
+    }
+
+    #[test]
+    fn market_proxy() {
+        use solana_sdk::instruction::{AccountMeta, Instruction};
+        use solana_sdk::pubkey::Pubkey;
+        use solarium::proxy::MarketProxy;
+
+        let dex = Pubkey::new_unique();
+        let identity = Pubkey::new_unique();
+        let proxy = MarketProxy::new(&Pubkey::new_unique(), &dex)
+            .middleware_account(AccountMeta::new_readonly(identity, false));
+
+        let market = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let instruction = Instruction {
+            program_id: dex,
+            accounts: vec![
+                AccountMeta::new(market, false),
+                AccountMeta::new_readonly(owner, true),
+            ],
+            data: vec![0, 10, 0, 0, 0],
+        };
+        let wrapped = proxy.wrap(instruction.clone());
+        assert_eq!(wrapped.program_id, *proxy.program());
+        assert_eq!(wrapped.data, instruction.data);
+        assert_eq!(
+            wrapped.accounts,
+            vec![
+                AccountMeta::new_readonly(dex, false),
+                AccountMeta::new_readonly(identity, false),
+                AccountMeta::new(market, false),
+                AccountMeta::new_readonly(owner, true),
+            ]
+        );
+
+        let other = Instruction {
+            program_id: Pubkey::new_unique(),
+            ..instruction
+        };
+        assert_eq!(proxy.wrap(other.clone()), other);
+    }
+
+    #[test]
+    fn market_proxy_routing() {
+        use solana_sdk::instruction::AccountMeta;
+        use solarium::proxy::MarketProxy;
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (participant, _) = trading_pair(&sandbox, &payer, &market);
+
+        // The proxy program is never called, so the participant is created
+        // on the market directly and attached to a proxied handle of it.
+        let program = solana_sdk::pubkey::Pubkey::new_unique();
+        let proxied = solarium::serum::Market::new_from_existing(
+            &sandbox,
+            &fixture.serum,
+            market.market().pubkey(),
+        )
+        .unwrap()
+        .with_proxy(MarketProxy::new(&program, &fixture.serum));
+        let keypair =
+            solana_sdk::signature::Keypair::from_bytes(&participant.account().keypair().to_bytes())
+                .unwrap();
+        let maker = Participant::from_existing(
+            &proxied,
+            Actor::from_keypair(&sandbox, keypair).unwrap(),
+            participant.base().pubkey(),
+            participant.quote().pubkey(),
+            participant.open_orders().pubkey(),
+        )
+        .unwrap();
+        let price = NonZeroU64::new(20).unwrap();
+        let size = NonZeroU64::new(15).unwrap();
+
+        let routed = [
+            proxied
+                .place_order_ix(&maker, Side::Ask, price, size, OrderType::Limit, 1)
+                .unwrap(),
+            proxied.settle_funds_ix(&maker, None).unwrap(),
+            proxied.cancel_order_ix(&maker, Side::Ask, 1).unwrap(),
+        ];
+        for instruction in routed {
+            assert_eq!(instruction.program_id, program);
+            assert_eq!(
+                instruction.accounts[0],
+                AccountMeta::new_readonly(fixture.serum, false)
+            );
+        }
+
+        let crank = proxied
+            .consume_events_ix(vec![maker.open_orders().pubkey()], 1)
+            .unwrap();
+        assert_eq!(crank.program_id, fixture.serum);
+    }
+
+    #[test]
+    fn participant_builder() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let size = NonZeroU64::new(10).unwrap();
+
+        // Orders are paid for by the participant, so an unfunded one is
+        // rejected before any account is created.
+        let result = Participant::builder(&payer, &market)
+            .quote(1000)
+            .resting_order(Side::Bid, NonZeroU64::new(19).unwrap(), size)
+            .build();
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err(),
+            Error::InputOutputError(err) if err.kind() == std::io::ErrorKind::InvalidInput
+        ));
+
+        let maker = Participant::builder(&payer, &market)
+            .lamports(LAMPORTS_PER_SOL)
+            .payer_funded()
+            .base(100)
+            .quote(1000)
+            .associated_token_accounts()
+            .resting_order(Side::Ask, NonZeroU64::new(21).unwrap(), size)
+            .resting_order(Side::Bid, NonZeroU64::new(19).unwrap(), size)
+            .build()
+            .unwrap();
+        assert_eq!(
+            sandbox
+                .client()
+                .get_balance(maker.account().pubkey())
+                .unwrap(),
+            LAMPORTS_PER_SOL
+        );
+        assert_eq!(
+            maker.base().pubkey(),
+            &solarium::token::associated_token_address(
+                maker.account().pubkey(),
+                fixture.base_mint.actor().pubkey()
+            )
+        );
+        let book = market.book_snapshot().unwrap();
+        assert_eq!(book.asks.len(), 1);
+        assert_eq!(book.bids.len(), 1);
+
+        let taker_account = Actor::new(&sandbox).unwrap();
+        let wallet = TokenAccount::new(
+            &sandbox,
+            &payer,
+            &fixture.base_mint,
+            Some(taker_account.pubkey()),
+        )
+        .unwrap();
+        let taker = Participant::builder(&payer, &market)
+            .account(taker_account)
+            .lamports(LAMPORTS_PER_SOL)
+            .base(50)
+            .base_account(wallet.account().pubkey())
+            .build()
+            .unwrap();
+        assert_eq!(taker.base().pubkey(), wallet.account().pubkey());
+        assert_eq!(wallet.get_account_info().unwrap().amount, 50);
+    }
+
+    #[test]
+    fn raw_market_instructions() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let maker = Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 100, 0).unwrap();
+
+        let instructions = [1, 2]
+            .into_iter()
+            .map(|client_order_id| {
+                market
+                    .new_order_ix(
+                        maker.base(),
+                        &maker,
+                        Side::Ask,
+                        NonZeroU64::new(20 + client_order_id).unwrap(),
+                        OrderType::PostOnly,
+                        NonZeroU64::new(10).unwrap(),
+                        client_order_id,
+                        SelfTradeBehavior::DecrementTake,
+                        u16::MAX,
+                        NonZeroU64::new(1).unwrap(),
+                        None,
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        sandbox
+            .transaction()
+            .instructions(&instructions)
+            .payer(maker.owner().keypair())
+            .send()
+            .unwrap();
+        assert_eq!(market.book_snapshot().unwrap().asks.len(), 2);
+
+        let orders = maker.open_orders_view().unwrap().orders;
+        let instructions = vec![
+            market
+                .cancel_order_ix(&maker, Side::Ask, orders[0].order_id)
+                .unwrap(),
+            market
+                .cancel_order_ix(&maker, Side::Ask, orders[1].order_id)
+                .unwrap(),
+            market
+                .consume_events_ix(vec![maker.open_orders().pubkey()], 10)
+                .unwrap(),
+            market.settle_funds_ix(&maker, None).unwrap(),
+        ];
+        sandbox
+            .transaction()
+            .instructions(&instructions)
+            .payer(maker.owner().keypair())
+            .send()
+            .unwrap();
+        assert!(market.book_snapshot().unwrap().asks.is_empty());
+        assert_eq!(maker.snapshot().unwrap().base, 100);
+    }
+
+    #[test]
+    fn rejoin_recreated_market() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let first = fixture.market();
+        let second = fixture.market();
+
+        let mut participant =
+            Participant::new(&sandbox, &payer, &first, LAMPORTS_PER_SOL, 100, 100).unwrap();
+        let open_orders = *participant.open_orders().pubkey();
+        first.check_open_orders(&participant).unwrap();
+        assert!(second.check_open_orders(&participant).is_err());
+        assert!(second.settle_funds(&payer, &participant).is_err());
+
+        participant.rejoin(&payer, &second).unwrap();
+        assert_eq!(participant.open_orders().pubkey(), &open_orders);
+        second.check_open_orders(&participant).unwrap();
+        assert_eq!(
+            participant.open_orders_view().unwrap().market,
+            *second.market().pubkey()
+        );
+        second.settle_funds(&payer, &participant).unwrap();
+    }
+
+    #[test]
+    fn open_orders_pool() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let attached = solarium::serum::Market::new_from_existing(
+            &sandbox,
+            &fixture.serum,
+            market.market().pubkey(),
+        )
+        .unwrap();
+        let pool = solarium::serum::OpenOrdersPool::new(&payer, &attached, 2).unwrap();
+        assert_eq!(pool.len(), 2);
+
+        // The attached market cannot sign for its mints, so minting starting
+        // tokens fails and the taken accounts go back to the pool.
+        assert!(pool
+            .participants(&payer, 2, LAMPORTS_PER_SOL, 100, 0)
+            .is_err());
+        assert_eq!(pool.len(), 2);
+
+        let participants = pool
+            .participants(&payer, 3, LAMPORTS_PER_SOL, 0, 0)
+            .unwrap();
+        assert!(pool.is_empty());
+        for participant in participants.iter() {
+            attached.check_open_orders(participant).unwrap();
+        }
+    }
+
+    #[test]
+    fn attach_existing_market() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let participant =
+            Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 100, 0).unwrap();
+
+        let attached = solarium::serum::Market::new_from_existing(
+            &sandbox,
+            &fixture.serum,
+            market.market().pubkey(),
+        )
+        .unwrap();
+        assert_eq!(
+            attached.base_mint().actor().pubkey(),
+            fixture.base_mint.actor().pubkey()
+        );
+        assert_eq!(attached.quote_mint().authority().pubkey(), payer.pubkey());
+        assert_eq!(attached.bids().pubkey(), market.bids().pubkey());
+
+        // Signing for watch-only actors fails instead of panicking.
+        let watched = Participant::from_existing(
+            &attached,
+            Actor::from_pubkey(&sandbox, participant.account().pubkey()),
+            participant.base().pubkey(),
+            participant.quote().pubkey(),
+            participant.open_orders().pubkey(),
+        )
+        .unwrap();
+        assert!(watched.account().try_keypair().is_err());
+        assert!(watched.top_up(1, 0).is_err());
+        assert!(TokenAccount::new(&sandbox, watched.account(), &fixture.base_mint, None).is_err());
+        assert!(solarium::crank::CrankHandle::start(
+            &attached,
+            watched.account(),
+            std::time::Duration::from_millis(100),
+            1
+        )
+        .is_err());
+        assert!(attached
+            .new_order(
+                watched.base(),
+                &watched,
+                Side::Ask,
+                NonZeroU64::new(10).unwrap(),
+                OrderType::Limit,
+                NonZeroU64::new(1).unwrap(),
+                1,
+                SelfTradeBehavior::DecrementTake,
+                1,
+                NonZeroU64::new(11).unwrap(),
+                None,
+            )
+            .is_err());
+        attached.settle_funds(&payer, &participant).unwrap();
+    }
+
+    #[test]
+    fn market_manifest() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let participants = (0..2)
+            .map(|_| {
+                Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 100, 100).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let path = sandbox.tmpdir().join("market.json");
+        let manifest = MarketManifest::from_market(&sandbox, &market, &participants);
+        manifest.save(&path).unwrap();
+        let loaded = MarketManifest::load(&path).unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(&loaded.program_id().unwrap(), &fixture.serum);
+
+        let serum = loaded.program_id().unwrap();
+        let stranger = Actor::new(&sandbox).unwrap();
+        assert!(loaded.mints(&sandbox, &stranger).is_err());
+        let (base, quote) = loaded.mints(&sandbox, &payer).unwrap();
+        let attached = loaded.market(&sandbox, &serum, &base, &quote).unwrap();
+        assert_eq!(attached.market().pubkey(), market.market().pubkey());
+        let mut malformed = loaded.clone();
+        malformed.participants[0].keypair = String::from("not a keypair");
+        assert!(malformed.participants(&attached).is_err());
+        let attached_participants = loaded.participants(&attached).unwrap();
+        assert_eq!(attached_participants.len(), participants.len());
+        for (attached, original) in attached_participants.iter().zip(&participants) {
+            assert_eq!(attached.account().pubkey(), original.account().pubkey());
+            assert_eq!(
+                attached.open_orders().pubkey(),
+                original.open_orders().pubkey()
+            );
+        }
+
+        let trader = &attached_participants[1];
+        attached
+            .new_order(
+                trader.quote(),
+                trader,
+                Side::Bid,
+                NonZeroU64::new(10).unwrap(),
+                OrderType::Limit,
+                NonZeroU64::new(5).unwrap(),
+                1,
+                SelfTradeBehavior::DecrementTake,
+                1,
+                NonZeroU64::new(60).unwrap(),
+                None,
+            )
+            .unwrap();
+        assert!(trader.open_orders_view().unwrap().native_pc_total >= 50);
+
+        let mut moved = loaded.clone();
+        moved.url = String::from("http://127.0.0.1:1");
+        assert!(moved.market(&sandbox, &serum, &base, &quote).is_err());
+    }
+
+    #[test]
+    fn market_teardown() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let maker = Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 100, 0).unwrap();
+        let taker = Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 0, 100).unwrap();
+
+        let order = |participant: &Participant, side: Side, price: u64, size: u64| {
+            let wallet = match side {
+                Side::Bid => participant.quote(),
+                Side::Ask => participant.base(),
+            };
+            market
+                .new_order(
+                    wallet,
+                    participant,
+                    side,
+                    NonZeroU64::new(price).unwrap(),
+                    OrderType::Limit,
+                    NonZeroU64::new(size).unwrap(),
+                    1,
+                    SelfTradeBehavior::DecrementTake,
+                    1,
+                    NonZeroU64::new(price * size + 1).unwrap(),
+                    None,
+                )
+                .unwrap();
+        };
+        order(&maker, Side::Ask, 10, 5);
+        order(&maker, Side::Ask, 12, 5);
+        order(&taker, Side::Bid, 10, 3);
+        let header = market.event_queue_header().unwrap();
+        assert!(header.count > 0);
+        assert_eq!(header.seq_num, header.count);
+        market.assert_book_consistent();
+
+        let open_orders = [*maker.open_orders().pubkey(), *taker.open_orders().pubkey()];
+        let report = market
+            .teardown(&payer, &[&maker, &taker], payer.pubkey())
+            .unwrap();
+        assert_eq!(report.orders_cancelled, 2);
+        assert!(report.lamports_reclaimed > 0);
+        assert!(report.lamports_locked > 0);
+        assert_eq!(report.base_vault, 0);
+        assert_eq!(market.event_queue_len().unwrap(), 0);
+        assert!(sandbox.leaked_accounts(&open_orders).unwrap().is_empty());
+        assert_eq!(
+            TokenAccount::from_pubkey(&sandbox, maker.base().pubkey())
+                .get_account_info()
+                .unwrap()
+                .amount,
+            97
+        );
+    }
+
+    #[test]
+    fn crank_all_chunks() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        // More open orders accounts than fit in one consume_events.
+        let makers =
+            Participant::new_batch(&sandbox, &payer, &market, 30, LAMPORTS_PER_SOL / 10, 1, 0)
+                .unwrap();
+        let taker = Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 0, 1000).unwrap();
+
+        let order = |participant: &Participant, side: Side, size: u64| {
+            let wallet = match side {
+                Side::Bid => participant.quote(),
+                Side::Ask => participant.base(),
+            };
+            market
+                .new_order(
+                    wallet,
+                    participant,
+                    side,
+                    NonZeroU64::new(10).unwrap(),
+                    OrderType::Limit,
+                    NonZeroU64::new(size).unwrap(),
+                    1,
+                    SelfTradeBehavior::DecrementTake,
+                    u16::MAX,
+                    NonZeroU64::new(10 * size + 1).unwrap(),
+                    None,
+                )
+                .unwrap();
+        };
+        for maker in makers.iter() {
+            order(maker, Side::Ask, 1);
+        }
+        order(&taker, Side::Bid, 30);
+        assert!(market.event_queue_len().unwrap() > 30);
+
+        market.drain_events(&payer, 100).unwrap();
+        assert_eq!(market.event_queue_len().unwrap(), 0);
+        for maker in makers.iter() {
+            assert_eq!(maker.open_orders_view().unwrap().native_pc_free, 10);
+        }
+    }
+
+    #[test]
+    fn scheduler() {
+        use solarium::scheduler::{Schedule, Scheduler};
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let recipient = Actor::new(&sandbox).unwrap();
+        payer.airdrop(10 * LAMPORTS_PER_SOL).unwrap();
+
+        let amount = LAMPORTS_PER_SOL / 100;
+        let (from, to) = (*payer.pubkey(), *recipient.pubkey());
+        let mut sent = 0;
+        let mut scheduler = Scheduler::new(&sandbox);
+        scheduler
+            .job(
+                "transfer",
+                Schedule::Every(Duration::from_millis(100)),
+                payer.keypair(),
+                &[],
+                move |_| {
+                    // Vary the amount so that runs within one blockhash do
+                    // not send identical transactions.
+                    sent += 1;
+                    Ok(vec![solana_sdk::system_instruction::transfer(
+                        &from,
+                        &to,
+                        amount + sent,
+                    )])
+                },
+            )
+            .job("fail", Schedule::EverySlot, payer.keypair(), &[], |_| {
+                Err(Error::from(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "no quote",
+                )))
+            });
+        assert!(!scheduler.is_running());
+        scheduler.start();
+        assert!(scheduler.is_running());
+        for _ in 0..100 {
+            if scheduler.runs("transfer") >= 3 && scheduler.runs("fail") >= 1 {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        scheduler.stop();
+        assert!(!scheduler.is_running());
+
+        // Every completed transfer run has landed, and nothing runs while
+        // the scheduler is stopped.
+        let runs = scheduler.runs("transfer");
+        assert!(runs >= 3);
+        let balance = || sandbox.client().get_balance(recipient.pubkey()).unwrap();
+        let total = |runs: u64| runs * amount + runs * (runs + 1) / 2;
+        assert_eq!(balance(), total(runs));
+        sleep(Duration::from_millis(300));
+        assert_eq!(scheduler.runs("transfer"), runs);
+
+        scheduler.start();
+        for _ in 0..100 {
+            if scheduler.runs("transfer") > runs {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        scheduler.stop();
+        assert!(scheduler.runs("transfer") > runs);
+        assert_eq!(balance(), total(scheduler.runs("transfer")));
+
+        let errors = scheduler.errors();
+        assert!(errors.iter().all(|error| error.job == "fail"));
+        assert_eq!(errors.len() as u64, scheduler.runs("fail"));
+        assert_eq!(errors[0].run, 0);
+        let err = scheduler.check().unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("job fail failed on run 0"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn crank_handle_metrics() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let (maker, taker) = trading_pair(&sandbox, &payer, &market);
+        rest_bid(&market, &taker);
+        cross_book(&market, &maker);
+        let pushed = market.event_queue_len().unwrap();
+        assert!(pushed > 0);
+
+        let crank =
+            solarium::crank::CrankHandle::start(&market, &payer, Duration::from_millis(50), 10)
+                .unwrap();
+        for _ in 0..100 {
+            if crank.metrics().events_consumed == pushed {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        let metrics = crank.stop().unwrap();
+        assert_eq!(metrics.events_consumed, pushed);
+        assert_eq!(market.event_queue_len().unwrap(), 0);
+        assert!(metrics.cranks >= 1);
+        assert!(metrics.errors.is_empty(), "{:?}", metrics.errors);
+        assert!(metrics.events_per_second() > 0.0);
+        assert!(metrics.last_progress.unwrap() <= metrics.elapsed);
+
+        // The first poll saw the crossed book's events, and the last one an
+        // empty queue, which is not a stall.
+        assert_eq!(metrics.queue_depth[0].depth as u64, pushed);
+        assert_eq!(metrics.max_queue_depth() as u64, pushed);
+        assert_eq!(metrics.current_queue_depth(), 0);
+        assert_eq!(metrics.stalled_for(), Duration::ZERO);
+    }
+
+    #[test]
+    fn crank_service() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let maker = Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 100, 0).unwrap();
+        let taker = Participant::new(&sandbox, &payer, &market, LAMPORTS_PER_SOL, 0, 100).unwrap();
+
+        let crank = CrankService::spawn(
+            &market,
+            &payer,
+            CrankConfig {
+                interval: Duration::from_millis(50),
+                ..CrankConfig::default()
+            },
+        )
+        .unwrap();
+        for (participant, side) in [(&maker, Side::Ask), (&taker, Side::Bid)] {
+            let wallet = match side {
+                Side::Bid => participant.quote(),
+                Side::Ask => participant.base(),
+            };
+            market
+                .new_order(
+                    wallet,
+                    participant,
+                    side,
+                    NonZeroU64::new(10).unwrap(),
+                    OrderType::Limit,
+                    NonZeroU64::new(5).unwrap(),
+                    1,
+                    SelfTradeBehavior::DecrementTake,
+                    1,
+                    NonZeroU64::new(51).unwrap(),
+                    None,
+                )
+                .unwrap();
+        }
+        for _ in 0..100 {
+            if market.event_queue_len().unwrap() == 0 {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        assert_eq!(market.event_queue_len().unwrap(), 0);
+        crank.check_health().unwrap();
+        let health = crank.health();
+        assert!(health.running);
+        assert_eq!(health.restarts, 0);
+
+        let metrics = crank.stop().unwrap();
+        assert!(metrics.events_consumed > 0);
+        assert!(metrics.last_poll.is_some());
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn crank_service_restarts() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let crank = CrankService::spawn(
+            &market,
+            &payer,
+            CrankConfig {
+                interval: Duration::from_millis(50),
+                max_restarts: 2,
+                restart_delay: Duration::from_millis(10),
+                ..CrankConfig::default()
+            },
+        )
+        .unwrap();
+        let wait_for = |done: &dyn Fn(&solarium::services::ServiceHealth) -> bool| {
+            for _ in 0..100 {
+                if done(&crank.health()) {
+                    break;
+                }
+                sleep(Duration::from_millis(50));
+            }
+            crank.health()
+        };
+
+        // A panic is survived by restarting the crank.
+        crank.inject_panics(1);
+        let health = wait_for(&|health| health.restarts == 1);
+        assert!(health.running);
+        assert_eq!(health.last_panic.as_deref(), Some("injected crank panic"));
+        let health = wait_for(&|health| health.since_last_poll < Duration::from_millis(500));
+        assert!(health.since_last_poll < Duration::from_millis(500));
+        crank.check_health().unwrap();
+        assert!(crank
+            .metrics()
+            .errors
+            .contains(&String::from("panic: injected crank panic")));
+
+        // Once the restarts are spent, the service gives up.
+        crank.inject_panics(2);
+        let health = wait_for(&|health| !health.running);
+        assert!(!health.running);
+        assert_eq!(health.restarts, 2);
+        assert!(crank.check_health().is_err());
+        assert!(crank.stop().is_err());
+    }
+
+    #[test]
+    fn seed_book() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let participants =
+            Participant::new_batch(&sandbox, &payer, &market, 2, LAMPORTS_PER_SOL, 0, 0).unwrap();
+        let participants: Vec<&Participant> = participants.iter().collect();
+
+        let placed = market.seed_book(&payer, &participants, 10, 3, 2).unwrap();
+        assert_eq!(placed, 60);
+        let best_bid = market.best_bid().unwrap().unwrap();
+        assert_eq!((best_bid.price, best_bid.orders), (10, 3));
+        let best_ask = market.best_ask().unwrap().unwrap();
+        assert_eq!((best_ask.price, best_ask.orders), (12, 3));
+        assert_eq!(market.depth(20).unwrap().asks.len(), 10);
+        assert_eq!(market.order_count(Side::Bid).unwrap(), 30);
+        assert_eq!(market.order_count(Side::Ask).unwrap(), 30);
+        market.assert_book_consistent();
+        let asks = sandbox.get_account_data(market.asks().pubkey()).unwrap();
+        assert_eq!(
+            sandbox
+                .get_account_slice(market.asks().pubkey(), 13, 32)
+                .unwrap(),
+            asks[13..45].to_vec()
+        );
+        let bump_index = u64::from_le_bytes(asks[13..21].try_into().unwrap()) as usize;
+        let nodes = market.book_nodes(Side::Ask).unwrap();
+        assert_eq!(nodes.len(), bump_index);
+        assert_eq!(
+            nodes,
+            solarium::views::SlabNodeView::load_all(&asks).unwrap()[..bump_index]
+        );
+        let book = market.book_snapshot().unwrap();
+        let json = serde_json::to_string(&book).unwrap();
+        assert_eq!(
+            serde_json::from_str::<solarium::matching::BookSnapshot>(&json).unwrap(),
+            book
+        );
+        let view = participants[0].open_orders_view().unwrap();
+        let json = serde_json::to_value(&view).unwrap();
+        assert_eq!(
+            json["owner"],
+            participants[0].account().pubkey().to_string()
+        );
+        assert_eq!(
+            serde_json::from_value::<solarium::views::OpenOrdersView>(json).unwrap(),
+            view
+        );
+        for participant in participants {
+            assert_eq!(participant.open_orders_view().unwrap().orders.len(), 30);
+        }
+        assert!(market.seed_book(&payer, &[], 1, 1, 1).is_err());
+    }
+
+    #[test]
+    fn trader_multi_market() {
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(100 * LAMPORTS_PER_SOL).unwrap();
+        let serum_program = payer
+            .deploy_remote(
+                "https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so",
+                "serum_dex.so",
+            )
+            .unwrap();
+        let first_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let second_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let quote_mint = Mint::new(&sandbox, &payer, 0, None, None).unwrap();
+        let markets = [&first_mint, &second_mint]
+            .into_iter()
+            .map(|base_mint| {
+                solarium::serum::Market::new(
+                    &sandbox,
+                    &payer,
+                    serum_program.pubkey(),
+                    base_mint,
+                    &quote_mint,
+                    None,
+                    &MarketParams::default_small(),
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut trader = Trader::new(&sandbox, LAMPORTS_PER_SOL).unwrap();
+        trader.fund(&payer, &quote_mint, 1000).unwrap();
+        for market in markets.iter() {
+            trader.join(&payer, market).unwrap();
+        }
+        assert!(trader.join(&payer, &markets[0]).is_err());
+        assert_eq!(trader.participants().len(), 2);
+
+        let first = trader.participant(&markets[0]).unwrap();
+        let second = trader.participant(&markets[1]).unwrap();
+        assert_eq!(first.account().pubkey(), trader.account().pubkey());
+        assert_eq!(first.quote().pubkey(), second.quote().pubkey());
+        assert_ne!(first.open_orders().pubkey(), second.open_orders().pubkey());
+
+        let pc_lot_size = markets[0].view().unwrap().pc_lot_size;
+        for participant in [first, second] {
+            participant
+                .market()
+                .new_order(
+                    participant.quote(),
+                    participant,
+                    Side::Bid,
+                    NonZeroU64::new(10).unwrap(),
+                    OrderType::PostOnly,
+                    NonZeroU64::new(1).unwrap(),
+                    1,
+                    SelfTradeBehavior::DecrementTake,
+                    u16::MAX,
+                    NonZeroU64::new(10 * pc_lot_size).unwrap(),
+                    None,
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            trader.balance(quote_mint.actor().pubkey()).unwrap(),
+            1000 - 20 * pc_lot_size
+        );
+        assert_eq!(trader.balance(first_mint.actor().pubkey()).unwrap(), 0);
+        trader.settle_all(&payer).unwrap();
+    }
+
+    #[test]
+    fn scenario() {
+        let scenario = Scenario::from_yaml(
+            "
+market:
+  base_lot_size: 100
+  quote_lot_size: 10
+participants:
+  - { name: maker, base: 1000000, quote: 1000000 }
+  - { name: taker, base: 1000000, quote: 1000000 }
+steps:
+  - { action: order, participant: maker, side: sell, price: 20, size: 10 }
+  - { action: order, participant: taker, side: buy, price: 20, size: 10 }
+  - { action: order, participant: taker, side: buy, price: 20, size: 100000, rejected: true }
+  - { action: crank }
+  - { action: settle, participant: maker }
+expect:
+  participants:
+    maker: { base: 999000, quote_locked: 0, open_orders: 0 }
+  ask_orders: 0
+",
+        )
+        .unwrap();
+        assert_eq!(scenario.steps.len(), 5);
+        assert_eq!(scenario.steps[3], Step::Crank { limit: None });
+        assert_eq!(
+            Scenario::from_json(&serde_json::to_string(&scenario).unwrap()).unwrap(),
+            scenario
+        );
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        payer.airdrop(100 * LAMPORTS_PER_SOL).unwrap();
+        let serum_program = payer
+            .deploy_remote(
+                "https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so",
+                "serum_dex.so",
+            )
+            .unwrap();
+        let outcome = scenario
+            .run(&sandbox, serum_program.pubkey(), &payer)
+            .unwrap();
+        assert!(outcome.snapshots["taker"].base_unsettled > 0);
+
+        let mut unmet = scenario.clone();
+        unmet.expect.ask_orders = Some(1);
+        assert!(unmet.run(&sandbox, serum_program.pubkey(), &payer).is_err());
+    }
+
+    #[test]
+    fn bench() {
+        use solarium::bench::{self, BenchOptions, LatencyStats};
+
+        let mut stats = LatencyStats::default();
+        for millis in [5, 1, 4, 2, 3] {
+            stats.record(Duration::from_millis(millis));
+        }
+        assert_eq!(stats.percentile(50.0), Duration::from_millis(3));
+        assert_eq!(stats.percentile(100.0), Duration::from_millis(5));
+        assert_eq!(stats.min(), Duration::from_millis(1));
+        assert_eq!(stats.mean(), Duration::from_millis(3));
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let participants = Participant::new_batch(
+            &sandbox,
+            &payer,
+            &market,
+            2,
+            LAMPORTS_PER_SOL,
+            1000000,
+            1000000,
+        )
+        .unwrap();
+
+        let report = bench::market_making(
+            &market,
+            &participants[0],
+            &participants[1],
+            &payer,
+            &BenchOptions {
+                iterations: 3,
+                ..BenchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(report.place_order.count(), 6);
+        assert_eq!(report.crank.count(), 3);
+        assert_eq!(report.settle.count(), 6);
+        assert!(report.place_order.percentile(99.0) <= report.elapsed);
+        assert_eq!(market.book_snapshot().unwrap().asks.len(), 0);
+    }
+
+    #[test]
+    fn replay() {
+        use solarium::replay::{Capture, CaptureKind, ReplayOptions};
+
+        let capture = Capture::from_csv(
+            "timestamp_ms,kind,owner,side,price,size,order_type,client_order_id
+1000,new,maker-address,sell,20,10,limit,1
+1100,new,taker-address,buy,20,4,immediate_or_cancel,7
+1200,cancel,maker-address,sell,,,,1
+1300,cancel,maker-address,sell,,,,2
+",
+        )
+        .unwrap();
+        assert_eq!(capture.records.len(), 4);
+        assert_eq!(capture.records[2].kind, CaptureKind::Cancel);
+        assert_eq!(
+            Capture::from_json(&serde_json::to_string(&capture.records).unwrap()).unwrap(),
+            capture
+        );
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let participants = Participant::new_batch(
+            &sandbox,
+            &payer,
+            &market,
+            2,
+            LAMPORTS_PER_SOL,
+            1000000,
+            1000000,
+        )
+        .unwrap();
+
+        let stats = capture
+            .replay(
+                &market,
+                &participants,
+                &payer,
+                &ReplayOptions {
+                    speed: Some(10.0),
+                    crank_every: Some(2),
+                    ..ReplayOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(stats.orders_placed, 2);
+        assert_eq!(stats.orders_rejected, 0);
+        assert_eq!(stats.cancels, 1);
+        assert_eq!(stats.cancels_skipped, 1);
+        assert_eq!(stats.cranks, 2);
+        assert!(stats.elapsed >= Duration::from_millis(30));
+        assert_eq!(market.book_snapshot().unwrap().asks.len(), 0);
+    }
+
+    #[test]
+    fn replay_rejections() {
+        use solarium::replay::{Capture, ReplayOptions};
+
+        let sandbox = Sandbox::new().unwrap();
+        let payer = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &payer);
+        let market = fixture.market();
+        let participants =
+            Participant::new_batch(&sandbox, &payer, &market, 1, LAMPORTS_PER_SOL, 1000, 1000)
+                .unwrap();
+
+        // The participant cannot pay for the order, so the market rejects it.
+        let unfunded = Capture::from_csv(
+            "timestamp_ms,kind,owner,side,price,size,order_type,client_order_id
+1000,new,taker-address,buy,20,1000000,limit,1
+",
+        )
+        .unwrap();
+        let stats = unfunded
+            .replay(&market, &participants, &payer, &ReplayOptions::default())
+            .unwrap();
+        assert_eq!(stats.orders_placed, 0);
+        assert_eq!(stats.orders_rejected, 1);
+
+        // An order that never reaches the market stops the replay instead.
+        let invalid = Capture::from_csv(
+            "timestamp_ms,kind,owner,side,price,size,order_type,client_order_id
+1000,new,taker-address,buy,0,1,limit,1
+",
+        )
+        .unwrap();
+        let err = invalid
+            .replay(&market, &participants, &payer, &ReplayOptions::default())
+            .unwrap_err();
+        assert!(!err.is_program_error());
+        assert_eq!(err.contexts(), vec!["replaying order 0"]);
+    }
+
+    #[test]
+    fn market_harness() {
+        let sandbox = Sandbox::new().unwrap();
+        let market_creator = Actor::new(&sandbox).unwrap();
+        let fixture = serum_fixture(&sandbox, &market_creator);
+        let market = fixture.market();
+        let participants = Participant::new_batch(
+            &sandbox,
+            &market_creator,
+            &market,
+            3,
+            LAMPORTS_PER_SOL,
+            1000,
+            2000,
+        )
+        .unwrap();
+
+        let mut harness = MarketHarness::new(&market_creator, &market, participants).unwrap();
+        let operations = random_operations(&mut sandbox.rng(), 30, 40, 20);
+        harness.run(&operations).unwrap();
+    }
+
+    /// Builds the pyth_sim in this repository and deploys it, since the
+    /// published pyth_sim.so lacks the admin instructions.
+    fn local_pyth_sim<'a>(payer: &'a Actor<'a>) -> Actor<'a> {
+        let out_dir = payer.sandbox().tmpdir().join("pyth_sim");
+        let status = std::process::Command::new("cargo")
+            .arg("build-bpf")
+            .arg("--manifest-path")
+            .arg(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("pyth/Cargo.toml"))
+            .arg("--bpf-out-dir")
+            .arg(&out_dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "could not build pyth_sim");
+        payer.deploy_local(&out_dir.join("pyth_sim.so")).unwrap()
+    }
+
+    /// The Serum DEX program and a pair of mints, deployed and created by a
+    /// payer funded with 100 SOL, from which tests build markets.
+    struct SerumFixture<'a> {
+        sandbox: &'a Sandbox,
+        payer: &'a Actor<'a>,
+        serum: solana_sdk::pubkey::Pubkey,
+        base_mint: Mint<'a>,
+        quote_mint: Mint<'a>,
+    }
+
+    impl<'a> SerumFixture<'a> {
+        /// Creates a market with default_small parameters and no authority.
+        fn market(&self) -> solarium::serum::Market<'_> {
+            solarium::serum::Market::new(
+                self.sandbox,
+                self.payer,
+                &self.serum,
+                &self.base_mint,
+                &self.quote_mint,
+                None,
+                &MarketParams::default_small(),
+            )
+            .unwrap()
+        }
+    }
+
+    fn serum_fixture<'a>(sandbox: &'a Sandbox, payer: &'a Actor<'a>) -> SerumFixture<'a> {
+        payer.airdrop(100 * LAMPORTS_PER_SOL).unwrap();
+        let serum = *payer
+            .deploy_remote(
+                "https://github.com/foonetic/solarium-deps/raw/main/serum_dex.so",
+                "serum_dex.so",
+            )
+            .unwrap()
+            .pubkey();
+        SerumFixture {
+            sandbox,
+            payer,
+            serum,
+            base_mint: Mint::new(sandbox, payer, 0, None, None).unwrap(),
+            quote_mint: Mint::new(sandbox, payer, 0, None, None).unwrap(),
+        }
+    }
+
+    /// Creates a maker and a taker funded with 10 SOL, 1000 base and 2000
+    /// quote tokens each.
+    fn trading_pair<'a>(
+        sandbox: &'a Sandbox,
+        payer: &'a Actor<'a>,
+        market: &'a solarium::serum::Market<'a>,
+    ) -> (Participant<'a>, Participant<'a>) {
+        let maker = Participant::new(sandbox, payer, market, 10 * LAMPORTS_PER_SOL, 1000, 2000);
+        let taker = Participant::new(sandbox, payer, market, 10 * LAMPORTS_PER_SOL, 1000, 2000);
+        (maker.unwrap(), taker.unwrap())
+    }
+
+    /// Trades 100 base lots at 100 between the pair, large enough for the
+    /// taker fee to fund a referrer rebate, and cranks the fills.
+    fn fee_trade<'a>(
+        market: &solarium::serum::Market<'a>,
+        payer: &Actor,
+        maker: &Participant<'a>,
+        taker: &Participant<'a>,
+    ) {
+        let (price, size) = (NonZeroU64::new(100).unwrap(), NonZeroU64::new(100).unwrap());
+        taker.top_up(0, 20_000).unwrap();
+        market.place_limit(maker, Side::Ask, price, size).unwrap();
+        market.place_limit(taker, Side::Bid, price, size).unwrap();
+        market.drain_events(payer, 10).unwrap();
+    }
+
+    /// Rests the taker's bid at 20, which its 300 quote tokens limit to 15.
+    fn rest_bid(market: &solarium::serum::Market, taker: &Participant) {
+        market
+            .new_order(
+                taker.quote(),
+                taker,
+                Side::Bid,
+                NonZeroU64::new(20).unwrap(),
+                OrderType::Limit,
+                NonZeroU64::new(500).unwrap(),
+                1,
+                SelfTradeBehavior::DecrementTake,
+                1,
+                NonZeroU64::new(300).unwrap(),
+                None,
+            )
+            .unwrap();
+    }
+
+    /// Sells 15 at 20 into the bid placed by rest_bid, filling both orders.
+    fn cross_book(market: &solarium::serum::Market, maker: &Participant) {
+        market
+            .new_order(
+                maker.base(),
+                maker,
+                Side::Ask,
+                NonZeroU64::new(20).unwrap(),
+                OrderType::Limit,
+                NonZeroU64::new(15).unwrap(),
+                1,
+                SelfTradeBehavior::DecrementTake,
+                1,
+                NonZeroU64::new(500).unwrap(),
+                None,
+            )
+            .unwrap();
+    }
+
+    fn do_vecs_match<T: PartialEq>(a: &Vec<T>, b: &Vec<T>) -> bool {
+        let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
+        matching == a.len() && matching == b.len()
     }
 }